@@ -0,0 +1,79 @@
+//! Accounting of the memory the extension allocates on the Rust heap.
+//!
+//! Generation runs entirely on the Rust heap, outside of Postgres memory
+//! contexts, so the global allocator keeps track of the bytes in use and their
+//! high-water mark to report how much memory generating a table took.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+impl CountingAllocator {
+    fn grow(size: usize) {
+        let in_use = IN_USE.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(in_use, Ordering::Relaxed);
+    }
+
+    fn shrink(size: usize) {
+        IN_USE.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+// SAFETY: every call is forwarded to the system allocator unchanged.
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            Self::grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::shrink(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            Self::shrink(layout.size());
+            Self::grow(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Tracks the peak of Rust heap usage over a span of work.
+pub struct PeakTracker {
+    baseline: usize,
+}
+
+impl PeakTracker {
+    /// Starts tracking from the current usage.
+    pub fn start() -> Self {
+        let baseline = IN_USE.load(Ordering::Relaxed);
+        PEAK.store(baseline, Ordering::Relaxed);
+        PeakTracker { baseline }
+    }
+
+    /// Bytes allocated at the peak since [`PeakTracker::start`], on top of
+    /// what was in use before.
+    pub fn peak(&self) -> u64 {
+        PEAK.load(Ordering::Relaxed).saturating_sub(self.baseline) as u64
+    }
+}
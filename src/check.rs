@@ -0,0 +1,235 @@
+//! Checks that a database holds the data a benchmark expects.
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::schema;
+use crate::tables::TABLES;
+
+/// Query computing `(rows, checksum)` of `table`.
+///
+/// The checksum adds up a hash of every row, so it doesn't depend on the
+/// physical row order, and hashes the JSON form of the row because that spells
+/// dates and numbers the same regardless of `DateStyle` and other settings.
+/// It only relies on built-in functions, so it can run against a database
+/// without pg_tpch.
+fn checksum_query(table: &str) -> String {
+    format!(
+        "SELECT count(*), \
+                coalesce(sum(('x' || left(md5(row_to_json(t)::text), 16))::bit(64)::bigint), 0)::text \
+         FROM {table} t"
+    )
+}
+
+/// Returns the row count and checksum of `table` in this database.
+pub fn table_checksum(table: &str) -> spi::Result<(i64, String)> {
+    let (rows, checksum) = Spi::get_two::<i64, String>(&checksum_query(table))?;
+    Ok((rows.unwrap_or(0), checksum.unwrap_or_default()))
+}
+
+/// Compares every TPC-H table with the database at `conninfo`.
+///
+/// Both sides must have been loaded with the same scale factor and options for
+/// the checksums to match. The remote side is queried through `dblink`, which
+/// has to be installed in this database.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_compare_databases(
+    conninfo: &str,
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(table_name, String),
+            name!(local_rows, i64),
+            name!(remote_rows, Option<i64>),
+            name!(local_checksum, String),
+            name!(remote_checksum, Option<String>),
+            name!(matches, bool),
+        ),
+    >,
+> {
+    let dblink =
+        Spi::get_one::<bool>("SELECT EXISTS (SELECT FROM pg_extension WHERE extname = 'dblink')")?;
+    if dblink != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "tpch_compare_databases requires the dblink extension",
+            "Run CREATE EXTENSION dblink first."
+        );
+    }
+
+    let mut results = Vec::with_capacity(TABLES.len());
+    for table in TABLES {
+        let (local_rows, local_checksum) = table_checksum(table)?;
+        let (remote_rows, remote_checksum) = Spi::get_two_with_args::<i64, String>(
+            "SELECT * FROM dblink($1, $2) AS t(rows bigint, checksum text)",
+            &[conninfo.into(), checksum_query(table).into()],
+        )?;
+        let matches =
+            remote_rows == Some(local_rows) && remote_checksum.as_ref() == Some(&local_checksum);
+        results.push((
+            table.to_string(),
+            local_rows,
+            remote_rows,
+            local_checksum,
+            remote_checksum,
+            matches,
+        ));
+    }
+    Ok(TableIterator::new(results))
+}
+
+type Deviation = (
+    String,
+    Option<String>,
+    String,
+    Option<String>,
+    Option<String>,
+);
+
+/// Lists how the live TPC-H tables differ from the definitions of `profile`.
+///
+/// Reports missing tables, missing, unexpected or retyped columns, changed
+/// nullability and `CHECK` constraints, which can reject generated rows or
+/// change plans. An empty result means the schema is untouched.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_check_schema(
+    profile: default!(&str, "'default'"),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(table_name, String),
+            name!(object_name, Option<String>),
+            name!(problem, String),
+            name!(expected, Option<String>),
+            name!(actual, Option<String>),
+        ),
+    >,
+> {
+    if !schema::PROFILES.contains(&profile) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!(
+                "unknown schema profile \"{profile}\", expected one of {:?}",
+                schema::PROFILES
+            )
+        );
+    }
+
+    let mut deviations = Vec::new();
+    for table in TABLES {
+        deviations.extend(check_table(profile, table)?);
+    }
+    Ok(TableIterator::new(deviations))
+}
+
+/// Lists how `table` differs from its definition in `profile`.
+pub fn check_table(profile: &str, table: &str) -> spi::Result<Vec<Deviation>> {
+    let expected = schema::expected_columns(profile, table).unwrap_or_default();
+    let deviation = |object: Option<&str>, problem: &str, expected, actual| {
+        (
+            table.to_string(),
+            object.map(str::to_string),
+            problem.to_string(),
+            expected,
+            actual,
+        )
+    };
+
+    let exists =
+        Spi::get_one_with_args::<bool>("SELECT to_regclass($1) IS NOT NULL", &[table.into()])?;
+    if exists != Some(true) {
+        return Ok(vec![deviation(None, "missing table", None, None)]);
+    }
+
+    let (live, constraints) = Spi::connect(|client| {
+        let live = client
+            .select(
+                "SELECT attname::text, format_type(atttypid, atttypmod), attnotnull
+                 FROM pg_attribute
+                 WHERE attrelid = to_regclass($1) AND attnum > 0 AND NOT attisdropped
+                 ORDER BY attnum",
+                None,
+                &[table.into()],
+            )?
+            .map(|row| {
+                Ok((
+                    row.get::<String>(1)?,
+                    row.get::<String>(2)?,
+                    row.get::<bool>(3)?,
+                ))
+            })
+            .collect::<spi::Result<Vec<_>>>()?;
+        let constraints = client
+            .select(
+                "SELECT conname::text, pg_get_constraintdef(oid)
+                 FROM pg_constraint
+                 WHERE conrelid = to_regclass($1) AND contype = 'c'
+                 ORDER BY conname",
+                None,
+                &[table.into()],
+            )?
+            .map(|row| Ok((row.get::<String>(1)?, row.get::<String>(2)?)))
+            .collect::<spi::Result<Vec<_>>>()?;
+        Ok::<_, spi::SpiError>((live, constraints))
+    })?;
+
+    let nullability = |not_null: bool| if not_null { "NOT NULL" } else { "NULL" }.to_string();
+    let mut deviations = Vec::new();
+    for &(column, type_name, not_null) in &expected {
+        let Some((_, live_type, live_not_null)) = live
+            .iter()
+            .find(|(name, _, _)| name.as_deref() == Some(column))
+        else {
+            deviations.push(deviation(
+                Some(column),
+                "missing column",
+                Some(type_name.to_string()),
+                None,
+            ));
+            continue;
+        };
+        if live_type.as_deref() != Some(type_name) {
+            deviations.push(deviation(
+                Some(column),
+                "type differs",
+                Some(type_name.to_string()),
+                live_type.clone(),
+            ));
+        }
+        if *live_not_null != Some(not_null) {
+            deviations.push(deviation(
+                Some(column),
+                "nullability differs",
+                Some(nullability(not_null)),
+                live_not_null.map(nullability),
+            ));
+        }
+    }
+    for (name, live_type, _) in &live {
+        if !expected
+            .iter()
+            .any(|(column, _, _)| name.as_deref() == Some(*column))
+        {
+            deviations.push(deviation(
+                name.as_deref(),
+                "unexpected column",
+                None,
+                live_type.clone(),
+            ));
+        }
+    }
+    for (name, definition) in constraints {
+        deviations.push(deviation(
+            name.as_deref(),
+            "unexpected constraint",
+            None,
+            definition,
+        ));
+    }
+    Ok(deviations)
+}
@@ -0,0 +1,196 @@
+//! One-call validation of a Postgres build, for CI jobs.
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::runner::{self, RunOptions};
+use crate::tables::TABLES;
+use crate::{check, constraints};
+
+/// Digest of a query's result, `(query_nr, rows, checksum)`, see
+/// [`result_digest`].
+type Answer = (i32, i64, &'static str);
+
+/// Answers of every query by scale factor.
+const ANSWERS: &[(f64, &[Answer])] = &[
+    (
+        0.01,
+        &[
+            (1, 1, "6228496792163285753"),
+            (2, 1, "5568811199730906090"),
+            (3, 10, "-17040177666750781818"),
+            (4, 1, "3298359383248549950"),
+            (5, 1, "-4532525513628012445"),
+            (6, 1, "-3913035644923579693"),
+            (7, 1, "5670754191622824866"),
+            (8, 1, "-2104078480332853098"),
+            (9, 1, "-2521232563558457193"),
+            (10, 1, "-507294490035261025"),
+            (11, 1, "5073898759383688921"),
+            (12, 1, "-488232203339842906"),
+            (13, 1, "4623921621872604682"),
+            (14, 1, "-6556100010895364067"),
+            (15, 1, "-1903022019824645817"),
+            (16, 1, "-1174575047517198356"),
+            (17, 1, "7799709448905659861"),
+            (18, 0, "0"),
+            (19, 1, "7799709448905659861"),
+            (20, 1, "-3344867001328087006"),
+            (21, 1, "-6901394802854155659"),
+            (22, 1, "6358245109954461468"),
+        ],
+    ),
+    (
+        1.,
+        &[
+            (1, 1, "7202613470258625204"),
+            (2, 100, "27511182835524202428"),
+            (3, 10, "34352866622026945068"),
+            (4, 1, "-2221994280263165851"),
+            (5, 1, "-8190852393307743009"),
+            (6, 1, "-8897098245673443353"),
+            (7, 1, "7166682410872514793"),
+            (8, 1, "-6638901907808938774"),
+            (9, 1, "-8973904984085567606"),
+            (10, 1, "-121289191159983182"),
+            (11, 1, "8535333849740973866"),
+            (12, 1, "-9157201566523308686"),
+            (13, 1, "-896758886452482919"),
+            (14, 1, "-3508592301227598820"),
+            (15, 1, "-6448916089020098348"),
+            (16, 1, "2740531066366881086"),
+            (17, 1, "7799709448905659861"),
+            (18, 1, "-7423273044061225955"),
+            (19, 1, "-8801918437881215659"),
+            (20, 1, "-7052399657163143978"),
+            (21, 1, "-4470748969143603270"),
+            (22, 1, "-7577400098769104539"),
+        ],
+    ),
+];
+
+/// Returns the row count and a checksum of the result persisted in `table`.
+///
+/// Like [`check::table_checksum`] the checksum adds up a hash per row, so it
+/// ignores the row order, but numbers are rounded to two decimals first so
+/// averages don't depend on how many digits a version carries.
+fn result_digest(table: &str) -> spi::Result<(i64, String)> {
+    let (rows, checksum) = Spi::get_two::<i64, String>(&format!(
+        "SELECT count(*), coalesce(sum(('x' || left(md5(r), 16))::bit(64)::bigint), 0)::text
+         FROM (SELECT (SELECT string_agg(coalesce(CASE jsonb_typeof(value)
+                                                      WHEN 'number' THEN round(value::numeric, 2)::text
+                                                      ELSE value #>> '{{}}'
+                                                  END, 'NULL'), '|' ORDER BY key)
+                       FROM jsonb_each(to_jsonb(t) - 'result_row')) AS r
+               FROM {} t) s",
+        spi::quote_identifier(table)
+    ))?;
+    Ok((rows.unwrap_or(0), checksum.unwrap_or_default()))
+}
+
+/// Lists what is wrong with the loaded dataset: an unfinished load, schema
+/// changes, tables that don't hold the rows the load recorded and rows
+/// referencing missing ones.
+fn check_integrity() -> spi::Result<Vec<String>> {
+    let mut failures = Vec::new();
+    let state = crate::metadata::tpch_dataset_state()?;
+    if state != "complete" {
+        failures.push(format!("dataset is {state}"));
+    }
+    for table in TABLES {
+        for (table, object, problem, expected, actual) in check::check_table("default", table)? {
+            failures.push(format!(
+                "schema of {table}{}: {problem} (expected {}, got {})",
+                object
+                    .map(|object| format!(".{object}"))
+                    .unwrap_or_default(),
+                expected.as_deref().unwrap_or("nothing"),
+                actual.as_deref().unwrap_or("nothing"),
+            ));
+        }
+        let (rows, loaded) = Spi::get_two::<i64, i64>(&format!(
+            "SELECT (SELECT count(*) FROM {table}),
+                    (SELECT sum(rows)::bigint FROM pg_tpch_load_stats
+                     WHERE table_name = '{table}'
+                       AND load_id = (SELECT max(load_id) FROM pg_tpch_dataset))"
+        ))?;
+        if rows != loaded {
+            failures.push(format!(
+                "{table} holds {} rows, the load wrote {}",
+                rows.unwrap_or(0),
+                loaded.unwrap_or(0)
+            ));
+        }
+    }
+    // The foreign keys may not be declared, so look for orphans.
+    for (table, columns, referenced, referenced_columns) in constraints::FOREIGN_KEYS {
+        let orphans = Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM {table} t
+             WHERE NOT EXISTS (SELECT FROM {referenced} r
+                               WHERE ({referenced_columns}) = (t.{}))",
+            columns.replace(", ", ", t.")
+        ))?;
+        if orphans != Some(0) {
+            failures.push(format!(
+                "{} rows of {table} reference missing {referenced} ({columns})",
+                orphans.unwrap_or(0)
+            ));
+        }
+    }
+    Ok(failures)
+}
+
+/// Loads SF=`sf`, checks the dataset, runs all 22 queries and compares their
+/// results with the embedded answers, returning whether everything passed and
+/// what didn't.
+///
+/// Meant to be the one statement a CI job runs against a new Postgres build.
+/// Answers are embedded for SF 0.01 and 1. The results are persisted, so a
+/// failing query can be inspected through `pg_tpch_run_results`. A query
+/// that raises an error fails the call with that error.
+#[pg_extern]
+fn tpch_ci(
+    sf: default!(f64, 0.01),
+) -> spi::Result<TableIterator<'static, (name!(passed, bool), name!(failures, Vec<String>))>> {
+    let Some((_, answers)) = ANSWERS.iter().find(|(answer_sf, _)| *answer_sf == sf) else {
+        let supported: Vec<f64> = ANSWERS.iter().map(|(sf, _)| *sf).collect();
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            format!("no answers for SF={sf}, tpch_ci supports SF in {supported:?}")
+        );
+    };
+
+    Spi::run_with_args("SELECT tpch_load($1)", &[sf.into()])?;
+    let mut failures = check_integrity()?;
+
+    let plan: Vec<(i32, i32)> = answers
+        .iter()
+        .map(|&(query_nr, _, _)| (query_nr, 1))
+        .collect();
+    let results = runner::run_queries(
+        &plan,
+        &RunOptions {
+            profile: "default",
+            role: None,
+            persist_results: true,
+            baseline_run: None,
+            max_regression: 0.,
+            max_duration: None,
+            cancel_in_flight: false,
+            dataset: None,
+            prewarm: false,
+        },
+    )?;
+    for (result, &(query_nr, rows, checksum)) in results.iter().zip(answers.iter()) {
+        let table = format!("tpch_result_q{query_nr}_run{}", result.run_id);
+        let (actual_rows, actual_checksum) = result_digest(&table)?;
+        if (actual_rows, actual_checksum.as_str()) != (rows, checksum) {
+            failures.push(format!(
+                "query {query_nr} returned {actual_rows} rows with checksum {actual_checksum}, \
+                 expected {rows} rows with checksum {checksum}"
+            ));
+        }
+    }
+
+    Ok(TableIterator::once((failures.is_empty(), failures)))
+}
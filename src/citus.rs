@@ -0,0 +1,70 @@
+//! Distributing the TPC-H tables over a Citus cluster.
+use pgrx::prelude::*;
+use pgrx::spi;
+
+/// How the tables are laid out on a Citus cluster as `(table, distribution
+/// column)`, in load order, with no column for reference tables copied to
+/// every node. `orders` and `lineitem` share the order key, so the joins
+/// between them stay on one node, and so do `part` and `partsupp`.
+const DISTRIBUTION: [(&str, Option<&str>); 8] = [
+    ("region", None),
+    ("nation", None),
+    ("part", Some("p_partkey")),
+    ("supplier", None),
+    ("partsupp", Some("ps_partkey")),
+    ("customer", Some("c_custkey")),
+    ("orders", Some("o_orderkey")),
+    ("lineitem", Some("l_orderkey")),
+];
+
+/// Turns the TPC-H tables into Citus distributed and reference tables and
+/// returns what it did, one line per table.
+///
+/// `region`, `nation` and `supplier` become reference tables, the others are
+/// hash distributed on their first key: `orders` and `lineitem` on the order
+/// key, `part` and `partsupp` on the part key and `customer` on the customer
+/// key. Tables Citus already manages are left alone. Call it on empty tables
+/// before loading, Citus would otherwise move the rows the tables have to
+/// the workers. Foreign keys between distributed tables have to include the
+/// distribution column, so most of those of `tpch_add_constraints()` can't
+/// be added.
+#[pg_extern]
+fn tpch_distribute() -> spi::Result<Vec<String>> {
+    let citus =
+        Spi::get_one::<bool>("SELECT EXISTS (SELECT FROM pg_extension WHERE extname = 'citus')")?;
+    if citus != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "tpch_distribute needs the Citus extension",
+            "Run CREATE EXTENSION citus."
+        );
+    }
+    let mut distributed = Vec::new();
+    for (table, column) in DISTRIBUTION {
+        let managed = Spi::get_one_with_args::<bool>(
+            "SELECT EXISTS (SELECT FROM pg_dist_partition WHERE logicalrelid = to_regclass($1))",
+            &[table.into()],
+        )?;
+        if managed == Some(true) {
+            continue;
+        }
+        match column {
+            Some(column) => {
+                Spi::run_with_args(
+                    "SELECT create_distributed_table($1::regclass, $2)",
+                    &[table.into(), column.into()],
+                )?;
+                distributed.push(format!("{table} distributed by {column}"));
+            }
+            None => {
+                Spi::run_with_args(
+                    "SELECT create_reference_table($1::regclass)",
+                    &[table.into()],
+                )?;
+                distributed.push(format!("{table} replicated to every node"));
+            }
+        }
+    }
+    Ok(distributed)
+}
@@ -0,0 +1,171 @@
+//! Generating `orders` and `lineitem` in a single pass.
+//!
+//! `tpchgen` generates the two tables independently, and to fill in
+//! `o_totalprice` and `o_orderstatus` the order generator simulates every line
+//! item of the order, drawing the same random values the line item generator
+//! draws again later. Here the line items are generated once and the order
+//! columns that depend on them are computed from the actual rows, so only the
+//! order's own columns are drawn separately. The random streams and their
+//! seeds mirror `tpchgen`'s `OrderGeneratorIterator`, so the rows are identical
+//! to those of [`OrderGenerator`].
+use std::io;
+
+use tpchgen::dates::{GenerateUtils, TPCHDate};
+use tpchgen::decimal::TPCHDecimal;
+use tpchgen::distribution::Distributions;
+use tpchgen::generators::{
+    ClerkName, LineItem, LineItemGenerator, Order, OrderGenerator, OrderStatus,
+};
+use tpchgen::random::{RandomBoundedInt, RandomBoundedLong, RandomString, RandomText};
+use tpchgen::text::TextPool;
+
+use crate::format::{RowWriter, TpchRow};
+use crate::progress;
+
+// Constants private to tpchgen's generators.
+const CUSTOMER_SCALE_BASE: i32 = 150_000;
+const CUSTOMER_MORTALITY: i64 = 3;
+const CLERK_SCALE_BASE: i32 = 1000;
+const COMMENT_AVERAGE_LENGTH: f64 = 49.;
+
+/// Draws the columns of an order that don't depend on its line items.
+struct OrderColumns {
+    order_date_random: RandomBoundedInt,
+    customer_key_random: RandomBoundedLong,
+    order_priority_random: RandomString<'static>,
+    clerk_random: RandomBoundedInt,
+    comment_random: RandomText<'static>,
+    max_customer_key: i64,
+}
+
+impl OrderColumns {
+    fn new(sf: f64, start_index: i64) -> OrderColumns {
+        let distributions = Distributions::static_default();
+        let text_pool = TextPool::get_or_init_default();
+        let max_customer_key = (CUSTOMER_SCALE_BASE as f64 * sf) as i64;
+        let max_clerk = (sf * CLERK_SCALE_BASE as f64).max(CLERK_SCALE_BASE as f64) as i32;
+
+        let mut columns = OrderColumns {
+            order_date_random: OrderGenerator::create_order_date_random(),
+            customer_key_random: RandomBoundedLong::new(
+                851767375,
+                sf >= 30000.,
+                1,
+                max_customer_key,
+            ),
+            order_priority_random: RandomString::new(591449447, distributions.order_priority()),
+            clerk_random: RandomBoundedInt::new(1171034773, 1, max_clerk),
+            comment_random: RandomText::new(276090261, text_pool, COMMENT_AVERAGE_LENGTH),
+            max_customer_key,
+        };
+        columns.order_date_random.advance_rows(start_index);
+        columns.customer_key_random.advance_rows(start_index);
+        columns.order_priority_random.advance_rows(start_index);
+        columns.clerk_random.advance_rows(start_index);
+        columns.comment_random.advance_rows(start_index);
+        columns
+    }
+
+    /// Builds the order with key `order_key` out of `lines`, its line items.
+    fn next(&mut self, order_key: i64, lines: &[LineItem<'static>]) -> Order<'static> {
+        let order_date = self.order_date_random.next_value();
+
+        // Customers whose key is a multiple of 3 never place orders.
+        let mut customer_key = self.customer_key_random.next_value();
+        let mut delta = 1;
+        while customer_key % CUSTOMER_MORTALITY == 0 {
+            customer_key += delta;
+            customer_key = customer_key.min(self.max_customer_key);
+            delta *= -1;
+        }
+
+        let total_price = lines
+            .iter()
+            .map(|line| {
+                let discounted = line.l_extendedprice.0 * (100 - line.l_discount.0);
+                ((discounted / 100) * (100 + line.l_tax.0)) / 100
+            })
+            .sum();
+        let shipped = lines.iter().filter(|line| line.l_linestatus == "F").count();
+        let order_status = if shipped == lines.len() {
+            OrderStatus::Fulfilled
+        } else if shipped > 0 {
+            OrderStatus::Pending
+        } else {
+            OrderStatus::Open
+        };
+
+        let order = Order {
+            o_orderkey: order_key,
+            o_custkey: customer_key,
+            o_orderstatus: order_status,
+            o_totalprice: TPCHDecimal(total_price),
+            o_orderdate: TPCHDate::new(order_date),
+            o_orderpriority: self.order_priority_random.next_value(),
+            o_clerk: ClerkName::new(self.clerk_random.next_value()),
+            o_shippriority: 0,
+            o_comment: self.comment_random.next_value(),
+        };
+
+        self.order_date_random.row_finished();
+        self.customer_key_random.row_finished();
+        self.order_priority_random.row_finished();
+        self.clerk_random.row_finished();
+        self.comment_random.row_finished();
+        order
+    }
+}
+
+/// Calls `f` with every order of part `part` of `num_parts` and its line
+/// items, in key order.
+pub fn generate(
+    sf: f64,
+    part: i32,
+    num_parts: i32,
+    mut f: impl FnMut(&Order<'static>, &[LineItem<'static>]) -> io::Result<()>,
+) -> io::Result<()> {
+    let start_index =
+        GenerateUtils::calculate_start_index(OrderGenerator::SCALE_BASE, sf, part, num_parts);
+    let row_count = OrderGenerator::calculate_row_count(sf, part, num_parts);
+    let mut columns = OrderColumns::new(sf, start_index);
+    let mut line_items = LineItemGenerator::new(sf, part, num_parts)
+        .iter()
+        .peekable();
+    let mut lines = Vec::new();
+    for index in start_index + 1..=start_index + row_count {
+        let order_key = OrderGenerator::make_order_key(index);
+        lines.clear();
+        while let Some(line) = line_items.next_if(|line| line.l_orderkey == order_key) {
+            lines.push(line);
+        }
+        f(&columns.next(order_key, &lines), &lines)?;
+    }
+    Ok(())
+}
+
+/// Writes part `part` of `num_parts` of `orders` and `lineitem` in one pass
+/// and returns the row counts of both.
+pub fn write_rows(
+    sf: f64,
+    part: i32,
+    num_parts: i32,
+    mut orders: impl RowWriter,
+    mut lineitem: impl RowWriter,
+) -> io::Result<(u64, u64)> {
+    orders.write_header(Order::COLUMNS)?;
+    lineitem.write_header(LineItem::COLUMNS)?;
+    let (mut order_rows, mut lineitem_rows) = (0, 0);
+    generate(sf, part, num_parts, |order, lines| {
+        orders.write_row(order)?;
+        order_rows += 1;
+        progress::row_generated();
+        for line in lines {
+            lineitem.write_row(line)?;
+        }
+        lineitem_rows += lines.len() as u64;
+        Ok(())
+    })?;
+    orders.finish()?;
+    lineitem.finish()?;
+    Ok((order_rows, lineitem_rows))
+}
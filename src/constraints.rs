@@ -0,0 +1,322 @@
+//! Primary and foreign keys of the TPC-H schema.
+//!
+//! The tables are created without constraints, `tpch_add_constraints()` adds
+//! the keys the specification defines and `tpch_drop_constraints()` drops
+//! them again. Validating the foreign keys reads the whole referencing table,
+//! which dominates at large scale factors, so they can be added `NOT VALID`
+//! and validated later by `tpch_validate_constraints()`, with several
+//! background workers at once. `tpch_load(constraints => 'not_valid')` adds
+//! them that way as soon as the load completes. Every validation records its
+//! duration in `pg_tpch_constraint_validations`, written by whichever session
+//! ran it.
+use std::time::Instant;
+
+use pgrx::bgworkers::{BackgroundWorker, BackgroundWorkerBuilder};
+use pgrx::pg_sys;
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::parallel;
+use crate::tables::TABLES;
+
+extension_sql!(
+    r#"
+    CREATE TABLE pg_tpch_constraint_validations (
+        table_name text NOT NULL,
+        constraint_name text NOT NULL,
+        duration_ms double precision NOT NULL,
+        finished_at timestamptz NOT NULL DEFAULT clock_timestamp()
+    );
+    COMMENT ON TABLE pg_tpch_constraint_validations IS 'constraints validated by tpch_validate_constraints, written by the session that validated them';
+    "#,
+    name = "constraint_validations",
+    requires = ["create_schema"]
+);
+
+/// Primary keys of the TPC-H schema as `(table, columns)`.
+pub const PRIMARY_KEYS: &[(&str, &str)] = &[
+    ("region", "r_regionkey"),
+    ("nation", "n_nationkey"),
+    ("part", "p_partkey"),
+    ("supplier", "s_suppkey"),
+    ("partsupp", "ps_partkey, ps_suppkey"),
+    ("customer", "c_custkey"),
+    ("orders", "o_orderkey"),
+    ("lineitem", "l_orderkey, l_linenumber"),
+];
+
+/// Foreign keys of the TPC-H schema as `(table, columns, referenced table,
+/// referenced columns)`, each referencing a primary key.
+pub const FOREIGN_KEYS: &[(&str, &str, &str, &str)] = &[
+    ("nation", "n_regionkey", "region", "r_regionkey"),
+    ("supplier", "s_nationkey", "nation", "n_nationkey"),
+    ("customer", "c_nationkey", "nation", "n_nationkey"),
+    ("partsupp", "ps_partkey", "part", "p_partkey"),
+    ("partsupp", "ps_suppkey", "supplier", "s_suppkey"),
+    ("orders", "o_custkey", "customer", "c_custkey"),
+    ("lineitem", "l_orderkey", "orders", "o_orderkey"),
+    (
+        "lineitem",
+        "l_partkey, l_suppkey",
+        "partsupp",
+        "ps_partkey, ps_suppkey",
+    ),
+];
+
+/// Name of the constraint on `columns` of `table`, the one Postgres would
+/// pick.
+fn constraint_name(table: &str, columns: &str, suffix: &str) -> String {
+    format!("{table}_{}_{suffix}", columns.replace(", ", "_"))
+}
+
+/// Whether `table` has a constraint called `name`.
+fn has_constraint(table: &str, name: &str) -> spi::Result<bool> {
+    Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS (SELECT FROM pg_constraint WHERE conrelid = to_regclass($1) AND conname = $2)",
+        &[table.into(), name.into()],
+    )
+    .map(|exists| exists == Some(true))
+}
+
+/// Drops the keys of [`PRIMARY_KEYS`] and [`FOREIGN_KEYS`] that exist,
+/// foreign keys first, and returns their names.
+pub fn drop_constraints() -> spi::Result<Vec<String>> {
+    let foreign = FOREIGN_KEYS
+        .iter()
+        .map(|(table, columns, _, _)| (*table, constraint_name(table, columns, "fkey")));
+    let primary = PRIMARY_KEYS
+        .iter()
+        .map(|(table, columns)| (*table, constraint_name(table, columns, "pkey")));
+    let mut dropped = Vec::new();
+    for (table, name) in foreign.chain(primary) {
+        if has_constraint(table, &name)? {
+            Spi::run(&format!(
+                "ALTER TABLE {table} DROP CONSTRAINT {}",
+                spi::quote_identifier(&name)
+            ))?;
+            dropped.push(name);
+        }
+    }
+    Ok(dropped)
+}
+
+/// Validates constraint `name` of `table` and records how long it took.
+fn validate(table: &str, name: &str) -> spi::Result<f64> {
+    let started = Instant::now();
+    Spi::run(&format!(
+        "ALTER TABLE {table} VALIDATE CONSTRAINT {}",
+        spi::quote_identifier(name)
+    ))?;
+    let duration_ms = started.elapsed().as_secs_f64() * 1000.;
+    Spi::run_with_args(
+        "INSERT INTO pg_tpch_constraint_validations (table_name, constraint_name, duration_ms)
+         VALUES ($1, $2, $3)",
+        &[table.into(), name.into(), duration_ms.into()],
+    )?;
+    Ok(duration_ms)
+}
+
+/// Entry point of the workers started by [`tpch_validate_constraints`], which
+/// get the table and constraint through `bgw_extra`, tab-separated.
+#[pg_guard]
+#[no_mangle]
+pub extern "C-unwind" fn pg_tpch_validate_worker(arg: pg_sys::Datum) {
+    parallel::connect_worker(arg);
+
+    let job = BackgroundWorker::get_extra();
+    let Some((table, name)) = job.split_once('\t') else {
+        panic!("invalid validation job \"{job}\"");
+    };
+    BackgroundWorker::transaction(|| validate(table, name))
+        .unwrap_or_else(|e| panic!("could not validate constraint \"{name}\" of {table}: {e}"));
+}
+
+/// Adds the primary and foreign keys of the TPC-H specification to the
+/// tables and returns how long each took, leaving out those that exist.
+///
+/// With `not_valid`, foreign keys are added `NOT VALID`: they are enforced
+/// for new rows right away, without reading the rows already loaded, and
+/// become valid with `tpch_validate_constraints()`. Primary keys are always
+/// built and checked, the foreign keys need their indexes.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_add_constraints(
+    not_valid: default!(bool, false),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(table_name, String),
+            name!(constraint_name, String),
+            name!(duration_ms, f64),
+        ),
+    >,
+> {
+    Ok(TableIterator::new(add_constraints(not_valid)?))
+}
+
+/// Adds the keys of [`PRIMARY_KEYS`] and [`FOREIGN_KEYS`] that don't exist,
+/// the foreign keys `NOT VALID` with `not_valid`, and returns them with how
+/// long each took.
+pub fn add_constraints(not_valid: bool) -> spi::Result<Vec<(String, String, f64)>> {
+    let mut statements = Vec::new();
+    for (table, columns) in PRIMARY_KEYS {
+        let name = constraint_name(table, columns, "pkey");
+        let definition = format!("PRIMARY KEY ({columns})");
+        statements.push((*table, name, definition));
+    }
+    for (table, columns, referenced, referenced_columns) in FOREIGN_KEYS {
+        let name = constraint_name(table, columns, "fkey");
+        let definition = format!(
+            "FOREIGN KEY ({columns}) REFERENCES {referenced} ({referenced_columns}){}",
+            if not_valid { " NOT VALID" } else { "" }
+        );
+        statements.push((*table, name, definition));
+    }
+
+    let mut added = Vec::new();
+    for (table, name, definition) in statements {
+        if has_constraint(table, &name)? {
+            continue;
+        }
+        let started = Instant::now();
+        Spi::run(&format!(
+            "ALTER TABLE {table} ADD CONSTRAINT {} {definition}",
+            spi::quote_identifier(&name)
+        ))?;
+        added.push((
+            table.to_string(),
+            name,
+            started.elapsed().as_secs_f64() * 1000.,
+        ));
+    }
+    Ok(added)
+}
+
+/// Drops the primary and foreign keys `tpch_add_constraints()` adds, foreign
+/// keys first, and returns the names of those that existed.
+///
+/// Constraints added by hand under other names are left alone.
+#[pg_extern]
+fn tpch_drop_constraints() -> spi::Result<Vec<String>> {
+    drop_constraints()
+}
+
+/// Validates every `NOT VALID` constraint of the TPC-H tables and returns how
+/// long each validation took.
+///
+/// With `parallel` above 1, that many background workers validate
+/// constraints at a time, each in its own transaction that commits on its
+/// own, biggest tables first. Two constraints of the same table still wait
+/// for each other. The workers can't validate what this transaction added or
+/// locked, so the constraints have to be added in an earlier one.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_validate_constraints(
+    parallel: default!(i32, 1),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(table_name, String),
+            name!(constraint_name, String),
+            name!(duration_ms, f64),
+        ),
+    >,
+> {
+    if parallel < 1 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("parallel must be at least 1, got {parallel}")
+        );
+    }
+    let pending = Spi::connect(|client| {
+        client
+            .select(
+                "SELECT c.conrelid::regclass::text, c.conname::text
+                 FROM pg_constraint c
+                 WHERE NOT c.convalidated
+                   AND c.conrelid = ANY(SELECT to_regclass(t) FROM unnest($1::text[]) t)
+                 ORDER BY pg_relation_size(c.conrelid) DESC, c.conname",
+                None,
+                &[TABLES.to_vec().into()],
+            )?
+            .map(|row| {
+                Ok((
+                    row.get::<String>(1)?.unwrap_or_default(),
+                    row.get::<String>(2)?.unwrap_or_default(),
+                ))
+            })
+            .collect::<spi::Result<Vec<_>>>()
+    })?;
+
+    if parallel == 1 {
+        let mut validated = Vec::with_capacity(pending.len());
+        for (table, name) in pending {
+            let duration_ms = validate(&table, &name)?;
+            validated.push((table, name, duration_ms));
+        }
+        return Ok(TableIterator::new(validated));
+    }
+
+    // A lock of this transaction would leave the workers waiting for it while
+    // it waits for them.
+    if parallel::holds_table_locks()? {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            "validating with workers needs the constraints committed",
+            "Add them with tpch_add_constraints in a separate transaction first."
+        );
+    }
+    let started = Spi::get_one::<TimestampWithTimeZone>("SELECT clock_timestamp()")?;
+    for batch in pending.chunks(parallel as usize) {
+        let mut handles = Vec::with_capacity(batch.len());
+        for (table, name) in batch {
+            let handle = BackgroundWorkerBuilder::new(&format!("pg_tpch validate {name}"))
+                .set_type("pg_tpch validate")
+                .set_library("pg_tpch")
+                .set_function("pg_tpch_validate_worker")
+                .enable_spi_access()
+                .set_argument(Some(parallel::session_argument()))
+                .set_extra(&format!("{table}\t{name}"))
+                // SAFETY: MyProcPid is set for the lifetime of the backend.
+                .set_notify_pid(unsafe { pg_sys::MyProcPid })
+                .load_dynamic()
+                .unwrap_or_else(|_| {
+                    ereport!(
+                        ERROR,
+                        PgSqlErrorCode::ERRCODE_CONFIGURATION_LIMIT_EXCEEDED,
+                        "could not start a background worker to validate a constraint",
+                        "Use a lower parallel or raise max_worker_processes."
+                    );
+                });
+            handles.push(handle);
+        }
+        for handle in handles {
+            // A worker that already stopped is fine, its outcome is checked
+            // below.
+            let _ = handle.wait_for_shutdown();
+        }
+    }
+
+    let mut validated = Vec::with_capacity(pending.len());
+    for (table, name) in pending {
+        let duration_ms = Spi::get_one_with_args::<f64>(
+            "SELECT max(duration_ms) FROM pg_tpch_constraint_validations
+             WHERE table_name = $1 AND constraint_name = $2 AND finished_at >= $3",
+            &[table.as_str().into(), name.as_str().into(), started.into()],
+        )?;
+        let Some(duration_ms) = duration_ms else {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_EXTERNAL_ROUTINE_EXCEPTION,
+                format!("could not validate constraint \"{name}\" of {table}"),
+                "The worker validating it failed, see the server log for its error."
+            );
+        };
+        validated.push((table, name, duration_ms));
+    }
+    Ok(TableIterator::new(validated))
+}
@@ -0,0 +1,273 @@
+//! Running the `COPY` that loads a generated file, with useful errors.
+//!
+//! When `COPY` rejects a row, Postgres reports the line and column only in the
+//! error context, which doesn't survive the trip back through SPI. We rebuild
+//! that information ourselves: the number of rows the `COPY` had processed
+//! before failing (from its progress counters, Postgres 14 and later) points at
+//! the approximate line, which is read back from the file to name the
+//! offending field.
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use pgrx::pg_sys::panic::CaughtError;
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::format::CsvOptions;
+
+/// Loads the CSV file at `path` into `table` and returns the number of rows
+/// loaded.
+///
+/// If the file is rejected, the error names the table, file, line and field.
+/// With `rejects` set, the rejected line is also appended to that file. With
+/// `freeze`, the rows are loaded frozen, see [`freeze_option`].
+pub fn copy_from(
+    table: &str,
+    path: &Path,
+    csv: &CsvOptions,
+    rejects: Option<&Path>,
+    freeze: bool,
+) -> u64 {
+    let query = format!(
+        "COPY {} FROM {} WITH ({}{})",
+        table,
+        spi::quote_literal(path.display().to_string()),
+        crate::copy_options(csv),
+        freeze_option(freeze)
+    );
+
+    PgTryBuilder::new(|| {
+        Spi::connect_mut(|client| client.update(query.as_str(), None, &[]).map(|t| t.len()))
+    })
+    .catch_others(|error| {
+        let CaughtError::PostgresError(report) = &error else {
+            error.rethrow();
+        };
+        let line = rows_processed().map(|rows| rows + 2);
+        let mut detail = match line {
+            Some(line) => describe_line(path, line, csv, report.message()),
+            None => "The rejected line is unknown on this Postgres version.".to_string(),
+        };
+        if let Some(original) = report.detail() {
+            detail = format!("{detail}\n{original}");
+        }
+        if let (Some(rejects), Some(line)) = (rejects, line) {
+            let saved = match save_line(path, line, rejects) {
+                Ok(()) => format!("The rejected line was saved to \"{}\".", rejects.display()),
+                Err(e) => format!(
+                    "Could not save the rejected line to \"{}\": {e}",
+                    rejects.display()
+                ),
+            };
+            detail = format!("{detail}\n{saved}");
+        }
+        ereport!(
+            ERROR,
+            report.sql_error_code(),
+            format!(
+                "could not load \"{}\" into {table}: {}",
+                path.display(),
+                report.message()
+            ),
+            detail
+        );
+    })
+    .execute()
+    .unwrap_or_else(|e| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INTERNAL_ERROR,
+            format!("could not load \"{}\" into {table}: {e}", path.display())
+        );
+    }) as u64
+}
+
+/// Loads the file at `path`, in the binary format of `COPY`, into `table`
+/// and returns the number of rows loaded.
+///
+/// The file has no lines to show, so a rejected row is only located by its
+/// number.
+pub fn copy_from_binary(table: &str, path: &Path, freeze: bool) -> u64 {
+    let query = format!(
+        "COPY {} FROM {} WITH (FORMAT binary{})",
+        table,
+        spi::quote_literal(path.display().to_string()),
+        freeze_option(freeze)
+    );
+
+    PgTryBuilder::new(|| {
+        Spi::connect_mut(|client| client.update(query.as_str(), None, &[]).map(|t| t.len()))
+    })
+    .catch_others(|error| {
+        let CaughtError::PostgresError(report) = &error else {
+            error.rethrow();
+        };
+        let mut detail = match rows_processed() {
+            Some(rows) => format!("Row {} of the file (approximately).", rows + 1),
+            None => "The rejected row is unknown on this Postgres version.".to_string(),
+        };
+        if let Some(original) = report.detail() {
+            detail = format!("{detail}\n{original}");
+        }
+        ereport!(
+            ERROR,
+            report.sql_error_code(),
+            format!(
+                "could not load \"{}\" into {table}: {}",
+                path.display(),
+                report.message()
+            ),
+            detail
+        );
+    })
+    .execute()
+    .unwrap_or_else(|e| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INTERNAL_ERROR,
+            format!("could not load \"{}\" into {table}: {e}", path.display())
+        );
+    }) as u64
+}
+
+/// Returns the `FREEZE` option to add to a `COPY` with `freeze`, if any.
+///
+/// `COPY ... FREEZE` writes the rows frozen and marked visible to everyone,
+/// so the first queries don't set hint bits on every page and no vacuum has
+/// to freeze them later. Postgres only allows it into a table created or
+/// truncated by the transaction running it.
+pub fn freeze_option(freeze: bool) -> &'static str {
+    if freeze {
+        ", FREEZE"
+    } else {
+        ""
+    }
+}
+
+/// Loads the output of the shell `command` into `table` with the `COPY`
+/// options `options` and returns the number of rows loaded.
+///
+/// The output can't be read back, so a rejected row is only located by its
+/// line number.
+pub fn copy_from_program(table: &str, command: &str, options: &str) -> u64 {
+    let query = format!(
+        "COPY {} FROM PROGRAM {} WITH ({options})",
+        table,
+        spi::quote_literal(command)
+    );
+
+    PgTryBuilder::new(|| {
+        Spi::connect_mut(|client| client.update(query.as_str(), None, &[]).map(|t| t.len()))
+    })
+    .catch_others(|error| {
+        let CaughtError::PostgresError(report) = &error else {
+            error.rethrow();
+        };
+        let mut detail = match rows_processed() {
+            Some(rows) => format!("Line {} of the output (approximately).", rows + 1),
+            None => "The rejected line is unknown on this Postgres version.".to_string(),
+        };
+        if let Some(original) = report.detail() {
+            detail = format!("{detail}\n{original}");
+        }
+        ereport!(
+            ERROR,
+            report.sql_error_code(),
+            format!(
+                "could not load the output of \"{command}\" into {table}: {}",
+                report.message()
+            ),
+            detail
+        );
+    })
+    .execute()
+    .unwrap_or_else(|e| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INTERNAL_ERROR,
+            format!("could not load the output of \"{command}\" into {table}: {e}")
+        );
+    }) as u64
+}
+
+/// Rows the running `COPY` of this backend inserted before it failed.
+#[cfg(not(feature = "pg13"))]
+fn rows_processed() -> Option<u64> {
+    // SAFETY: MyBEEntry is either null or points at this backend's status
+    // entry, and the progress counters are only reset once the transaction
+    // aborts, after we return.
+    let entry = unsafe { pg_sys::MyBEEntry.as_ref()? };
+    if entry.st_progress_command != pg_sys::ProgressCommandType::PROGRESS_COMMAND_COPY {
+        return None;
+    }
+    let rows = entry.st_progress_param[pg_sys::PROGRESS_COPY_TUPLES_PROCESSED as usize];
+    u64::try_from(rows).ok()
+}
+
+/// `COPY` reports progress starting with Postgres 14.
+#[cfg(feature = "pg13")]
+fn rows_processed() -> Option<u64> {
+    None
+}
+
+/// Describes line `line` of `path` and the field `message` most likely refers to.
+///
+/// Rows are inserted in batches, so a failing constraint check can be reported
+/// a few lines after the row that caused it. Input errors point at the exact
+/// line.
+fn describe_line(path: &Path, line: u64, csv: &CsvOptions, message: &str) -> String {
+    let header = if csv.header { read_line(path, 1) } else { None };
+    let (header, record) = match (header, read_line(path, line)) {
+        (Some(header), Some(record)) => (header, record),
+        _ => return format!("Line {line} (approximately)."),
+    };
+    let columns = csv.split_record(&header);
+    let fields = csv.split_record(&record);
+    let offending = columns
+        .iter()
+        .zip(&fields)
+        .find(|(column, value)| points_at(message, column, value));
+    match offending {
+        Some((column, value)) => {
+            format!("Line {line} (approximately), field {column}: \"{value}\".")
+        }
+        None => format!("Line {line} (approximately): {record}"),
+    }
+}
+
+/// Whether the error `message` is about `column` holding `value`.
+fn points_at(message: &str, column: &str, value: &str) -> bool {
+    if message.contains(&format!("column \"{column}\"")) {
+        return true;
+    }
+    if message.ends_with(&format!(": \"{value}\"")) {
+        return true;
+    }
+    // value too long for type character varying(44)
+    message
+        .strip_prefix("value too long for type ")
+        .and_then(|rest| rest.rsplit_once('('))
+        .and_then(|(_, len)| len.trim_end_matches(')').parse::<usize>().ok())
+        .is_some_and(|max| value.chars().count() > max)
+}
+
+fn read_line(path: &Path, line: u64) -> Option<String> {
+    let file = fs::File::open(path).ok()?;
+    BufReader::new(file)
+        .lines()
+        .nth(usize::try_from(line).ok()? - 1)?
+        .ok()
+}
+
+fn save_line(path: &Path, line: u64, rejects: &Path) -> std::io::Result<()> {
+    let record = read_line(path, line)
+        .ok_or_else(|| std::io::Error::other(format!("line {line} not found")))?;
+    let mut out = OpenOptions::new().create(true).append(true).open(rejects)?;
+    writeln!(out, "{record}")
+}
+
+/// Where the rejected lines of `table` are saved when requested.
+pub fn rejects_path(dir: &Path, table: &str) -> PathBuf {
+    dir.join(format!("{table}.rejects.csv"))
+}
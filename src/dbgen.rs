@@ -0,0 +1,140 @@
+//! Classic dbgen `.tbl` output.
+//!
+//! The `Display` impls of the `tpchgen` rows reproduce dbgen's formatting:
+//! `|` separated fields with a trailing `|`, two decimal places for money
+//! columns, integral quantities and ISO dates. Files written in strict mode are
+//! checked against CRC-32 checksums recorded from earlier `tpchgen` output and
+//! embedded below, so a change to the generator or the formatting that alters
+//! an export is caught (compare with e.g. `crc32 lineitem.tbl`). They were not
+//! taken from dbgen itself and don't certify an export against it.
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use crate::format::TpchRow;
+use crate::tables::TableVisitor;
+
+/// CRC-32 checksums of single-part `tbl` exports as `(sf, table, rows, crc32)`.
+///
+/// Recorded from the `tpchgen` output, to check later exports against.
+const REFERENCE_CHECKSUMS: &[(f64, &str, u64, u32)] = &[
+    (0.01, "region", 5, 0xd3e71733),
+    (0.01, "nation", 25, 0x0744e8ca),
+    (0.01, "part", 2000, 0x2bbe9a68),
+    (0.01, "supplier", 100, 0x226ce16c),
+    (0.01, "partsupp", 8000, 0x0210a76e),
+    (0.01, "customer", 1500, 0xca8be0cb),
+    (0.01, "orders", 15000, 0x19c60c22),
+    (0.01, "lineitem", 60175, 0x11117fd4),
+    (0.1, "region", 5, 0xd3e71733),
+    (0.1, "nation", 25, 0x0744e8ca),
+    (0.1, "part", 20000, 0xee2b41ce),
+    (0.1, "supplier", 1000, 0x5d92a266),
+    (0.1, "partsupp", 80000, 0xd6d0f6f7),
+    (0.1, "customer", 15000, 0x8259ec4d),
+    (0.1, "orders", 150000, 0x916ceea9),
+    (0.1, "lineitem", 600572, 0x832f766a),
+    (1., "region", 5, 0xd3e71733),
+    (1., "nation", 25, 0x0744e8ca),
+    (1., "part", 200000, 0xd796b8ae),
+    (1., "supplier", 10000, 0xfb551f21),
+    (1., "partsupp", 800000, 0x738e6bc3),
+    (1., "customer", 150000, 0xe60ea986),
+    (1., "orders", 1500000, 0xda10ba06),
+    (1., "lineitem", 6001215, 0x9f66d600),
+];
+
+/// Returns the expected `(rows, crc32)` of `table` at `sf`, if known.
+pub fn reference_checksum(table: &str, sf: f64) -> Option<(u64, u32)> {
+    REFERENCE_CHECKSUMS
+        .iter()
+        .find(|(ref_sf, ref_table, _, _)| *ref_sf == sf && *ref_table == table)
+        .map(|(_, _, rows, crc)| (*rows, *crc))
+}
+
+/// Scale factors for which [`reference_checksum`] knows every table.
+pub fn reference_scale_factors() -> Vec<f64> {
+    let mut sfs: Vec<f64> = REFERENCE_CHECKSUMS.iter().map(|r| r.0).collect();
+    sfs.dedup();
+    sfs
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Passes writes through while computing their CRC-32 (IEEE) and size.
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    crc: u32,
+    bytes: u64,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChecksumWriter {
+            inner,
+            crc: !0,
+            bytes: 0,
+        }
+    }
+
+    pub fn crc32(&self) -> u32 {
+        !self.crc
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        for &b in &buf[..written] {
+            self.crc = CRC32_TABLE[((self.crc ^ b as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes every row in dbgen's `.tbl` format and returns the row count.
+pub struct TblVisitor<W: Write>(pub W);
+
+impl<W: Write> TableVisitor for TblVisitor<W> {
+    type Output = io::Result<u64>;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output {
+        let mut out = self.0;
+        let mut count = 0;
+        for row in rows {
+            writeln!(out, "{row}")?;
+            count += 1;
+        }
+        out.flush()?;
+        Ok(count)
+    }
+}
@@ -0,0 +1,56 @@
+//! A tiny dataset for tutorials and documentation examples.
+use std::time::Instant;
+
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::format::CsvOptions;
+use crate::tables::TABLES;
+use crate::{alloc, metadata, stream, timing};
+
+/// Scale factor the demo dataset is cut from.
+const DEMO_SF: f64 = 0.001;
+/// `orders` and `lineitem` keep the first of this many parts, 150 orders.
+const DEMO_ORDER_PARTS: i32 = 10;
+
+/// Loads a dataset of a few hundred rows per table into the TPC-H tables.
+///
+/// It is SF=0.001 with only the first 150 orders and their line items, which
+/// keeps every foreign key intact: the orders reference existing customers
+/// and their line items existing parts and suppliers. The queries run on it,
+/// though many return few or no rows. The load is recorded with the preset
+/// `demo` in its options. The rows go straight into `COPY`, without files,
+/// so any role that can write the tables can load it.
+#[pg_extern]
+fn tpch_demo() -> spi::Result<Option<String>> {
+    crate::truncate_tables()?;
+    let started = Instant::now();
+    let load_id = metadata::find_or_begin_load(DEMO_SF, 1, &[("preset", "demo".to_string())])?;
+    metadata::mark_tables(true)?;
+
+    let csv = CsvOptions::default();
+    let mut total = 0;
+    for table in TABLES {
+        let parts = match table {
+            "orders" | "lineitem" => DEMO_ORDER_PARTS,
+            _ => 1,
+        };
+        let generation = alloc::PeakTracker::start();
+        let timer = timing::TableTimer::start();
+        let rows = stream::copy_generated(table, DEMO_SF, 1, parts, &csv, None, false)?;
+        let stats = metadata::TableStats {
+            table,
+            rows,
+            file_bytes: 0,
+            peak_memory: generation.peak(),
+            table_bytes: Some(crate::table_size(table)?),
+            timing: Some(timer.split()),
+        };
+        metadata::record_table_stats(load_id, 1, &stats)?;
+        total += rows;
+    }
+
+    metadata::mark_tables(false)?;
+    metadata::complete_part(load_id, 1, started.elapsed().as_secs_f64())?;
+    Ok(Some(format!("TPC-H demo dataset loaded ({total} rows)")))
+}
@@ -0,0 +1,181 @@
+//! Comparing runs: the results they persisted and their timings.
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::queries;
+
+/// Returns the table holding the result of `query_nr` in run `run_id`.
+fn result_table(run_id: i64, query_nr: i32) -> spi::Result<String> {
+    let table = Spi::get_one_with_args::<String>(
+        "SELECT (array_agg(result_table ORDER BY iteration))[1] FROM pg_tpch_run_results
+         WHERE run_id = $1 AND query_nr = $2 AND result_table IS NOT NULL",
+        &[run_id.into(), query_nr.into()],
+    )?;
+    Ok(table.unwrap_or_else(|| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_NO_DATA_FOUND,
+            format!("run {run_id} has no persisted result for query {query_nr}"),
+            "Run the query with persist_results => true."
+        );
+    }))
+}
+
+/// Numbers the rows of `table`, the result of `query_nr`, in a canonical order.
+///
+/// Rows keep the position the query returned them in, except that rows tied
+/// on the query's `ORDER BY` columns, which the specification allows in any
+/// order, are sorted by content among themselves. With `ignore_order` all rows
+/// are sorted by content.
+fn numbered_rows(table: &str, query_nr: i32, ignore_order: bool) -> String {
+    let ties = if ignore_order {
+        "1".to_string()
+    } else {
+        let columns: Vec<_> = queries::order_by(query_nr)
+            .iter()
+            .map(|column| format!("t.{}", spi::quote_identifier(column)))
+            .collect();
+        if columns.is_empty() {
+            "1".to_string()
+        } else {
+            format!(
+                "min(t.result_row) OVER (PARTITION BY {})",
+                columns.join(", ")
+            )
+        }
+    };
+    format!(
+        "SELECT row_number() OVER (ORDER BY tie_group, r) AS result_row, r \
+         FROM (SELECT {ties} AS tie_group, to_jsonb(t) - 'result_row' AS r FROM {} t) s",
+        spi::quote_identifier(table)
+    )
+}
+
+/// Lists the rows in which the results of `query_nr` differ between runs
+/// `run_a` and `run_b`.
+///
+/// Rows are matched by position after normalizing their order, see
+/// [`numbered_rows`]. Numbers match if they differ by at most `tolerance`,
+/// everything else has to be equal. Rows only one run returned are listed with
+/// the other side NULL.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_diff_results(
+    run_a: i64,
+    run_b: i64,
+    query_nr: i32,
+    tolerance: default!(f64, 0.01),
+    ignore_order: default!(bool, false),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(result_row, i64),
+            name!(run_a_row, Option<String>),
+            name!(run_b_row, Option<String>),
+        ),
+    >,
+> {
+    let table_a = result_table(run_a, query_nr)?;
+    let table_b = result_table(run_b, query_nr)?;
+    let query = format!(
+        "WITH a AS ({}), b AS ({})
+         SELECT result_row, a.r::text, b.r::text
+         FROM a FULL JOIN b USING (result_row)
+         WHERE a.r IS NULL OR b.r IS NULL OR EXISTS (
+             SELECT FROM jsonb_each(a.r) x FULL JOIN jsonb_each(b.r) y USING (key)
+             WHERE x.value IS DISTINCT FROM y.value
+               AND NOT coalesce(
+                   jsonb_typeof(x.value) = 'number' AND jsonb_typeof(y.value) = 'number'
+                   AND abs(x.value::numeric - y.value::numeric) <= $1,
+                   false
+               )
+         )
+         ORDER BY result_row",
+        numbered_rows(&table_a, query_nr, ignore_order),
+        numbered_rows(&table_b, query_nr, ignore_order),
+    );
+
+    let differences = Spi::connect(|client| {
+        client
+            .select(&query, None, &[tolerance.into()])?
+            .map(|row| {
+                Ok((
+                    row.get::<i64>(1)?.unwrap_or_default(),
+                    row.get::<String>(2)?,
+                    row.get::<String>(3)?,
+                ))
+            })
+            .collect::<spi::Result<Vec<_>>>()
+    })?;
+    Ok(TableIterator::new(differences))
+}
+
+/// Lines up the timings of runs `run_ids` per query, one row per query any
+/// of the runs executed and one array element per run, in the order of
+/// `run_ids`.
+///
+/// `duration_ms` holds the fastest iteration of the query in each run and
+/// `relative` how much slower (positive) or faster (negative) than in the
+/// first run it was, as a fraction. Runs that didn't execute a query have
+/// NULL in its row.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_matrix(
+    run_ids: Vec<i64>,
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(query_nr, i32),
+            name!(duration_ms, Vec<Option<f64>>),
+            name!(relative, Vec<Option<f64>>),
+        ),
+    >,
+> {
+    let missing = Spi::get_one_with_args::<i64>(
+        "SELECT min(ids.run_id) FROM unnest($1::bigint[]) AS ids (run_id)
+         WHERE NOT EXISTS (SELECT FROM pg_tpch_runs r WHERE r.run_id = ids.run_id)",
+        &[run_ids.clone().into()],
+    )?;
+    if let Some(run_id) = missing {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_NO_DATA_FOUND,
+            format!("run {run_id} does not exist")
+        );
+    }
+    let rows = Spi::connect(|client| {
+        client
+            .select(
+                "SELECT q.query_nr,
+                        array_agg(t.duration_ms ORDER BY runs.position),
+                        array_agg(t.duration_ms / nullif(first.duration_ms, 0) - 1
+                                  ORDER BY runs.position)
+                 FROM (SELECT DISTINCT query_nr FROM pg_tpch_run_results
+                       WHERE run_id = ANY($1)) q
+                 CROSS JOIN unnest($1::bigint[]) WITH ORDINALITY AS runs (run_id, position)
+                 LEFT JOIN LATERAL (
+                     SELECT min(duration_ms) AS duration_ms FROM pg_tpch_run_results r
+                     WHERE r.run_id = runs.run_id AND r.query_nr = q.query_nr
+                 ) t ON true
+                 LEFT JOIN LATERAL (
+                     SELECT min(duration_ms) AS duration_ms FROM pg_tpch_run_results r
+                     WHERE r.run_id = $1[1] AND r.query_nr = q.query_nr
+                 ) first ON true
+                 GROUP BY q.query_nr
+                 ORDER BY q.query_nr",
+                None,
+                &[run_ids.into()],
+            )?
+            .map(|row| {
+                Ok((
+                    row.get::<i32>(1)?.unwrap_or_default(),
+                    row.get::<Vec<Option<f64>>>(2)?.unwrap_or_default(),
+                    row.get::<Vec<Option<f64>>>(3)?.unwrap_or_default(),
+                ))
+            })
+            .collect::<spi::Result<Vec<_>>>()
+    })?;
+    Ok(TableIterator::new(rows))
+}
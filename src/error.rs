@@ -0,0 +1,85 @@
+//! Failures of a load that come from the file system rather than Postgres.
+//!
+//! Writing, resolving and reading spill files, and starting the threads that
+//! generate rows, fail with an [`io::Error`] that says what went wrong but not
+//! where. [`LoadError`] adds the table, the part of it being loaded and the
+//! file, and reports the failure with an SQLSTATE matching its cause, so a
+//! caller can tell a full disk from missing permissions without parsing the
+//! message.
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use pgrx::prelude::*;
+
+/// A failure loading part `part` of `parts` of `table`.
+#[derive(Debug)]
+pub struct LoadError {
+    pub table: String,
+    pub part: i32,
+    pub parts: i32,
+    /// The file being written or read, if the failure concerns one.
+    pub path: Option<PathBuf>,
+    pub source: io::Error,
+}
+
+impl LoadError {
+    pub fn new(table: &str, part: i32, parts: i32, source: io::Error) -> LoadError {
+        LoadError {
+            table: table.to_string(),
+            part,
+            parts,
+            path: None,
+            source,
+        }
+    }
+
+    /// Records that the failure concerns the file at `path`.
+    pub fn at(mut self, path: &Path) -> LoadError {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+
+    /// Returns the SQLSTATE the failure is reported with.
+    pub fn sqlstate(&self) -> PgSqlErrorCode {
+        match self.source.kind() {
+            ErrorKind::NotFound => PgSqlErrorCode::ERRCODE_UNDEFINED_FILE,
+            ErrorKind::PermissionDenied => PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded => PgSqlErrorCode::ERRCODE_DISK_FULL,
+            _ => PgSqlErrorCode::ERRCODE_IO_ERROR,
+        }
+    }
+
+    /// Raises the failure as a Postgres error.
+    pub fn report(&self) -> ! {
+        if self.sqlstate() == PgSqlErrorCode::ERRCODE_DISK_FULL {
+            ereport!(
+                ERROR,
+                self.sqlstate(),
+                self.to_string(),
+                "Set pg_tpch.spill_compression or pg_tpch.spill_chunk_size, or point pg_tpch.data_dir at a bigger disk."
+            );
+        }
+        ereport!(ERROR, self.sqlstate(), self.to_string());
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "could not load part {} of {} of {}",
+            self.part, self.parts, self.table
+        )?;
+        if let Some(path) = &self.path {
+            write!(f, " through \"{}\"", path.display())?;
+        }
+        write!(f, ": {}", self.source)
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
@@ -0,0 +1,111 @@
+//! Machine-readable log lines marking the phases of loads and runs.
+//!
+//! With `pg_tpch.log_events` on, every phase transition is logged as one
+//! JSON object at level `LOG`, so log pipelines can rebuild the timeline of a
+//! benchmark without querying the database. Every object has the `event`, the
+//! Unix time `at` in seconds and the `pid` of the backend, followed by the
+//! fields of the event.
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pgrx::prelude::*;
+
+use crate::guc::LOG_EVENTS;
+
+/// Value of an event field.
+pub enum Value<'a> {
+    Text(&'a str),
+    Int(i64),
+    Float(f64),
+}
+
+impl<'a> From<&'a str> for Value<'a> {
+    fn from(value: &'a str) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<i32> for Value<'_> {
+    fn from(value: i32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<i64> for Value<'_> {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<u64> for Value<'_> {
+    fn from(value: u64) -> Self {
+        Value::Int(value as i64)
+    }
+}
+
+impl From<f64> for Value<'_> {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+/// Appends `text` to `out` as a JSON string.
+fn push_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Renders `event` with `fields` as a JSON object.
+pub fn render(event: &str, at: f64, pid: i32, fields: &[(&str, Value)]) -> String {
+    let mut line = String::from("{\"event\":");
+    push_string(&mut line, event);
+    let _ = write!(line, ",\"at\":{at:.6},\"pid\":{pid}");
+    for (name, value) in fields {
+        line.push(',');
+        push_string(&mut line, name);
+        line.push(':');
+        match value {
+            Value::Text(text) => push_string(&mut line, text),
+            Value::Int(n) => {
+                let _ = write!(line, "{n}");
+            }
+            // JSON has no NaN or infinities.
+            Value::Float(x) if x.is_finite() => {
+                let _ = write!(line, "{x}");
+            }
+            Value::Float(_) => line.push_str("null"),
+        }
+    }
+    line.push('}');
+    line
+}
+
+/// Logs `event` with `fields` if `pg_tpch.log_events` is on.
+pub fn emit(event: &str, fields: &[(&str, Value)]) {
+    if !LOG_EVENTS.get() {
+        return;
+    }
+    let at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0., |elapsed| elapsed.as_secs_f64());
+    // SAFETY: MyProcPid is set for the lifetime of the backend.
+    let pid = unsafe { pg_sys::MyProcPid };
+    ereport!(
+        LOG,
+        PgSqlErrorCode::ERRCODE_SUCCESSFUL_COMPLETION,
+        render(event, at, pid, fields)
+    );
+}
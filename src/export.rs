@@ -0,0 +1,246 @@
+//! Writing generated data to server-side files without loading it.
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use pgrx::pg_sys;
+use pgrx::prelude::*;
+
+use crate::dbgen::{self, ChecksumWriter, TblVisitor};
+use crate::format::{CsvOptions, CsvVisitor, CsvWriter};
+use crate::guc;
+use crate::manifest::{Manifest, ManifestFile};
+use crate::strategy;
+use crate::tables::{self, TABLES};
+
+/// Returns the canonical name of the encoding `name`, raising an error if
+/// Postgres doesn't know it.
+fn encoding_name(name: &str) -> String {
+    let name_c = CString::new(name).unwrap_or_default();
+    // SAFETY: both functions only look the name up in a static table.
+    let encoding = unsafe { pg_sys::pg_char_to_encoding(name_c.as_ptr()) };
+    if encoding < 0 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("unknown encoding \"{name}\"")
+        );
+    }
+    // SAFETY: see above, the result points into the same static table.
+    unsafe { CStr::from_ptr(pg_sys::pg_encoding_to_char(encoding)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Generates every table into `dir` as `csv` or dbgen-compatible `tbl` files.
+///
+/// CSV files start with a header line unless `header => false`, quote values
+/// equal to the `null` string, which must differ from the delimiter and quote,
+/// and are declared as `encoding`, any encoding name Postgres knows. Loaders
+/// differ in what they expect here; TPC-H data itself has no NULLs and is
+/// plain ASCII, so the bytes are the same in every encoding.
+///
+/// The rows are generated with dbgen's fixed seeds, so two exports of the
+/// same scale factor and options are byte-identical.
+///
+/// A manifest listing the files with their sizes and checksums is written
+/// next to them, see [`crate::manifest`].
+///
+/// With `strict => true` the export must use the `tbl` format for a single
+/// part of a scale factor with embedded reference checksums, and any file
+/// that doesn't match the recorded `tpchgen` output raises an error, which
+/// catches regressions of the generator rather than comparing with dbgen.
+#[allow(clippy::too_many_arguments)]
+#[pg_extern]
+fn tpch_export(
+    dir: &str,
+    sf: default!(f64, 1.),
+    format: default!(&str, "'csv'"),
+    strict: default!(bool, false),
+    children: default!(i64, 1),
+    step: default!(i64, 0),
+    delimiter: default!(&str, "','"),
+    quote: default!(&str, "'\"'"),
+    header: default!(bool, true),
+    null: default!(&str, "''"),
+    encoding: default!(&str, "'UTF8'"),
+) -> TableIterator<
+    'static,
+    (
+        name!(table_name, String),
+        name!(path, String),
+        name!(rows, i64),
+        name!(bytes, i64),
+        name!(crc32, String),
+        name!(verified, Option<bool>),
+    ),
+> {
+    strategy::require_role(
+        "pg_write_server_files",
+        "exporting to files on the server",
+        "Fetch the rows over the connection with tpch_copy_chunk instead.",
+    );
+    if children < 1 || step < 0 || step >= children {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("step must be between 0 and {}, got {step}", children - 1)
+        );
+    }
+    let csv = match format {
+        "csv" => Some(
+            CsvOptions::new(delimiter, quote)
+                .and_then(|csv| csv.with_export_options(header, null, &encoding_name(encoding)))
+                .unwrap_or_else(|message| {
+                    ereport!(
+                        ERROR,
+                        PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                        message
+                    );
+                }),
+        ),
+        "tbl" => None,
+        _ => {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                format!("unknown export format \"{format}\", expected \"csv\" or \"tbl\"")
+            );
+        }
+    };
+    if strict {
+        if csv.is_some() || children != 1 {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                "strict mode requires format => 'tbl' and children => 1"
+            );
+        }
+        if !dbgen::reference_scale_factors().contains(&sf) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                format!(
+                    "no reference checksums for SF={sf}, strict mode supports SF in {:?}",
+                    dbgen::reference_scale_factors()
+                )
+            );
+        }
+    }
+
+    let dir = PathBuf::from(dir);
+    fs::create_dir_all(&dir).unwrap_or_else(|e| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_IO_ERROR,
+            format!("could not create directory \"{}\": {e}", dir.display())
+        );
+    });
+
+    let part = (step + 1) as i32;
+    let num_parts = children as i32;
+    let mut results = Vec::with_capacity(TABLES.len());
+    let mut manifest = Manifest {
+        sf,
+        format: format.to_string(),
+        part,
+        num_parts,
+        delimiter: csv.as_ref().map_or(',', |csv| csv.delimiter),
+        quote: csv.as_ref().map_or('"', |csv| csv.quote),
+        header: csv.as_ref().is_none_or(|csv| csv.header),
+        null: csv
+            .as_ref()
+            .map_or_else(String::new, |csv| csv.null.clone()),
+        encoding: csv
+            .as_ref()
+            .map_or_else(|| "UTF8".to_string(), |csv| csv.encoding.clone()),
+        files: Vec::with_capacity(TABLES.len()),
+    };
+
+    for table in TABLES {
+        let extension = if csv.is_some() { "csv" } else { "tbl" };
+        let file_name = if num_parts == 1 {
+            format!("{table}.{extension}")
+        } else {
+            format!("{table}.{extension}.{part}")
+        };
+        let path = dir.join(&file_name);
+
+        let written = fs::File::create(&path).and_then(|file| {
+            let mut out =
+                BufWriter::with_capacity(guc::generation_buffer_bytes(), ChecksumWriter::new(file));
+            let rows = match &csv {
+                Some(csv) => tables::generate(
+                    table,
+                    sf,
+                    part,
+                    num_parts,
+                    CsvVisitor(CsvWriter::new(&mut out, csv)),
+                )?,
+                None => tables::generate(table, sf, part, num_parts, TblVisitor(&mut out))?,
+            };
+            out.flush()?;
+            Ok((rows, out.get_ref().bytes(), out.get_ref().crc32()))
+        });
+        let (rows, bytes, crc32) = written.unwrap_or_else(|e| {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_IO_ERROR,
+                format!("could not write \"{}\": {e}", path.display())
+            );
+        });
+
+        let verified = match (&csv, num_parts) {
+            (None, 1) => {
+                dbgen::reference_checksum(table, sf).map(|reference| reference == (rows, crc32))
+            }
+            _ => None,
+        };
+        if strict && verified != Some(true) {
+            let (expected_rows, expected_crc32) = dbgen::reference_checksum(table, sf)
+                .expect("strict mode checked that reference checksums exist");
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_DATA_CORRUPTED,
+                format!(
+                    "\"{}\" does not match the recorded tpchgen output",
+                    path.display()
+                ),
+                format!(
+                    "Expected {expected_rows} rows with CRC-32 {expected_crc32:08x}, \
+                     got {rows} rows with CRC-32 {crc32:08x}."
+                )
+            );
+        }
+
+        manifest.files.push(ManifestFile {
+            table: table.to_string(),
+            name: file_name,
+            rows,
+            bytes,
+            crc32,
+        });
+        results.push((
+            table.to_string(),
+            path.display().to_string(),
+            rows as i64,
+            bytes as i64,
+            format!("{crc32:08x}"),
+            verified,
+        ));
+    }
+
+    manifest.write(&dir).unwrap_or_else(|e| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_IO_ERROR,
+            format!(
+                "could not write the manifest into \"{}\": {e}",
+                dir.display()
+            )
+        );
+    });
+
+    TableIterator::new(results)
+}
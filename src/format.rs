@@ -0,0 +1,731 @@
+//! Serialization of generated rows into the files handed to `COPY`.
+//!
+//! The `tpchgen` CSV formatters hardcode `,` as delimiter and wrap comment
+//! columns in quotes without escaping, which only works as long as the text
+//! pool never produces a quote character. Instead every row type describes its
+//! fields through [`TpchRow`] and the writers in this module apply exactly the
+//! quoting rules `COPY ... (FORMAT csv)` uses, so whatever we write is read
+//! back verbatim.
+//!
+//! [`BinaryWriter`] writes the same fields in the binary format of `COPY`
+//! instead, which spares the server parsing numbers and dates out of text.
+use std::fmt::{self, Display, Write as _};
+use std::io::{self, Write};
+
+use tpchgen::dates::TPCHDate;
+use tpchgen::decimal::TPCHDecimal;
+use tpchgen::generators::{Customer, LineItem, Nation, Order, Part, PartSupp, Region, Supplier};
+
+use crate::tables::TableVisitor;
+
+/// Receives the fields of a row in column order.
+pub trait FieldSink {
+    fn int(&mut self, value: i64) -> io::Result<()>;
+    fn decimal(&mut self, value: TPCHDecimal) -> io::Result<()>;
+    fn date(&mut self, value: TPCHDate) -> io::Result<()>;
+    fn text(&mut self, value: &dyn Display) -> io::Result<()>;
+}
+
+/// A generated row that knows how to emit its fields.
+pub trait TpchRow {
+    /// Column names in the order fields are emitted.
+    const COLUMNS: &'static [&'static str];
+
+    fn write_fields<S: FieldSink>(&self, sink: &mut S) -> io::Result<()>;
+}
+
+/// Writes the rows of tables in a format `COPY` reads.
+pub trait RowWriter {
+    /// Starts a table with the given columns.
+    fn write_header(&mut self, columns: &[&str]) -> io::Result<()>;
+    fn write_row<R: TpchRow>(&mut self, row: &R) -> io::Result<()>;
+    /// Ends the table and flushes the output.
+    fn finish(self) -> io::Result<()>;
+}
+
+/// CSV dialect shared by the writer and the `COPY` statement reading its output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub quote: char,
+    pub escape: char,
+    pub null: String,
+    /// Whether files start with a line of column names.
+    pub header: bool,
+    /// Encoding files are declared in. Generated data is plain ASCII, so the
+    /// bytes are the same in every encoding Postgres supports.
+    pub encoding: String,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            quote: '"',
+            escape: '"',
+            null: String::new(),
+            header: true,
+            encoding: "UTF8".to_string(),
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Builds options from user supplied delimiter and quote characters.
+    ///
+    /// The escape character is always the quote character (quotes are
+    /// doubled) and NULL is the unquoted empty string, as in `COPY`'s own CSV
+    /// defaults. TPC-H data never contains NULLs, so every empty value is
+    /// written quoted.
+    pub fn new(delimiter: &str, quote: &str) -> Result<Self, String> {
+        let delimiter = single_byte_char("delimiter", delimiter)?;
+        let quote = single_byte_char("quote", quote)?;
+        if delimiter == quote {
+            return Err(format!(
+                "delimiter and quote must be different, got {delimiter:?} for both"
+            ));
+        }
+        Ok(CsvOptions {
+            delimiter,
+            quote,
+            escape: quote,
+            ..CsvOptions::default()
+        })
+    }
+
+    /// Sets the options exports let downstream loaders pick on top of the
+    /// delimiter and quote: whether to write a header, the NULL string and
+    /// the encoding, which the caller has validated.
+    ///
+    /// The NULL string must be printable ASCII without the delimiter or quote
+    /// character, which `COPY` doesn't accept either.
+    pub fn with_export_options(
+        mut self,
+        header: bool,
+        null: &str,
+        encoding: &str,
+    ) -> Result<Self, String> {
+        if !null.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+            return Err(format!("null must be printable ASCII, got {null:?}"));
+        }
+        if null.contains(self.delimiter) || null.contains(self.quote) {
+            return Err(format!(
+                "null must not contain the delimiter or quote character, got {null:?}"
+            ));
+        }
+        self.header = header;
+        self.null = null.to_string();
+        self.encoding = encoding.to_string();
+        Ok(self)
+    }
+
+    /// Header line (without line terminator) for the given columns.
+    pub fn header(&self, columns: &[&str]) -> String {
+        columns.join(&self.delimiter.to_string())
+    }
+
+    /// Whether `value` has to be quoted to survive a `COPY ... (FORMAT csv)` read.
+    fn needs_quote(&self, value: &str) -> bool {
+        value == self.null
+            || value
+                .chars()
+                .any(|c| c == self.delimiter || c == self.quote || c == '\n' || c == '\r')
+    }
+
+    /// Splits one line written by [`CsvWriter`] back into its field values.
+    ///
+    /// Generated values never contain line breaks, so a line is always a
+    /// complete record.
+    pub fn split_record(&self, line: &str) -> Vec<String> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if quoted && c == self.escape && chars.peek() == Some(&self.quote) {
+                field.push(self.quote);
+                chars.next();
+            } else if c == self.quote {
+                quoted = !quoted;
+            } else if !quoted && c == self.delimiter {
+                fields.push(std::mem::take(&mut field));
+            } else {
+                field.push(c);
+            }
+        }
+        fields.push(field);
+        fields
+    }
+}
+
+fn single_byte_char(option: &str, value: &str) -> Result<char, String> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii() && c != '\n' && c != '\r' => Ok(c),
+        _ => Err(format!(
+            "{option} must be a single one-byte character other than newline or carriage return, got {value:?}"
+        )),
+    }
+}
+
+/// Writes rows as CSV according to a [`CsvOptions`] dialect.
+pub struct CsvWriter<'a, W: Write> {
+    out: W,
+    options: &'a CsvOptions,
+    scratch: String,
+    first_field: bool,
+    /// Which fields of a row are written, all of them if empty.
+    keep: Vec<bool>,
+    index: usize,
+}
+
+impl<'a, W: Write> CsvWriter<'a, W> {
+    pub fn new(out: W, options: &'a CsvOptions) -> Self {
+        CsvWriter {
+            out,
+            options,
+            scratch: String::new(),
+            first_field: true,
+            keep: Vec::new(),
+            index: 0,
+        }
+    }
+
+    /// Writes only the fields whose position in a row is `true` in `keep`.
+    pub fn keep_fields(mut self, keep: Vec<bool>) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Writes the header line, unless the dialect has none.
+    pub fn write_header(&mut self, columns: &[&str]) -> io::Result<()> {
+        if !self.options.header {
+            return Ok(());
+        }
+        writeln!(self.out, "{}", self.options.header(columns))
+    }
+
+    pub fn write_row<R: TpchRow>(&mut self, row: &R) -> io::Result<()> {
+        self.first_field = true;
+        self.index = 0;
+        row.write_fields(self)?;
+        self.out.write_all(b"\n")
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn field(&mut self, value: impl Display) -> io::Result<()> {
+        let index = self.index;
+        self.index += 1;
+        if !self.keep.is_empty() && !self.keep[index] {
+            return Ok(());
+        }
+        self.scratch.clear();
+        write!(self.scratch, "{value}").map_err(fmt_error)?;
+
+        if !self.first_field {
+            self.out.write_all(&[self.options.delimiter as u8])?;
+        }
+        self.first_field = false;
+
+        let value = self.scratch.as_bytes();
+        if !self.options.needs_quote(&self.scratch) {
+            return self.out.write_all(value);
+        }
+
+        // The dialect characters are validated to be single-byte ASCII, so
+        // they can be matched byte-wise without splitting UTF-8 sequences.
+        let quote = self.options.quote as u8;
+        let escape = self.options.escape as u8;
+        self.out.write_all(&[quote])?;
+        let mut start = 0;
+        for (i, &b) in value.iter().enumerate() {
+            if b == quote || b == escape {
+                self.out.write_all(&value[start..i])?;
+                self.out.write_all(&[escape])?;
+                start = i;
+            }
+        }
+        self.out.write_all(&value[start..])?;
+        self.out.write_all(&[quote])
+    }
+}
+
+impl<W: Write> FieldSink for CsvWriter<'_, W> {
+    fn int(&mut self, value: i64) -> io::Result<()> {
+        self.field(value)
+    }
+
+    fn decimal(&mut self, value: TPCHDecimal) -> io::Result<()> {
+        self.field(value)
+    }
+
+    fn date(&mut self, value: TPCHDate) -> io::Result<()> {
+        self.field(value)
+    }
+
+    fn text(&mut self, value: &dyn Display) -> io::Result<()> {
+        self.field(value)
+    }
+}
+
+impl<W: Write> RowWriter for CsvWriter<'_, W> {
+    fn write_header(&mut self, columns: &[&str]) -> io::Result<()> {
+        CsvWriter::write_header(self, columns)
+    }
+
+    fn write_row<R: TpchRow>(&mut self, row: &R) -> io::Result<()> {
+        CsvWriter::write_row(self, row)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        self.into_inner().flush()
+    }
+}
+
+/// Writes a header and every row as CSV and returns the row count.
+pub struct CsvVisitor<'a, W: Write>(pub CsvWriter<'a, W>);
+
+impl<W: Write> TableVisitor for CsvVisitor<'_, W> {
+    type Output = io::Result<u64>;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output {
+        let mut writer = self.0;
+        writer.write_header(R::COLUMNS)?;
+        let mut count = 0;
+        for row in rows {
+            writer.write_row(&row)?;
+            count += 1;
+        }
+        writer.into_inner().flush()?;
+        Ok(count)
+    }
+}
+
+/// Like [`CsvVisitor`], but writes only the listed columns.
+pub struct ColumnsCsvVisitor<'a, W: Write>(pub CsvWriter<'a, W>, pub &'a [&'a str]);
+
+impl<W: Write> TableVisitor for ColumnsCsvVisitor<'_, W> {
+    type Output = io::Result<u64>;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output {
+        let ColumnsCsvVisitor(writer, columns) = self;
+        let keep: Vec<bool> = R::COLUMNS.iter().map(|c| columns.contains(c)).collect();
+        let header: Vec<&str> = R::COLUMNS
+            .iter()
+            .copied()
+            .filter(|c| columns.contains(c))
+            .collect();
+        let mut writer = writer.keep_fields(keep);
+        writer.write_header(&header)?;
+        let mut count = 0;
+        for row in rows {
+            writer.write_row(&row)?;
+            count += 1;
+        }
+        writer.into_inner().flush()?;
+        Ok(count)
+    }
+}
+
+/// How a column is represented in binary `COPY` data, which depends on the
+/// type of the column rather than on the generated value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Int2,
+    Int4,
+    Int8,
+    Float8,
+    Numeric,
+    Date,
+    /// Character types and enums, whose binary form is the text itself.
+    Text,
+}
+
+/// Days from the Unix epoch to 2000-01-01, where Postgres dates count from.
+const POSTGRES_EPOCH_DAYS: i32 = 10957;
+
+/// Writes rows in the binary format of `COPY` into columns of the given
+/// kinds.
+pub struct BinaryWriter<W: Write> {
+    out: W,
+    columns: Vec<ColumnKind>,
+    scratch: Vec<u8>,
+    index: usize,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    pub fn new(out: W, columns: Vec<ColumnKind>) -> Self {
+        BinaryWriter {
+            out,
+            columns,
+            scratch: Vec::new(),
+            index: 0,
+        }
+    }
+
+    /// Kind of the column the next field goes to.
+    fn next_column(&mut self) -> io::Result<ColumnKind> {
+        let kind = self.columns.get(self.index).copied().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "row has more fields than the table",
+            )
+        })?;
+        self.index += 1;
+        Ok(kind)
+    }
+
+    /// Writes the value in `scratch` as the next field.
+    fn field(&mut self) -> io::Result<()> {
+        self.out
+            .write_all(&(self.scratch.len() as i32).to_be_bytes())?;
+        self.out.write_all(&self.scratch)
+    }
+
+    fn mismatch(kind: ColumnKind, value: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("can't write {value} into a column of kind {kind:?}"),
+        )
+    }
+}
+
+/// Appends `value` with `scale` decimal digits, `value / 10^scale`, to `out`
+/// as a `numeric` in binary form. `scale` is 0 or 2.
+fn push_numeric(out: &mut Vec<u8>, value: i64, scale: u16) {
+    let magnitude = value.unsigned_abs();
+    let (mut integer, fraction) = match scale {
+        0 => (magnitude, 0),
+        _ => (magnitude / 100, magnitude % 100 * 100),
+    };
+    // Digits are base 10000, the first one weighted 10000^weight.
+    let mut digits = Vec::new();
+    while integer > 0 {
+        digits.push((integer % 10000) as i16);
+        integer /= 10000;
+    }
+    digits.reverse();
+    let weight = digits.len() as i16 - 1;
+    if fraction != 0 {
+        digits.push(fraction as i16);
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+    let weight = if digits.is_empty() { 0 } else { weight };
+    let sign: u16 = if value < 0 { 0x4000 } else { 0 };
+    out.extend_from_slice(&(digits.len() as i16).to_be_bytes());
+    out.extend_from_slice(&weight.to_be_bytes());
+    out.extend_from_slice(&sign.to_be_bytes());
+    out.extend_from_slice(&scale.to_be_bytes());
+    for digit in digits {
+        out.extend_from_slice(&digit.to_be_bytes());
+    }
+}
+
+impl<W: Write> RowWriter for BinaryWriter<W> {
+    /// Writes the signature, flags and header extension of the format. The
+    /// format has no column names.
+    fn write_header(&mut self, _columns: &[&str]) -> io::Result<()> {
+        self.out.write_all(b"PGCOPY\n\xff\r\n\0")?;
+        self.out.write_all(&0i32.to_be_bytes())?;
+        self.out.write_all(&0i32.to_be_bytes())
+    }
+
+    fn write_row<R: TpchRow>(&mut self, row: &R) -> io::Result<()> {
+        self.index = 0;
+        self.out
+            .write_all(&(self.columns.len() as i16).to_be_bytes())?;
+        row.write_fields(self)?;
+        if self.index != self.columns.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "row has fewer fields than the table",
+            ));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.out.write_all(&(-1i16).to_be_bytes())?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> FieldSink for BinaryWriter<W> {
+    fn int(&mut self, value: i64) -> io::Result<()> {
+        self.scratch.clear();
+        let kind = self.next_column()?;
+        let out_of_range = || Self::mismatch(kind, &value.to_string());
+        match kind {
+            ColumnKind::Int2 => self.scratch.extend_from_slice(
+                &i16::try_from(value)
+                    .map_err(|_| out_of_range())?
+                    .to_be_bytes(),
+            ),
+            ColumnKind::Int4 => self.scratch.extend_from_slice(
+                &i32::try_from(value)
+                    .map_err(|_| out_of_range())?
+                    .to_be_bytes(),
+            ),
+            ColumnKind::Int8 => self.scratch.extend_from_slice(&value.to_be_bytes()),
+            ColumnKind::Float8 => self
+                .scratch
+                .extend_from_slice(&(value as f64).to_be_bytes()),
+            ColumnKind::Numeric => push_numeric(&mut self.scratch, value, 0),
+            ColumnKind::Text => write!(self.scratch, "{value}")?,
+            ColumnKind::Date => return Err(out_of_range()),
+        }
+        self.field()
+    }
+
+    fn decimal(&mut self, value: TPCHDecimal) -> io::Result<()> {
+        self.scratch.clear();
+        match self.next_column()? {
+            ColumnKind::Numeric => push_numeric(&mut self.scratch, value.0, 2),
+            // The quotient is rounded like the float Postgres parses from
+            // the CSV.
+            ColumnKind::Float8 => self
+                .scratch
+                .extend_from_slice(&(value.0 as f64 / 100.).to_be_bytes()),
+            ColumnKind::Text => write!(self.scratch, "{value}")?,
+            kind => return Err(Self::mismatch(kind, &value.to_string())),
+        }
+        self.field()
+    }
+
+    fn date(&mut self, value: TPCHDate) -> io::Result<()> {
+        self.scratch.clear();
+        match self.next_column()? {
+            ColumnKind::Date => self
+                .scratch
+                .extend_from_slice(&(value.to_unix_epoch() - POSTGRES_EPOCH_DAYS).to_be_bytes()),
+            ColumnKind::Text => write!(self.scratch, "{value}")?,
+            kind => return Err(Self::mismatch(kind, &value.to_string())),
+        }
+        self.field()
+    }
+
+    fn text(&mut self, value: &dyn Display) -> io::Result<()> {
+        self.scratch.clear();
+        match self.next_column()? {
+            ColumnKind::Text => write!(self.scratch, "{value}")?,
+            kind => return Err(Self::mismatch(kind, &value.to_string())),
+        }
+        self.field()
+    }
+}
+
+/// Writes every row of a table in the binary format of `COPY` and returns
+/// the row count.
+pub struct BinaryVisitor<W: Write>(pub BinaryWriter<W>);
+
+impl<W: Write> TableVisitor for BinaryVisitor<W> {
+    type Output = io::Result<u64>;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output {
+        let mut writer = self.0;
+        writer.write_header(R::COLUMNS)?;
+        let mut count = 0;
+        for row in rows {
+            writer.write_row(&row)?;
+            count += 1;
+        }
+        writer.finish()?;
+        Ok(count)
+    }
+}
+
+fn fmt_error(_: fmt::Error) -> io::Error {
+    io::Error::other("failed to format field")
+}
+
+impl TpchRow for Region<'_> {
+    const COLUMNS: &'static [&'static str] = &["r_regionkey", "r_name", "r_comment"];
+
+    fn write_fields<S: FieldSink>(&self, sink: &mut S) -> io::Result<()> {
+        sink.int(self.r_regionkey)?;
+        sink.text(&self.r_name)?;
+        sink.text(&self.r_comment)
+    }
+}
+
+impl TpchRow for Nation<'_> {
+    const COLUMNS: &'static [&'static str] = &["n_nationkey", "n_name", "n_regionkey", "n_comment"];
+
+    fn write_fields<S: FieldSink>(&self, sink: &mut S) -> io::Result<()> {
+        sink.int(self.n_nationkey)?;
+        sink.text(&self.n_name)?;
+        sink.int(self.n_regionkey)?;
+        sink.text(&self.n_comment)
+    }
+}
+
+impl TpchRow for Part<'_> {
+    const COLUMNS: &'static [&'static str] = &[
+        "p_partkey",
+        "p_name",
+        "p_mfgr",
+        "p_brand",
+        "p_type",
+        "p_size",
+        "p_container",
+        "p_retailprice",
+        "p_comment",
+    ];
+
+    fn write_fields<S: FieldSink>(&self, sink: &mut S) -> io::Result<()> {
+        sink.int(self.p_partkey)?;
+        sink.text(&self.p_name)?;
+        sink.text(&self.p_mfgr)?;
+        sink.text(&self.p_brand)?;
+        sink.text(&self.p_type)?;
+        sink.int(self.p_size.into())?;
+        sink.text(&self.p_container)?;
+        sink.decimal(self.p_retailprice)?;
+        sink.text(&self.p_comment)
+    }
+}
+
+impl TpchRow for Supplier {
+    const COLUMNS: &'static [&'static str] = &[
+        "s_suppkey",
+        "s_name",
+        "s_address",
+        "s_nationkey",
+        "s_phone",
+        "s_acctbal",
+        "s_comment",
+    ];
+
+    fn write_fields<S: FieldSink>(&self, sink: &mut S) -> io::Result<()> {
+        sink.int(self.s_suppkey)?;
+        sink.text(&self.s_name)?;
+        sink.text(&self.s_address)?;
+        sink.int(self.s_nationkey)?;
+        sink.text(&self.s_phone)?;
+        sink.decimal(self.s_acctbal)?;
+        sink.text(&self.s_comment)
+    }
+}
+
+impl TpchRow for PartSupp<'_> {
+    const COLUMNS: &'static [&'static str] = &[
+        "ps_partkey",
+        "ps_suppkey",
+        "ps_availqty",
+        "ps_supplycost",
+        "ps_comment",
+    ];
+
+    fn write_fields<S: FieldSink>(&self, sink: &mut S) -> io::Result<()> {
+        sink.int(self.ps_partkey)?;
+        sink.int(self.ps_suppkey)?;
+        sink.int(self.ps_availqty.into())?;
+        sink.decimal(self.ps_supplycost)?;
+        sink.text(&self.ps_comment)
+    }
+}
+
+impl TpchRow for Customer<'_> {
+    const COLUMNS: &'static [&'static str] = &[
+        "c_custkey",
+        "c_name",
+        "c_address",
+        "c_nationkey",
+        "c_phone",
+        "c_acctbal",
+        "c_mktsegment",
+        "c_comment",
+    ];
+
+    fn write_fields<S: FieldSink>(&self, sink: &mut S) -> io::Result<()> {
+        sink.int(self.c_custkey)?;
+        sink.text(&self.c_name)?;
+        sink.text(&self.c_address)?;
+        sink.int(self.c_nationkey)?;
+        sink.text(&self.c_phone)?;
+        sink.decimal(self.c_acctbal)?;
+        sink.text(&self.c_mktsegment)?;
+        sink.text(&self.c_comment)
+    }
+}
+
+impl TpchRow for Order<'_> {
+    const COLUMNS: &'static [&'static str] = &[
+        "o_orderkey",
+        "o_custkey",
+        "o_orderstatus",
+        "o_totalprice",
+        "o_orderdate",
+        "o_orderpriority",
+        "o_clerk",
+        "o_shippriority",
+        "o_comment",
+    ];
+
+    fn write_fields<S: FieldSink>(&self, sink: &mut S) -> io::Result<()> {
+        sink.int(self.o_orderkey)?;
+        sink.int(self.o_custkey)?;
+        sink.text(&self.o_orderstatus)?;
+        sink.decimal(self.o_totalprice)?;
+        sink.date(self.o_orderdate)?;
+        sink.text(&self.o_orderpriority)?;
+        sink.text(&self.o_clerk)?;
+        sink.int(self.o_shippriority.into())?;
+        sink.text(&self.o_comment)
+    }
+}
+
+impl TpchRow for LineItem<'_> {
+    const COLUMNS: &'static [&'static str] = &[
+        "l_orderkey",
+        "l_partkey",
+        "l_suppkey",
+        "l_linenumber",
+        "l_quantity",
+        "l_extendedprice",
+        "l_discount",
+        "l_tax",
+        "l_returnflag",
+        "l_linestatus",
+        "l_shipdate",
+        "l_commitdate",
+        "l_receiptdate",
+        "l_shipinstruct",
+        "l_shipmode",
+        "l_comment",
+    ];
+
+    fn write_fields<S: FieldSink>(&self, sink: &mut S) -> io::Result<()> {
+        sink.int(self.l_orderkey)?;
+        sink.int(self.l_partkey)?;
+        sink.int(self.l_suppkey)?;
+        sink.int(self.l_linenumber.into())?;
+        // l_quantity is a whole number but lives in a numeric(15,2) column.
+        sink.decimal(TPCHDecimal(self.l_quantity * 100))?;
+        sink.decimal(self.l_extendedprice)?;
+        sink.decimal(self.l_discount)?;
+        sink.decimal(self.l_tax)?;
+        sink.text(&self.l_returnflag)?;
+        sink.text(&self.l_linestatus)?;
+        sink.date(self.l_shipdate)?;
+        sink.date(self.l_commitdate)?;
+        sink.date(self.l_receiptdate)?;
+        sink.text(&self.l_shipinstruct)?;
+        sink.text(&self.l_shipmode)?;
+        sink.text(&self.l_comment)
+    }
+}
@@ -0,0 +1,235 @@
+//! Configuration parameters in the `pg_tpch.` namespace.
+use std::ffi::CString;
+
+use pgrx::guc::{GucContext, GucFlags, GucRegistry, GucSetting};
+use pgrx::prelude::*;
+
+/// What to do when operating on a dataset that is only partially loaded.
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PartialDatasetAction {
+    #[name = c"ignore"]
+    Ignore,
+    #[name = c"warning"]
+    Warning,
+    #[name = c"error"]
+    Error,
+}
+
+pub static ON_PARTIAL_DATASET: GucSetting<PartialDatasetAction> =
+    GucSetting::<PartialDatasetAction>::new(PartialDatasetAction::Warning);
+
+/// How files spilled during a load are compressed.
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpillCompression {
+    #[name = c"none"]
+    None,
+    #[name = c"gzip"]
+    Gzip,
+    #[name = c"zstd"]
+    Zstd,
+    #[name = c"lz4"]
+    Lz4,
+}
+
+pub static SPILL_COMPRESSION: GucSetting<SpillCompression> =
+    GucSetting::<SpillCompression>::new(SpillCompression::None);
+
+/// Compression level passed to the `pg_tpch.spill_compression` codec.
+pub static SPILL_COMPRESSION_LEVEL: GucSetting<i32> = GucSetting::<i32>::new(1);
+
+/// Size of the buffer generated rows are written through, in kB.
+pub static GENERATION_BUFFER: GucSetting<i32> = GucSetting::<i32>::new(1024);
+
+/// Capacity in bytes of the buffer generated rows go through.
+pub fn generation_buffer_bytes() -> usize {
+    GENERATION_BUFFER.get() as usize * 1024
+}
+
+/// Threads generating a table at once.
+pub static GENERATION_THREADS: GucSetting<i32> = GucSetting::<i32>::new(1);
+
+/// Most disk space the spill files of a load may take at once, in kB, or -1
+/// for no limit.
+pub static MAX_TEMP_BYTES: GucSetting<i32> = GucSetting::<i32>::new(-1);
+
+/// `pg_tpch.max_temp_bytes` in bytes, if set.
+pub fn max_temp_bytes() -> Option<u64> {
+    let kb = MAX_TEMP_BYTES.get();
+    (kb >= 0).then(|| kb as u64 * 1024)
+}
+
+/// Size of the pieces a table is spilled and loaded in, in kB, or -1 to
+/// spill every table in one file.
+pub static SPILL_CHUNK_SIZE: GucSetting<i32> = GucSetting::<i32>::new(-1);
+
+/// `pg_tpch.spill_chunk_size` in bytes, if set.
+pub fn spill_chunk_bytes() -> Option<u64> {
+    let kb = SPILL_CHUNK_SIZE.get();
+    (kb >= 0).then(|| kb as u64 * 1024)
+}
+
+/// Directory loads spill generated data into.
+pub static DATA_DIR: GucSetting<Option<CString>> =
+    GucSetting::<Option<CString>>::new(Some(c"/tmp/pg_tpch_data"));
+
+/// Connection string of the primary that runs on a standby are recorded on.
+pub static PRIMARY_CONNINFO: GucSetting<Option<CString>> = GucSetting::<Option<CString>>::new(None);
+
+/// Most rows a load step generates per second, or 0 for no limit.
+pub static MAX_LOAD_ROWS_PER_SEC: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/// Most megabytes a load step hands to Postgres per second, or 0 for no
+/// limit.
+pub static MAX_LOAD_MB_PER_SEC: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/// Storage parameters of the TPC-H tables, as comma-separated `name=value`
+/// pairs.
+pub static STORAGE_PARAMETERS: GucSetting<Option<CString>> =
+    GucSetting::<Option<CString>>::new(None);
+
+/// Whether loads and runs log their phases as JSON.
+pub static LOG_EVENTS: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+pub fn init() {
+    GucRegistry::define_enum_guc(
+        c"pg_tpch.on_partial_dataset",
+        c"Action taken when querying a partially loaded TPC-H dataset.",
+        c"One of ignore, warning or error. A dataset is partial while a multi-step load is \
+          missing steps or while a load step has not finished.",
+        &ON_PARTIAL_DATASET,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_enum_guc(
+        c"pg_tpch.spill_compression",
+        c"Codec compressing the files generated data is spilled into during a load.",
+        c"One of none, gzip, zstd or lz4. Compressed files are written and read through the \
+          codec's command line tool, which has to be installed on the server, and need less \
+          temporary disk space at the cost of CPU time.",
+        &SPILL_COMPRESSION,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"pg_tpch.spill_compression_level",
+        c"Compression level of pg_tpch.spill_compression.",
+        c"Passed to the codec as is, so the valid range depends on the codec: up to 9 for \
+          gzip, 19 for zstd and 12 for lz4.",
+        &SPILL_COMPRESSION_LEVEL,
+        1,
+        19,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"pg_tpch.generation_buffer",
+        c"Memory used to buffer generated rows before they are written out.",
+        c"Rows are streamed through a buffer of this size, which bounds the memory a load \
+          or export needs for generation independently of the scale factor.",
+        &GENERATION_BUFFER,
+        8,
+        1024 * 1024,
+        GucContext::Userset,
+        GucFlags::UNIT_KB,
+    );
+    GucRegistry::define_int_guc(
+        c"pg_tpch.generation_threads",
+        c"Threads generating the rows of a table at once during a load.",
+        c"Each thread generates a share of the rows loaded in one go, through COPY from the \
+          generator or into one spill file, so a load uses several cores. Rows then reach \
+          the table in a different order, and every thread buffers up to a few MB. Tables \
+          whose rows can't be shared out evenly are generated by fewer threads.",
+        &GENERATION_THREADS,
+        1,
+        64,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"pg_tpch.max_temp_bytes",
+        c"Most disk space the files spilled during a load may take at once.",
+        c"A load that is projected to exceed the limit fails before generating anything, \
+          one that exceeds it while generating fails right away and removes its files. -1 \
+          means no limit.",
+        &MAX_TEMP_BYTES,
+        -1,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::UNIT_KB,
+    );
+    GucRegistry::define_int_guc(
+        c"pg_tpch.spill_chunk_size",
+        c"Size of the files a table is spilled into and loaded from one at a time.",
+        c"With a size set, generating a table stops whenever its file holds this much \
+          uncompressed data, the file is loaded and removed, and generation goes on in a \
+          new file, so a load needs about this much temporary disk space. -1 spills every \
+          table into one file.",
+        &SPILL_CHUNK_SIZE,
+        -1,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::UNIT_KB,
+    );
+    GucRegistry::define_int_guc(
+        c"pg_tpch.max_load_rows_per_sec",
+        c"Most rows a load step generates per second.",
+        c"Throttles loads so they leave disk and CPU to other work on a shared server. \
+          Applies to each backend that loads, with the stream and insert strategies, which \
+          auto picks while a limit is set. 0 means no limit.",
+        &MAX_LOAD_ROWS_PER_SEC,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"pg_tpch.max_load_mb_per_sec",
+        c"Most megabytes of COPY input or INSERT statements a load step produces per second.",
+        c"Throttles loads like pg_tpch.max_load_rows_per_sec, counting the bytes handed to \
+          Postgres instead of rows. 0 means no limit.",
+        &MAX_LOAD_MB_PER_SEC,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_string_guc(
+        c"pg_tpch.data_dir",
+        c"Directory on the server loads write their intermediate files to.",
+        c"Must be an absolute path the server can write to, and is created if missing. The \
+          files of a table are removed once it is loaded, but a load needs room for the \
+          biggest table it generates at once.",
+        &DATA_DIR,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_string_guc(
+        c"pg_tpch.storage_parameters",
+        c"Storage parameters of the TPC-H tables pg_tpch creates or loads.",
+        c"Comma-separated name=value pairs, like fillfactor=90, autovacuum_enabled=off or \
+          parallel_workers=8. Tables get them when a load truncates them and when pg_tpch \
+          creates them, partitions instead of partitioned tables.",
+        &STORAGE_PARAMETERS,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_string_guc(
+        c"pg_tpch.primary_conninfo",
+        c"Connection string of the primary server for runs on a standby.",
+        c"tpch_run_replica records its runs in pg_tpch_runs on this server through dblink, \
+          the standby itself is read-only. Runs are not recorded if it is empty.",
+        &PRIMARY_CONNINFO,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"pg_tpch.log_events",
+        c"Logs the phases of loads and runs as JSON objects.",
+        c"Every load step, table loaded, run and query logs one line at level LOG when it \
+          starts and ends, carrying the load or run id, for log pipelines to rebuild the \
+          timeline of a benchmark from.",
+        &LOG_EVENTS,
+        GucContext::Suset,
+        GucFlags::default(),
+    );
+}
@@ -0,0 +1,721 @@
+//! Sets of indexes on the TPC-H tables, created and dropped together.
+//!
+//! The tables come without indexes. An index profile is a set of indexes
+//! meant to be compared against another one, and every index created from a
+//! profile is recorded in `pg_tpch_indexes`, so the set can be dropped again
+//! without touching indexes created by hand.
+//!
+//! Loads can drop all indexes and keys of the tables, of profiles or not,
+//! and build them again afterwards, see [`drop_for_load`].
+//!
+//! `tpch_index_advisor()` estimates what each index of a profile would save
+//! the queries before any of them is built.
+use std::time::Instant;
+
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::queries;
+use crate::runner::{self, RunOptions};
+use crate::tables::TABLES;
+
+extension_sql!(
+    r#"
+    CREATE TABLE pg_tpch_indexes (
+        index_name text PRIMARY KEY,
+        profile text NOT NULL,
+        table_name text NOT NULL,
+        definition text NOT NULL,
+        build_ms double precision NOT NULL,
+        created_at timestamptz NOT NULL DEFAULT now()
+    );
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_indexes', '');
+    "#,
+    name = "indexes",
+    requires = ["create_schema"]
+);
+
+/// An index of a profile.
+struct IndexDef {
+    name: &'static str,
+    table: &'static str,
+    method: &'static str,
+    keys: &'static str,
+    /// Columns stored in the index without being part of the key.
+    include: &'static str,
+    /// Condition of the rows a partial index covers.
+    predicate: &'static str,
+    unique: bool,
+}
+
+const fn index(
+    name: &'static str,
+    table: &'static str,
+    method: &'static str,
+    keys: &'static str,
+) -> IndexDef {
+    IndexDef {
+        name,
+        table,
+        method,
+        keys,
+        include: "",
+        predicate: "",
+        unique: false,
+    }
+}
+
+/// A unique btree index on the columns of a primary key.
+const fn unique(name: &'static str, table: &'static str, keys: &'static str) -> IndexDef {
+    IndexDef {
+        unique: true,
+        ..index(name, table, "btree", keys)
+    }
+}
+
+const fn covering(
+    name: &'static str,
+    table: &'static str,
+    keys: &'static str,
+    include: &'static str,
+) -> IndexDef {
+    IndexDef {
+        include,
+        ..index(name, table, "btree", keys)
+    }
+}
+
+const fn partial(
+    name: &'static str,
+    table: &'static str,
+    keys: &'static str,
+    predicate: &'static str,
+) -> IndexDef {
+    IndexDef {
+        predicate,
+        ..index(name, table, "btree", keys)
+    }
+}
+
+/// Indexes of every profile, by profile name.
+///
+/// `btree` indexes the order keys and the dates most queries filter on.
+/// `brin` indexes the same columns with BRIN indexes, which are a fraction of
+/// the size: the order keys are loaded in ascending order, the dates however
+/// are random within the orders, so the ranges of the date indexes overlap.
+/// `covering` has btree indexes that include every column some of the
+/// queries read, so those can use index-only scans once a `VACUUM` has set
+/// the visibility map: Q6 and Q14 and Q15 on the ship date, Q4 on the order
+/// date and the order's line items, Q17 on the part.
+/// `partial` has btree indexes on the keys of the rows matching predicates
+/// of the queries: returned line items (Q10), line items received late (Q4,
+/// Q21), finished orders (Q21) and line items delivered in person by air
+/// (Q19).
+/// `minimal` has unique indexes on the primary keys of the specification,
+/// without adding the keys themselves, see `tpch_add_constraints()` for that.
+/// `extended` is the set commonly used to run TPC-H with indexes: those of
+/// `minimal`, one on the columns of every foreign key, which the joins of
+/// almost every query use, and the ship date, order date and market segment
+/// the queries filter on.
+const PROFILES: &[(&str, &[IndexDef])] = &[
+    (
+        "btree",
+        &[
+            index("tpch_btree_l_orderkey", "lineitem", "btree", "l_orderkey"),
+            index("tpch_btree_l_shipdate", "lineitem", "btree", "l_shipdate"),
+            index("tpch_btree_o_orderkey", "orders", "btree", "o_orderkey"),
+            index("tpch_btree_o_orderdate", "orders", "btree", "o_orderdate"),
+        ],
+    ),
+    (
+        "brin",
+        &[
+            index("tpch_brin_l_orderkey", "lineitem", "brin", "l_orderkey"),
+            index("tpch_brin_l_shipdate", "lineitem", "brin", "l_shipdate"),
+            index("tpch_brin_o_orderkey", "orders", "brin", "o_orderkey"),
+            index("tpch_brin_o_orderdate", "orders", "brin", "o_orderdate"),
+        ],
+    ),
+    (
+        "covering",
+        &[
+            covering(
+                "tpch_covering_q6",
+                "lineitem",
+                "l_shipdate",
+                "l_quantity, l_extendedprice, l_discount",
+            ),
+            covering(
+                "tpch_covering_q14",
+                "lineitem",
+                "l_shipdate",
+                "l_partkey, l_suppkey, l_extendedprice, l_discount",
+            ),
+            covering(
+                "tpch_covering_q4",
+                "orders",
+                "o_orderdate",
+                "o_orderkey, o_orderpriority",
+            ),
+            covering(
+                "tpch_covering_q4_lines",
+                "lineitem",
+                "l_orderkey",
+                "l_commitdate, l_receiptdate",
+            ),
+            covering(
+                "tpch_covering_q17",
+                "lineitem",
+                "l_partkey",
+                "l_quantity, l_extendedprice",
+            ),
+        ],
+    ),
+    (
+        "partial",
+        &[
+            partial(
+                "tpch_partial_returned",
+                "lineitem",
+                "l_orderkey",
+                "l_returnflag = 'R'",
+            ),
+            partial(
+                "tpch_partial_late",
+                "lineitem",
+                "l_orderkey",
+                "l_receiptdate > l_commitdate",
+            ),
+            partial(
+                "tpch_partial_finished",
+                "orders",
+                "o_orderkey",
+                "o_orderstatus = 'F'",
+            ),
+            partial(
+                "tpch_partial_in_person",
+                "lineitem",
+                "l_partkey",
+                "l_shipmode IN ('AIR', 'AIR REG') AND l_shipinstruct = 'DELIVER IN PERSON'",
+            ),
+        ],
+    ),
+    (
+        "minimal",
+        &[
+            unique("tpch_minimal_r_regionkey", "region", "r_regionkey"),
+            unique("tpch_minimal_n_nationkey", "nation", "n_nationkey"),
+            unique("tpch_minimal_p_partkey", "part", "p_partkey"),
+            unique("tpch_minimal_s_suppkey", "supplier", "s_suppkey"),
+            unique(
+                "tpch_minimal_ps_partkey",
+                "partsupp",
+                "ps_partkey, ps_suppkey",
+            ),
+            unique("tpch_minimal_c_custkey", "customer", "c_custkey"),
+            unique("tpch_minimal_o_orderkey", "orders", "o_orderkey"),
+            unique(
+                "tpch_minimal_l_orderkey",
+                "lineitem",
+                "l_orderkey, l_linenumber",
+            ),
+        ],
+    ),
+    (
+        "extended",
+        &[
+            unique("tpch_extended_r_regionkey", "region", "r_regionkey"),
+            unique("tpch_extended_n_nationkey", "nation", "n_nationkey"),
+            unique("tpch_extended_p_partkey", "part", "p_partkey"),
+            unique("tpch_extended_s_suppkey", "supplier", "s_suppkey"),
+            unique(
+                "tpch_extended_ps_partkey",
+                "partsupp",
+                "ps_partkey, ps_suppkey",
+            ),
+            unique("tpch_extended_c_custkey", "customer", "c_custkey"),
+            unique("tpch_extended_o_orderkey", "orders", "o_orderkey"),
+            unique(
+                "tpch_extended_l_orderkey",
+                "lineitem",
+                "l_orderkey, l_linenumber",
+            ),
+            index(
+                "tpch_extended_n_regionkey",
+                "nation",
+                "btree",
+                "n_regionkey",
+            ),
+            index(
+                "tpch_extended_s_nationkey",
+                "supplier",
+                "btree",
+                "s_nationkey",
+            ),
+            index(
+                "tpch_extended_c_nationkey",
+                "customer",
+                "btree",
+                "c_nationkey",
+            ),
+            index(
+                "tpch_extended_ps_suppkey",
+                "partsupp",
+                "btree",
+                "ps_suppkey",
+            ),
+            index("tpch_extended_o_custkey", "orders", "btree", "o_custkey"),
+            index(
+                "tpch_extended_l_partkey",
+                "lineitem",
+                "btree",
+                "l_partkey, l_suppkey",
+            ),
+            index(
+                "tpch_extended_l_shipdate",
+                "lineitem",
+                "btree",
+                "l_shipdate",
+            ),
+            index(
+                "tpch_extended_o_orderdate",
+                "orders",
+                "btree",
+                "o_orderdate",
+            ),
+            index(
+                "tpch_extended_c_mktsegment",
+                "customer",
+                "btree",
+                "c_mktsegment",
+            ),
+        ],
+    ),
+];
+
+fn profile_indexes(profile: &str) -> &'static [IndexDef] {
+    PROFILES
+        .iter()
+        .find(|(name, _)| *name == profile)
+        .map(|(_, indexes)| *indexes)
+        .unwrap_or_else(|| {
+            let names: Vec<_> = PROFILES.iter().map(|(name, _)| *name).collect();
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                format!("unknown index profile \"{profile}\", expected one of {names:?}")
+            );
+        })
+}
+
+impl IndexDef {
+    /// The `CREATE INDEX` statement of the index, BRIN indexes summarizing
+    /// `pages_per_range` pages.
+    fn definition(&self, pages_per_range: i32) -> String {
+        let storage = match self.method {
+            "brin" => format!(" WITH (pages_per_range = {pages_per_range})"),
+            _ => String::new(),
+        };
+        let include = match self.include {
+            "" => String::new(),
+            include => format!(" INCLUDE ({include})"),
+        };
+        let predicate = match self.predicate {
+            "" => String::new(),
+            predicate => format!(" WHERE {predicate}"),
+        };
+        format!(
+            "CREATE {}INDEX {} ON {} USING {} ({}){include}{storage}{predicate}",
+            if self.unique { "UNIQUE " } else { "" },
+            self.name,
+            self.table,
+            self.method,
+            self.keys
+        )
+    }
+}
+
+/// Creates the indexes of `profile` that don't exist yet and returns all
+/// indexes of the profile with their size and how long building them took.
+pub fn create_indexes(
+    profile: &str,
+    pages_per_range: i32,
+) -> spi::Result<Vec<(String, String, i64, f64)>> {
+    if !(1..=131072).contains(&pages_per_range) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("pages_per_range must be between 1 and 131072, got {pages_per_range}")
+        );
+    }
+    let mut created = Vec::new();
+    for index in profile_indexes(profile) {
+        let definition = index.definition(pages_per_range);
+        let exists = Spi::get_one_with_args::<bool>(
+            "SELECT to_regclass($1) IS NOT NULL",
+            &[index.name.into()],
+        )?;
+        if exists != Some(true) {
+            let started = Instant::now();
+            Spi::run(&definition)?;
+            Spi::run_with_args(
+                "INSERT INTO pg_tpch_indexes (index_name, profile, table_name, definition, build_ms)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (index_name) DO UPDATE
+                 SET definition = excluded.definition, build_ms = excluded.build_ms,
+                     created_at = excluded.created_at",
+                &[
+                    index.name.into(),
+                    profile.into(),
+                    index.table.into(),
+                    definition.into(),
+                    (started.elapsed().as_secs_f64() * 1000.).into(),
+                ],
+            )?;
+        }
+        let (size, build_ms) = Spi::get_two_with_args::<i64, f64>(
+            "SELECT pg_relation_size(to_regclass($1)),
+                    (SELECT build_ms FROM pg_tpch_indexes WHERE index_name = $1)",
+            &[index.name.into()],
+        )?;
+        created.push((
+            index.name.to_string(),
+            index.table.to_string(),
+            size.unwrap_or(0),
+            build_ms.unwrap_or(0.),
+        ));
+    }
+    Ok(created)
+}
+
+/// Drops the indexes of `profile`, or of all profiles, that were created by
+/// [`create_indexes`] and returns their names.
+pub fn drop_indexes(profile: Option<&str>) -> spi::Result<Vec<String>> {
+    if let Some(profile) = profile {
+        profile_indexes(profile);
+    }
+    let names = Spi::get_one_with_args::<Vec<String>>(
+        "SELECT coalesce(array_agg(index_name ORDER BY index_name), '{}')
+         FROM pg_tpch_indexes WHERE $1 IS NULL OR profile = $1",
+        &[profile.into()],
+    )?
+    .unwrap_or_default();
+    for name in &names {
+        Spi::run(&format!(
+            "DROP INDEX IF EXISTS {}",
+            spi::quote_identifier(name)
+        ))?;
+    }
+    Spi::run_with_args(
+        "DELETE FROM pg_tpch_indexes WHERE $1 IS NULL OR profile = $1",
+        &[profile.into()],
+    )?;
+    Ok(names)
+}
+
+/// Indexes and constraints of the TPC-H tables dropped by [`drop_for_load`],
+/// with the statements creating them again in the order to run them.
+pub struct Dropped {
+    statements: Vec<String>,
+}
+
+/// Drops every index and every primary, unique and foreign key of the TPC-H
+/// tables, foreign keys from other tables included, so a load doesn't have
+/// to maintain them row by row.
+///
+/// Building an index or checking a key once over all rows is much faster
+/// than updating it for every row `COPY` adds.
+pub fn drop_for_load() -> spi::Result<Dropped> {
+    // Foreign keys go first, they depend on the keys they reference.
+    let constraints = Spi::connect(|client| {
+        client
+            .select(
+                "SELECT c.conrelid::regclass::text, quote_ident(c.conname), pg_get_constraintdef(c.oid)
+                 FROM pg_constraint c
+                 WHERE c.contype IN ('p', 'u', 'x', 'f')
+                   AND (c.conrelid = ANY(SELECT to_regclass(t) FROM unnest($1::text[]) t)
+                        OR c.confrelid = ANY(SELECT to_regclass(t) FROM unnest($1::text[]) t))
+                 ORDER BY c.contype = 'f' DESC, c.oid",
+                None,
+                &[TABLES.to_vec().into()],
+            )?
+            .map(|row| {
+                Ok((
+                    row.get::<String>(1)?.unwrap_or_default(),
+                    row.get::<String>(2)?.unwrap_or_default(),
+                    row.get::<String>(3)?.unwrap_or_default(),
+                ))
+            })
+            .collect::<spi::Result<Vec<_>>>()
+    })?;
+    // Indexes of keys go with their keys.
+    let indexes = Spi::connect(|client| {
+        client
+            .select(
+                "SELECT i.indexrelid::regclass::text, pg_get_indexdef(i.indexrelid)
+                 FROM pg_index i
+                 WHERE i.indrelid = ANY(SELECT to_regclass(t) FROM unnest($1::text[]) t)
+                   AND NOT EXISTS (SELECT FROM pg_constraint c
+                                   WHERE c.conindid = i.indexrelid AND c.contype IN ('p', 'u', 'x'))
+                 ORDER BY i.indexrelid",
+                None,
+                &[TABLES.to_vec().into()],
+            )?
+            .map(|row| {
+                Ok((
+                    row.get::<String>(1)?.unwrap_or_default(),
+                    row.get::<String>(2)?.unwrap_or_default(),
+                ))
+            })
+            .collect::<spi::Result<Vec<_>>>()
+    })?;
+
+    for (table, name, _) in &constraints {
+        Spi::run(&format!("ALTER TABLE {table} DROP CONSTRAINT {name}"))?;
+    }
+    for (name, _) in &indexes {
+        Spi::run(&format!("DROP INDEX {name}"))?;
+    }
+    // Indexes of a partitioned table are defined `ON ONLY` it, without
+    // those of its partitions.
+    let mut statements: Vec<String> = indexes
+        .into_iter()
+        .map(|(_, definition)| definition.replacen(" ON ONLY ", " ON ", 1))
+        .collect();
+    statements.extend(
+        constraints
+            .into_iter()
+            .rev()
+            .map(|(table, name, definition)| {
+                format!("ALTER TABLE {table} ADD CONSTRAINT {name} {definition}")
+            }),
+    );
+    Ok(Dropped { statements })
+}
+
+impl Dropped {
+    /// Creates the dropped indexes and constraints again, indexes first,
+    /// then keys and the foreign keys referencing them.
+    pub fn restore(self) -> spi::Result<()> {
+        for statement in self.statements {
+            Spi::run(&statement)?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the indexes of index profile `profile` and returns them with their
+/// size in bytes and build time.
+///
+/// `pages_per_range` is the storage parameter of BRIN indexes, how many
+/// table pages one summary covers: fewer make the index larger and more
+/// selective. Indexes that already exist are left alone.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_create_indexes(
+    profile: default!(&str, "'btree'"),
+    pages_per_range: default!(i32, 128),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(index_name, String),
+            name!(table_name, String),
+            name!(size_bytes, i64),
+            name!(build_ms, f64),
+        ),
+    >,
+> {
+    Ok(TableIterator::new(create_indexes(
+        profile,
+        pages_per_range,
+    )?))
+}
+
+/// Drops the indexes `tpch_create_indexes` created for `profile`, or for
+/// every profile, and returns their names.
+#[pg_extern]
+fn tpch_drop_indexes(profile: default!(Option<&str>, "NULL")) -> spi::Result<Vec<String>> {
+    drop_indexes(profile)
+}
+
+/// Runs the queries `query_nrs`, all 22 by default, once per index profile in
+/// `profiles` with only that profile's indexes in place, and returns the
+/// total size of the indexes and the run the queries were recorded in.
+///
+/// Per query timings of the runs can be lined up with `tpch_matrix`. The
+/// indexes of all profiles are dropped before each profile and again at the
+/// end.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_compare_indexes(
+    profiles: default!(Vec<String>, "'{btree,brin}'"),
+    query_nrs: default!(Option<Vec<i32>>, "NULL"),
+    pages_per_range: default!(i32, 128),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(profile, String),
+            name!(index_bytes, i64),
+            name!(build_ms, f64),
+            name!(run_id, i64),
+            name!(total_ms, f64),
+        ),
+    >,
+> {
+    for profile in &profiles {
+        profile_indexes(profile);
+    }
+    let plan: Vec<(i32, i32)> = match query_nrs {
+        Some(query_nrs) => query_nrs.into_iter().map(|nr| (nr, 1)).collect(),
+        None => queries::QUERIES.iter().map(|(nr, _)| (*nr, 1)).collect(),
+    };
+    let mut report = Vec::with_capacity(profiles.len());
+    for profile in profiles {
+        drop_indexes(None)?;
+        let indexes = create_indexes(&profile, pages_per_range)?;
+        Spi::run("ANALYZE lineitem, orders")?;
+        let results = runner::run_queries(
+            &plan,
+            &RunOptions {
+                profile: "default",
+                role: None,
+                persist_results: false,
+                baseline_run: None,
+                max_regression: 0.,
+                max_duration: None,
+                cancel_in_flight: false,
+                dataset: None,
+                prewarm: false,
+            },
+        )?;
+        report.push((
+            profile,
+            indexes.iter().map(|(_, _, size, _)| size).sum(),
+            indexes.iter().map(|(_, _, _, build_ms)| build_ms).sum(),
+            results.first().map_or(0, |result| result.run_id),
+            results.iter().map(|result| result.duration_ms).sum(),
+        ));
+    }
+    drop_indexes(None)?;
+    Ok(TableIterator::new(report))
+}
+
+/// Lists for each of the 22 queries which indexes of index profile `profile`
+/// its plan uses, as `EXPLAIN` shows it without running the query.
+///
+/// The indexes have to exist, see `tpch_create_indexes`, and the tables
+/// should have been analyzed since they were loaded.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_index_usage(
+    profile: default!(&str, "'partial'"),
+) -> spi::Result<TableIterator<'static, (name!(query_nr, i32), name!(indexes, Vec<String>))>> {
+    let names: Vec<&str> = profile_indexes(profile)
+        .iter()
+        .map(|index| index.name)
+        .collect();
+    let mut usage = Vec::with_capacity(queries::QUERIES.len());
+    for (query_nr, query) in queries::QUERIES {
+        let plans = runner::explain(query)?;
+        let used = Spi::get_one_with_args::<Vec<String>>(
+            "SELECT coalesce(array_agg(DISTINCT name ORDER BY name), '{}')
+             FROM unnest($1::jsonb[]) plan,
+                  jsonb_path_query(plan, 'strict $.**.\"Index Name\"') index_name,
+                  LATERAL (SELECT index_name #>> '{}' AS name) n
+             WHERE name = ANY($2)",
+            &[plans.into(), names.clone().into()],
+        )?;
+        usage.push((*query_nr, used.unwrap_or_default()));
+    }
+    Ok(TableIterator::new(usage))
+}
+
+/// Total estimated cost of the statements of `query` as [`runner::explain`]
+/// plans them.
+fn estimated_cost(query: &str) -> spi::Result<f64> {
+    let plans = runner::explain(query)?;
+    Spi::get_one_with_args::<f64>(
+        "SELECT sum((plan::jsonb #>> '{0,Plan,Total Cost}')::float8) FROM unnest($1::text[]) plan",
+        &[plans.into()],
+    )
+    .map(Option::unwrap_or_default)
+}
+
+/// Suggests the indexes of index profile `profile` that don't exist yet, one
+/// row per index and query whose estimated cost the index lowers by at least
+/// `min_benefit`, a fraction, with the `CREATE INDEX` statement and the
+/// estimates of the query without and with it.
+///
+/// Each index is weighed on its own against the indexes that exist, by
+/// planning the 22 queries with `EXPLAIN`, so the benefits of two indexes
+/// don't add up. With the hypopg extension the indexes are hypothetical and
+/// nothing is built, otherwise each is built and dropped again in this
+/// transaction, which takes as long as creating it. Estimates are only as
+/// good as the statistics, analyze the tables after loading them.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_index_advisor(
+    profile: default!(&str, "'extended'"),
+    min_benefit: default!(f64, 0.1),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(index_name, String),
+            name!(definition, String),
+            name!(query_nr, i32),
+            name!(cost_without, f64),
+            name!(cost_with, f64),
+            name!(benefit, f64),
+        ),
+    >,
+> {
+    let hypopg = Spi::get_one::<String>(
+        "SELECT max(extnamespace::regnamespace::text) FROM pg_extension WHERE extname = 'hypopg'",
+    )?;
+    let mut baseline = Vec::with_capacity(queries::QUERIES.len());
+    for (query_nr, query) in queries::QUERIES {
+        baseline.push((*query_nr, *query, estimated_cost(query)?));
+    }
+
+    let mut advice = Vec::new();
+    for index in profile_indexes(profile) {
+        let exists = Spi::get_one_with_args::<bool>(
+            "SELECT to_regclass($1) IS NOT NULL",
+            &[index.name.into()],
+        )?;
+        if exists == Some(true) {
+            continue;
+        }
+        let definition = index.definition(128);
+        match &hypopg {
+            // Uniqueness doesn't change the estimates of these queries, and
+            // not every version of hypopg takes it.
+            Some(hypopg) => Spi::run_with_args(
+                &format!("SELECT {hypopg}.hypopg_create_index($1)"),
+                &[definition.replacen("CREATE UNIQUE ", "CREATE ", 1).into()],
+            )?,
+            None => Spi::run(&definition)?,
+        }
+        for &(query_nr, query, cost_without) in &baseline {
+            let cost_with = estimated_cost(query)?;
+            if cost_without > 0. && cost_with <= cost_without * (1. - min_benefit) {
+                advice.push((
+                    index.name.to_string(),
+                    definition.clone(),
+                    query_nr,
+                    cost_without,
+                    cost_with,
+                    1. - cost_with / cost_without,
+                ));
+            }
+        }
+        match &hypopg {
+            Some(hypopg) => Spi::run(&format!("SELECT {hypopg}.hypopg_reset()"))?,
+            None => Spi::run(&format!("DROP INDEX {}", index.name))?,
+        }
+    }
+    Ok(TableIterator::new(advice))
+}
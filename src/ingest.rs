@@ -0,0 +1,484 @@
+//! Loading data that was generated earlier instead of generating it again.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::dbgen::ChecksumWriter;
+use crate::format::CsvOptions;
+use crate::manifest::{Manifest, MANIFEST};
+use crate::tables::TABLES;
+use crate::{copy, metadata, parallel, strategy};
+
+/// `COPY` options reading dbgen `.tbl` files once the `|` ending every line
+/// is stripped.
+const TBL_COPY_OPTIONS: &str = "FORMAT text, DELIMITER '|'";
+
+/// Format of data files that weren't generated by this load.
+pub enum FileFormat {
+    /// CSV with a header line, as written by `tpch_export`.
+    Csv(CsvOptions),
+    /// dbgen's `|`-separated format without a header.
+    Tbl,
+}
+
+impl FileFormat {
+    /// Parses the `format`, `delimiter` and `quote` arguments of a loader.
+    pub fn new(format: &str, delimiter: &str, quote: &str) -> FileFormat {
+        match format {
+            "csv" => FileFormat::Csv(CsvOptions::new(delimiter, quote).unwrap_or_else(|message| {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    message
+                );
+            })),
+            "tbl" => FileFormat::Tbl,
+            _ => {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    format!("unknown file format \"{format}\", expected \"csv\" or \"tbl\"")
+                );
+            }
+        }
+    }
+
+    /// Applies the header, NULL string and encoding a CSV export was
+    /// written with, see [`CsvOptions::with_export_options`].
+    pub fn with_export_options(self, header: bool, null: &str, encoding: &str) -> FileFormat {
+        match self {
+            FileFormat::Csv(csv) => FileFormat::Csv(
+                csv.with_export_options(header, null, encoding)
+                    .unwrap_or_else(|message| {
+                        ereport!(
+                            ERROR,
+                            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                            message
+                        );
+                    }),
+            ),
+            FileFormat::Tbl => FileFormat::Tbl,
+        }
+    }
+
+    /// Returns `command` adapted to produce what [`FileFormat::copy_options`]
+    /// reads.
+    pub fn command(&self, command: &str) -> String {
+        match self {
+            FileFormat::Csv(_) => command.to_string(),
+            FileFormat::Tbl => format!("{command} | sed 's/|$//'"),
+        }
+    }
+
+    /// Renders the `COPY` options reading this format.
+    pub fn copy_options(&self) -> String {
+        match self {
+            FileFormat::Csv(csv) => crate::copy_options(csv),
+            FileFormat::Tbl => TBL_COPY_OPTIONS.to_string(),
+        }
+    }
+
+    /// Extension of the files of this format.
+    fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Csv(_) => "csv",
+            FileFormat::Tbl => "tbl",
+        }
+    }
+
+    /// Loads the file at `path` into `table` and returns the number of rows.
+    pub fn copy_file(&self, table: &str, path: &Path) -> u64 {
+        match self {
+            FileFormat::Csv(csv) => copy::copy_from(table, path, csv, None, false),
+            FileFormat::Tbl => {
+                let cat = format!("cat {}", shell_quote(&path.display().to_string()));
+                copy::copy_from_program(table, &self.command(&cat), &self.copy_options())
+            }
+        }
+    }
+}
+
+/// Quotes `word` for `/bin/sh`.
+pub fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+/// Checks that `table` is one of the TPC-H tables.
+pub fn check_table(table: &str) {
+    if !TABLES.contains(&table) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("unknown TPC-H table \"{table}\", expected one of {TABLES:?}")
+        );
+    }
+}
+
+/// Loads `table_name` from the output of the shell command `command` and
+/// returns the number of rows loaded.
+///
+/// `command` runs on the server through `COPY FROM PROGRAM`, for example
+/// `zstd -dc /data/lineitem.csv.zst` to ingest a compressed export, so it needs
+/// superuser or `pg_execute_server_program`. `format` is `csv` for files
+/// written by `tpch_export` with the same `delimiter` and `quote`, or `tbl`
+/// for dbgen output. The table is marked dirty until the rows are in.
+#[pg_extern]
+fn tpch_load_program(
+    table_name: &str,
+    command: &str,
+    format: default!(&str, "'csv'"),
+    delimiter: default!(&str, "','"),
+    quote: default!(&str, "'\"'"),
+) -> spi::Result<i64> {
+    check_table(table_name);
+    let format = FileFormat::new(format, delimiter, quote);
+
+    metadata::mark_table(table_name, true)?;
+    let rows =
+        copy::copy_from_program(table_name, &format.command(command), &format.copy_options());
+    metadata::mark_table(table_name, false)?;
+    Ok(rows as i64)
+}
+
+/// A file to load and what its manifest says about it.
+struct DataFile {
+    table: String,
+    path: PathBuf,
+    expected: Option<(u64, u64, u32)>,
+}
+
+fn io_error(what: String, e: io::Error) -> ! {
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_IO_ERROR,
+        format!("could not read {what}: {e}")
+    );
+}
+
+/// Reads every manifest in `dir` and checks that they describe all parts of
+/// one export.
+fn read_manifests(dir: &Path) -> Vec<Manifest> {
+    let entries = fs::read_dir(dir)
+        .unwrap_or_else(|e| io_error(format!("directory \"{}\"", dir.display()), e));
+    let mut manifests = Vec::new();
+    for entry in entries {
+        let path = entry
+            .unwrap_or_else(|e| io_error(format!("directory \"{}\"", dir.display()), e))
+            .path();
+        let is_manifest = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(MANIFEST));
+        if is_manifest {
+            manifests.push(
+                Manifest::read(&path)
+                    .unwrap_or_else(|e| io_error(format!("\"{}\"", path.display()), e)),
+            );
+        }
+    }
+    manifests.sort_by_key(|manifest| manifest.part);
+
+    if let Some(first) = manifests.first() {
+        let parts: Vec<i32> = manifests.iter().map(|manifest| manifest.part).collect();
+        let consistent = manifests.iter().all(|manifest| {
+            manifest.sf == first.sf
+                && manifest.format == first.format
+                && manifest.num_parts == first.num_parts
+        });
+        if !consistent || parts != (1..=first.num_parts).collect::<Vec<_>>() {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_DATA_CORRUPTED,
+                format!(
+                    "\"{}\" does not hold all parts of one export",
+                    dir.display()
+                ),
+                format!(
+                    "Found the manifests of parts {parts:?} of {}.",
+                    first.num_parts
+                )
+            );
+        }
+    }
+    manifests
+}
+
+/// Finds the files of every table in `dir` by name, `<table>.<extension>` or
+/// chunks `<table>.<extension>.<n>`.
+fn find_files(dir: &Path, extension: &str) -> Vec<DataFile> {
+    let mut files = Vec::new();
+    for table in TABLES {
+        let single = dir.join(format!("{table}.{extension}"));
+        if single.exists() {
+            files.push(DataFile {
+                table: table.to_string(),
+                path: single,
+                expected: None,
+            });
+            continue;
+        }
+        for chunk in 1.. {
+            let path = dir.join(format!("{table}.{extension}.{chunk}"));
+            if !path.exists() {
+                break;
+            }
+            files.push(DataFile {
+                table: table.to_string(),
+                path,
+                expected: None,
+            });
+        }
+    }
+    files
+}
+
+/// Returns the format of files without a manifest in `dir`: `tbl` when there
+/// are dbgen files, `csv` otherwise.
+fn detect_format(dir: &Path) -> &'static str {
+    if find_files(dir, "tbl").is_empty() {
+        "csv"
+    } else {
+        "tbl"
+    }
+}
+
+/// Infers the scale factor of files without a manifest from the rows of
+/// `supplier`, 10,000 per unit of scale factor.
+fn infer_scale_factor(dir: &Path, files: &[DataFile], header: bool) -> f64 {
+    let mut rows = 0;
+    for file in files.iter().filter(|file| file.table == "supplier") {
+        let content = fs::read(&file.path)
+            .unwrap_or_else(|e| io_error(format!("\"{}\"", file.path.display()), e));
+        let lines = content.iter().filter(|&&byte| byte == b'\n').count();
+        rows += lines.saturating_sub(usize::from(header));
+    }
+    if rows == 0 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!(
+                "\"{}\" has no manifest and no suppliers, the scale factor is unknown",
+                dir.display()
+            ),
+            "Pass the scale factor the files were generated with as sf."
+        );
+    }
+    rows as f64 / 10_000.
+}
+
+/// Checks that the file at `path` has the size and CRC-32 the manifest lists.
+fn verify_file(path: &Path, bytes: u64, crc32: u32) {
+    let mut checksum = ChecksumWriter::new(io::sink());
+    fs::File::open(path)
+        .and_then(|mut file| io::copy(&mut file, &mut checksum))
+        .unwrap_or_else(|e| io_error(format!("\"{}\"", path.display()), e));
+    if (checksum.bytes(), checksum.crc32()) != (bytes, crc32) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_DATA_CORRUPTED,
+            format!("\"{}\" does not match the manifest", path.display()),
+            format!(
+                "Expected {bytes} bytes with CRC-32 {crc32:08x}, got {} bytes with CRC-32 {:08x}.",
+                checksum.bytes(),
+                checksum.crc32()
+            )
+        );
+    }
+}
+
+/// Loads the files in `dir` into the TPC-H tables instead of generating them.
+///
+/// Directories written by `tpch_export` carry a manifest that gives the scale
+/// factor and dialect, and every file is checked against the size, checksum
+/// and row count it lists before and while loading. Files from elsewhere, such
+/// as dbgen's `.tbl` output, are found by table name, `<table>.tbl` or chunks
+/// `<table>.tbl.1`, `<table>.tbl.2` and so on, and loaded chunk by chunk.
+/// Without a manifest, `format` defaults to `tbl` if there are `.tbl` files,
+/// and `sf` to the number of suppliers divided by 10,000, as dbgen
+/// generates them.
+///
+/// The tables are truncated first and the result is recorded as a load of
+/// one part, like `tpch_load` with `children => 1`. Parquet files can't be
+/// read by `COPY` and are not supported.
+///
+/// With `workers` above 1, that many background workers load files
+/// concurrently, which pays off for tables exported in several parts. Each
+/// worker commits on its own, so a failed load leaves the files loaded so far
+/// in the tables, and the tables have to be empty to begin with.
+#[pg_extern]
+fn tpch_load_from(
+    dir: &str,
+    format: default!(Option<&str>, "NULL"),
+    sf: default!(Option<f64>, "NULL"),
+    delimiter: default!(&str, "','"),
+    quote: default!(&str, "'\"'"),
+    workers: default!(i32, 1),
+) -> spi::Result<Option<String>> {
+    if workers < 1 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("workers must be at least 1, got {workers}")
+        );
+    }
+    strategy::require_role(
+        "pg_read_server_files",
+        "loading files on the server",
+        "Generate the data with tpch_load(strategy => 'stream') instead.",
+    );
+    if format == Some("parquet") {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "parquet files can't be loaded with COPY",
+            "Convert them to CSV, or load them with tpch_load_program and a converting command."
+        );
+    }
+    let dir = PathBuf::from(dir);
+    let manifests = read_manifests(&dir);
+
+    let (sf, file_format, files) = match manifests.first() {
+        Some(first) => {
+            if format.is_some_and(|format| format != first.format)
+                || sf.is_some_and(|sf| sf != first.sf)
+            {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    format!(
+                        "\"{}\" holds SF={} in format \"{}\"",
+                        dir.display(),
+                        first.sf,
+                        first.format
+                    )
+                );
+            }
+            let file_format = FileFormat::new(
+                &first.format,
+                &first.delimiter.to_string(),
+                &first.quote.to_string(),
+            )
+            .with_export_options(first.header, &first.null, &first.encoding);
+            let mut files: Vec<DataFile> = manifests
+                .iter()
+                .flat_map(|manifest| &manifest.files)
+                .map(|file| DataFile {
+                    table: file.table.clone(),
+                    path: dir.join(&file.name),
+                    expected: Some((file.rows, file.bytes, file.crc32)),
+                })
+                .collect();
+            // Load in table order, not part order.
+            files.sort_by_key(|file| TABLES.iter().position(|table| *table == file.table));
+            (first.sf, file_format, files)
+        }
+        None => {
+            let file_format = FileFormat::new(
+                format.unwrap_or_else(|| detect_format(&dir)),
+                delimiter,
+                quote,
+            );
+            let files = find_files(&dir, file_format.extension());
+            let header = matches!(&file_format, FileFormat::Csv(csv) if csv.header);
+            let sf = sf.unwrap_or_else(|| infer_scale_factor(&dir, &files, header));
+            (sf, file_format, files)
+        }
+    };
+    for table in TABLES {
+        if !files.iter().any(|file| file.table == table) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_UNDEFINED_FILE,
+                format!("no {table} data in \"{}\"", dir.display())
+            );
+        }
+    }
+    crate::check_scale_factor(sf, false);
+    for file in &files {
+        check_table(&file.table);
+        if let Some((_, bytes, crc32)) = file.expected {
+            verify_file(&file.path, bytes, crc32);
+        }
+    }
+
+    if workers > 1 {
+        // Truncating would lock the workers out until we commit.
+        for table in TABLES {
+            if Spi::get_one::<bool>(&format!("SELECT EXISTS (SELECT FROM {table})"))? == Some(true)
+            {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+                    format!("loading with workers needs empty tables, {table} is not"),
+                    "Truncate the tables with tpch_load(0) in a separate transaction first."
+                );
+            }
+        }
+        metadata::mark_truncated()?;
+    } else {
+        crate::truncate_tables()?;
+    }
+    let started = Instant::now();
+    let load_id = metadata::find_or_begin_load(
+        sf,
+        1,
+        &[
+            ("source", dir.display().to_string()),
+            ("format", file_format.extension().to_string()),
+        ],
+    )?;
+    metadata::mark_tables(true)?;
+    let loaded = if workers > 1 {
+        let jobs: Vec<(&str, &Path)> = files
+            .iter()
+            .map(|file| (file.table.as_str(), file.path.as_path()))
+            .collect();
+        parallel::copy_files(&jobs, &file_format, workers as usize)?
+    } else {
+        files
+            .iter()
+            .map(|file| file_format.copy_file(&file.table, &file.path))
+            .collect()
+    };
+    for table in TABLES {
+        let mut stats = metadata::TableStats {
+            table,
+            rows: 0,
+            file_bytes: 0,
+            peak_memory: 0,
+            table_bytes: None,
+            timing: None,
+        };
+        for (file, &rows) in files.iter().zip(&loaded) {
+            if file.table != table {
+                continue;
+            }
+            if let Some((expected_rows, _, _)) = file.expected {
+                if rows != expected_rows {
+                    ereport!(
+                        ERROR,
+                        PgSqlErrorCode::ERRCODE_DATA_CORRUPTED,
+                        format!(
+                            "loaded {rows} rows from \"{}\", the manifest lists {expected_rows}",
+                            file.path.display()
+                        )
+                    );
+                }
+            }
+            stats.rows += rows;
+            stats.file_bytes += fs::metadata(&file.path).map_or(0, |m| m.len());
+        }
+        metadata::record_table_stats(load_id, 1, &stats)?;
+    }
+    metadata::mark_tables(false)?;
+    metadata::complete_part(load_id, 1, started.elapsed().as_secs_f64())?;
+
+    Ok(Some(format!(
+        "TPC-H SF={sf} loaded from \"{}\"",
+        dir.display()
+    )))
+}
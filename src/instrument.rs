@@ -0,0 +1,109 @@
+//! Resource usage of the queries a run executes.
+//!
+//! The executor memory of a query is released when the query ends, so an
+//! `ExecutorEnd` hook looks at the query's memory context right before that
+//! happens, while hash tables and sort buffers are still allocated. Spilling to
+//! temporary files and, with `track_io_timing` on, the time spent reading and
+//! writing blocks show up in the backend's buffer usage counters.
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use pgrx::pg_sys;
+use pgrx::prelude::*;
+
+static mut PREV_EXECUTOR_END: pg_sys::ExecutorEnd_hook_type = None;
+
+/// Whether a measurement is running.
+static MEASURING: AtomicBool = AtomicBool::new(false);
+/// Largest executor memory seen since the measurement started.
+static PEAK_MEMORY: AtomicU64 = AtomicU64::new(0);
+
+#[pg_guard]
+unsafe extern "C-unwind" fn executor_end(query_desc: *mut pg_sys::QueryDesc) {
+    if MEASURING.load(Ordering::Relaxed) {
+        let estate = (*query_desc).estate;
+        if !estate.is_null() {
+            let allocated = pg_sys::MemoryContextMemAllocated((*estate).es_query_cxt, true);
+            PEAK_MEMORY.fetch_max(allocated as u64, Ordering::Relaxed);
+        }
+    }
+    match PREV_EXECUTOR_END {
+        Some(prev) => prev(query_desc),
+        None => pg_sys::standard_ExecutorEnd(query_desc),
+    }
+}
+
+/// Installs the executor hook, called once from `_PG_init`.
+pub fn init() {
+    // SAFETY: _PG_init runs once per backend before any query uses the hook.
+    unsafe {
+        PREV_EXECUTOR_END = pg_sys::ExecutorEnd_hook;
+        pg_sys::ExecutorEnd_hook = Some(executor_end);
+    }
+}
+
+/// Resources a measured piece of work used.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Usage {
+    /// Executor memory of the most memory-hungry statement, in bytes.
+    pub peak_memory: u64,
+    /// Bytes written to temporary files by sorts and hashes that spilled.
+    pub temp_bytes: u64,
+    /// Time spent reading blocks, in milliseconds. Zero unless
+    /// `track_io_timing` is on.
+    pub io_read_ms: f64,
+    /// Time spent writing blocks, in milliseconds. Zero unless
+    /// `track_io_timing` is on.
+    pub io_write_ms: f64,
+}
+
+#[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15"))]
+fn nanos(time: pg_sys::instr_time) -> u64 {
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+#[cfg(not(any(feature = "pg13", feature = "pg14", feature = "pg15")))]
+fn nanos(time: pg_sys::instr_time) -> u64 {
+    time.ticks as u64
+}
+
+/// Block read and write time of this backend so far, in nanoseconds.
+fn io_time() -> (u64, u64) {
+    // SAFETY: pgBufferUsage is only ever written by this backend.
+    let usage = unsafe { pg_sys::pgBufferUsage };
+    #[cfg(any(feature = "pg13", feature = "pg14"))]
+    return (nanos(usage.blk_read_time), nanos(usage.blk_write_time));
+    #[cfg(any(feature = "pg15", feature = "pg16"))]
+    return (
+        nanos(usage.blk_read_time) + nanos(usage.temp_blk_read_time),
+        nanos(usage.blk_write_time) + nanos(usage.temp_blk_write_time),
+    );
+    #[cfg(not(any(feature = "pg13", feature = "pg14", feature = "pg15", feature = "pg16")))]
+    return (
+        nanos(usage.shared_blk_read_time)
+            + nanos(usage.local_blk_read_time)
+            + nanos(usage.temp_blk_read_time),
+        nanos(usage.shared_blk_write_time)
+            + nanos(usage.local_blk_write_time)
+            + nanos(usage.temp_blk_write_time),
+    );
+}
+
+/// Runs `f` and reports the resources the queries it executes use.
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, Usage) {
+    // SAFETY: pgBufferUsage is only ever written by this backend.
+    let temp_blocks = || unsafe { pg_sys::pgBufferUsage.temp_blks_written as u64 };
+    let temp_before = temp_blocks();
+    let (read_before, write_before) = io_time();
+    PEAK_MEMORY.store(0, Ordering::Relaxed);
+    MEASURING.store(true, Ordering::Relaxed);
+    let result = f();
+    MEASURING.store(false, Ordering::Relaxed);
+    let (read_after, write_after) = io_time();
+    let usage = Usage {
+        peak_memory: PEAK_MEMORY.load(Ordering::Relaxed),
+        temp_bytes: (temp_blocks() - temp_before) * pg_sys::BLCKSZ as u64,
+        io_read_ms: (read_after - read_before) as f64 / 1e6,
+        io_write_ms: (write_after - write_before) as f64 / 1e6,
+    };
+    (result, usage)
+}
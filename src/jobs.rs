@@ -0,0 +1,288 @@
+//! Loads that run in the background while the session that started them
+//! moves on.
+//!
+//! `tpch_load_async()` hands a load to a background worker and returns its
+//! job id right away. The worker runs the steps of the load one transaction
+//! at a time and keeps its row of `pg_tpch_load_jobs` up to date, committing
+//! after every step, so `tpch_load_status()` can tell how far it got, or why
+//! it failed, from any session.
+use pgrx::bgworkers::{BackgroundWorker, BackgroundWorkerBuilder};
+use pgrx::pg_sys;
+use pgrx::pg_sys::panic::CaughtError;
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::strategy::LoadStrategy;
+use crate::{parallel, spill};
+
+extension_sql!(
+    r#"
+    CREATE TABLE pg_tpch_load_jobs (
+        job_id bigserial PRIMARY KEY,
+        sf double precision NOT NULL,
+        children integer NOT NULL,
+        strategy text NOT NULL,
+        pid integer NOT NULL,
+        state text NOT NULL DEFAULT 'running',
+        steps_completed integer NOT NULL DEFAULT 0,
+        error text,
+        started_at timestamptz NOT NULL DEFAULT clock_timestamp(),
+        finished_at timestamptz
+    );
+    COMMENT ON TABLE pg_tpch_load_jobs IS 'loads started by tpch_load_async, written by the workers running them';
+    COMMENT ON COLUMN pg_tpch_load_jobs.state IS 'running, succeeded or failed';
+    "#,
+    name = "load_jobs",
+    requires = ["create_schema"]
+);
+
+/// Entry point of the workers started by [`tpch_load_async`], which get the
+/// job id, scale factor, number of steps, strategy and data directory
+/// through `bgw_extra`, tab-separated.
+#[pg_guard]
+#[no_mangle]
+pub extern "C-unwind" fn pg_tpch_async_load_worker(arg: pg_sys::Datum) {
+    parallel::connect_worker(arg);
+
+    let job = BackgroundWorker::get_extra();
+    let fields: Vec<&str> = job.splitn(5, '\t').collect();
+    let [job_id, sf, children, strategy, data_dir] = fields[..] else {
+        panic!("invalid load job \"{job}\"");
+    };
+    let (Ok(job_id), Ok(sf), Ok(children)) = (
+        job_id.parse::<i64>(),
+        sf.parse::<f64>(),
+        children.parse::<i64>(),
+    ) else {
+        panic!("invalid load job \"{job}\"");
+    };
+    BackgroundWorker::transaction(|| {
+        Spi::run_with_args(
+            "INSERT INTO pg_tpch_load_jobs (job_id, sf, children, strategy, pid)
+             VALUES ($1, $2, $3, $4, pg_backend_pid())",
+            &[
+                job_id.into(),
+                sf.into(),
+                (children as i32).into(),
+                strategy.into(),
+            ],
+        )
+    })
+    .unwrap_or_else(|e| panic!("could not record load job {job_id}: {e}"));
+
+    for step in 0..children {
+        let loaded = PgTryBuilder::new(|| {
+            BackgroundWorker::transaction(|| {
+                crate::load_step(
+                    sf,
+                    children,
+                    step,
+                    ",",
+                    "\"",
+                    false,
+                    "error",
+                    false,
+                    "uniform",
+                    None,
+                    "none",
+                    strategy,
+                    "csv",
+                    Some(data_dir),
+                    false,
+                    crate::StepMode::Alone,
+                    None,
+                )?;
+                Spi::run_with_args(
+                    "UPDATE pg_tpch_load_jobs SET steps_completed = $2 WHERE job_id = $1",
+                    &[job_id.into(), (step as i32 + 1).into()],
+                )
+            })
+            .map_err(|e| e.to_string())
+        })
+        .catch_others(|error| match error {
+            CaughtError::PostgresError(report)
+            | CaughtError::ErrorReport(report)
+            | CaughtError::RustPanic {
+                ereport: report, ..
+            } => Err(report.message().to_string()),
+        })
+        .execute();
+        if let Err(message) = loaded {
+            // SAFETY: the failed step's transaction is still open, rolling it
+            // back is what Postgres would do once the worker exits.
+            unsafe { pg_sys::AbortCurrentTransaction() };
+            finish(job_id, Some(&message));
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_EXTERNAL_ROUTINE_EXCEPTION,
+                format!("load job {job_id} failed in step {step}: {message}")
+            );
+        }
+    }
+    finish(job_id, None);
+}
+
+/// Records that load job `job_id` ended, failing with `error` if set.
+fn finish(job_id: i64, error: Option<&str>) {
+    BackgroundWorker::transaction(|| {
+        Spi::run_with_args(
+            "UPDATE pg_tpch_load_jobs
+             SET state = CASE WHEN $2::text IS NULL THEN 'succeeded' ELSE 'failed' END,
+                 error = $2, finished_at = clock_timestamp()
+             WHERE job_id = $1",
+            &[job_id.into(), error.into()],
+        )
+    })
+    .unwrap_or_else(|e| panic!("could not record the end of load job {job_id}: {e}"));
+}
+
+/// Starts loading SF=`sf` in `children` steps in a background worker and
+/// returns the id of the job, for `tpch_load_status`.
+///
+/// The worker loads like `tpch_load` with its default options, one step per
+/// transaction. It uses the server's configuration, not the settings of this
+/// session, except for `pg_tpch.data_dir`. It starts right away and commits
+/// on its own, even if this transaction rolls back, so this transaction must
+/// not lock the TPC-H tables.
+#[pg_extern]
+fn tpch_load_async(
+    sf: default!(f64, 1.),
+    children: default!(i32, 1),
+    strategy: default!(&str, "'auto'"),
+) -> spi::Result<i64> {
+    if children < 1 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("children must be at least 1, got {children}")
+        );
+    }
+    crate::check_scale_factor(sf, false);
+    if parallel::holds_table_locks()? {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            "loading in the background needs the TPC-H tables unlocked",
+            "Call tpch_load_async in a transaction of its own."
+        );
+    }
+    let dir = spill::data_dir(None);
+    let strategy = LoadStrategy::new(strategy, &dir)?;
+    if strategy.uses_files() {
+        spill::create_dir(&dir);
+    }
+
+    // Sequences aren't transactional, the worker can use the id before this
+    // transaction commits.
+    let job_id = Spi::get_one::<i64>("SELECT nextval('pg_tpch_load_jobs_job_id_seq')")?
+        .expect("nextval returns a value");
+    let job = format!("{job_id}\t{sf}\t{children}\t{strategy}\t{}", dir.display());
+    if job.len() > parallel::MAX_JOB_LEN {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_NAME_TOO_LONG,
+            "pg_tpch.data_dir is too long to hand to a worker",
+            "Use a directory with a shorter path."
+        );
+    }
+    BackgroundWorkerBuilder::new(&format!("pg_tpch load job {job_id}"))
+        .set_type("pg_tpch load job")
+        .set_library("pg_tpch")
+        .set_function("pg_tpch_async_load_worker")
+        .enable_spi_access()
+        .set_argument(Some(parallel::session_argument()))
+        .set_extra(&job)
+        .load_dynamic()
+        .unwrap_or_else(|_| {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_CONFIGURATION_LIMIT_EXCEEDED,
+                "could not start a background worker to load",
+                "Raise max_worker_processes."
+            );
+        });
+    Ok(job_id)
+}
+
+/// Reports how far load job `job_id` of `tpch_load_async` got.
+///
+/// `state` is `starting` until the worker recorded the job, then `running`,
+/// `succeeded` or `failed`, also when the worker stopped without recording
+/// why. `current_table` is the table a `COPY` of the job is loading, if any.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_load_status(
+    job_id: i64,
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(state, String),
+            name!(current_table, Option<String>),
+            name!(steps_completed, i32),
+            name!(steps, i32),
+            name!(error, Option<String>),
+            name!(started_at, Option<TimestampWithTimeZone>),
+            name!(finished_at, Option<TimestampWithTimeZone>),
+        ),
+    >,
+> {
+    let status = Spi::connect(|client| {
+        let mut rows = client.select(
+            "SELECT CASE WHEN j.state = 'running'
+                          AND NOT EXISTS (SELECT FROM pg_stat_activity a WHERE a.pid = j.pid)
+                         THEN 'failed' ELSE j.state END,
+                    (SELECT p.table_name FROM pg_tpch_load_progress p
+                     WHERE j.state = 'running' AND p.state = 'copying'
+                       AND j.pid IN (p.pid, p.leader_pid)
+                     LIMIT 1),
+                    j.steps_completed, j.children,
+                    CASE WHEN j.state = 'running'
+                          AND NOT EXISTS (SELECT FROM pg_stat_activity a WHERE a.pid = j.pid)
+                         THEN 'the worker stopped, see the server log for its error'
+                         ELSE j.error END,
+                    j.started_at, j.finished_at
+             FROM pg_tpch_load_jobs j WHERE j.job_id = $1",
+            None,
+            &[job_id.into()],
+        )?;
+        let Some(row) = rows.next() else {
+            return Ok(None);
+        };
+        Ok::<_, spi::SpiError>(Some((
+            row.get::<String>(1)?.unwrap_or_default(),
+            row.get::<String>(2)?,
+            row.get::<i32>(3)?.unwrap_or_default(),
+            row.get::<i32>(4)?.unwrap_or_default(),
+            row.get::<String>(5)?,
+            row.get::<TimestampWithTimeZone>(6)?,
+            row.get::<TimestampWithTimeZone>(7)?,
+        )))
+    })?;
+    if let Some(status) = status {
+        return Ok(TableIterator::once(status));
+    }
+
+    // The worker records the job once it runs, ids handed out before that
+    // are starting.
+    let issued = Spi::get_one_with_args::<bool>(
+        "SELECT $1 > 0 AND $1 <= last_value AND is_called FROM pg_tpch_load_jobs_job_id_seq",
+        &[job_id.into()],
+    )?;
+    if issued != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("load job {job_id} does not exist")
+        );
+    }
+    Ok(TableIterator::once((
+        "starting".to_string(),
+        None,
+        0,
+        0,
+        None,
+        None,
+        None,
+    )))
+}
@@ -1,20 +1,23 @@
+use error::LoadError;
+use format::{BinaryVisitor, BinaryWriter, ColumnKind, CsvOptions, CsvVisitor, CsvWriter};
 use pgrx::prelude::*;
 use pgrx::spi::{self, Spi};
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
-use tpchgen::{
-    csv::{
-        CustomerCsv, LineItemCsv, NationCsv, OrderCsv, PartCsv, PartSuppCsv, RegionCsv, SupplierCsv,
-    },
-    generators::{
-        CustomerGenerator, LineItemGenerator, NationGenerator, OrderGenerator, PartGenerator,
-        PartSuppGenerator, RegionGenerator, SupplierGenerator,
-    },
-};
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+use strategy::LoadStrategy;
+use tables::TABLES;
+use threads::Encoding;
 
 ::pgrx::pg_module_magic!(name, version);
 
+#[pg_guard]
+pub extern "C-unwind" fn _PG_init() {
+    guc::init();
+    instrument::init();
+}
+
 extension_sql!(
     r#"
     CREATE TABLE IF NOT EXISTS region (
@@ -98,29 +101,608 @@ extension_sql!(
     name = "create_schema"
 );
 
+mod alloc;
+mod check;
+mod ci;
+mod citus;
+mod cogen;
+mod constraints;
+mod copy;
+mod dbgen;
+mod demo;
+mod diff;
+mod error;
+mod events;
+mod export;
+mod format;
+mod guc;
+mod indexes;
+mod ingest;
+mod instrument;
+mod jobs;
+mod maintenance;
+mod manifest;
+mod mapping;
+mod metadata;
+mod parallel;
+mod presets;
+mod progress;
+mod provision;
 mod queries;
-
-const TPCH_DATA_DIR: &str = "/tmp/pg_tpch_data";
+mod replica;
+mod runner;
+mod scale;
+mod schema;
+mod share;
+mod spill;
+mod strategy;
+mod stream;
+mod tables;
+mod threads;
+mod throttle;
+mod timing;
+mod tuning;
 
 fn truncate_tables() -> spi::Result<()> {
     Spi::run(
         r#"
     TRUNCATE TABLE region, nation, part, supplier, partsupp, customer, orders, lineitem RESTART IDENTITY;
     "#,
+    )?;
+    schema::apply_storage_parameters()?;
+    metadata::mark_truncated()?;
+    metadata::mark_tables(false)
+}
+
+/// Warns about scale factors the specification doesn't allow, or refuses
+/// them with `strict`. Any positive scale factor is fine for development.
+fn check_scale_factor(sf: f64, strict: bool) {
+    if !(sf > 0. && sf.is_finite()) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("sf must be a positive number, got {sf}")
+        );
+    }
+    if tables::SPEC_SCALE_FACTORS.contains(&sf) {
+        return;
+    }
+    let message = format!("SF={sf} is not a scale factor allowed by the TPC-H specification");
+    let hint = format!(
+        "Results are only comparable for SF in {:?}.",
+        tables::SPEC_SCALE_FACTORS
+    );
+    if strict {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            message,
+            hint
+        );
+    }
+    ereport!(WARNING, PgSqlErrorCode::ERRCODE_WARNING, message, hint);
+}
+
+/// Bytes `table` takes on disk, those of its partitions included and
+/// indexes left out.
+fn table_size(table: &str) -> spi::Result<u64> {
+    Spi::get_one_with_args::<i64>(
+        "SELECT coalesce((SELECT sum(pg_table_size(relid))::bigint
+                          FROM pg_partition_tree($1::regclass)),
+                         pg_table_size($1::regclass))",
+        &[table.into()],
     )
+    .map(|bytes| bytes.unwrap_or_default() as u64)
+}
+
+/// Deletes the rows part `part` of `num_parts` generated from every table.
+fn delete_part(sf: f64, part: i32, num_parts: i32, auto_partitioning: bool) -> spi::Result<()> {
+    for table in TABLES {
+        let parts = table_parts(table, sf, num_parts, auto_partitioning);
+        if part > parts {
+            continue;
+        }
+        match tables::key_range(table, sf, part, parts) {
+            Some((column, first, last)) => Spi::run_with_args(
+                &format!("DELETE FROM {table} WHERE {column} BETWEEN $1 AND $2"),
+                &[first.into(), last.into()],
+            )?,
+            None => Spi::run(&format!("DELETE FROM {table}"))?,
+        }
+    }
+    Ok(())
+}
+
+/// Number of parts `table` is split into by a load in `num_parts` steps.
+///
+/// Every part of a fixed-size table holds all of its rows, so those tables
+/// are a single part loaded by the first step.
+fn table_parts(table: &str, sf: f64, num_parts: i32, auto_partitioning: bool) -> i32 {
+    if auto_partitioning {
+        tables::auto_parts(table, sf, num_parts)
+    } else if tables::FIXED_SIZE_TABLES.contains(&table) {
+        1
+    } else {
+        num_parts
+    }
+}
+
+/// Renders the `COPY` options that read files produced with `csv`.
+fn copy_options(csv: &CsvOptions) -> String {
+    format!(
+        "FORMAT csv, HEADER {}, DELIMITER {}, QUOTE {}, ESCAPE {}, NULL {}, ENCODING {}",
+        csv.header,
+        spi::quote_literal(csv.delimiter.to_string()),
+        spi::quote_literal(csv.quote.to_string()),
+        spi::quote_literal(csv.escape.to_string()),
+        spi::quote_literal(&csv.null),
+        spi::quote_literal(&csv.encoding),
+    )
+}
+
+/// Returns how each column of `table` that `COPY` fills is written in the
+/// binary format, failing unless they are the generated columns in order.
+///
+/// The binary format carries no type information, so every value has to be
+/// sent as the type of its column: the kinds follow the live types, through
+/// domains, and the character types and enums of every schema profile take
+/// text.
+fn binary_columns(table: &str) -> spi::Result<Vec<ColumnKind>> {
+    let columns = Spi::connect(|client| {
+        client
+            .select(
+                "SELECT a.attname::text, b.oid, b.typtype = 'e' OR b.typcategory = 'S',
+                        format_type(a.atttypid, a.atttypmod)
+                 FROM pg_attribute a
+                 JOIN pg_type t ON t.oid = a.atttypid
+                 JOIN pg_type b ON b.oid = coalesce(nullif(t.typbasetype, 0), t.oid)
+                 WHERE a.attrelid = $1::regclass AND a.attnum > 0
+                   AND NOT a.attisdropped AND a.attgenerated = ''
+                 ORDER BY a.attnum",
+                None,
+                &[table.into()],
+            )?
+            .map(|row| {
+                Ok((
+                    row.get::<String>(1)?.unwrap_or_default(),
+                    row.get::<pg_sys::Oid>(2)?.unwrap_or_default(),
+                    row.get::<bool>(3)?.unwrap_or_default(),
+                    row.get::<String>(4)?.unwrap_or_default(),
+                ))
+            })
+            .collect::<spi::Result<Vec<_>>>()
+    })?;
+
+    let names: Vec<&str> = columns.iter().map(|(name, ..)| name.as_str()).collect();
+    if names != tables::columns(table) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            format!("binary COPY needs the columns of {table} as they are generated"),
+            format!("Expected {:?}, found {names:?}.", tables::columns(table))
+        );
+    }
+    let mut kinds = Vec::with_capacity(columns.len());
+    for (name, base, text, type_name) in columns {
+        kinds.push(match base {
+            _ if text => ColumnKind::Text,
+            pg_sys::INT2OID => ColumnKind::Int2,
+            pg_sys::INT4OID => ColumnKind::Int4,
+            pg_sys::INT8OID => ColumnKind::Int8,
+            pg_sys::FLOAT8OID => ColumnKind::Float8,
+            pg_sys::NUMERICOID => ColumnKind::Numeric,
+            pg_sys::DATEOID => ColumnKind::Date,
+            _ => {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                    format!("binary COPY can't fill column {name} of type {type_name}"),
+                    "Load with format => 'csv' instead."
+                );
+            }
+        });
+    }
+    Ok(kinds)
+}
+
+/// Parses the `format` argument of a load, returning whether it is binary.
+fn parse_format(format: &str) -> bool {
+    if !["csv", "binary"].contains(&format) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("unknown format \"{format}\", expected \"csv\" or \"binary\"")
+        );
+    }
+    format == "binary"
 }
 
+/// How generated rows of a table get into it.
+struct Loader<'a> {
+    strategy: LoadStrategy,
+    /// Directory of the spill files.
+    dir: &'a Path,
+    csv: &'a CsvOptions,
+    /// Whether rows go through `COPY` in its binary format.
+    binary: bool,
+    save_rejects: bool,
+    /// Whether rows are loaded with `COPY ... FREEZE`.
+    freeze: bool,
+    /// Size of the spill files a table is cut into, see
+    /// `pg_tpch.spill_chunk_size`.
+    chunk_bytes: Option<u64>,
+}
+
+impl Loader<'_> {
+    /// Generates part `part` of `parts` of `table` at SF=`sf` and loads it,
+    /// returning the rows and the bytes spilled on the way.
+    fn load(&self, table: &str, sf: f64, part: i32, parts: i32) -> spi::Result<(u64, u64)> {
+        let columns = self.binary.then(|| binary_columns(table)).transpose()?;
+        if !self.strategy.uses_files() {
+            let rows = match self.strategy {
+                LoadStrategy::Stream => {
+                    stream::copy_generated(table, sf, part, parts, self.csv, columns, self.freeze)?
+                }
+                _ => strategy::insert_rows(table, sf, part, parts)?,
+            };
+            return Ok((rows, 0));
+        }
+
+        if let Some(chunk_bytes) = self.chunk_bytes {
+            let rejects = self
+                .save_rejects
+                .then(|| copy::rejects_path(self.dir, table));
+            let visitor = spill::ChunkVisitor {
+                dir: self.dir,
+                table,
+                csv: self.csv,
+                columns,
+                rejects: rejects.as_deref(),
+                via_program: self.strategy == LoadStrategy::Program,
+                freeze: self.freeze,
+                chunk_bytes,
+                part,
+                parts,
+            };
+            return Ok(
+                tables::generate(table, sf, part, parts, visitor).unwrap_or_else(|e| e.report())
+            );
+        }
+
+        let spill = spill::SpillFile::new(self.dir, table).binary(self.binary);
+        let encoding = match &columns {
+            Some(columns) => Encoding::Binary(columns.clone()),
+            None => Encoding::Csv(self.csv.clone()),
+        };
+        let (rows, file_bytes) = timing::generating(|| {
+            spill.write(|out| {
+                match (
+                    threads::generate(table, sf, part, parts, encoding)?,
+                    columns,
+                ) {
+                    (Some(generated), _) => generated.write_to(out),
+                    (None, Some(columns)) => tables::generate(
+                        table,
+                        sf,
+                        part,
+                        parts,
+                        BinaryVisitor(BinaryWriter::new(out, columns)),
+                    ),
+                    (None, None) => write_table_csv(table, sf, part, parts, out, self.csv),
+                }
+            })
+        })
+        .and_then(|rows| Ok((rows, fs::metadata(&spill.path)?.len())))
+        .unwrap_or_else(|e| {
+            LoadError::new(table, part, parts, e)
+                .at(&spill.path)
+                .report()
+        });
+        self.copy_spilled(&spill, table);
+        Ok((rows, file_bytes))
+    }
+
+    /// Loads the spill file `spill` into `table`.
+    fn copy_spilled(&self, spill: &spill::SpillFile, table: &str) {
+        let rejects = self
+            .save_rejects
+            .then(|| copy::rejects_path(self.dir, table));
+        let via_program = self.strategy == LoadStrategy::Program;
+        spill.copy_into(
+            table,
+            self.csv,
+            rejects.as_deref(),
+            via_program,
+            self.freeze,
+        );
+    }
+}
+
+/// Generates part `part` of `num_parts` of `table` as CSV into `out`.
+///
+/// Callers pass a buffer sized by `pg_tpch.generation_buffer`, so memory use
+/// doesn't grow with the table.
+fn write_table_csv(
+    table: &str,
+    sf: f64,
+    part: i32,
+    num_parts: i32,
+    out: impl io::Write,
+    csv: &CsvOptions,
+) -> io::Result<u64> {
+    tables::generate(
+        table,
+        sf,
+        part,
+        num_parts,
+        CsvVisitor(CsvWriter::new(out, csv)),
+    )
+}
+
+/// Loads the TPC-H tables at scale factor `sf`, in step `step` of
+/// `children`.
+///
+/// The rows are generated with the fixed seeds of dbgen, so loads at the same
+/// scale factor hold the same rows. `pg_tpch.generation_threads` only changes
+/// the order they are loaded in.
+#[allow(clippy::too_many_arguments)]
 #[pg_extern]
 fn tpch_load(
     sf: default!(f64, 1.),
     children: default!(i64, 1),
     step: default!(i64, 0),
+    delimiter: default!(&str, "','"),
+    quote: default!(&str, "'\"'"),
+    save_rejects: default!(bool, false),
+    on_repeat: default!(&str, "'error'"),
+    strict: default!(bool, false),
+    partitioning: default!(&str, "'uniform'"),
+    mapping: default!(Option<pgrx::JsonB>, "NULL"),
+    checkpoint: default!(&str, "'none'"),
+    strategy: default!(&str, "'auto'"),
+    format: default!(&str, "'csv'"),
+    data_dir: default!(Option<&str>, "NULL"),
+    resume: default!(bool, false),
+    tune: default!(bool, false),
+    freeze: default!(bool, false),
+    target_schema: default!(Option<&str>, "NULL"),
+    temporary: default!(bool, false),
+    analyze: default!(bool, true),
+    rebuild_indexes: default!(bool, false),
+    constraints: default!(&str, "'none'"),
+) -> spi::Result<Option<String>> {
+    if !["none", "valid", "not_valid"].contains(&constraints) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!(
+                "unknown constraints option \"{constraints}\", expected \"none\", \"valid\" or \"not_valid\""
+            )
+        );
+    }
+    if tune {
+        tuning::tune_load()?;
+    }
+    match (target_schema, temporary) {
+        (Some(_), true) => {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                "temporary => true can't be combined with target_schema"
+            );
+        }
+        (Some(target_schema), false) => schema::use_target_schema(target_schema)?,
+        (None, true) => schema::use_temp_tables()?,
+        (None, false) => {}
+    }
+    let (step, mode) = if resume {
+        match resume_point(sf, children)? {
+            Some(point) => point,
+            None => {
+                return Ok(Some(format!(
+                    "TPC-H SF={sf} already loaded in {children} steps"
+                )))
+            }
+        }
+    } else {
+        (step, StepMode::Alone)
+    };
+    // Every step drops and builds them, they are best rebuilt around loads
+    // in a single step. Loads into mapped tables don't write the TPC-H ones.
+    let dropped = if rebuild_indexes && sf > 0. && mapping.is_none() {
+        Some(indexes::drop_for_load()?)
+    } else {
+        None
+    };
+    let mapped = mapping.is_some();
+    let loaded = load_step(
+        sf,
+        children,
+        step,
+        delimiter,
+        quote,
+        save_rejects,
+        on_repeat,
+        strict,
+        partitioning,
+        mapping,
+        checkpoint,
+        strategy,
+        format,
+        data_dir,
+        freeze,
+        mode,
+        None,
+    )?;
+    if let Some(dropped) = dropped {
+        dropped.restore()?;
+    }
+    // Truncating leaves nothing to register.
+    if let Some(target_schema) = target_schema.filter(|_| sf > 0.) {
+        metadata::register_dataset(target_schema, sf)?;
+    }
+    // Queries right after a load would be planned without statistics, the
+    // step that completes the load gathers them, after adding the keys. Loads
+    // into mapped tables leave the TPC-H tables and their metadata alone.
+    let complete =
+        sf > 0. && !mapped && metadata::incomplete_parts(sf, children as i32)? == Some(vec![]);
+    if complete && constraints != "none" {
+        constraints::add_constraints(constraints == "not_valid")?;
+    }
+    if analyze && complete {
+        Spi::run(&format!("ANALYZE {}", TABLES.join(", ")))?;
+    }
+    Ok(loaded)
+}
+
+/// Returns the step a resumed load of SF=`sf` in `children` steps goes on
+/// with, the first one that didn't finish, or `None` if all did.
+fn resume_point(sf: f64, children: i64) -> spi::Result<Option<(i64, StepMode)>> {
+    Ok(
+        match metadata::incomplete_parts(sf, children as i32)?.as_deref() {
+            None => Some((0, StepMode::Alone)),
+            Some([]) => None,
+            Some([part, ..]) => Some((*part as i64 - 1, StepMode::Resumed)),
+        },
+    )
+}
+
+/// Loads the next table a load of SF=`sf` in `children` steps is missing, in
+/// a resumed load's first unfinished step, and returns what it loaded, or
+/// NULL once the load is complete.
+///
+/// Every call is a small transaction of its own, `tpch_load_proc` calls it
+/// until the load is complete and commits after each table.
+#[pg_extern]
+fn tpch_load_next(
+    sf: default!(f64, 1.),
+    children: default!(i64, 1),
+    strategy: default!(&str, "'auto'"),
+    format: default!(&str, "'csv'"),
+    data_dir: default!(Option<&str>, "NULL"),
+) -> spi::Result<Option<String>> {
+    let Some((step, mode)) = resume_point(sf, children)? else {
+        return Ok(None);
+    };
+    load_step(
+        sf,
+        children,
+        step,
+        ",",
+        "\"",
+        false,
+        "error",
+        false,
+        "uniform",
+        None,
+        "none",
+        strategy,
+        format,
+        data_dir,
+        false,
+        mode,
+        Some(1),
+    )
+}
+
+extension_sql!(
+    r#"
+    CREATE PROCEDURE tpch_load_proc(
+        sf double precision DEFAULT 1,
+        children bigint DEFAULT 1,
+        strategy text DEFAULT 'auto',
+        format text DEFAULT 'csv',
+        data_dir text DEFAULT NULL,
+        resume boolean DEFAULT false,
+        vacuum boolean DEFAULT false
+    ) LANGUAGE plpgsql AS $$
+    DECLARE
+        loaded text;
+    BEGIN
+        IF NOT resume THEN
+            PERFORM tpch_load(0);
+            COMMIT;
+        END IF;
+        LOOP
+            loaded := tpch_load_next(sf, children, strategy, format, data_dir);
+            EXIT WHEN loaded IS NULL;
+            RAISE NOTICE '%', loaded;
+            COMMIT;
+        END LOOP;
+        IF vacuum THEN
+            COMMIT;
+            RAISE NOTICE '%', tpch_vacuum();
+        END IF;
+    END
+    $$;
+    COMMENT ON PROCEDURE tpch_load_proc IS 'loads like tpch_load, all steps, committing after every table; CALL it with resume => true to finish an interrupted load, with vacuum => true to vacuum, freeze and analyze the tables afterwards';
+    "#,
+    name = "load_proc",
+    requires = [tpch_load, tpch_load_next, tpch_vacuum]
+);
+
+/// How a step of a load relates to the other steps.
+#[derive(Clone, Copy, PartialEq)]
+enum StepMode {
+    /// Run on its own, step 0 starting from empty tables.
+    Alone,
+    /// Resuming a load, so even step 0 adds to the rows already loaded.
+    Resumed,
+    /// Run concurrently with the other steps of its load, see [`parallel`]:
+    /// whoever started them empties the tables and marks them dirty before,
+    /// and clean after, all steps ran, so the step does neither.
+    Coordinated,
+}
+
+/// Options of a load recorded in `pg_tpch_load_info`.
+fn load_options(
+    csv: &CsvOptions,
+    partitioning: &str,
+    strategy: LoadStrategy,
+    format: &str,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("delimiter", csv.delimiter.to_string()),
+        ("quote", csv.quote.to_string()),
+        ("partitioning", partitioning.to_string()),
+        ("strategy", strategy.to_string()),
+        ("format", format.to_string()),
+    ]
+}
+
+/// Runs step `step` of a load in `children` steps, see `tpch_load`.
+///
+/// Tables the step already loaded, recorded in `pg_tpch_load_steps`, are
+/// skipped. With `table_limit`, at most that many tables are loaded, and the
+/// step only counts as finished once the last of its tables is.
+#[allow(clippy::too_many_arguments)]
+fn load_step(
+    sf: f64,
+    children: i64,
+    step: i64,
+    delimiter: &str,
+    quote: &str,
+    save_rejects: bool,
+    on_repeat: &str,
+    strict: bool,
+    partitioning: &str,
+    mapping: Option<pgrx::JsonB>,
+    checkpoint: &str,
+    strategy: &str,
+    format: &str,
+    data_dir: Option<&str>,
+    freeze: bool,
+    mode: StepMode,
+    table_limit: Option<usize>,
 ) -> spi::Result<Option<String>> {
     if sf == 0. {
         truncate_tables()?;
         return Ok(Some("TPC-H tables truncated".to_string()));
     }
 
+    check_scale_factor(sf, strict);
+    schema::check_key_range(sf)?;
+
     if children < 1 || step < 0 || step >= children {
         return Err(spi::SpiError::PreparedStatementArgumentMismatch {
             expected: children as usize,
@@ -128,88 +710,450 @@ fn tpch_load(
         });
     }
 
-    if step == 0 {
-        truncate_tables()?;
+    let csv = CsvOptions::new(delimiter, quote).unwrap_or_else(|message| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            message
+        );
+    });
+
+    if !["error", "skip", "replace"].contains(&on_repeat) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!(
+                "unknown on_repeat \"{on_repeat}\", expected \"error\", \"skip\" or \"replace\""
+            )
+        );
     }
 
-    let part = (step + 1) as i32;
-    let num_parts = children as i32;
+    if !["uniform", "auto"].contains(&partitioning) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("unknown partitioning \"{partitioning}\", expected \"uniform\" or \"auto\"")
+        );
+    }
+    let auto_partitioning = partitioning == "auto";
+    // Loading a big table leaves a lot of WAL behind. Checkpointing after
+    // every table spreads the writes out instead of having them pile up
+    // into one checkpoint after lineitem, flushing at least keeps the WAL
+    // writer from falling behind.
+    if !["none", "checkpoint", "flush"].contains(&checkpoint) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!(
+                "unknown checkpoint \"{checkpoint}\", expected \"none\", \"checkpoint\" or \"flush\""
+            )
+        );
+    }
 
-    macro_rules! generate_and_copy_csv_table {
-        ($table_name:expr, $generator:expr, $csv_formatter:ty) => {
-            || -> spi::Result<()> {
-                let dir = PathBuf::from(TPCH_DATA_DIR);
-                fs::create_dir_all(&dir).unwrap();
+    let binary = parse_format(format);
+    if binary && (mapping.is_some() || save_rejects) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "format => 'binary' can't be combined with mapping or save_rejects"
+        );
+    }
+    // COPY FREEZE only goes into tables the transaction truncated, which
+    // the first step of a load does.
+    if freeze && (mapping.is_some() || step != 0 || mode != StepMode::Alone) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "freeze => true needs the TPC-H tables truncated by the same step",
+            "Load in one step, or pass freeze => true to step 0 only, without mapping or resume."
+        );
+    }
 
-                let file_path = dir.join(format!("{}.csv", $table_name));
-                let mut file = fs::File::create(&file_path).unwrap();
+    // User tables are loaded as they are, without the bookkeeping of the
+    // TPC-H tables.
+    if let Some(mapping) = mapping {
+        let mappings = mapping::parse(mapping)?;
+        let dir = spill::data_dir(data_dir);
+        spill::create_dir(&dir);
+        let (part, num_parts) = ((step + 1) as i32, children as i32);
+        mapping::load(
+            &mappings,
+            sf,
+            part,
+            |table| table_parts(table, sf, num_parts, auto_partitioning),
+            &csv,
+            &dir,
+        )?;
+        return Ok(Some(format!(
+            "TPC-H SF={sf} loaded into mapped tables (part {part}/{num_parts})"
+        )));
+    }
 
-                // Write header
-                writeln!(&mut file, "{}", <$csv_formatter>::header()).unwrap();
+    if step == 0 && mode == StepMode::Alone {
+        truncate_tables()?;
+    }
 
-                // Write rows
-                for item in $generator {
-                    writeln!(&mut file, "{}", <$csv_formatter>::new(item)).unwrap();
-                }
+    let dir = spill::data_dir(data_dir);
+    let strategy = LoadStrategy::new(strategy, &dir)?;
+    if binary && strategy == LoadStrategy::Insert {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "format => 'binary' needs a strategy that loads through COPY"
+        );
+    }
+    if freeze && strategy == LoadStrategy::Insert {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "freeze => true needs a strategy that loads through COPY"
+        );
+    }
 
-                let absolute_file_path = fs::canonicalize(&file_path).unwrap();
+    let part = (step + 1) as i32;
+    let num_parts = children as i32;
+    // Fail before generating anything if the files won't fit.
+    if strategy.uses_files() {
+        let lineitem_parts = table_parts("lineitem", sf, num_parts, auto_partitioning);
+        for table in TABLES {
+            let parts = table_parts(table, sf, num_parts, auto_partitioning);
+            if part > parts {
+                continue;
+            }
+            let spilled: &[&str] = if table == "orders" && lineitem_parts == parts {
+                &["orders", "lineitem"]
+            } else {
+                &[table]
+            };
+            spill::check_projected_size(spilled, sf, parts);
+        }
+    }
 
-                let copy_query = format!(
-                    "COPY {} FROM '{}' WITH (FORMAT csv, HEADER true, DELIMITER ',')",
-                    $table_name,
-                    absolute_file_path.display()
-                );
+    let started = Instant::now();
+    let load_id = metadata::find_or_begin_load(
+        sf,
+        num_parts,
+        &load_options(&csv, partitioning, strategy, format),
+    )?;
 
-                Spi::run(&copy_query)?;
+    // Step 0 always starts from empty tables, later steps may run twice.
+    let repeated = metadata::part_completed(load_id, part)?;
+    if repeated {
+        match on_repeat {
+            "skip" => {
+                return Ok(Some(format!(
+                    "TPC-H SF={sf} part {part}/{num_parts} already loaded, skipped"
+                )))
+            }
+            "replace" => {
+                delete_part(sf, part, num_parts, auto_partitioning)?;
+                metadata::forget_tables(load_id, part)?;
+            }
+            _ => {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+                    format!("TPC-H SF={sf} part {part}/{num_parts} is already loaded"),
+                    "Pass on_repeat => 'skip' or on_repeat => 'replace' to run it again."
+                );
+            }
+        }
+    }
+    if mode != StepMode::Coordinated {
+        metadata::mark_tables(true)?;
+    }
+    events::emit(
+        "load_start",
+        &[
+            ("load_id", load_id.into()),
+            ("sf", sf.into()),
+            ("part", part.into()),
+            ("parts", num_parts.into()),
+            ("strategy", strategy.to_string().as_str().into()),
+        ],
+    );
 
-                fs::remove_file(&file_path).unwrap();
+    if strategy.uses_files() {
+        spill::create_dir(&dir);
+    }
+    // Tables of this step that made it in before, in a step that committed
+    // them but failed later, aren't loaded twice.
+    let done = metadata::completed_tables(load_id, part)?;
+    let mut step_tables: Vec<(&'static str, i32, i32)> = TABLES
+        .iter()
+        .map(|&table| {
+            (
+                table,
+                part,
+                table_parts(table, sf, num_parts, auto_partitioning),
+            )
+        })
+        .filter(|&(table, part, parts)| part <= parts && !done.iter().any(|t| t == table))
+        .collect();
+    let remaining = step_tables.len();
+    step_tables.truncate(table_limit.unwrap_or(remaining));
+    progress::begin(sf, &step_tables);
+    throttle::begin();
 
-                Ok(())
-            }()
+    // With the same parts, lineitem is generated along with orders and
+    // waits here until its turn to be loaded.
+    let mut cogenerated: Option<(spill::SpillFile, u64, u64)> = None;
+    let loader = Loader {
+        strategy,
+        dir: &dir,
+        csv: &csv,
+        binary,
+        save_rejects,
+        freeze,
+        chunk_bytes: guc::spill_chunk_bytes(),
+    };
+    for &(table, _, parts) in &step_tables {
+        events::emit(
+            "table_start",
+            &[
+                ("load_id", load_id.into()),
+                ("table", table.into()),
+                ("part", part.into()),
+                ("parts", parts.into()),
+            ],
+        );
+        let table_started = Instant::now();
+        let timer = timing::TableTimer::start();
+        let size_before = table_size(table)?;
+        progress::start_table(table);
+        let generation = alloc::PeakTracker::start();
+        let stats = match cogenerated.take() {
+            Some((spill, rows, peak_memory)) if table == "lineitem" => {
+                let stats = metadata::TableStats {
+                    table,
+                    rows,
+                    file_bytes: fs::metadata(&spill.path)
+                        .unwrap_or_else(|e| {
+                            LoadError::new(table, part, parts, e)
+                                .at(&spill.path)
+                                .report()
+                        })
+                        .len(),
+                    peak_memory,
+                    table_bytes: None,
+                    timing: None,
+                };
+                loader.copy_spilled(&spill, table);
+                stats
+            }
+            // Chunks of orders and lineitem couldn't be cut at the same
+            // order, so the two are only generated together in whole files.
+            _ if table == "orders"
+                && step_tables.iter().any(|&(t, _, _)| t == "lineitem")
+                && loader.strategy.uses_files()
+                && loader.chunk_bytes.is_none()
+                && table_parts("lineitem", sf, num_parts, auto_partitioning) == parts =>
+            {
+                let spill = spill::SpillFile::new(&dir, table).binary(binary);
+                let lineitem = spill::SpillFile::new(&dir, "lineitem").binary(binary);
+                let columns = binary.then(|| binary_columns(table)).transpose()?;
+                let lineitem_columns = binary.then(|| binary_columns("lineitem")).transpose()?;
+                let (rows, lineitem_rows) = timing::generating(|| {
+                    spill.write(|orders| {
+                        lineitem.write(|lines| match (columns, lineitem_columns) {
+                            (Some(columns), Some(lineitem_columns)) => cogen::write_rows(
+                                sf,
+                                part,
+                                parts,
+                                BinaryWriter::new(orders, columns),
+                                BinaryWriter::new(lines, lineitem_columns),
+                            ),
+                            _ => cogen::write_rows(
+                                sf,
+                                part,
+                                parts,
+                                CsvWriter::new(orders, &csv),
+                                CsvWriter::new(lines, &csv),
+                            ),
+                        })
+                    })
+                })
+                .unwrap_or_else(|e| LoadError::new(table, part, parts, e).report());
+                let peak_memory = generation.peak();
+                progress::generated("lineitem", lineitem_rows);
+                cogenerated = Some((lineitem, lineitem_rows, peak_memory));
+                let stats = metadata::TableStats {
+                    table,
+                    rows,
+                    file_bytes: fs::metadata(&spill.path)
+                        .unwrap_or_else(|e| {
+                            LoadError::new(table, part, parts, e)
+                                .at(&spill.path)
+                                .report()
+                        })
+                        .len(),
+                    peak_memory,
+                    table_bytes: None,
+                    timing: None,
+                };
+                loader.copy_spilled(&spill, table);
+                stats
+            }
+            _ => {
+                let (rows, file_bytes) = loader.load(table, sf, part, parts)?;
+                metadata::TableStats {
+                    table,
+                    rows,
+                    file_bytes,
+                    peak_memory: generation.peak(),
+                    table_bytes: None,
+                    timing: None,
+                }
+            }
         };
+        // Lineitem generated along with orders counts for orders.
+        let (generation_time, copy_time) = timer.split();
+        let stats = metadata::TableStats {
+            table_bytes: Some(table_size(table)?.saturating_sub(size_before)),
+            timing: Some((generation_time, copy_time)),
+            ..stats
+        };
+        metadata::record_table_stats(load_id, part, &stats)?;
+        metadata::complete_table(load_id, part, table)?;
+        progress::finish_table(table, stats.rows, stats.file_bytes);
+        events::emit(
+            "table_end",
+            &[
+                ("load_id", load_id.into()),
+                ("table", table.into()),
+                ("part", part.into()),
+                ("rows", stats.rows.into()),
+                (
+                    "duration_ms",
+                    (table_started.elapsed().as_secs_f64() * 1000.).into(),
+                ),
+                (
+                    "generation_ms",
+                    (generation_time.as_secs_f64() * 1000.).into(),
+                ),
+                ("copy_ms", (copy_time.as_secs_f64() * 1000.).into()),
+            ],
+        );
+
+        match checkpoint {
+            "checkpoint" => Spi::run("CHECKPOINT")?,
+            // SAFETY: flushing up to the current insert position only waits
+            // for WAL that has already been inserted.
+            "flush" => unsafe { pg_sys::XLogFlush(pg_sys::GetXLogInsertRecPtr()) },
+            _ => {}
+        }
     }
 
-    generate_and_copy_csv_table!(
-        "region",
-        RegionGenerator::new(sf, part, num_parts),
-        RegionCsv
-    )?;
-    generate_and_copy_csv_table!(
-        "nation",
-        NationGenerator::new(sf, part, num_parts),
-        NationCsv
-    )?;
-    generate_and_copy_csv_table!("part", PartGenerator::new(sf, part, num_parts), PartCsv)?;
-    generate_and_copy_csv_table!(
-        "supplier",
-        SupplierGenerator::new(sf, part, num_parts),
-        SupplierCsv
-    )?;
-    generate_and_copy_csv_table!(
-        "partsupp",
-        PartSuppGenerator::new(sf, part, num_parts),
-        PartSuppCsv
-    )?;
-    generate_and_copy_csv_table!(
-        "customer",
-        CustomerGenerator::new(sf, part, num_parts),
-        CustomerCsv
-    )?;
-    generate_and_copy_csv_table!("orders", OrderGenerator::new(sf, part, num_parts), OrderCsv)?;
-    generate_and_copy_csv_table!(
-        "lineitem",
-        LineItemGenerator::new(sf, part, num_parts),
-        LineItemCsv
-    )?;
+    if step_tables.len() < remaining {
+        let loaded: Vec<&str> = step_tables.iter().map(|&(table, _, _)| table).collect();
+        return Ok(Some(format!(
+            "TPC-H SF={sf} {} loaded (part {part}/{num_parts}, strategy {strategy})",
+            loaded.join(" and ")
+        )));
+    }
+    if mode != StepMode::Coordinated {
+        metadata::mark_tables(false)?;
+    }
+    metadata::complete_part(load_id, part, started.elapsed().as_secs_f64())?;
+    events::emit(
+        "load_end",
+        &[
+            ("load_id", load_id.into()),
+            ("part", part.into()),
+            (
+                "duration_ms",
+                (started.elapsed().as_secs_f64() * 1000.).into(),
+            ),
+        ],
+    );
 
     Ok(Some(format!(
-        "TPC-H SF={} loaded (part {}/{})",
+        "TPC-H SF={} loaded (part {}/{}, strategy {})",
         sf,
         step + 1,
-        children
+        children,
+        strategy
     )))
 }
 
+/// Loads part `step + 1` of `children` of `table_name` alone, the way
+/// `tpch_load` loads it, and returns the number of rows.
+///
+/// Step 0 empties the table first. The other tables and the load history are
+/// left alone, so a table changed by an experiment can be regenerated without
+/// loading everything again. The table is marked dirty until the step that
+/// loads its last part has its rows in, so that step must run last.
+/// `nation` and `region` are loaded by step 0 only.
+#[pg_extern]
+fn tpch_load_table(
+    table_name: &str,
+    sf: default!(f64, 1.),
+    children: default!(i64, 1),
+    step: default!(i64, 0),
+    strategy: default!(&str, "'auto'"),
+    format: default!(&str, "'csv'"),
+    data_dir: default!(Option<&str>, "NULL"),
+) -> spi::Result<i64> {
+    ingest::check_table(table_name);
+    check_scale_factor(sf, false);
+    schema::check_key_range(sf)?;
+    if !(1..=i32::MAX as i64).contains(&children) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!(
+                "children must be between 1 and {}, got {children}",
+                i32::MAX
+            )
+        );
+    }
+    if step < 0 || step >= children {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("step must be between 0 and {}, got {step}", children - 1)
+        );
+    }
+    let (children, step) = (children as i32, step as i32);
+    let binary = parse_format(format);
+    let dir = spill::data_dir(data_dir);
+    let strategy = LoadStrategy::new(strategy, &dir)?;
+    if binary && strategy == LoadStrategy::Insert {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "format => 'binary' needs a strategy that loads through COPY"
+        );
+    }
+    if strategy.uses_files() {
+        spill::create_dir(&dir);
+    }
+
+    if step == 0 {
+        Spi::run(&format!("TRUNCATE TABLE {table_name}"))?;
+    }
+    let parts = table_parts(table_name, sf, children, false);
+    if step >= parts {
+        return Ok(0);
+    }
+    metadata::mark_table(table_name, true)?;
+    let csv = CsvOptions::default();
+    let loader = Loader {
+        strategy,
+        dir: &dir,
+        csv: &csv,
+        binary,
+        save_rejects: false,
+        freeze: false,
+        chunk_bytes: guc::spill_chunk_bytes(),
+    };
+    let (rows, _) = loader.load(table_name, sf, step + 1, parts)?;
+    if step + 1 == parts {
+        metadata::mark_table(table_name, false)?;
+    }
+    Ok(rows as i64)
+}
+
 #[pg_extern]
 fn tpch_queries() -> Vec<String> {
     queries::QUERIES
@@ -231,13 +1175,1758 @@ fn tpch_query(query_nr: i32) -> spi::Result<String> {
 #[pg_schema]
 mod tests {
     use pgrx::prelude::*;
+    use pgrx::spi;
 
     #[pg_test]
     fn test_tpch_load_truncate() {
-        let result = crate::tpch_load(0.0, 1, 0).unwrap();
+        let result = Spi::get_one::<String>("SELECT tpch_load(0)").unwrap();
         assert_eq!(result, Some("TPC-H tables truncated".to_string()));
     }
 
+    /// Loads SF=0.1 with the given dialect, exports every table back through
+    /// `COPY TO` with the same options and expects the generated bytes.
+    fn assert_csv_round_trip(delimiter: &str, quote: &str) {
+        let csv = crate::format::CsvOptions::new(delimiter, quote).unwrap();
+        Spi::run(&format!(
+            "SELECT tpch_load(0.1, delimiter => {}, quote => {})",
+            spi::quote_literal(delimiter),
+            spi::quote_literal(quote)
+        ))
+        .unwrap();
+
+        let dir = crate::spill::data_dir(None);
+        crate::spill::create_dir(&dir);
+
+        for table in crate::TABLES {
+            let expected_path = dir.join(format!("{table}.expected.csv"));
+            let actual_path = dir.join(format!("{table}.actual.csv"));
+            let expected = std::fs::File::create(&expected_path).unwrap();
+            crate::write_table_csv(table, 0.1, 1, 1, std::io::BufWriter::new(expected), &csv)
+                .unwrap();
+
+            // Casting to text strips the blank padding of character(n) columns.
+            let columns = Spi::get_one::<String>(&format!(
+                "SELECT string_agg(quote_ident(attname) || '::text', ', ' ORDER BY attnum) \
+                 FROM pg_attribute \
+                 WHERE attrelid = '{table}'::regclass AND attnum > 0 AND NOT attisdropped"
+            ))
+            .unwrap()
+            .unwrap();
+            Spi::run(&format!(
+                "COPY (SELECT {columns} FROM {table} ORDER BY ctid) TO {} WITH ({})",
+                spi::quote_literal(actual_path.display().to_string()),
+                crate::copy_options(&csv)
+            ))
+            .unwrap();
+
+            let expected = std::fs::read(&expected_path).unwrap();
+            let actual = std::fs::read(&actual_path).unwrap();
+            std::fs::remove_file(&expected_path).unwrap();
+            std::fs::remove_file(&actual_path).unwrap();
+            assert!(
+                expected == actual,
+                "{table} did not round-trip with delimiter {delimiter:?} and quote {quote:?}"
+            );
+        }
+    }
+
+    #[pg_test]
+    fn test_csv_round_trip_default_dialect() {
+        assert_csv_round_trip(",", "\"");
+    }
+
+    #[pg_test]
+    fn test_csv_round_trip_custom_dialect() {
+        // TPC-H comments contain semicolons, so most of them end up quoted.
+        assert_csv_round_trip(";", "'");
+    }
+
+    #[pg_test(error = "delimiter and quote must be different, got ',' for both")]
+    fn test_tpch_load_rejects_ambiguous_dialect() {
+        Spi::run("SELECT tpch_load(0.01, delimiter => ',', quote => ',')").unwrap();
+    }
+
+    #[pg_test(
+        error = "could not load \"/tmp/pg_tpch_data/region.csv\" into region: value too long for type character varying(10)"
+    )]
+    fn test_tpch_load_reports_rejected_row() {
+        Spi::run("ALTER TABLE region ALTER COLUMN r_comment TYPE varchar(10)").unwrap();
+        Spi::run("SELECT tpch_load(0.01, save_rejects => true)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_load_records_load_info() {
+        Spi::run("SELECT tpch_load(0.01, 2, 0)").unwrap();
+        let partial = Spi::get_two::<bool, bool>(
+            "SELECT parts_completed = '{1}', completed_at IS NULL FROM pg_tpch_dataset",
+        )
+        .unwrap();
+        assert_eq!(partial, (Some(true), Some(true)));
+
+        Spi::run("SELECT tpch_load(0.01, 2, 1)").unwrap();
+        let complete = Spi::get_three::<f64, bool, i64>(
+            "SELECT sf, completed_at IS NOT NULL, count(*) OVER () FROM pg_tpch_dataset",
+        )
+        .unwrap();
+        assert_eq!(complete, (Some(0.01), Some(true), Some(1)));
+
+        Spi::run("SELECT tpch_load(0)").unwrap();
+        let current = Spi::get_one::<i64>("SELECT count(*) FROM pg_tpch_dataset").unwrap();
+        assert_eq!(current, Some(0));
+    }
+
+    #[pg_test(error = "SF=0.01 is not a scale factor allowed by the TPC-H specification")]
+    fn test_tpch_load_strict_scale_factor() {
+        Spi::run("SELECT tpch_load(0.01, strict => true)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_load_records_spec_compliance() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let compliant = Spi::get_one::<bool>("SELECT spec_compliant FROM pg_tpch_dataset").unwrap();
+        assert_eq!(compliant, Some(false));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_records_table_stats() {
+        Spi::run("SET pg_tpch.generation_buffer = '64kB'").unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let stats = Spi::get_three::<i64, i64, bool>(
+            "SELECT count(*), sum(rows), bool_and(file_bytes > 0 AND peak_memory > 0) \
+             FROM pg_tpch_load_stats JOIN pg_tpch_dataset USING (load_id)",
+        )
+        .unwrap();
+        assert_eq!(stats, (Some(8), Some(86_805), Some(true)));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_analyzes() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let reltuples =
+            Spi::get_one::<f32>("SELECT reltuples FROM pg_class WHERE oid = 'lineitem'::regclass");
+        assert_eq!(reltuples, Ok(Some(60_175.)));
+    }
+
+    #[pg_test(error = "vacuuming needs the TPC-H tables unlocked")]
+    fn test_tpch_vacuum_after_load_in_transaction() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT tpch_vacuum()").unwrap();
+    }
+
+    #[pg_test(error = "tpch_prewarm needs the pg_prewarm extension")]
+    fn test_tpch_prewarm_needs_pg_prewarm() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT tpch_run_all(prewarm => true)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_load_records_timing() {
+        Spi::run("SELECT tpch_load(0.01, strategy => 'stream')").unwrap();
+        let timed = Spi::get_two::<bool, bool>(
+            "SELECT bool_and(generation_ms >= 0 AND copy_ms >= 0), \
+                    bool_or(table_name = 'lineitem' AND generation_ms > 0 AND copy_ms > 0) \
+             FROM pg_tpch_load_stats JOIN pg_tpch_dataset USING (load_id)",
+        )
+        .unwrap();
+        assert_eq!(timed, (Some(true), Some(true)));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_throughput() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let total = Spi::get_three::<i64, bool, bool>(
+            "SELECT rows, rows_per_sec > 0, mb_per_sec > 0 FROM pg_tpch_load_throughput \
+             WHERE table_name IS NULL",
+        )
+        .unwrap();
+        assert_eq!(total, (Some(86_805), Some(true), Some(true)));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_throttled() {
+        Spi::run("SET pg_tpch.max_load_rows_per_sec = 50000").unwrap();
+        let started = std::time::Instant::now();
+        let loaded = Spi::get_one::<String>("SELECT tpch_load(0.01)")
+            .unwrap()
+            .unwrap();
+        assert!(loaded.ends_with("strategy stream)"), "{loaded}");
+        // 86,805 rows at 50,000 a second.
+        assert!(started.elapsed().as_secs_f64() >= 1.7);
+    }
+
+    #[pg_test]
+    fn test_tpch_load_estimate() {
+        let estimate = Spi::get_three::<i64, i64, bool>(
+            "SELECT sum(rows)::bigint, (sum(table_bytes) / 1e9)::bigint, sum(load_seconds) > 0 \
+             FROM tpch_load_estimate(100)",
+        )
+        .unwrap();
+        assert_eq!(estimate, (Some(866_037_932), Some(134), Some(true)));
+    }
+
+    #[pg_test]
+    fn test_tpch_scale_up() {
+        let checksums = "SELECT (SELECT md5(string_agg(c::text, ',' ORDER BY c_custkey)) FROM customer c), \
+                                (SELECT md5(string_agg(l::text, ',' ORDER BY l_orderkey, l_linenumber)) \
+                                 FROM lineitem l)";
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT tpch_scale_up(0.03)").unwrap();
+        let scaled = Spi::get_two::<String, String>(checksums).unwrap();
+        let sf = Spi::get_one::<f64>("SELECT sf FROM pg_tpch_dataset");
+        assert_eq!(sf, Ok(Some(0.03)));
+        Spi::run("SELECT tpch_load(0.03)").unwrap();
+        assert_eq!(scaled, Spi::get_two::<String, String>(checksums).unwrap());
+    }
+
+    #[pg_test]
+    fn test_cogenerated_orders_match_tpchgen() {
+        use tpchgen::generators::{LineItemGenerator, OrderGenerator};
+
+        let mut orders = OrderGenerator::new(0.01, 2, 3).iter();
+        let mut lineitems = LineItemGenerator::new(0.01, 2, 3).iter();
+        crate::cogen::generate(0.01, 2, 3, |order, lines| {
+            assert_eq!(order, &orders.next().unwrap());
+            for line in lines {
+                assert_eq!(line, &lineitems.next().unwrap());
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert!(orders.next().is_none() && lineitems.next().is_none());
+    }
+
+    #[pg_test]
+    fn test_tpch_load_with_compressed_spill() {
+        Spi::run("SET pg_tpch.spill_compression = 'gzip'").unwrap();
+        Spi::run("SET pg_tpch.spill_compression_level = 6").unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let rows = Spi::get_one::<i64>("SELECT count(*) FROM lineitem").unwrap();
+        assert_eq!(rows, Some(60_175));
+    }
+
+    #[pg_test(error = "gzip compression level must be at most 9, got 12")]
+    fn test_tpch_load_rejects_invalid_compression_level() {
+        Spi::run("SET pg_tpch.spill_compression = 'gzip'").unwrap();
+        Spi::run("SET pg_tpch.spill_compression_level = 12").unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+    }
+
+    #[pg_test(
+        error = "spilling orders and lineitem would take about 9MB, more than pg_tpch.max_temp_bytes (4MB)"
+    )]
+    fn test_tpch_load_rejects_projected_temp_bytes() {
+        Spi::run("SET pg_tpch.max_temp_bytes = '4MB'").unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+    }
+
+    #[pg_test(
+        error = "spill files exceeded pg_tpch.max_temp_bytes (1MB) while writing \"lineitem.csv.gz\""
+    )]
+    fn test_tpch_load_stops_at_max_temp_bytes() {
+        // Compressed files are only checked while written.
+        Spi::run("SET pg_tpch.spill_compression = 'gzip'").unwrap();
+        Spi::run("SET pg_tpch.max_temp_bytes = '1MB'").unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_ci() {
+        let (passed, failures) =
+            Spi::get_two::<bool, Vec<String>>("SELECT * FROM tpch_ci()").unwrap();
+        assert_eq!(failures, Some(vec![]));
+        assert_eq!(passed, Some(true));
+    }
+
+    #[pg_test(error = "no answers for SF=0.5, tpch_ci supports SF in [0.01, 1.0]")]
+    fn test_tpch_ci_rejects_scale_factor_without_answers() {
+        Spi::run("SELECT * FROM tpch_ci(0.5)").unwrap();
+    }
+
+    #[pg_test(error = "unknown option \"step\"")]
+    fn test_tpch_provision_rejects_unknown_option() {
+        Spi::run("SELECT tpch_provision('benchmark', 0.01, '{\"step\": 1}')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_read_only_query_15() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SET transaction_read_only = on").unwrap();
+        let query = crate::runner::read_only(crate::queries::QUERIES[14].1);
+        let (_, rows, _) = crate::runner::execute(&query).unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    #[pg_test(error = "tpch_run_replica must run on a standby")]
+    fn test_tpch_run_replica_requires_standby() {
+        Spi::run("SELECT * FROM tpch_run_replica()").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_tuning_advice() {
+        let (settings, statements) = Spi::get_two::<Vec<String>, bool>(
+            "SELECT array_agg(setting), bool_and(statement LIKE 'ALTER SYSTEM SET %') \
+             FROM tpch_tuning_advice(100)",
+        )
+        .unwrap();
+        // The test server runs with the default 128MB of shared_buffers.
+        assert!(settings.unwrap().contains(&"shared_buffers".to_string()));
+        assert_eq!(statements, Some(true));
+    }
+
+    #[pg_test(
+        error = "exporting to files on the server needs the privileges of the pg_write_server_files role"
+    )]
+    fn test_tpch_export_unprivileged() {
+        Spi::run("CREATE ROLE pg_tpch_unprivileged").unwrap();
+        Spi::run("SET LOCAL ROLE pg_tpch_unprivileged").unwrap();
+        Spi::run("SELECT * FROM tpch_export('/tmp/pg_tpch_unprivileged', sf => 0.01)").unwrap();
+    }
+
+    #[pg_test(
+        error = "compressing spill files with gzip needs the privileges of the pg_execute_server_program role"
+    )]
+    fn test_spill_compression_unprivileged() {
+        Spi::run("CREATE ROLE pg_tpch_no_programs").unwrap();
+        Spi::run("GRANT pg_read_server_files, pg_write_server_files TO pg_tpch_no_programs")
+            .unwrap();
+        Spi::run("GRANT ALL ON ALL TABLES IN SCHEMA public TO pg_tpch_no_programs").unwrap();
+        Spi::run("SET LOCAL pg_tpch.spill_compression = 'gzip'").unwrap();
+        Spi::run("SET LOCAL ROLE pg_tpch_no_programs").unwrap();
+        Spi::run("SELECT tpch_load_table('region', 0.01, strategy => 'file')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_demo() {
+        Spi::run("SELECT tpch_demo()").unwrap();
+        let counts = Spi::get_three::<i64, i64, i64>(
+            "SELECT (SELECT count(*) FROM orders), \
+                    (SELECT count(*) FROM lineitem \
+                     WHERE l_orderkey NOT IN (SELECT o_orderkey FROM orders)), \
+                    (SELECT count(*) FROM orders \
+                     WHERE o_custkey NOT IN (SELECT c_custkey FROM customer))",
+        )
+        .unwrap();
+        assert_eq!(counts, (Some(150), Some(0), Some(0)));
+        let state = Spi::get_one::<String>("SELECT tpch_dataset_state()").unwrap();
+        assert_eq!(state.as_deref(), Some("complete"));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_strategies() {
+        let message = Spi::get_one::<String>("SELECT tpch_load(0.01)").unwrap();
+        assert_eq!(
+            message.as_deref(),
+            Some("TPC-H SF=0.01 loaded (part 1/1, strategy file)")
+        );
+        Spi::run("SELECT tpch_load(0.01, strategy => 'insert')").unwrap();
+        let counts = Spi::get_two::<i64, i64>(
+            "SELECT (SELECT count(*) FROM lineitem), (SELECT count(*) FROM partsupp)",
+        )
+        .unwrap();
+        assert_eq!(counts, (Some(60_175), Some(8_000)));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_stream() {
+        let digest = "SELECT (SELECT count(*) FROM lineitem), \
+                             (SELECT sum(l_extendedprice)::text FROM lineitem), \
+                             (SELECT max(c_comment) FROM customer)";
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let from_file = Spi::get_three::<i64, String, String>(digest).unwrap();
+        let message = Spi::get_one::<String>("SELECT tpch_load(0.01, strategy => 'stream')");
+        assert_eq!(
+            message.unwrap().as_deref(),
+            Some("TPC-H SF=0.01 loaded (part 1/1, strategy stream)")
+        );
+        let streamed = Spi::get_three::<i64, String, String>(digest).unwrap();
+        assert_eq!(streamed, from_file);
+    }
+
+    #[pg_test]
+    fn test_tpch_load_binary() {
+        let digest = "SELECT (SELECT count(*) FROM lineitem), \
+                             (SELECT sum(l_extendedprice - l_discount)::text FROM lineitem), \
+                             (SELECT max(o_orderdate)::text || min(s_acctbal) FROM orders, supplier)";
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let csv = Spi::get_three::<i64, String, String>(digest).unwrap();
+        for strategy in ["file", "stream"] {
+            Spi::run(&format!(
+                "SELECT tpch_load(0.01, strategy => '{strategy}', format => 'binary')"
+            ))
+            .unwrap();
+            let binary = Spi::get_three::<i64, String, String>(digest).unwrap();
+            assert_eq!(binary, csv, "strategy {strategy}");
+        }
+    }
+
+    #[pg_test]
+    fn test_tpch_load_data_dir() {
+        let dir = format!("{}/elsewhere", crate::spill::data_dir(None).display());
+        let _ = std::fs::remove_dir_all(&dir);
+        Spi::run(&format!(
+            "SET LOCAL pg_tpch.data_dir = {}",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        Spi::run("SELECT tpch_load(0.01, strategy => 'file')").unwrap();
+        assert!(std::path::Path::new(&dir).is_dir());
+        let rows = Spi::get_one::<i64>("SELECT count(*) FROM lineitem").unwrap();
+        assert_eq!(rows, Some(60_175));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_in_chunks() {
+        let digest = "SELECT (SELECT count(*) FROM lineitem), \
+                             (SELECT sum(o_totalprice)::text FROM orders), \
+                             (SELECT max(ps_comment) FROM partsupp)";
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let whole = Spi::get_three::<i64, String, String>(digest).unwrap();
+        Spi::run("SET LOCAL pg_tpch.spill_chunk_size = '64kB'").unwrap();
+        for format in ["csv", "binary"] {
+            Spi::run(&format!(
+                "SELECT tpch_load(0.01, strategy => 'file', format => '{format}')"
+            ))
+            .unwrap();
+            let chunked = Spi::get_three::<i64, String, String>(digest).unwrap();
+            assert_eq!(chunked, whole, "format {format}");
+        }
+    }
+
+    #[pg_test]
+    fn test_tpch_load_table() {
+        let digest = "SELECT (SELECT count(*) FROM orders), \
+                             (SELECT sum(o_totalprice)::text FROM orders), \
+                             (SELECT count(*)::text FROM lineitem)";
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let loaded = Spi::get_three::<i64, String, String>(digest).unwrap();
+        let rows = Spi::get_one::<i64>("SELECT tpch_load_table('orders', 0.01, 2, 0)").unwrap();
+        assert_eq!(rows, Some(7_500));
+        let (half, state) = Spi::get_two::<i64, String>(
+            "SELECT (SELECT count(*) FROM orders), tpch_dataset_state()",
+        )
+        .unwrap();
+        assert_eq!((half, state.as_deref()), (Some(7_500), Some("dirty")));
+        Spi::run("SELECT tpch_load_table('orders', 0.01, 2, 1)").unwrap();
+        let reloaded = Spi::get_three::<i64, String, String>(digest).unwrap();
+        assert_eq!(reloaded, loaded);
+        let state = Spi::get_one::<String>("SELECT tpch_dataset_state()").unwrap();
+        assert_eq!(state.as_deref(), Some("complete"));
+    }
+
+    #[pg_test(error = "children must be between 1 and 2147483647, got 4294967297")]
+    fn test_tpch_load_table_too_many_children() {
+        Spi::run("SELECT tpch_load_table('orders', 0.01, 4294967297, 1)").unwrap();
+    }
+
+    #[pg_test(error = "data directory must be an absolute path, got \"relative\"")]
+    fn test_tpch_load_relative_data_dir() {
+        Spi::run("SELECT tpch_load(0.01, data_dir => 'relative')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_load_with_checkpoints() {
+        Spi::run("SELECT tpch_load(0.01, checkpoint => 'checkpoint')").unwrap();
+        Spi::run("SELECT tpch_load(0.01, checkpoint => 'flush')").unwrap();
+        let rows = Spi::get_one::<i64>("SELECT count(*) FROM lineitem").unwrap();
+        assert_eq!(rows, Some(60_175));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_auto_partitioning() {
+        for step in 0..4 {
+            Spi::run(&format!(
+                "SELECT tpch_load(0.01, 4, {step}, partitioning => 'auto')"
+            ))
+            .unwrap();
+        }
+        let counts = Spi::get_three::<i64, i64, i64>(
+            "SELECT (SELECT count(*) FROM nation), (SELECT count(*) FROM supplier), \
+                    (SELECT count(*) FROM lineitem)",
+        )
+        .unwrap();
+        assert_eq!(counts, (Some(25), Some(100), Some(60_175)));
+        let supplier_parts = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_tpch_load_stats WHERE table_name = 'supplier'",
+        )
+        .unwrap();
+        assert_eq!(supplier_parts, Some(1));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_into_mapped_table() {
+        Spi::run("CREATE TABLE app_countries (id int PRIMARY KEY, name text, created_at timestamptz DEFAULT now())")
+            .unwrap();
+        Spi::run(
+            r#"SELECT tpch_load(0.01, mapping => '{"nation": {"table": "app_countries",
+                   "columns": {"n_nationkey": "id", "n_name": "name"}}}')"#,
+        )
+        .unwrap();
+        let (mapped, nation) = Spi::get_two::<i64, i64>(
+            "SELECT (SELECT count(*) FROM app_countries WHERE name = 'GERMANY'), \
+                    (SELECT count(*) FROM nation)",
+        )
+        .unwrap();
+        assert_eq!((mapped, nation), (Some(1), Some(0)));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_fixed_size_tables_once() {
+        for step in 0..3 {
+            Spi::run(&format!("SELECT tpch_load(0.01, 3, {step})")).unwrap();
+        }
+        let counts = Spi::get_three::<i64, i64, i64>(
+            "SELECT (SELECT count(*) FROM region), (SELECT count(*) FROM nation), \
+                    (SELECT count(*) FROM supplier)",
+        )
+        .unwrap();
+        assert_eq!(counts, (Some(5), Some(25), Some(100)));
+        let loads = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_tpch_load_stats WHERE table_name = 'nation'",
+        );
+        assert_eq!(loads, Ok(Some(1)));
+
+        let rows = Spi::get_one::<i64>("SELECT tpch_load_table('nation', 0.01, 3, 2)").unwrap();
+        assert_eq!(rows, Some(0));
+    }
+
+    #[pg_test(error = "loading with workers needs the TPC-H tables unlocked")]
+    fn test_tpch_load_parallel_locked() {
+        Spi::run("TRUNCATE lineitem").unwrap();
+        Spi::run("SELECT tpch_load_parallel(0.01, 2)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_pg_tpch_progress() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let progress = Spi::get_three::<i64, bool, bool>(
+            "SELECT count(*), bool_and(estimated_completion IS NOT NULL),
+                    bool_and(rows_loaded = rows_expected) FILTER (WHERE table_name <> 'lineitem')
+             FROM pg_tpch_progress WHERE pid = pg_backend_pid()",
+        );
+        assert_eq!(progress, Ok((Some(8), Some(true), Some(true))));
+    }
+
+    #[pg_test(error = "load job 42 does not exist")]
+    fn test_tpch_load_status_unknown_job() {
+        Spi::run("SELECT * FROM tpch_load_status(42)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_load_tune() {
+        Spi::run("SET maintenance_work_mem = '2GB'").unwrap();
+        Spi::run("SELECT tpch_load(0, tune => true)").unwrap();
+        let setting = |name: &str| {
+            Spi::get_one_with_args::<String>("SELECT current_setting($1)", &[name.into()])
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(setting("synchronous_commit"), "off");
+        // Sizes the session raised itself stay.
+        assert_eq!(setting("maintenance_work_mem"), "2GB");
+    }
+
+    #[pg_test]
+    fn test_tpch_load_freeze() {
+        // COPY FREEZE fails rather than loading unfrozen rows.
+        for strategy in ["stream", "file"] {
+            Spi::run(&format!(
+                "SELECT tpch_load(0.01, strategy => '{strategy}', freeze => true)"
+            ))
+            .unwrap();
+            let rows = Spi::get_one::<i64>("SELECT count(*) FROM lineitem").unwrap();
+            assert_eq!(rows, Some(60175));
+        }
+    }
+
+    #[pg_test(error = "freeze => true needs the TPC-H tables truncated by the same step")]
+    fn test_tpch_load_freeze_later_step() {
+        Spi::run("SELECT tpch_load(0.01, children => 2, step => 1, freeze => true)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_load_resume() {
+        Spi::run("SELECT tpch_load(0.01, 3, 0)").unwrap();
+        for _ in 0..2 {
+            Spi::run("SELECT tpch_load(0.01, 3, resume => true)").unwrap();
+        }
+        let done = Spi::get_one::<String>("SELECT tpch_load(0.01, 3, resume => true)");
+        assert_eq!(
+            done,
+            Ok(Some("TPC-H SF=0.01 already loaded in 3 steps".to_string()))
+        );
+
+        let lineitem = Spi::get_one::<i64>("SELECT count(*) FROM lineitem");
+        assert_eq!(lineitem, Ok(Some(60175)));
+        let steps = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_tpch_load_steps WHERE table_name = 'lineitem'",
+        );
+        assert_eq!(steps, Ok(Some(3)));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_generation_threads() {
+        Spi::run("SET pg_tpch.generation_threads = 4").unwrap();
+        for (strategy, format) in [("stream", "csv"), ("file", "binary")] {
+            Spi::run(&format!(
+                "SELECT tpch_load(0.01, strategy => '{strategy}', format => '{format}')"
+            ))
+            .unwrap();
+            let counts = Spi::get_two::<i64, i64>(
+                "SELECT (SELECT count(*) FROM orders), (SELECT count(*) FROM lineitem)",
+            );
+            assert_eq!(counts, Ok((Some(15000), Some(60175))));
+        }
+    }
+
+    #[pg_test]
+    fn test_tpch_load_next() {
+        let mut loaded = Vec::new();
+        while let Some(message) =
+            Spi::get_one::<String>("SELECT tpch_load_next(0.01, strategy => 'stream')").unwrap()
+        {
+            loaded.push(message);
+        }
+        assert_eq!(loaded.len(), 8);
+        assert_eq!(
+            loaded[0],
+            "TPC-H SF=0.01 region loaded (part 1/1, strategy stream)"
+        );
+
+        let state = Spi::get_one::<String>("SELECT tpch_dataset_state()");
+        assert_eq!(state, Ok(Some("complete".to_string())));
+        let lineitem = Spi::get_one::<i64>("SELECT count(*) FROM lineitem");
+        assert_eq!(lineitem, Ok(Some(60175)));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_repeated_step() {
+        Spi::run("SELECT tpch_load(0.01, 3, 0)").unwrap();
+        Spi::run("SELECT tpch_load(0.01, 3, 1)").unwrap();
+        let loaded = Spi::get_one::<i64>("SELECT count(*) FROM lineitem").unwrap();
+
+        let skipped =
+            Spi::get_one::<String>("SELECT tpch_load(0.01, 3, 1, on_repeat => 'skip')").unwrap();
+        assert_eq!(
+            skipped.as_deref(),
+            Some("TPC-H SF=0.01 part 2/3 already loaded, skipped")
+        );
+        Spi::run("SELECT tpch_load(0.01, 3, 1, on_repeat => 'replace')").unwrap();
+        let replaced = Spi::get_one::<i64>("SELECT count(*) FROM lineitem").unwrap();
+        assert_eq!(replaced, loaded);
+
+        let parts = Spi::get_one::<Vec<i32>>("SELECT parts_completed FROM pg_tpch_dataset")
+            .unwrap()
+            .unwrap();
+        assert_eq!(parts, vec![1, 2]);
+    }
+
+    #[pg_test(error = "TPC-H SF=0.01 part 2/2 is already loaded")]
+    fn test_tpch_load_refuses_repeated_step() {
+        Spi::run("SELECT tpch_load(0.01, 2, 0)").unwrap();
+        Spi::run("SELECT tpch_load(0.01, 2, 1)").unwrap();
+        Spi::run("SELECT tpch_load(0.01, 2, 1)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_dataset_state() {
+        Spi::run("SELECT tpch_load(0)").unwrap();
+        assert_eq!(crate::metadata::tpch_dataset_state().unwrap(), "empty");
+        Spi::run("SELECT tpch_load(0.01, 2, 0)").unwrap();
+        assert_eq!(crate::metadata::tpch_dataset_state().unwrap(), "partial");
+        Spi::run("SELECT tpch_load(0.01, 2, 1)").unwrap();
+        assert_eq!(crate::metadata::tpch_dataset_state().unwrap(), "complete");
+        Spi::run("UPDATE pg_tpch_table_state SET dirty = true WHERE table_name = 'lineitem'")
+            .unwrap();
+        assert_eq!(crate::metadata::tpch_dataset_state().unwrap(), "dirty");
+    }
+
+    #[pg_test(error = "running queries on a partial TPC-H dataset, results are not meaningful")]
+    fn test_partial_dataset_is_refused() {
+        Spi::run("SELECT tpch_load(0.01, 2, 0)").unwrap();
+        Spi::run("SET pg_tpch.on_partial_dataset = 'error'").unwrap();
+        // Fetching the text of a query doesn't touch the tables.
+        crate::tpch_query(1).unwrap();
+        Spi::run("SELECT * FROM tpch_run(1)").unwrap();
+    }
+
+    #[pg_test(error = "running queries on a partial TPC-H dataset, results are not meaningful")]
+    fn test_partial_named_dataset_is_refused() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT tpch_load(0.01, 2, 0, target_schema => 'tpch_half')").unwrap();
+        Spi::run("RESET search_path").unwrap();
+        Spi::run("SET pg_tpch.on_partial_dataset = 'error'").unwrap();
+        Spi::run("SELECT * FROM tpch_run(1)").unwrap();
+        Spi::run("SELECT * FROM tpch_run(1, dataset => 'tpch_half')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_export_strict_matches_dbgen() {
+        let dir = format!("{}/export", crate::spill::data_dir(None).display());
+        let verified = Spi::get_one::<bool>(&format!(
+            "SELECT bool_and(verified) FROM tpch_export({}, 0.01, format => 'tbl', strict => true)",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(verified, Some(true));
+    }
+
+    #[pg_test]
+    fn test_tpch_export_is_deterministic() {
+        let dir = crate::spill::data_dir(None).display().to_string();
+        for run in ["first", "second"] {
+            Spi::run(&format!(
+                "SELECT count(*) FROM tpch_export({}, 0.01)",
+                spi::quote_literal(format!("{dir}/{run}"))
+            ))
+            .unwrap();
+        }
+        for table in crate::TABLES {
+            let first = std::fs::read(format!("{dir}/first/{table}.csv")).unwrap();
+            let second = std::fs::read(format!("{dir}/second/{table}.csv")).unwrap();
+            assert!(first == second, "{table}.csv differs between exports");
+        }
+        std::fs::remove_dir_all(format!("{dir}/first")).unwrap();
+        std::fs::remove_dir_all(format!("{dir}/second")).unwrap();
+    }
+
+    #[pg_test(error = "strict mode requires format => 'tbl' and children => 1")]
+    fn test_tpch_export_strict_rejects_csv() {
+        Spi::run("SELECT * FROM tpch_export('/tmp/pg_tpch_data/export', 0.01, strict => true)")
+            .unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_load_program() {
+        let dir = format!("{}/program", crate::spill::data_dir(None).display());
+        Spi::run(&format!(
+            "SELECT * FROM tpch_export({0}, 0.01); SELECT * FROM tpch_export({0}, 0.01, format => 'tbl')",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        let (region, nation) = Spi::get_two::<i64, i64>(&format!(
+            "SELECT tpch_load_program('region', {}), \
+                    tpch_load_program('nation', {}, format => 'tbl')",
+            spi::quote_literal(format!("cat {dir}/region.csv")),
+            spi::quote_literal(format!("cat {dir}/nation.tbl")),
+        ))
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((region, nation), (Some(5), Some(25)));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_from() {
+        let dir = format!("{}/load_from", crate::spill::data_dir(None).display());
+        Spi::run(&format!(
+            "SELECT * FROM tpch_export({}, 0.01, delimiter => '|')",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        Spi::run(&format!(
+            "SELECT tpch_load_from({})",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (lineitem, state) = Spi::get_two::<i64, String>(
+            "SELECT (SELECT count(*) FROM lineitem), tpch_dataset_state()",
+        )
+        .unwrap();
+        assert_eq!(lineitem, Some(60175));
+        assert_eq!(state.as_deref(), Some("complete"));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_from_dbgen_chunks() {
+        let dir = format!("{}/dbgen_chunks", crate::spill::data_dir(None).display());
+        Spi::run(&format!(
+            "SELECT * FROM tpch_export({0}, 0.01, format => 'tbl', children => 2, step => 0); \
+             SELECT * FROM tpch_export({0}, 0.01, format => 'tbl', children => 2, step => 1)",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        for part in [1, 2] {
+            std::fs::remove_file(format!("{dir}/pg_tpch_manifest.tsv.{part}")).unwrap();
+        }
+        Spi::run(&format!(
+            "SELECT tpch_load_from({})",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (lineitem, sf) = Spi::get_two::<i64, f64>(
+            "SELECT (SELECT count(*) FROM lineitem), (SELECT max(sf) FROM pg_tpch_dataset)",
+        )
+        .unwrap();
+        assert_eq!(lineitem, Some(60175));
+        assert_eq!(sf, Some(0.01));
+    }
+
+    #[pg_test]
+    fn test_tpch_export_dialect_options() {
+        let dir = format!("{}/dialect", crate::spill::data_dir(None).display());
+        Spi::run(&format!(
+            "SELECT * FROM tpch_export({}, 0.01, header => false, null => 'NA', \
+                                       encoding => 'latin1')",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        let first_line = std::fs::read_to_string(format!("{dir}/region.csv")).unwrap();
+        assert!(first_line.starts_with("0,AFRICA,"));
+        let manifest = std::fs::read_to_string(format!("{dir}/pg_tpch_manifest.tsv")).unwrap();
+        assert!(manifest.contains("header\tfalse\nnull\tNA\nencoding\tLATIN1\n"));
+        Spi::run(&format!(
+            "SELECT tpch_load_from({})",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let region = Spi::get_one::<i64>("SELECT count(*) FROM region").unwrap();
+        assert_eq!(region, Some(5));
+    }
+
+    #[pg_test(error = "\"/tmp/pg_tpch_data/corrupt/nation.csv\" does not match the manifest")]
+    fn test_tpch_load_from_detects_corrupt_files() {
+        let dir = format!("{}/corrupt", crate::spill::data_dir(None).display());
+        Spi::run(&format!(
+            "SELECT * FROM tpch_export({}, 0.01)",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        std::fs::write(format!("{dir}/nation.csv"), "n_nationkey\n").unwrap();
+        Spi::run(&format!(
+            "SELECT tpch_load_from({})",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_cleanup() {
+        let dir = format!("{}/leftovers", crate::spill::data_dir(None).display());
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "lineitem.csv",
+            "orders.csv.zst",
+            "notes.txt",
+            "region.rejects.csv",
+        ] {
+            std::fs::write(format!("{dir}/{name}"), "0123456789").unwrap();
+        }
+        let removed = Spi::get_two::<i64, i64>(&format!(
+            "SELECT count(*), sum(bytes)::bigint FROM tpch_cleanup({0}) \
+             WHERE starts_with(path, {0})",
+            spi::quote_literal(&dir)
+        ))
+        .unwrap();
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(removed, (Some(2), Some(20)));
+        assert_eq!(left, ["notes.txt", "region.rejects.csv"]);
+    }
+
+    #[pg_test]
+    fn test_tpch_copy_chunk() {
+        let (chunks, data) = Spi::get_two::<i64, String>(
+            "SELECT count(*), string_agg(chunk, '' ORDER BY n) \
+             FROM tpch_copy_chunk('nation', 0.01, chunk_rows => 10) WITH ORDINALITY c(chunk, n)",
+        )
+        .unwrap();
+        assert_eq!(chunks, Some(3));
+        let lines: Vec<&str> = data.as_deref().unwrap().lines().collect();
+        assert_eq!(lines.len(), 26);
+        assert_eq!(lines[0], "n_nationkey,n_name,n_regionkey,n_comment");
+    }
+
+    #[pg_test]
+    fn test_table_checksum_detects_changes() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let (rows, loaded) = crate::check::table_checksum("nation").unwrap();
+        assert_eq!(rows, 25);
+
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        assert_eq!(crate::check::table_checksum("nation").unwrap().1, loaded);
+
+        Spi::run("UPDATE nation SET n_comment = 'changed' WHERE n_nationkey = 7").unwrap();
+        assert_ne!(crate::check::table_checksum("nation").unwrap().1, loaded);
+    }
+
+    #[pg_test]
+    fn test_tpch_check_schema() {
+        let deviations = Spi::get_one::<i64>("SELECT count(*) FROM tpch_check_schema()").unwrap();
+        assert_eq!(deviations, Some(0));
+
+        Spi::run("ALTER TABLE orders ALTER COLUMN o_comment TYPE text").unwrap();
+        Spi::run("ALTER TABLE orders ADD CONSTRAINT positive CHECK (o_totalprice > 0)").unwrap();
+        let problems = Spi::get_one::<String>(
+            "SELECT string_agg(object_name || ' ' || problem, ', ' ORDER BY problem) \
+             FROM tpch_check_schema()",
+        )
+        .unwrap();
+        assert_eq!(
+            problems.as_deref(),
+            Some("o_comment type differs, positive unexpected constraint")
+        );
+    }
+
+    #[pg_test]
+    fn test_tpch_create_and_drop_indexes() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("CREATE INDEX by_hand ON lineitem (l_partkey)").unwrap();
+        let created = Spi::get_one::<i64>(
+            "SELECT count(*) FROM tpch_create_indexes('brin', pages_per_range => 16)",
+        )
+        .unwrap();
+        assert_eq!(created, Some(4));
+        let dropped = Spi::get_one::<Vec<String>>("SELECT tpch_drop_indexes()").unwrap();
+        assert_eq!(dropped.map(|dropped| dropped.len()), Some(4));
+        let left = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_indexes WHERE tablename IN ('lineitem', 'orders')",
+        )
+        .unwrap();
+        assert_eq!(left, Some(1));
+    }
+
+    #[pg_test]
+    fn test_tpch_create_indexes_extended() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let (minimal, extended) = Spi::get_two::<i64, i64>(
+            "SELECT (SELECT count(*) FROM tpch_create_indexes('minimal')), \
+                    (SELECT count(*) FROM tpch_create_indexes('extended'))",
+        )
+        .unwrap();
+        assert_eq!((minimal, extended), (Some(8), Some(17)));
+        let unique = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_indexes WHERE indexdef LIKE 'CREATE UNIQUE INDEX tpch_%'",
+        )
+        .unwrap();
+        assert_eq!(unique, Some(16));
+        let dropped = Spi::get_one::<Vec<String>>("SELECT tpch_drop_indexes('extended')").unwrap();
+        assert_eq!(dropped.map(|dropped| dropped.len()), Some(17));
+    }
+
+    #[pg_test]
+    fn test_tpch_index_advisor() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let (suggested, queries) = Spi::get_two::<i64, i64>(
+            "SELECT count(DISTINCT index_name), count(DISTINCT query_nr) \
+             FROM tpch_index_advisor('extended', min_benefit => 0.01) \
+             WHERE cost_with < cost_without",
+        )
+        .unwrap();
+        assert!(suggested.unwrap_or_default() > 0);
+        assert!(queries.unwrap_or_default() > 0);
+        let left = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_indexes WHERE indexname LIKE 'tpch_extended_%'",
+        )
+        .unwrap();
+        assert_eq!(left, Some(0));
+    }
+
+    #[pg_test]
+    fn test_tpch_drop_indexes_of_one_profile() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT * FROM tpch_create_indexes('brin')").unwrap();
+        Spi::run("SELECT * FROM tpch_create_indexes('covering')").unwrap();
+        let included = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_indexes WHERE indexdef LIKE '% INCLUDE (%'",
+        )
+        .unwrap();
+        assert_eq!(included, Some(5));
+        Spi::run("SELECT tpch_drop_indexes('covering')").unwrap();
+        let left = Spi::get_one::<String>(
+            "SELECT string_agg(DISTINCT profile, ',') FROM pg_tpch_indexes \
+             WHERE to_regclass(index_name) IS NOT NULL",
+        )
+        .unwrap();
+        assert_eq!(left.as_deref(), Some("brin"));
+    }
+
+    #[pg_test]
+    fn test_tpch_index_usage() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT * FROM tpch_create_indexes('partial')").unwrap();
+        Spi::run("ANALYZE lineitem, orders").unwrap();
+        let (queries, using) = Spi::get_two::<i64, i64>(
+            "SELECT count(*), count(*) FILTER (WHERE cardinality(indexes) > 0) \
+             FROM tpch_index_usage('partial')",
+        )
+        .unwrap();
+        assert_eq!(queries, Some(22));
+        assert!(using.unwrap() > 0);
+    }
+
+    #[pg_test]
+    fn test_tpch_compare_indexes() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let sizes = Spi::get_one::<Vec<i64>>(
+            "SELECT array_agg(index_bytes ORDER BY profile DESC) \
+             FROM tpch_compare_indexes(query_nrs => '{3, 6}')",
+        )
+        .unwrap()
+        .unwrap();
+        // btree first, then brin.
+        assert!(sizes[0] > sizes[1]);
+        let left = Spi::get_one::<i64>("SELECT count(*) FROM pg_tpch_indexes").unwrap();
+        assert_eq!(left, Some(0));
+    }
+
+    #[pg_test]
+    fn test_tpch_set_schema_profile() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let changed =
+            Spi::get_one::<Vec<String>>("SELECT tpch_set_schema_profile('enum')").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(4));
+        let (deviations, profile) = Spi::get_two::<i64, String>(
+            "SELECT (SELECT count(*) FROM tpch_check_schema('enum')), \
+                    (SELECT schema_profile FROM pg_tpch_dataset)",
+        )
+        .unwrap();
+        assert_eq!((deviations, profile.as_deref()), (Some(0), Some("enum")));
+        let rows = Spi::get_one::<i64>("SELECT sum(rows)::bigint FROM tpch_run_set('{1, 4, 12}')");
+        assert!(rows.unwrap().unwrap() > 0);
+
+        Spi::run("SELECT tpch_set_schema_profile('default')").unwrap();
+        let deviations = Spi::get_one::<i64>("SELECT count(*) FROM tpch_check_schema()").unwrap();
+        assert_eq!(deviations, Some(0));
+    }
+
+    #[pg_test]
+    fn test_tpch_schema_info() {
+        let (columns, keys, references) = Spi::get_three::<i64, i64, i64>(
+            "SELECT count(*), count(*) FILTER (WHERE primary_key), \
+                    count(references_table) FROM tpch_schema_info()",
+        )
+        .unwrap();
+        assert_eq!((columns, keys, references), (Some(61), Some(10), Some(9)));
+        let reference = Spi::get_two::<String, String>(
+            "SELECT references_table, references_column FROM tpch_schema_info() \
+             WHERE column_name = 'l_suppkey'",
+        )
+        .unwrap();
+        assert_eq!(
+            reference,
+            (Some("partsupp".to_string()), Some("ps_suppkey".to_string()))
+        );
+    }
+
+    #[pg_test]
+    fn test_tpch_set_generated_columns() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT tpch_run_set('{1, 19}', persist_results => true)").unwrap();
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_generated_columns()").unwrap();
+        assert_eq!(
+            changed,
+            Some(vec![
+                "lineitem.l_charge".to_string(),
+                "lineitem.l_revenue".to_string()
+            ])
+        );
+        Spi::run("SELECT tpch_run_set('{1, 19}', persist_results => true)").unwrap();
+
+        // The second run read the columns and got the same results.
+        let (generated, reads_columns) = Spi::get_two::<bool, bool>(
+            "SELECT bool_and(generated_columns), \
+                    bool_and(plan::text LIKE '%l_revenue%') \
+             FROM pg_tpch_runs JOIN pg_tpch_run_plans USING (run_id) \
+             WHERE run_id = (SELECT max(run_id) FROM pg_tpch_runs)",
+        )
+        .unwrap();
+        assert_eq!((generated, reads_columns), (Some(true), Some(true)));
+        let differing = Spi::get_one::<i64>(
+            "SELECT count(*) FROM unnest('{1, 19}'::int[]) q, tpch_diff_results(
+                 (SELECT min(run_id) FROM pg_tpch_runs), (SELECT max(run_id) FROM pg_tpch_runs), q)",
+        )
+        .unwrap();
+        assert_eq!(differing, Some(0));
+
+        Spi::run("SELECT tpch_set_generated_columns(false)").unwrap();
+        let deviations = Spi::get_one::<i64>("SELECT count(*) FROM tpch_check_schema()").unwrap();
+        assert_eq!(deviations, Some(0));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_target_schema() {
+        let home = Spi::get_one::<String>(
+            "SELECT extnamespace::regnamespace::text FROM pg_extension WHERE extname = 'pg_tpch'",
+        )
+        .unwrap()
+        .unwrap();
+        Spi::run("SELECT tpch_load(0.01, target_schema => 'bench')").unwrap();
+        let (loaded, elsewhere) = Spi::get_two::<i64, i64>(&format!(
+            "SELECT (SELECT count(*) FROM bench.lineitem), (SELECT count(*) FROM {home}.lineitem)"
+        ))
+        .unwrap();
+        assert_eq!((loaded, elsewhere), (Some(60175), Some(0)));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_temporary() {
+        Spi::run("SELECT tpch_load(0.01, temporary => true)").unwrap();
+        let (persistence, state) = Spi::get_two::<String, String>(
+            "SELECT (SELECT relpersistence::text FROM pg_class WHERE oid = 'lineitem'::regclass), \
+                    tpch_dataset_state()",
+        )
+        .unwrap();
+        assert_eq!(
+            (persistence.as_deref(), state.as_deref()),
+            (Some("t"), Some("complete"))
+        );
+        let rows = Spi::get_one::<i64>("SELECT rows FROM tpch_run(1)");
+        assert_eq!(rows, Ok(Some(4)));
+        // Neither the load nor the run was recorded for the extension's tables.
+        let home = Spi::get_one::<String>(
+            "SELECT extnamespace::regnamespace::text FROM pg_extension WHERE extname = 'pg_tpch'",
+        )
+        .unwrap()
+        .unwrap();
+        let recorded = Spi::get_one::<i64>(&format!(
+            "SELECT (SELECT count(*) FROM {home}.pg_tpch_load_info) \
+                  + (SELECT count(*) FROM {home}.pg_tpch_runs)"
+        ));
+        assert_eq!(recorded, Ok(Some(0)));
+    }
+
+    #[pg_test]
+    fn test_tpch_run_dataset() {
+        Spi::run("SELECT tpch_load(0.01, target_schema => 'tpch_sf001')").unwrap();
+        // Back to the empty tables of the extension.
+        Spi::run("RESET search_path").unwrap();
+        let rows = Spi::get_one::<i64>("SELECT rows FROM tpch_run(1, dataset => 'tpch_sf001')");
+        assert_eq!(rows, Ok(Some(4)));
+        let (sf, same_load) = Spi::get_two::<f64, bool>(
+            "SELECT d.sf, r.load_id = d.load_id FROM pg_tpch_runs r \
+             JOIN pg_tpch_datasets d ON d.schema_name = r.dataset",
+        )
+        .unwrap();
+        assert_eq!((sf, same_load), (Some(0.01), Some(true)));
+    }
+
+    #[pg_test]
+    fn test_datasets_keep_their_own_loads() {
+        Spi::run("SELECT tpch_load(0.01, 2, 0), tpch_load(0.01, 2, 1)").unwrap();
+        Spi::run("SELECT tpch_load(0.01, 2, 0, target_schema => 'tpch_half')").unwrap();
+        Spi::run("RESET search_path").unwrap();
+        let (current, loads, state) = Spi::get_three::<i64, i64, String>(
+            "SELECT (SELECT count(*) FROM pg_tpch_dataset), \
+                    (SELECT count(DISTINCT load_id) FROM pg_tpch_load_info \
+                     WHERE truncated_at IS NULL), \
+                    tpch_dataset_state()",
+        )
+        .unwrap();
+        assert_eq!(
+            (current, loads, state.as_deref()),
+            (Some(1), Some(2), Some("complete"))
+        );
+        Spi::run("SET LOCAL search_path = tpch_half, public").unwrap();
+        let state = Spi::get_one::<String>("SELECT tpch_dataset_state()").unwrap();
+        assert_eq!(state.as_deref(), Some("partial"));
+    }
+
+    #[pg_test]
+    fn test_tpch_set_unlogged() {
+        Spi::run("SELECT tpch_add_constraints()").unwrap();
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_unlogged()").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(8));
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let (persistence, unlogged) = Spi::get_two::<String, bool>(
+            "SELECT (SELECT relpersistence::text FROM pg_class WHERE oid = 'lineitem'::regclass), \
+                    (SELECT unlogged FROM pg_tpch_dataset)",
+        )
+        .unwrap();
+        assert_eq!((persistence.as_deref(), unlogged), (Some("u"), Some(true)));
+
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_unlogged(false)").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(8));
+    }
+
+    #[pg_test]
+    fn test_tpch_set_unlogged_without_tables() {
+        Spi::run("SELECT tpch_drop_tables()").unwrap();
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_unlogged()").unwrap();
+        assert_eq!(changed, Some(Vec::new()));
+    }
+
+    #[pg_test(
+        error = "SF=400 generates o_orderkey values up to 2400000000, too big for an integer"
+    )]
+    fn test_tpch_load_key_overflow() {
+        Spi::run("SELECT tpch_load(400)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_set_bigint_keys() {
+        Spi::run("SELECT tpch_add_constraints()").unwrap();
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_bigint_keys()").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(15));
+        Spi::run("SELECT tpch_load(0.01, format => 'binary')").unwrap();
+        let (key_type, bigint_keys) = Spi::get_two::<String, bool>(
+            "SELECT (SELECT format_type(atttypid, atttypmod) FROM pg_attribute \
+                     WHERE attrelid = 'lineitem'::regclass AND attname = 'l_orderkey'), \
+                    (SELECT bigint_keys FROM pg_tpch_dataset)",
+        )
+        .unwrap();
+        assert_eq!(
+            (key_type.as_deref(), bigint_keys),
+            (Some("bigint"), Some(true))
+        );
+
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_bigint_keys(false)").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(15));
+    }
+
+    #[pg_test]
+    fn test_tpch_set_double_precision() {
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_double_precision()").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(9));
+        for format in ["csv", "binary"] {
+            Spi::run(&format!("SELECT tpch_load(0.01, format => '{format}')")).unwrap();
+            let (revenue, double_precision) = Spi::get_two::<f64, bool>(
+                "SELECT (SELECT sum(l_extendedprice * (1 - l_discount)) FROM lineitem), \
+                        (SELECT double_precision FROM pg_tpch_dataset)",
+            )
+            .unwrap();
+            assert!((revenue.unwrap() - 2_045_134_942.09).abs() < 0.01);
+            assert_eq!(double_precision, Some(true));
+        }
+
+        let changed =
+            Spi::get_one::<Vec<String>>("SELECT tpch_set_double_precision(false)").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(9));
+    }
+
+    #[pg_test]
+    fn test_tpch_set_text_columns() {
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_text_columns()").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(29));
+        Spi::run("SELECT tpch_load(0.01, format => 'binary')").unwrap();
+        let (segment, text_columns) = Spi::get_two::<String, bool>(
+            "SELECT (SELECT max(c_mktsegment) FROM customer), \
+                    (SELECT text_columns FROM pg_tpch_dataset)",
+        )
+        .unwrap();
+        assert_eq!(
+            (segment.as_deref(), text_columns),
+            (Some("MACHINERY"), Some(true))
+        );
+
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_text_columns(false)").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(29));
+    }
+
+    #[pg_test]
+    fn test_tpch_set_range_partitioning() {
+        Spi::run("CREATE INDEX lineitem_shipdate ON lineitem (l_shipdate)").unwrap();
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_range_partitioning()").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(2));
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let counts = Spi::get_three::<i64, i64, String>(
+            "SELECT (SELECT count(*) FROM lineitem), \
+                    (SELECT count(*) FROM lineitem_default) + (SELECT count(*) FROM orders_default), \
+                    (SELECT range_partitioning FROM pg_tpch_dataset)",
+        )
+        .unwrap();
+        assert_eq!(counts, (Some(60_175), Some(0), Some("month".to_string())));
+        let shipdate_index =
+            Spi::get_one::<bool>("SELECT to_regclass('lineitem_shipdate') IS NOT NULL");
+        assert_eq!(shipdate_index, Ok(Some(true)));
+
+        Spi::run("SELECT tpch_set_range_partitioning('none')").unwrap();
+        let (relkind, rows) = Spi::get_two::<String, i64>(
+            "SELECT (SELECT relkind::text FROM pg_class WHERE oid = 'lineitem'::regclass), \
+                    (SELECT count(*) FROM lineitem)",
+        )
+        .unwrap();
+        assert_eq!((relkind.as_deref(), rows), (Some("r"), Some(60_175)));
+    }
+
+    #[pg_test]
+    fn test_range_partitioning_keeps_persistence_and_grants() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT tpch_set_unlogged()").unwrap();
+        Spi::run("CREATE ROLE tpch_reader").unwrap();
+        Spi::run("GRANT SELECT ON lineitem TO tpch_reader").unwrap();
+        Spi::run("SELECT tpch_set_range_partitioning('year')").unwrap();
+        let (persistence, granted, rows) = Spi::get_three::<String, bool, i64>(
+            "SELECT (SELECT relpersistence::text FROM pg_class WHERE oid = 'lineitem_1995'::regclass), \
+                    has_table_privilege('tpch_reader', 'lineitem', 'SELECT'), \
+                    (SELECT count(*) FROM lineitem)",
+        )
+        .unwrap();
+        assert_eq!(
+            (persistence.as_deref(), granted, rows),
+            (Some("u"), Some(true), Some(60_175))
+        );
+    }
+
+    #[pg_test]
+    fn test_tpch_set_hash_partitioning() {
+        Spi::run("SELECT tpch_set_range_partitioning('year')").unwrap();
+        let changed = Spi::get_one::<Vec<String>>("SELECT tpch_set_hash_partitioning(4)").unwrap();
+        assert_eq!(changed.map(|changed| changed.len()), Some(2));
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let counts = Spi::get_three::<i64, i64, i32>(
+            "SELECT (SELECT count(*) FROM lineitem), \
+                    (SELECT count(*) FROM pg_partition_tree('orders') WHERE isleaf), \
+                    (SELECT hash_partitions FROM pg_tpch_dataset)",
+        )
+        .unwrap();
+        assert_eq!(counts, (Some(60_175), Some(4), Some(4)));
+        Spi::run("SET LOCAL enable_partitionwise_join = on").unwrap();
+        let plan = Spi::get_one::<pgrx::Json>(
+            "EXPLAIN (FORMAT JSON) SELECT count(*) FROM orders JOIN lineitem ON l_orderkey = o_orderkey",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(plan.0.to_string().matches("\"Join Type\"").count(), 4);
+
+        Spi::run("SELECT tpch_set_hash_partitioning(0)").unwrap();
+        let (relkind, range_partitioning) = Spi::get_two::<String, String>(
+            "SELECT (SELECT relkind::text FROM pg_class WHERE oid = 'orders'::regclass), \
+                    (SELECT range_partitioning FROM pg_tpch_dataset)",
+        )
+        .unwrap();
+        assert_eq!(
+            (relkind.as_deref(), range_partitioning.as_deref()),
+            (Some("r"), Some("none"))
+        );
+    }
+
+    #[pg_test]
+    fn test_hash_partitioning_keeps_persistence_and_grants() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT tpch_set_unlogged()").unwrap();
+        Spi::run("CREATE ROLE tpch_reader").unwrap();
+        Spi::run("GRANT SELECT ON lineitem TO tpch_reader").unwrap();
+        Spi::run("SELECT tpch_set_hash_partitioning(4)").unwrap();
+        let (persistence, granted, rows) = Spi::get_three::<String, bool, i64>(
+            "SELECT (SELECT relpersistence::text FROM pg_class WHERE oid = 'lineitem_p0'::regclass), \
+                    has_table_privilege('tpch_reader', 'lineitem', 'SELECT'), \
+                    (SELECT count(*) FROM lineitem)",
+        )
+        .unwrap();
+        assert_eq!(
+            (persistence.as_deref(), granted, rows),
+            (Some("u"), Some(true), Some(60_175))
+        );
+    }
+
+    #[pg_test]
+    fn test_storage_parameters() {
+        Spi::run("SET LOCAL pg_tpch.storage_parameters = 'fillfactor=90, autovacuum_enabled=off'")
+            .unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let (reloptions, recorded) = Spi::get_two::<Vec<String>, Vec<String>>(
+            "SELECT (SELECT reloptions::text[] FROM pg_class WHERE oid = 'orders'::regclass), \
+                    (SELECT storage_parameters FROM pg_tpch_dataset)",
+        )
+        .unwrap();
+        let expected = vec![
+            "fillfactor=90".to_string(),
+            "autovacuum_enabled=off".to_string(),
+        ];
+        assert_eq!(reloptions.as_ref(), Some(&expected));
+        assert_eq!(recorded.as_ref(), Some(&expected));
+
+        Spi::run("SET LOCAL pg_tpch.storage_parameters = 'parallel_workers=4'").unwrap();
+        Spi::run("SELECT tpch_set_hash_partitioning(2)").unwrap();
+        let reloptions = Spi::get_one::<Vec<String>>(
+            "SELECT reloptions::text[] FROM pg_class WHERE oid = 'orders_p1'::regclass",
+        )
+        .unwrap();
+        assert_eq!(
+            reloptions,
+            Some(vec![
+                "fillfactor=90".to_string(),
+                "autovacuum_enabled=off".to_string(),
+                "parallel_workers=4".to_string()
+            ])
+        );
+    }
+
+    #[pg_test(error = "invalid storage parameter \"fillfactor\" in pg_tpch.storage_parameters")]
+    fn test_storage_parameters_invalid() {
+        Spi::run("SET LOCAL pg_tpch.storage_parameters = 'fillfactor'").unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_set_access_method() {
+        // A copy of heap stands in for the access method of an extension.
+        Spi::run("CREATE ACCESS METHOD heap_copy TYPE TABLE HANDLER heap_tableam_handler").unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let changed = Spi::get_one::<Vec<String>>(
+            "SELECT tpch_set_access_method('heap_copy', '{orders, lineitem}')",
+        )
+        .unwrap();
+        assert_eq!(
+            changed,
+            Some(vec!["orders".to_string(), "lineitem".to_string()])
+        );
+        let (access_method, rows) = Spi::get_two::<String, i64>(
+            "SELECT (SELECT amname::text FROM pg_class c JOIN pg_am a ON a.oid = c.relam \
+                     WHERE c.oid = 'lineitem'::regclass), \
+                    (SELECT count(*) FROM lineitem)",
+        )
+        .unwrap();
+        assert_eq!(
+            (access_method.as_deref(), rows),
+            (Some("heap_copy"), Some(60_175))
+        );
+        let recorded =
+            Spi::get_one::<String>("SELECT access_methods->>'orders' FROM pg_tpch_dataset");
+        assert_eq!(recorded, Ok(Some("heap_copy".to_string())));
+    }
+
+    #[pg_test]
+    fn test_tpch_set_access_method_keeps_persistence_and_grants() {
+        Spi::run("CREATE ACCESS METHOD heap_copy TYPE TABLE HANDLER heap_tableam_handler").unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT tpch_set_unlogged()").unwrap();
+        Spi::run("CREATE ROLE tpch_reader").unwrap();
+        Spi::run("GRANT SELECT ON lineitem TO tpch_reader").unwrap();
+        Spi::run("SELECT tpch_set_access_method('heap_copy', '{lineitem}')").unwrap();
+        let (persistence, granted, rows) = Spi::get_three::<String, bool, i64>(
+            "SELECT (SELECT relpersistence::text FROM pg_class WHERE oid = 'lineitem'::regclass), \
+                    has_table_privilege('tpch_reader', 'lineitem', 'SELECT'), \
+                    (SELECT count(*) FROM lineitem)",
+        )
+        .unwrap();
+        assert_eq!(
+            (persistence.as_deref(), granted, rows),
+            (Some("u"), Some(true), Some(60_175))
+        );
+    }
+
+    #[pg_test(error = "tpch_distribute needs the Citus extension")]
+    fn test_tpch_distribute_needs_citus() {
+        Spi::run("SELECT tpch_distribute()").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_set_tablespace() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        // The tables are in the default tablespace already.
+        let moved = Spi::get_one::<Vec<String>>("SELECT tpch_set_tablespace('pg_default')");
+        assert_eq!(moved, Ok(Some(vec![])));
+        let tablespace = Spi::get_one::<String>("SELECT tablespace FROM pg_tpch_dataset");
+        assert_eq!(tablespace, Ok(None));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_rebuild_indexes() {
+        Spi::run("SELECT tpch_add_constraints()").unwrap();
+        Spi::run("SELECT tpch_create_indexes('btree')").unwrap();
+        Spi::run("SELECT tpch_load(0.01, rebuild_indexes => true)").unwrap();
+        let (constraints, indexes) = Spi::get_two::<i64, i64>(
+            "SELECT (SELECT count(*) FROM pg_constraint \
+                     WHERE conrelid = ANY('{lineitem, orders}'::regclass[]) AND convalidated), \
+                    (SELECT count(*) FROM pg_tpch_indexes WHERE to_regclass(index_name) IS NOT NULL)",
+        )
+        .unwrap();
+        // The primary keys of both tables and the three foreign keys.
+        assert_eq!((constraints, indexes), (Some(5), Some(4)));
+    }
+
+    #[pg_test(error = "could not load part 1 of 2 of region through \"/x\": no space left")]
+    fn test_load_error_report() {
+        let error = crate::error::LoadError::new(
+            "region",
+            1,
+            2,
+            std::io::Error::new(std::io::ErrorKind::StorageFull, "no space left"),
+        )
+        .at(std::path::Path::new("/x"));
+        assert_eq!(error.sqlstate(), PgSqlErrorCode::ERRCODE_DISK_FULL);
+        error.report();
+    }
+
+    #[pg_test]
+    fn test_tpch_add_constraints_not_valid() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let (keys, not_valid) = Spi::get_two::<i64, i64>(
+            "SELECT count(*), count(*) FILTER (WHERE constraint_name LIKE '%fkey') \
+             FROM tpch_add_constraints(not_valid => true)",
+        )
+        .unwrap();
+        assert_eq!((keys, not_valid), (Some(16), Some(8)));
+        let pending =
+            Spi::get_one::<i64>("SELECT count(*) FROM pg_constraint WHERE NOT convalidated");
+        assert_eq!(pending, Ok(Some(8)));
+
+        let validated =
+            Spi::get_one::<i64>("SELECT count(*) FROM tpch_validate_constraints()").unwrap();
+        assert_eq!(validated, Some(8));
+        let pending =
+            Spi::get_one::<i64>("SELECT count(*) FROM pg_constraint WHERE NOT convalidated");
+        assert_eq!(pending, Ok(Some(0)));
+        let again = Spi::get_one::<i64>("SELECT count(*) FROM tpch_add_constraints()").unwrap();
+        assert_eq!(again, Some(0));
+    }
+
+    #[pg_test]
+    fn test_tpch_load_adds_constraints_not_valid() {
+        Spi::run("SELECT tpch_load(0.01, children => 2, step => 0, constraints => 'not_valid')")
+            .unwrap();
+        let after_first = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_constraint \
+             WHERE conrelid = 'lineitem'::regclass AND contype IN ('p', 'f')",
+        );
+        assert_eq!(after_first, Ok(Some(0)));
+        Spi::run("SELECT tpch_load(0.01, children => 2, step => 1, constraints => 'not_valid')")
+            .unwrap();
+        let (keys, pending) = Spi::get_two::<i64, i64>(
+            "SELECT count(*), count(*) FILTER (WHERE NOT convalidated) FROM pg_constraint \
+             WHERE contype IN ('p', 'f') \
+               AND conrelid IN ('lineitem'::regclass, 'orders'::regclass)",
+        )
+        .unwrap();
+        // The primary keys of both tables and their three foreign keys.
+        assert_eq!((keys, pending), (Some(5), Some(3)));
+    }
+
+    #[pg_test]
+    fn test_tpch_drop_constraints() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT tpch_add_constraints()").unwrap();
+        let dropped = Spi::get_one::<Vec<String>>("SELECT tpch_drop_constraints()").unwrap();
+        assert_eq!(dropped.map(|dropped| dropped.len()), Some(16));
+        let left = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_constraint WHERE conrelid = 'lineitem'::regclass \
+                                                 AND contype IN ('p', 'f')",
+        );
+        assert_eq!(left, Ok(Some(0)));
+        let again = Spi::get_one::<Vec<String>>("SELECT tpch_drop_constraints()").unwrap();
+        assert_eq!(again, Some(vec![]));
+    }
+
+    #[pg_test]
+    fn test_tpch_drop_tables() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT * FROM tpch_create_indexes('minimal')").unwrap();
+        let dropped = Spi::get_one::<Vec<String>>("SELECT tpch_drop_tables()").unwrap();
+        assert_eq!(dropped.map(|dropped| dropped.len()), Some(8));
+        let (left, indexes, state) = Spi::get_three::<i64, i64, String>(
+            "SELECT (SELECT count(*) FROM unnest(ARRAY['region', 'lineitem']) t \
+                     WHERE to_regclass(t) IS NOT NULL), \
+                    (SELECT count(*) FROM pg_tpch_indexes), tpch_dataset_state()",
+        )
+        .unwrap();
+        assert_eq!((left, indexes), (Some(0), Some(0)));
+        assert_eq!(state.as_deref(), Some("empty"));
+    }
+
+    #[pg_test]
+    fn test_tpch_reset() {
+        Spi::run("SELECT tpch_set_bigint_keys(), tpch_set_range_partitioning('year')").unwrap();
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let created = Spi::get_one::<Vec<String>>("SELECT tpch_reset()").unwrap();
+        assert_eq!(created.map(|created| created.len()), Some(8));
+        let (rows, bigint, partitioning) = Spi::get_three::<i64, bool, String>(
+            "SELECT (SELECT count(*) FROM lineitem), \
+                    (SELECT format_type(atttypid, atttypmod) = 'bigint' FROM pg_attribute \
+                     WHERE attrelid = 'orders'::regclass AND attname = 'o_orderkey'), \
+                    (SELECT string_agg(c.relname, ',' ORDER BY c.relname) FROM pg_inherits i \
+                     JOIN pg_class c ON c.oid = i.inhrelid \
+                     WHERE i.inhparent = 'orders'::regclass AND c.relname LIKE 'orders_199_')",
+        )
+        .unwrap();
+        assert_eq!((rows, bigint), (Some(0), Some(true)));
+        assert_eq!(
+            partitioning.as_deref(),
+            Some("orders_1992,orders_1993,orders_1994,orders_1995,orders_1996,orders_1997,orders_1998")
+        );
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_tune_statistics() {
+        Spi::run("SELECT tpch_load(0.01, analyze => false)").unwrap();
+        let tuned = Spi::get_one::<i64>("SELECT count(*) FROM tpch_tune_statistics(500)").unwrap();
+        assert_eq!(tuned, Some(11));
+        let (target, analyzed) = Spi::get_two::<i32, bool>(
+            "SELECT (SELECT attstattarget::int FROM pg_attribute \
+                     WHERE attrelid = 'lineitem'::regclass AND attname = 'l_shipdate'), \
+                    EXISTS (SELECT FROM pg_stats WHERE tablename = 'part' AND attname = 'p_type')",
+        )
+        .unwrap();
+        assert_eq!((target, analyzed), (Some(500), Some(true)));
+    }
+
+    #[pg_test]
+    fn test_load_progress_is_empty_after_load() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let copying = Spi::get_one::<i64>("SELECT count(*) FROM pg_tpch_load_progress").unwrap();
+        assert_eq!(copying, Some(0));
+    }
+
+    #[pg_test]
+    fn test_event_is_one_json_object() {
+        let line = crate::events::render(
+            "table_end",
+            1.5,
+            42,
+            &[
+                ("table", "line\"item\n".into()),
+                ("rows", 6005_i64.into()),
+                ("duration_ms", f64::NAN.into()),
+            ],
+        );
+        assert_eq!(
+            line,
+            r#"{"event":"table_end","at":1.500000,"pid":42,"table":"line\"item\n","rows":6005,"duration_ms":null}"#
+        );
+        let parsed =
+            Spi::get_one_with_args::<String>("SELECT $1::jsonb ->> 'table'", &[line.into()]);
+        assert_eq!(parsed, Ok(Some("line\"item\n".to_string())));
+    }
+
+    #[pg_test]
+    fn test_tpch_preset() {
+        let queries = Spi::get_one::<i64>("SELECT count(*) FROM tpch_preset('indexed', 0.01)");
+        assert_eq!(queries, Ok(Some(22)));
+        let (preset, profile, keys) = Spi::get_three::<String, String, i64>(
+            "SELECT preset, profile, \
+                    (SELECT count(*) FROM pg_constraint WHERE conname LIKE '%\\_pkey') \
+             FROM pg_tpch_runs ORDER BY run_id DESC LIMIT 1",
+        )
+        .unwrap();
+        assert_eq!(
+            (preset.as_deref(), profile.as_deref(), keys),
+            (Some("indexed"), Some("analytics"), Some(8))
+        );
+
+        Spi::run("SELECT tpch_preset('vanilla', 0.01)").unwrap();
+        let left = Spi::get_one::<i64>(
+            "SELECT (SELECT count(*) FROM pg_tpch_indexes) + \
+                    (SELECT count(*) FROM pg_constraint WHERE conname LIKE '%\\_pkey')",
+        );
+        assert_eq!(left, Ok(Some(0)));
+    }
+
+    #[pg_test]
+    fn test_tpch_run_all() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SET work_mem = '1MB'").unwrap();
+        let (queries, rows) = Spi::get_two::<i64, i64>(
+            "SELECT count(*), sum(rows)::bigint FROM tpch_run_all(profile => 'analytics')",
+        )
+        .unwrap();
+        assert_eq!(queries, Some(22));
+        assert!(rows.unwrap() > 0);
+
+        let profile = Spi::get_one::<String>("SELECT profile FROM pg_tpch_runs").unwrap();
+        assert_eq!(profile.as_deref(), Some("analytics"));
+        let work_mem = Spi::get_one::<String>("SHOW work_mem").unwrap();
+        assert_eq!(work_mem.as_deref(), Some("1MB"));
+    }
+
+    #[pg_test(
+        error = "unknown tuning profile \"fast\", expected one of [\"default\", \"analytics\", \"oltp\"]"
+    )]
+    fn test_tpch_run_rejects_unknown_profile() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT * FROM tpch_run(1, profile => 'fast')").unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_run_set() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let runs = Spi::get_one::<String>(
+            "SELECT string_agg(query_nr || '.' || iteration, ' ' ORDER BY query_nr, iteration) \
+             FROM tpch_run_set('{6, 14}', repetitions => '{3, 1}')",
+        )
+        .unwrap();
+        assert_eq!(runs.as_deref(), Some("6.1 6.2 6.3 14.1"));
+    }
+
+    #[pg_test]
+    fn test_tpch_run_stores_each_plan_once() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT * FROM tpch_run_set('{6, 15}')").unwrap();
+        Spi::run("SELECT * FROM tpch_run_set('{6, 15}')").unwrap();
+        let (plans, referenced) = Spi::get_two::<i64, i64>(
+            "SELECT (SELECT count(*) FROM pg_tpch_plans), \
+                    (SELECT count(*) FROM pg_tpch_run_plans)",
+        )
+        .unwrap();
+        assert_eq!((plans, referenced), (Some(2), Some(4)));
+    }
+
+    #[pg_test]
+    fn test_tpch_export_run_anonymized() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SET application_name = 'benchmark host 7'").unwrap();
+        let run_id = Spi::get_one::<i64>("SELECT run_id FROM tpch_run(6)")
+            .unwrap()
+            .unwrap();
+        let (query_nr, has_plan, identifying) = Spi::get_three::<i32, bool, bool>(&format!(
+            "SELECT (d->'results'->0->>'query_nr')::int, d->'results'->0->'plan' IS NOT NULL, \
+                    d::text LIKE '%benchmark host%' OR d->'server'->'settings' ? 'port' \
+             FROM tpch_export_run({run_id}) d"
+        ))
+        .unwrap();
+        assert_eq!(query_nr, Some(6));
+        assert_eq!(has_plan, Some(true));
+        assert_eq!(identifying, Some(false));
+    }
+
+    #[pg_test]
+    fn test_tpch_matrix() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let first = Spi::get_one::<i64>("SELECT max(run_id) FROM tpch_run_set('{6, 14}')");
+        let second = Spi::get_one::<i64>("SELECT max(run_id) FROM tpch_run_set('{6}')");
+        let (queries, runs, relative) = Spi::get_three::<String, String, f64>(&format!(
+            "SELECT string_agg(query_nr::text, ' ' ORDER BY query_nr), \
+                    string_agg((array_position(duration_ms, NULL) IS NOT NULL)::text, ' ' \
+                               ORDER BY query_nr), \
+                    min(relative[1]) \
+             FROM tpch_matrix(ARRAY[{}, {}])",
+            first.unwrap().unwrap(),
+            second.unwrap().unwrap()
+        ))
+        .unwrap();
+        assert_eq!(queries.as_deref(), Some("6 14"));
+        // Query 14 only ran in the first run.
+        assert_eq!(runs.as_deref(), Some("false true"));
+        assert_eq!(relative, Some(0.));
+    }
+
+    #[pg_test(error = "repetitions must be positive and either a single count or one per query")]
+    fn test_tpch_run_set_rejects_mismatched_repetitions() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        Spi::run("SELECT * FROM tpch_run_set('{1, 6, 14}', repetitions => '{2, 2}')").unwrap();
+    }
+
+    #[pg_test(error = "query 6 regressed against the baseline")]
+    fn test_tpch_run_stops_on_regression() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let baseline = Spi::get_one::<i64>("SELECT run_id FROM tpch_run(6)").unwrap();
+        Spi::run("UPDATE pg_tpch_run_results SET duration_ms = 1e-6").unwrap();
+        Spi::run(&format!(
+            "SELECT * FROM tpch_run_set('{{1, 6}}', baseline_run => {})",
+            baseline.unwrap()
+        ))
+        .unwrap();
+    }
+
+    #[pg_test]
+    fn test_tpch_run_stops_when_time_budget_runs_out() {
+        Spi::run("SELECT tpch_load(0.01)").unwrap();
+        let queries = Spi::get_one::<i64>(
+            "SELECT count(*) FROM tpch_run_all(max_duration => '1 microsecond')",
+        )
+        .unwrap();
+        assert!(queries.unwrap() < 22);
+        let exhausted = Spi::get_one::<bool>("SELECT budget_exhausted FROM pg_tpch_runs").unwrap();
+        assert_eq!(exhausted, Some(true));
+    }
+
     #[pg_test]
     fn test_tpch_queries() {
         let results = crate::tpch_queries();
@@ -0,0 +1,202 @@
+//! Maintenance of the TPC-H tables between a load and the first run.
+//!
+//! Freshly loaded tables have no hint bits set and nothing frozen, so the
+//! first queries write every page they read and the first vacuum rewrites
+//! them all. `tpch_vacuum()` does that work up front. `VACUUM` can't run in a
+//! function or a procedure, so it runs in a session of its own opened through
+//! dblink, which only gets at the tables once the load has committed.
+//!
+//! `tpch_prewarm()` reads the tables and their indexes into shared buffers
+//! with pg_prewarm, for runs that measure a warm cache.
+//!
+//! `tpch_tune_statistics()` raises the statistics targets of the columns the
+//! planner misestimates with the default one.
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::tables::TABLES;
+use crate::{parallel, provision};
+
+/// Schema the pg_prewarm extension is installed in.
+fn pg_prewarm_schema() -> spi::Result<String> {
+    let schema = Spi::get_one::<String>(
+        "SELECT max(extnamespace::regnamespace::text) FROM pg_extension WHERE extname = 'pg_prewarm'",
+    )?;
+    Ok(schema.unwrap_or_else(|| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "tpch_prewarm needs the pg_prewarm extension",
+            "Run CREATE EXTENSION pg_prewarm."
+        );
+    }))
+}
+
+/// Vacuums, freezes and analyzes the TPC-H tables, and returns what it did.
+///
+/// The transaction calling it must not have loaded the tables: the vacuum
+/// waits for their locks, so load in a transaction of your own first, or
+/// `CALL tpch_load_proc(..., vacuum => true)`. Temporary tables belong to
+/// this session and can't be vacuumed from another one. The usual dblink
+/// rules for passwords apply to non-superusers.
+#[pg_extern]
+fn tpch_vacuum() -> spi::Result<Option<String>> {
+    if parallel::holds_table_locks()? {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            "vacuuming needs the TPC-H tables unlocked",
+            "Call tpch_vacuum in a transaction of its own, after the load committed."
+        );
+    }
+    let (tables, temporary) = Spi::get_two_with_args::<String, bool>(
+        "SELECT string_agg(format('%I.%I', n.nspname, c.relname), ', '),
+                bool_or(c.relpersistence = 't')
+         FROM unnest($1::text[]) t
+         JOIN pg_class c ON c.oid = to_regclass(t)
+         JOIN pg_namespace n ON n.oid = c.relnamespace",
+        &[TABLES.to_vec().into()],
+    )?;
+    let Some(tables) = tables else {
+        return Ok(None);
+    };
+    if temporary == Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "temporary TPC-H tables can't be vacuumed by tpch_vacuum",
+            "Run VACUUM (FREEZE, ANALYZE) on them from psql."
+        );
+    }
+    let dblink = provision::dblink_schema("tpch_vacuum")?;
+    let database =
+        Spi::get_one::<String>("SELECT current_database()")?.expect("a session has a database");
+    Spi::run_with_args(
+        &format!("SELECT {dblink}.dblink_exec($1, $2)"),
+        &[
+            provision::connection_string(&database)?.into(),
+            format!("VACUUM (FREEZE, ANALYZE) {tables}").into(),
+        ],
+    )?;
+    Ok(Some(format!(
+        "TPC-H tables vacuumed, frozen and analyzed ({tables})"
+    )))
+}
+
+/// Reads the TPC-H tables and their indexes on the search path into shared
+/// buffers, partitions in place of partitioned tables, and returns how many
+/// blocks of each relation were read.
+pub fn prewarm() -> spi::Result<Vec<(String, i64)>> {
+    let pg_prewarm = pg_prewarm_schema()?;
+    Spi::connect(|client| {
+        client
+            .select(
+                &format!(
+                    "SELECT r.oid::regclass::text, {pg_prewarm}.pg_prewarm(r.oid)
+                     FROM (
+                         SELECT r.oid
+                         FROM unnest($1::text[]) WITH ORDINALITY t(name, nr)
+                         JOIN pg_class c ON c.oid = to_regclass(t.name)
+                         CROSS JOIN LATERAL (
+                             SELECT c.oid AS relid WHERE c.relkind <> 'p'
+                             UNION ALL
+                             SELECT relid FROM pg_partition_tree(c.oid) WHERE isleaf
+                         ) l
+                         CROSS JOIN LATERAL (
+                             SELECT l.relid AS oid, 0 AS kind
+                             UNION ALL
+                             SELECT indexrelid, 1 FROM pg_index WHERE indrelid = l.relid
+                         ) r
+                         ORDER BY t.nr, l.relid, r.kind, r.oid
+                     ) r"
+                ),
+                None,
+                &[TABLES.to_vec().into()],
+            )?
+            .map(|row| {
+                Ok((
+                    row.get::<String>(1)?.unwrap_or_default(),
+                    row.get::<i64>(2)?.unwrap_or_default(),
+                ))
+            })
+            .collect()
+    })
+}
+
+/// Reads the TPC-H tables and their indexes into shared buffers with
+/// pg_prewarm, and returns how many blocks of each relation were read.
+///
+/// Relations are read table by table, a table before its indexes. Whatever
+/// doesn't fit in `shared_buffers` pushes out what was read before, so a
+/// dataset bigger than that ends up only partly cached. The runners take a
+/// `prewarm` option that calls this before the first query.
+#[pg_extern]
+fn tpch_prewarm(
+) -> spi::Result<TableIterator<'static, (name!(relation, String), name!(blocks, i64))>> {
+    Ok(TableIterator::new(prewarm()?))
+}
+
+/// Columns whose distributions the default statistics target of 100 samples
+/// too coarsely, as `(table, column)`: the dates the queries restrict to
+/// ranges of a few months out of seven years, the part types, brands and
+/// containers they match with `LIKE` or lists of values, and the foreign
+/// keys whose number of distinct values sizes the joins.
+const SKEWED_COLUMNS: &[(&str, &str)] = &[
+    ("lineitem", "l_shipdate"),
+    ("lineitem", "l_commitdate"),
+    ("lineitem", "l_receiptdate"),
+    ("lineitem", "l_partkey"),
+    ("lineitem", "l_suppkey"),
+    ("orders", "o_orderdate"),
+    ("orders", "o_custkey"),
+    ("part", "p_type"),
+    ("part", "p_brand"),
+    ("part", "p_container"),
+    ("partsupp", "ps_suppkey"),
+];
+
+/// Sets the statistics target of the columns the planner misestimates with
+/// the default one to `target`, analyzes their tables again and returns the
+/// columns.
+///
+/// The default target leaves several of the 22 queries with row estimates
+/// off by orders of magnitude, mostly for date ranges on `lineitem` and
+/// `orders` and the `LIKE` patterns on `p_type`, which then get the wrong join
+/// order. A bigger target makes `ANALYZE` sample more rows and the planner
+/// read bigger statistics. `target => -1` goes back to
+/// `default_statistics_target`.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_tune_statistics(
+    target: default!(i32, 1000),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(table_name, String),
+            name!(column_name, String),
+            name!(statistics_target, i32),
+        ),
+    >,
+> {
+    if !(-1..=10000).contains(&target) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("target must be between -1 and 10000, got {target}")
+        );
+    }
+    let mut tables: Vec<&str> = Vec::new();
+    for &(table, column) in SKEWED_COLUMNS {
+        Spi::run(&format!(
+            "ALTER TABLE {table} ALTER COLUMN {column} SET STATISTICS {target}"
+        ))?;
+        if !tables.contains(&table) {
+            tables.push(table);
+        }
+    }
+    Spi::run(&format!("ANALYZE {}", tables.join(", ")))?;
+    Ok(TableIterator::new(SKEWED_COLUMNS.iter().map(
+        move |&(table, column)| (table.to_string(), column.to_string(), target),
+    )))
+}
@@ -0,0 +1,126 @@
+//! The manifest `tpch_export` writes next to the files it generates.
+//!
+//! It records what was generated and the size and CRC-32 of every file, so a
+//! directory can be checked before it is loaded somewhere else. Every line is
+//! `key<TAB>value`, followed by one `file<TAB>table<TAB>name<TAB>rows<TAB>bytes<TAB>crc32`
+//! line per file.
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// File name of the manifest of a single-part export. Multi-part exports
+/// write one per part, suffixed with the part like their data files.
+pub const MANIFEST: &str = "pg_tpch_manifest.tsv";
+
+/// One exported file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestFile {
+    pub table: String,
+    /// File name, relative to the manifest.
+    pub name: String,
+    pub rows: u64,
+    pub bytes: u64,
+    pub crc32: u32,
+}
+
+/// What one export step wrote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub sf: f64,
+    /// `csv` or `tbl`.
+    pub format: String,
+    pub part: i32,
+    pub num_parts: i32,
+    pub delimiter: char,
+    pub quote: char,
+    /// Whether CSV files start with a header line.
+    pub header: bool,
+    pub null: String,
+    pub encoding: String,
+    pub files: Vec<ManifestFile>,
+}
+
+impl Manifest {
+    /// Name of the manifest of `part` of `num_parts`.
+    pub fn file_name(part: i32, num_parts: i32) -> String {
+        if num_parts == 1 {
+            MANIFEST.to_string()
+        } else {
+            format!("{MANIFEST}.{part}")
+        }
+    }
+
+    /// Writes the manifest into `dir`.
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        let mut out = Vec::new();
+        writeln!(out, "sf\t{}", self.sf)?;
+        writeln!(out, "format\t{}", self.format)?;
+        writeln!(out, "part\t{}", self.part)?;
+        writeln!(out, "parts\t{}", self.num_parts)?;
+        writeln!(out, "delimiter\t{}", self.delimiter)?;
+        writeln!(out, "quote\t{}", self.quote)?;
+        writeln!(out, "header\t{}", self.header)?;
+        writeln!(out, "null\t{}", self.null)?;
+        writeln!(out, "encoding\t{}", self.encoding)?;
+        for file in &self.files {
+            writeln!(
+                out,
+                "file\t{}\t{}\t{}\t{}\t{:08x}",
+                file.table, file.name, file.rows, file.bytes, file.crc32
+            )?;
+        }
+        fs::write(dir.join(Self::file_name(self.part, self.num_parts)), out)
+    }
+
+    /// Reads the manifest at `path`.
+    pub fn read(path: &Path) -> io::Result<Manifest> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid manifest line \"{line}\""),
+            )
+        };
+        let mut manifest = Manifest {
+            sf: 0.,
+            format: String::new(),
+            part: 1,
+            num_parts: 1,
+            delimiter: ',',
+            quote: '"',
+            header: true,
+            null: String::new(),
+            encoding: "UTF8".to_string(),
+            files: Vec::new(),
+        };
+        for line in fs::read_to_string(path)?.lines() {
+            let (key, value) = line.split_once('\t').ok_or_else(|| invalid(line))?;
+            let char_value = || value.chars().next().ok_or_else(|| invalid(line));
+            match key {
+                "sf" => manifest.sf = value.parse().map_err(|_| invalid(line))?,
+                "format" => manifest.format = value.to_string(),
+                "part" => manifest.part = value.parse().map_err(|_| invalid(line))?,
+                "parts" => manifest.num_parts = value.parse().map_err(|_| invalid(line))?,
+                "delimiter" => manifest.delimiter = char_value()?,
+                "quote" => manifest.quote = char_value()?,
+                "header" => manifest.header = value.parse().map_err(|_| invalid(line))?,
+                "null" => manifest.null = value.to_string(),
+                "encoding" => manifest.encoding = value.to_string(),
+                "file" => {
+                    let fields: Vec<&str> = value.split('\t').collect();
+                    let [table, name, rows, bytes, crc32] = fields[..] else {
+                        return Err(invalid(line));
+                    };
+                    manifest.files.push(ManifestFile {
+                        table: table.to_string(),
+                        name: name.to_string(),
+                        rows: rows.parse().map_err(|_| invalid(line))?,
+                        bytes: bytes.parse().map_err(|_| invalid(line))?,
+                        crc32: u32::from_str_radix(crc32, 16).map_err(|_| invalid(line))?,
+                    });
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+        Ok(manifest)
+    }
+}
@@ -0,0 +1,167 @@
+//! Loading generated data into user tables instead of the TPC-H ones.
+//!
+//! A mapping names, per TPC-H table, the table to load instead and optionally
+//! which TPC-H columns go into which of its columns, as in
+//! `{"lineitem": {"table": "app.order_lines", "columns": {"l_orderkey": "order_id"}}}`.
+//! Only the mapped columns are written to the file, so the target table can
+//! have any shape as long as the mapped columns accept the generated values.
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+
+use pgrx::prelude::*;
+use pgrx::spi;
+use pgrx::JsonB;
+
+use crate::error::LoadError;
+use crate::format::{ColumnsCsvVisitor, CsvOptions, CsvWriter};
+use crate::tables::TABLES;
+use crate::{copy, guc, schema, tables};
+
+/// Where one TPC-H table is loaded.
+pub struct TableMapping {
+    pub table: &'static str,
+    /// Target table, quoted as needed.
+    pub target: String,
+    /// `(tpch_column, target_column)` in the TPC-H table's column order.
+    pub columns: Vec<(&'static str, String)>,
+}
+
+fn invalid(message: String) -> ! {
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+        message
+    );
+}
+
+/// Reads the mappings out of the `mapping` argument of `tpch_load`.
+pub fn parse(mapping: JsonB) -> spi::Result<Vec<TableMapping>> {
+    let entries = Spi::connect(|client| {
+        client
+            .select(
+                "SELECT key, value->>'table', value->'columns' FROM jsonb_each($1)",
+                None,
+                &[mapping.into()],
+            )?
+            .map(|row| {
+                Ok((
+                    row.get::<String>(1)?.unwrap_or_default(),
+                    row.get::<String>(2)?,
+                    row.get::<JsonB>(3)?,
+                ))
+            })
+            .collect::<spi::Result<Vec<_>>>()
+    })?;
+
+    let mut mappings = Vec::with_capacity(entries.len());
+    for (table, target, columns) in entries {
+        let Some(&table) = TABLES.iter().find(|t| **t == table) else {
+            invalid(format!("unknown TPC-H table \"{table}\" in mapping"));
+        };
+        let Some(target) = target else {
+            invalid(format!("mapping of {table} has no \"table\""));
+        };
+        let target =
+            Spi::get_one_with_args::<String>("SELECT $1::regclass::text", &[target.into()])?
+                .expect("regclass is not null");
+
+        let tpch_columns: Vec<&'static str> = schema::expected_columns("default", table)
+            .expect("the default profile exists")
+            .into_iter()
+            .map(|(column, _, _)| column)
+            .collect();
+        let renames: Vec<(String, String)> = match columns {
+            Some(columns) => Spi::connect(|client| {
+                client
+                    .select(
+                        "SELECT key, value FROM jsonb_each_text($1)",
+                        None,
+                        &[columns.into()],
+                    )?
+                    .map(|row| {
+                        Ok((
+                            row.get::<String>(1)?.unwrap_or_default(),
+                            row.get::<String>(2)?.unwrap_or_default(),
+                        ))
+                    })
+                    .collect::<spi::Result<Vec<_>>>()
+            })?,
+            None => tpch_columns
+                .iter()
+                .map(|c| (c.to_string(), c.to_string()))
+                .collect(),
+        };
+        for (column, _) in &renames {
+            if !tpch_columns.contains(&column.as_str()) {
+                invalid(format!("{table} has no column \"{column}\""));
+            }
+        }
+        let columns = tpch_columns
+            .into_iter()
+            .filter_map(|column| {
+                renames
+                    .iter()
+                    .find(|(from, _)| from == column)
+                    .map(|(_, to)| (column, to.clone()))
+            })
+            .collect();
+        mappings.push(TableMapping {
+            table,
+            target,
+            columns,
+        });
+    }
+    Ok(mappings)
+}
+
+/// Generates part `part` of `num_parts` of every mapped table and loads it
+/// into its target, using `dir` for the intermediate files.
+pub fn load(
+    mappings: &[TableMapping],
+    sf: f64,
+    part: i32,
+    parts: impl Fn(&str) -> i32,
+    csv: &CsvOptions,
+    dir: &Path,
+) -> spi::Result<()> {
+    for mapping in mappings {
+        let num_parts = parts(mapping.table);
+        if part > num_parts {
+            continue;
+        }
+        let path = dir.join(format!("{}.csv", mapping.table));
+        let columns: Vec<&str> = mapping.columns.iter().map(|(from, _)| *from).collect();
+        fs::File::create(&path)
+            .and_then(|file| {
+                let file = BufWriter::with_capacity(guc::generation_buffer_bytes(), file);
+                tables::generate(
+                    mapping.table,
+                    sf,
+                    part,
+                    num_parts,
+                    ColumnsCsvVisitor(CsvWriter::new(file, csv), &columns),
+                )
+            })
+            .unwrap_or_else(|e| {
+                LoadError::new(mapping.table, part, num_parts, e)
+                    .at(&path)
+                    .report()
+            });
+
+        let target_columns: Vec<String> = mapping
+            .columns
+            .iter()
+            .map(|(_, to)| spi::quote_identifier(to))
+            .collect();
+        let target = format!("{} ({})", mapping.target, target_columns.join(", "));
+        let path = fs::canonicalize(&path).unwrap_or_else(|e| {
+            LoadError::new(mapping.table, part, num_parts, e)
+                .at(&path)
+                .report()
+        });
+        copy::copy_from(&target, &path, csv, None, false);
+        fs::remove_file(&path).ok();
+    }
+    Ok(())
+}
@@ -0,0 +1,447 @@
+//! Bookkeeping of what has been loaded into this database.
+//!
+//! Every dataset load gets a row in `pg_tpch_load_info`, with the schema of
+//! the TPC-H tables it loaded. Step 0 of a load (or truncating with `sf => 0`)
+//! retires the previous rows of that schema by setting `truncated_at`, so the
+//! rows with `truncated_at IS NULL` of the schema the TPC-H tables on the
+//! search path are in (exposed through the `pg_tpch_dataset` view) describe
+//! what those tables currently hold. Datasets loaded into other schemas with
+//! `target_schema` keep their own records.
+//!
+//! `pg_tpch_table_state` carries a dirty marker per schema and table that is
+//! set when a load step starts and cleared once it succeeds, so a step that
+//! didn't finish is visible as such.
+//!
+//! `pg_tpch_load_stats` records per table and step how much was generated and
+//! what it cost, including how the time split between generating the rows
+//! and `COPY`, see [`crate::timing`]. `pg_tpch_load_throughput` sums them
+//! up into rows and megabytes per second.
+//!
+//! `pg_tpch_load_steps` records which tables every step of a load finished,
+//! so a step that runs again doesn't load them twice, and `tpch_load(...,
+//! resume => true)` picks up a load with the first step that is missing.
+use std::time::Duration;
+
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::guc::{PartialDatasetAction, ON_PARTIAL_DATASET};
+use crate::schema;
+use crate::tables::{SPEC_SCALE_FACTORS, TABLES};
+
+extension_sql!(
+    r#"
+    CREATE TABLE pg_tpch_load_info (
+        load_id bigserial PRIMARY KEY,
+        schema_name text NOT NULL,
+        sf double precision NOT NULL,
+        spec_compliant boolean NOT NULL,
+        children integer NOT NULL,
+        parts_completed integer[] NOT NULL DEFAULT '{}',
+        schema_profile text NOT NULL DEFAULT 'default',
+        generated_columns boolean NOT NULL DEFAULT false,
+        unlogged boolean NOT NULL DEFAULT false,
+        bigint_keys boolean NOT NULL DEFAULT false,
+        double_precision boolean NOT NULL DEFAULT false,
+        text_columns boolean NOT NULL DEFAULT false,
+        range_partitioning text NOT NULL DEFAULT 'none',
+        hash_partitions integer NOT NULL DEFAULT 0,
+        access_methods jsonb NOT NULL DEFAULT '{}',
+        tablespace text,
+        storage_parameters text[] NOT NULL DEFAULT '{}',
+        options jsonb NOT NULL DEFAULT '{}',
+        generator_version text NOT NULL,
+        server_version text NOT NULL DEFAULT current_setting('server_version'),
+        started_at timestamptz NOT NULL DEFAULT now(),
+        completed_at timestamptz,
+        duration interval NOT NULL DEFAULT '0',
+        truncated_at timestamptz
+    );
+    COMMENT ON COLUMN pg_tpch_load_info.schema_name IS 'schema of the TPC-H tables loaded';
+    COMMENT ON COLUMN pg_tpch_load_info.spec_compliant IS 'whether sf is one the TPC-H specification allows';
+    COMMENT ON COLUMN pg_tpch_load_info.generated_columns IS 'whether lineitem had the columns of tpch_set_generated_columns';
+    COMMENT ON COLUMN pg_tpch_load_info.unlogged IS 'whether the tables were unlogged, see tpch_set_unlogged';
+    COMMENT ON COLUMN pg_tpch_load_info.bigint_keys IS 'whether the key columns were bigint, see tpch_set_bigint_keys';
+    COMMENT ON COLUMN pg_tpch_load_info.double_precision IS 'whether the decimal columns were double precision, see tpch_set_double_precision';
+    COMMENT ON COLUMN pg_tpch_load_info.text_columns IS 'whether the character columns were text, see tpch_set_text_columns';
+    COMMENT ON COLUMN pg_tpch_load_info.range_partitioning IS 'how orders and lineitem were range partitioned, see tpch_set_range_partitioning';
+    COMMENT ON COLUMN pg_tpch_load_info.hash_partitions IS 'into how many partitions orders and lineitem were hash partitioned, 0 when they were not, see tpch_set_hash_partitioning';
+    COMMENT ON COLUMN pg_tpch_load_info.access_methods IS 'table access methods of the tables not stored with heap, see tpch_set_access_method';
+    COMMENT ON COLUMN pg_tpch_load_info.tablespace IS 'tablespace of lineitem, NULL for the default one of the database, see tpch_set_tablespace';
+    COMMENT ON COLUMN pg_tpch_load_info.storage_parameters IS 'storage parameters of lineitem, see pg_tpch.storage_parameters';
+    COMMENT ON COLUMN pg_tpch_load_info.server_version IS 'version of the Postgres server that ran the first step';
+    COMMENT ON COLUMN pg_tpch_load_info.duration IS 'sum of the durations of all completed steps';
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_load_info', '');
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_load_info_load_id_seq', '');
+
+    CREATE VIEW pg_tpch_dataset AS
+        SELECT * FROM pg_tpch_load_info
+        WHERE truncated_at IS NULL
+          AND schema_name = (SELECT n.nspname::text
+                             FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+                             WHERE c.oid = to_regclass('lineitem'));
+
+    CREATE TABLE pg_tpch_table_state (
+        schema_name text NOT NULL,
+        table_name text NOT NULL,
+        dirty boolean NOT NULL DEFAULT false,
+        changed_at timestamptz NOT NULL DEFAULT now(),
+        PRIMARY KEY (schema_name, table_name)
+    );
+    INSERT INTO pg_tpch_table_state (schema_name, table_name)
+        SELECT n.nspname, t
+        FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace,
+             unnest(ARRAY['region', 'nation', 'part', 'supplier',
+                          'partsupp', 'customer', 'orders', 'lineitem']) t
+        WHERE c.oid = 'lineitem'::regclass;
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_table_state', '');
+
+    CREATE TABLE pg_tpch_load_stats (
+        load_id bigint NOT NULL REFERENCES pg_tpch_load_info ON DELETE CASCADE,
+        part integer NOT NULL,
+        table_name text NOT NULL,
+        rows bigint NOT NULL,
+        file_bytes bigint NOT NULL,
+        peak_memory bigint NOT NULL,
+        table_bytes bigint,
+        generation_ms double precision,
+        copy_ms double precision,
+        recorded_at timestamptz NOT NULL DEFAULT now()
+    );
+    COMMENT ON COLUMN pg_tpch_load_stats.peak_memory IS 'peak bytes allocated while generating the table';
+    COMMENT ON COLUMN pg_tpch_load_stats.table_bytes IS 'bytes the table grew by on disk, NULL when loaded from files';
+    COMMENT ON COLUMN pg_tpch_load_stats.generation_ms IS 'time spent generating rows and spilling them, NULL when loaded from files';
+    COMMENT ON COLUMN pg_tpch_load_stats.copy_ms IS 'time Postgres spent ingesting the rows with COPY or INSERT, NULL when loaded from files';
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_load_stats', '');
+
+    CREATE TABLE pg_tpch_load_steps (
+        load_id bigint NOT NULL REFERENCES pg_tpch_load_info ON DELETE CASCADE,
+        part integer NOT NULL,
+        table_name text NOT NULL,
+        completed_at timestamptz NOT NULL DEFAULT clock_timestamp(),
+        PRIMARY KEY (load_id, part, table_name)
+    );
+    COMMENT ON TABLE pg_tpch_load_steps IS 'tables every step of a load finished';
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_load_steps', '');
+    "#,
+    name = "load_info",
+    requires = ["create_schema"]
+);
+
+extension_sql!(
+    r#"
+    CREATE TABLE pg_tpch_datasets (
+        schema_name text PRIMARY KEY,
+        sf double precision NOT NULL,
+        load_id bigint REFERENCES pg_tpch_load_info ON DELETE SET NULL,
+        registered_at timestamptz NOT NULL DEFAULT now(),
+        loaded_at timestamptz NOT NULL DEFAULT now()
+    );
+    COMMENT ON TABLE pg_tpch_datasets IS 'datasets loaded side by side with tpch_load(target_schema => ...), which the runners can target';
+    COMMENT ON COLUMN pg_tpch_datasets.load_id IS 'the last load into the schema';
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_datasets', '');
+    "#,
+    name = "datasets",
+    requires = ["load_info"]
+);
+
+// Steps of a load may run at the same time, the durations of the totals add
+// up those of the tables like `pg_tpch_load_info.duration` does.
+extension_sql!(
+    r#"
+    CREATE VIEW pg_tpch_load_throughput AS
+        SELECT s.load_id, i.sf, i.server_version, s.table_name,
+               sum(s.rows)::bigint AS rows,
+               sum(s.table_bytes)::bigint AS table_bytes,
+               sum(s.generation_ms + s.copy_ms) AS duration_ms,
+               sum(s.rows) * 1000 / nullif(sum(s.generation_ms + s.copy_ms), 0) AS rows_per_sec,
+               sum(s.table_bytes) / 1048576.0 * 1000
+                   / nullif(sum(s.generation_ms + s.copy_ms), 0) AS mb_per_sec
+        FROM pg_tpch_load_stats s
+        JOIN pg_tpch_load_info i USING (load_id)
+        GROUP BY GROUPING SETS ((s.load_id, i.sf, i.server_version, s.table_name),
+                                (s.load_id, i.sf, i.server_version));
+    COMMENT ON VIEW pg_tpch_load_throughput IS 'rows and megabytes loaded per second, per table and in total with table_name NULL';
+    "#,
+    name = "load_throughput",
+    requires = ["load_info"]
+);
+
+/// Recorded as `generator_version` so data can be traced to the code that produced it.
+const GENERATOR_VERSION: &str = concat!("pg_tpch ", env!("CARGO_PKG_VERSION"), " (tpchgen 2)");
+
+/// Schema of the TPC-H tables on the search path, whose loads are the
+/// current dataset, `None` if there are none.
+pub fn dataset_schema() -> spi::Result<Option<String>> {
+    Spi::get_one(
+        "SELECT max(n.nspname::text) FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+         WHERE c.oid = to_regclass('lineitem')",
+    )
+}
+
+/// Marks every current load of the TPC-H tables on the search path as gone
+/// after they were truncated.
+pub fn mark_truncated() -> spi::Result<()> {
+    Spi::run(
+        "UPDATE pg_tpch_load_info SET truncated_at = now()
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+    )
+}
+
+/// Returns the current load of `sf` split into `children` parts, creating it
+/// if this is the first step that runs.
+pub fn find_or_begin_load(sf: f64, children: i32, options: &[(&str, String)]) -> spi::Result<i64> {
+    let existing = Spi::get_one_with_args::<i64>(
+        "SELECT max(load_id) FROM pg_tpch_dataset WHERE sf = $1 AND children = $2",
+        &[sf.into(), children.into()],
+    )?;
+    if let Some(load_id) = existing {
+        return Ok(load_id);
+    }
+
+    let (keys, values): (Vec<&str>, Vec<String>) = options.iter().cloned().unzip();
+    let (tables, access_methods): (Vec<String>, Vec<String>) =
+        schema::live_access_methods()?.into_iter().unzip();
+    Spi::get_one_with_args::<i64>(
+        "INSERT INTO pg_tpch_load_info
+             (schema_name, sf, spec_compliant, children, schema_profile, generated_columns, unlogged,
+              bigint_keys, double_precision, text_columns, range_partitioning, hash_partitions,
+              access_methods, tablespace, storage_parameters, options, generator_version)
+         VALUES ($19, $1, $6, $2, $7, $8, $9, $10, $11, $12, $13, $17, jsonb_object($14, $15), $16,
+                 $18, jsonb_object($3, $4), $5)
+         RETURNING load_id",
+        &[
+            sf.into(),
+            children.into(),
+            keys.into(),
+            values.into(),
+            GENERATOR_VERSION.into(),
+            SPEC_SCALE_FACTORS.contains(&sf).into(),
+            schema::live_profile()?.into(),
+            schema::has_generated_columns()?.into(),
+            schema::has_unlogged_tables()?.into(),
+            schema::has_bigint_keys()?.into(),
+            schema::has_double_decimals()?.into(),
+            schema::has_text_columns()?.into(),
+            schema::live_range_partitioning()?.into(),
+            tables.into(),
+            access_methods.into(),
+            schema::live_tablespace()?.into(),
+            schema::live_hash_partitions()?.into(),
+            schema::live_storage_parameters("lineitem")?.into(),
+            dataset_schema()?
+                .expect("loads have TPC-H tables")
+                .into(),
+        ],
+    )
+    .map(|load_id| load_id.expect("INSERT ... RETURNING yields a row"))
+}
+
+/// Whether `part` of the load already finished.
+pub fn part_completed(load_id: i64, part: i32) -> spi::Result<bool> {
+    Spi::get_one_with_args::<bool>(
+        "SELECT $2 = ANY(parts_completed) FROM pg_tpch_load_info WHERE load_id = $1",
+        &[load_id.into(), part.into()],
+    )
+    .map(|completed| completed == Some(true))
+}
+
+/// Records that `part` of the load finished after `seconds`.
+///
+/// Reloading a part that already finished adds to the duration but doesn't
+/// record the part twice.
+pub fn complete_part(load_id: i64, part: i32, seconds: f64) -> spi::Result<()> {
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info
+         SET parts_completed = CASE
+                 WHEN $2 = ANY(parts_completed) THEN parts_completed
+                 ELSE array_append(parts_completed, $2)
+             END,
+             duration = duration + make_interval(secs => $3),
+             completed_at = CASE
+                 WHEN cardinality(array_remove(parts_completed, $2)) + 1 >= children THEN now()
+             END
+         WHERE load_id = $1",
+        &[load_id.into(), part.into(), seconds.into()],
+    )
+}
+
+/// Returns the parts of the current load of SF=`sf` in `children` steps that
+/// haven't finished, in order, or `None` if there is no such load.
+pub fn incomplete_parts(sf: f64, children: i32) -> spi::Result<Option<Vec<i32>>> {
+    let completed = Spi::get_one_with_args::<Vec<i32>>(
+        "SELECT (SELECT parts_completed FROM pg_tpch_dataset
+                 WHERE sf = $1 AND children = $2
+                 ORDER BY load_id DESC LIMIT 1)",
+        &[sf.into(), children.into()],
+    )?;
+    Ok(completed.map(|completed| {
+        (1..=children)
+            .filter(|part| !completed.contains(part))
+            .collect()
+    }))
+}
+
+/// Returns the tables `part` of the load finished so far.
+pub fn completed_tables(load_id: i64, part: i32) -> spi::Result<Vec<String>> {
+    Spi::get_one_with_args::<Vec<String>>(
+        "SELECT coalesce(array_agg(table_name), '{}') FROM pg_tpch_load_steps
+         WHERE load_id = $1 AND part = $2",
+        &[load_id.into(), part.into()],
+    )
+    .map(Option::unwrap_or_default)
+}
+
+/// Records that `part` of the load finished `table`.
+pub fn complete_table(load_id: i64, part: i32, table: &str) -> spi::Result<()> {
+    Spi::run_with_args(
+        "INSERT INTO pg_tpch_load_steps (load_id, part, table_name) VALUES ($1, $2, $3)
+         ON CONFLICT DO NOTHING",
+        &[load_id.into(), part.into(), table.into()],
+    )
+}
+
+/// Forgets the tables `part` of the load finished, before loading it again.
+pub fn forget_tables(load_id: i64, part: i32) -> spi::Result<()> {
+    Spi::run_with_args(
+        "DELETE FROM pg_tpch_load_steps WHERE load_id = $1 AND part = $2",
+        &[load_id.into(), part.into()],
+    )
+}
+
+/// Statistics of loading one table in one step.
+pub struct TableStats<'a> {
+    pub table: &'a str,
+    pub rows: u64,
+    pub file_bytes: u64,
+    pub peak_memory: u64,
+    /// Bytes the table grew by on disk, if measured.
+    pub table_bytes: Option<u64>,
+    /// Time spent generating and ingesting the rows, if measured.
+    pub timing: Option<(Duration, Duration)>,
+}
+
+/// Records the statistics of loading `stats.table` for `part` of the load.
+pub fn record_table_stats(load_id: i64, part: i32, stats: &TableStats) -> spi::Result<()> {
+    Spi::run_with_args(
+        "INSERT INTO pg_tpch_load_stats
+             (load_id, part, table_name, rows, file_bytes, peak_memory, table_bytes,
+              generation_ms, copy_ms)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        &[
+            load_id.into(),
+            part.into(),
+            stats.table.into(),
+            (stats.rows as i64).into(),
+            (stats.file_bytes as i64).into(),
+            (stats.peak_memory as i64).into(),
+            stats.table_bytes.map(|bytes| bytes as i64).into(),
+            stats.timing.map(|(generation, _)| ms(generation)).into(),
+            stats.timing.map(|(_, copy)| ms(copy)).into(),
+        ],
+    )
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
+
+/// Sets the dirty marker of every TPC-H table on the search path to `dirty`.
+pub fn mark_tables(dirty: bool) -> spi::Result<()> {
+    for table in TABLES {
+        mark_table(table, dirty)?;
+    }
+    Ok(())
+}
+
+/// Sets the dirty marker of `table` on the search path to `dirty`.
+pub fn mark_table(table: &str, dirty: bool) -> spi::Result<()> {
+    let Some(schema) = dataset_schema()? else {
+        return Ok(());
+    };
+    Spi::run_with_args(
+        "INSERT INTO pg_tpch_table_state (schema_name, table_name, dirty) VALUES ($1, $2, $3)
+         ON CONFLICT (schema_name, table_name)
+         DO UPDATE SET dirty = excluded.dirty, changed_at = now()",
+        &[schema.into(), table.into(), dirty.into()],
+    )
+}
+
+/// Summarizes the TPC-H tables on the search path as `empty`, `partial`,
+/// `dirty` or `complete`.
+///
+/// `partial` means a multi-step load is missing steps, `dirty` that a load
+/// step started but never finished.
+#[pg_extern]
+pub fn tpch_dataset_state() -> spi::Result<String> {
+    let dirty = Spi::get_one_with_args::<bool>(
+        "SELECT bool_or(dirty) FROM pg_tpch_table_state WHERE schema_name = $1",
+        &[dataset_schema()?.into()],
+    )?;
+    if dirty == Some(true) {
+        return Ok("dirty".to_string());
+    }
+    let complete =
+        Spi::get_one::<bool>("SELECT bool_and(completed_at IS NOT NULL) FROM pg_tpch_dataset")?;
+    Ok(match complete {
+        None => "empty",
+        Some(false) => "partial",
+        Some(true) => "complete",
+    }
+    .to_string())
+}
+
+/// Records that the current load of SF=`sf` went into schema `schema`, the
+/// dataset of that name.
+pub fn register_dataset(schema: &str, sf: f64) -> spi::Result<()> {
+    Spi::run_with_args(
+        "INSERT INTO pg_tpch_datasets (schema_name, sf, load_id)
+         SELECT $1, $2, max(load_id) FROM pg_tpch_dataset
+         ON CONFLICT (schema_name) DO UPDATE
+         SET sf = excluded.sf, load_id = excluded.load_id, loaded_at = now()",
+        &[schema.into(), sf.into()],
+    )
+}
+
+/// Fails unless `dataset` is registered and its schema still exists.
+pub fn check_registered(dataset: &str) -> spi::Result<()> {
+    let exists = Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS (SELECT FROM pg_tpch_datasets
+                        WHERE schema_name = $1 AND to_regnamespace(quote_ident($1)) IS NOT NULL)",
+        &[dataset.into()],
+    )?;
+    if exists != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("dataset \"{dataset}\" does not exist"),
+            format!("Load it with tpch_load(target_schema => '{dataset}').")
+        );
+    }
+    Ok(())
+}
+
+/// Applies `pg_tpch.on_partial_dataset` before `operation` uses the dataset.
+pub fn check_dataset(operation: &str) -> spi::Result<()> {
+    let action = ON_PARTIAL_DATASET.get();
+    if action == PartialDatasetAction::Ignore {
+        return Ok(());
+    }
+    let state = tpch_dataset_state()?;
+    if state != "partial" && state != "dirty" {
+        return Ok(());
+    }
+
+    let message = format!("{operation} on a {state} TPC-H dataset, results are not meaningful");
+    let hint = "Finish the load or set pg_tpch.on_partial_dataset to \"ignore\".";
+    if action == PartialDatasetAction::Error {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            message,
+            hint
+        );
+    }
+    ereport!(WARNING, PgSqlErrorCode::ERRCODE_WARNING, message, hint);
+    Ok(())
+}
@@ -0,0 +1,451 @@
+//! Loading the files of a dataset through several background workers at once.
+//!
+//! A single `COPY` runs on one core, so big machines load chunked tables much
+//! faster with one `COPY` per chunk running concurrently. Every worker is its
+//! own session: it records the file it starts on in `pg_tpch_chunk_copies`
+//! and commits, then loads the file and, in the same transaction, records the
+//! rows it loaded, which is how the caller learns which files made it in once
+//! the workers are gone. Meanwhile, `pg_tpch_load_progress` shows every file
+//! of the running loads next to the progress `COPY` reports for it.
+//!
+//! `tpch_load_parallel()` generates a dataset with several workers at once in
+//! the same way, each running one step of a multi-step load in a transaction
+//! of its own.
+use std::path::Path;
+
+use pgrx::bgworkers::{BackgroundWorker, BackgroundWorkerBuilder, SignalWakeFlags};
+use pgrx::pg_sys;
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::format::CsvOptions;
+use crate::ingest::FileFormat;
+use crate::strategy::LoadStrategy;
+use crate::tables::TABLES;
+use crate::{metadata, spill};
+
+extension_sql!(
+    r#"
+    CREATE TABLE pg_tpch_chunk_copies (
+        table_name text NOT NULL,
+        path text NOT NULL,
+        bytes bigint NOT NULL,
+        leader_pid integer NOT NULL,
+        pid integer NOT NULL,
+        rows bigint,
+        started_at timestamptz NOT NULL DEFAULT clock_timestamp(),
+        finished_at timestamptz
+    );
+    COMMENT ON TABLE pg_tpch_chunk_copies IS 'files loaded by background workers, written by the workers themselves';
+    COMMENT ON COLUMN pg_tpch_chunk_copies.leader_pid IS 'backend that started the worker';
+    COMMENT ON COLUMN pg_tpch_chunk_copies.rows IS 'NULL until the file is loaded';
+    "#,
+    name = "chunk_copies",
+    requires = ["create_schema"]
+);
+
+// The chunks of every load whose leader is still connected, with the `COPY`
+// progress of those being loaded, and the `COPY`s into the TPC-H tables that
+// run outside of workers. `COPY` reports progress from Postgres 14 on.
+#[cfg(not(feature = "pg13"))]
+extension_sql!(
+    r#"
+    CREATE VIEW pg_tpch_load_progress AS
+        SELECT c.table_name, c.path, c.leader_pid, c.pid,
+               CASE WHEN c.finished_at IS NOT NULL THEN 'loaded'
+                    WHEN p.pid IS NOT NULL THEN 'copying'
+                    ELSE 'failed'
+               END AS state,
+               coalesce(c.rows, p.tuples_processed) AS tuples_processed,
+               CASE WHEN c.finished_at IS NOT NULL THEN c.bytes ELSE p.bytes_processed END
+                   AS bytes_processed,
+               c.bytes AS bytes_total, c.started_at, c.finished_at
+        FROM pg_tpch_chunk_copies c
+        JOIN pg_stat_activity a ON a.pid = c.leader_pid AND c.started_at >= a.backend_start
+        LEFT JOIN pg_stat_progress_copy p ON p.pid = c.pid AND c.finished_at IS NULL
+        UNION ALL
+        SELECT p.relid::regclass::text, NULL, p.pid, p.pid, 'copying', p.tuples_processed,
+               p.bytes_processed, nullif(p.bytes_total, 0), a.query_start, NULL
+        FROM pg_stat_progress_copy p JOIN pg_stat_activity a USING (pid)
+        WHERE p.relid::regclass::text IN
+                  ('region', 'nation', 'part', 'supplier', 'partsupp', 'customer', 'orders',
+                   'lineitem')
+          AND NOT EXISTS (SELECT FROM pg_tpch_chunk_copies c WHERE c.pid = p.pid);
+    COMMENT ON VIEW pg_tpch_load_progress IS 'files of the running loads and how far COPY got with each';
+    "#,
+    name = "load_progress",
+    requires = ["chunk_copies"]
+);
+
+#[cfg(feature = "pg13")]
+extension_sql!(
+    r#"
+    CREATE VIEW pg_tpch_load_progress AS
+        SELECT c.table_name, c.path, c.leader_pid, c.pid,
+               CASE WHEN c.finished_at IS NOT NULL THEN 'loaded'
+                    WHEN EXISTS (SELECT FROM pg_stat_activity w WHERE w.pid = c.pid)
+                        THEN 'copying'
+                    ELSE 'failed'
+               END AS state,
+               c.rows AS tuples_processed,
+               CASE WHEN c.finished_at IS NOT NULL THEN c.bytes END AS bytes_processed,
+               c.bytes AS bytes_total, c.started_at, c.finished_at
+        FROM pg_tpch_chunk_copies c
+        JOIN pg_stat_activity a ON a.pid = c.leader_pid AND c.started_at >= a.backend_start;
+    COMMENT ON VIEW pg_tpch_load_progress IS 'files of the running loads and whether they are loaded';
+    "#,
+    name = "load_progress",
+    requires = ["chunk_copies"]
+);
+
+/// Room for the job description handed to a worker, `BGW_EXTRALEN` less the
+/// terminating NUL.
+pub const MAX_JOB_LEN: usize = pg_sys::BGW_EXTRALEN as usize - 1;
+
+/// What a worker loads, passed through `bgw_extra` as tab-separated fields.
+fn describe_job(table: &str, path: &Path, format: &FileFormat) -> String {
+    let (format_name, delimiter, quote, header, null, encoding) = match format {
+        FileFormat::Csv(csv) => (
+            "csv",
+            csv.delimiter,
+            csv.quote,
+            csv.header,
+            csv.null.as_str(),
+            csv.encoding.as_str(),
+        ),
+        FileFormat::Tbl => ("tbl", ',', '"', true, "", "UTF8"),
+    };
+    // SAFETY: MyProcPid is set for the lifetime of the backend.
+    let leader = unsafe { pg_sys::MyProcPid };
+    format!(
+        "{leader}\t{table}\t{format_name}\t{delimiter}\t{quote}\t{header}\t{null}\t{encoding}\t{}",
+        path.display()
+    )
+}
+
+/// Worker argument carrying the database and user OIDs of this session, see
+/// [`connect_worker`].
+pub fn session_argument() -> pg_sys::Datum {
+    // SAFETY: both are set once the backend is connected to its database.
+    let ids = unsafe {
+        (u32::from(pg_sys::MyDatabaseId) as u64) << 32 | u32::from(pg_sys::GetUserId()) as u64
+    };
+    pg_sys::Datum::from(ids)
+}
+
+/// Connects a worker to the database of the session that started it with
+/// [`session_argument`], as the same user, so it works with the same
+/// privileges.
+pub fn connect_worker(arg: pg_sys::Datum) {
+    let ids = arg.value() as u64;
+    let database = pg_sys::Oid::from((ids >> 32) as u32);
+    let user = pg_sys::Oid::from(ids as u32);
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGTERM);
+    BackgroundWorker::connect_worker_to_spi_by_oid(Some(database), Some(user));
+}
+
+/// Entry point of the workers started by [`copy_files`].
+#[pg_guard]
+#[no_mangle]
+pub extern "C-unwind" fn pg_tpch_copy_worker(arg: pg_sys::Datum) {
+    connect_worker(arg);
+
+    let job = BackgroundWorker::get_extra();
+    let fields: Vec<&str> = job.splitn(9, '\t').collect();
+    let [leader, table, format, delimiter, quote, header, null, encoding, path] = fields[..] else {
+        panic!("invalid copy job \"{job}\"");
+    };
+    let leader: i32 = leader
+        .parse()
+        .unwrap_or_else(|_| panic!("invalid copy job \"{job}\""));
+    let bytes = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+    BackgroundWorker::transaction(|| {
+        Spi::run_with_args(
+            "INSERT INTO pg_tpch_chunk_copies (table_name, path, bytes, leader_pid, pid)
+             VALUES ($1, $2, $3, $4, pg_backend_pid())",
+            &[
+                table.into(),
+                path.into(),
+                (bytes as i64).into(),
+                leader.into(),
+            ],
+        )
+    })
+    .unwrap_or_else(|e| panic!("could not record the copy of \"{path}\": {e}"));
+    BackgroundWorker::transaction(|| {
+        let rows = FileFormat::new(format, delimiter, quote)
+            .with_export_options(header == "true", null, encoding)
+            .copy_file(table, Path::new(path));
+        Spi::run_with_args(
+            "UPDATE pg_tpch_chunk_copies SET rows = $2, finished_at = clock_timestamp()
+             WHERE pid = pg_backend_pid() AND path = $1 AND finished_at IS NULL",
+            &[path.into(), (rows as i64).into()],
+        )
+    })
+    .unwrap_or_else(|e| panic!("could not record the copy of \"{path}\": {e}"));
+}
+
+/// Loads every `(table, path)` of `files` with up to `workers` background
+/// workers at a time and returns the rows each file held.
+///
+/// The workers commit on their own, so the tables must not be locked by the
+/// calling transaction, which would leave the workers waiting for it while it
+/// waits for them. In particular they can't have been truncated in it.
+pub fn copy_files(
+    files: &[(&str, &Path)],
+    format: &FileFormat,
+    workers: usize,
+) -> spi::Result<Vec<u64>> {
+    let started = Spi::get_one::<TimestampWithTimeZone>("SELECT clock_timestamp()")?;
+
+    for batch in files.chunks(workers) {
+        let mut handles = Vec::with_capacity(batch.len());
+        for &(table, path) in batch {
+            let job = describe_job(table, path, format);
+            if job.len() > MAX_JOB_LEN {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_NAME_TOO_LONG,
+                    format!(
+                        "path \"{}\" is too long to hand to a worker",
+                        path.display()
+                    ),
+                    "Load from a directory with a shorter path."
+                );
+            }
+            let handle = BackgroundWorkerBuilder::new(&format!("pg_tpch copy {table}"))
+                .set_type("pg_tpch copy")
+                .set_library("pg_tpch")
+                .set_function("pg_tpch_copy_worker")
+                .enable_spi_access()
+                .set_argument(Some(session_argument()))
+                .set_extra(&job)
+                // SAFETY: MyProcPid is set for the lifetime of the backend.
+                .set_notify_pid(unsafe { pg_sys::MyProcPid })
+                .load_dynamic()
+                .unwrap_or_else(|_| {
+                    ereport!(
+                        ERROR,
+                        PgSqlErrorCode::ERRCODE_CONFIGURATION_LIMIT_EXCEEDED,
+                        "could not start a background worker to load a file",
+                        "Use fewer workers or raise max_worker_processes."
+                    );
+                });
+            handles.push(handle);
+        }
+        for handle in handles {
+            // A worker that already stopped is fine, its outcome is checked
+            // below.
+            let _ = handle.wait_for_shutdown();
+        }
+    }
+
+    let mut rows = Vec::with_capacity(files.len());
+    for &(table, path) in files {
+        let loaded = Spi::get_one_with_args::<i64>(
+            "SELECT max(rows) FROM pg_tpch_chunk_copies
+             WHERE table_name = $1 AND path = $2 AND started_at >= $3",
+            &[
+                table.into(),
+                path.display().to_string().into(),
+                started.into(),
+            ],
+        )?;
+        let Some(loaded) = loaded else {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_EXTERNAL_ROUTINE_EXCEPTION,
+                format!("could not load \"{}\" into {table}", path.display()),
+                "The worker loading it failed, see the server log for its error."
+            );
+        };
+        rows.push(loaded as u64);
+    }
+    Ok(rows)
+}
+
+/// Whether this transaction holds a lock on a TPC-H table that workers would
+/// have to wait for, anything beyond the share lock of reading it.
+pub fn holds_table_locks() -> spi::Result<bool> {
+    let locked = Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS (SELECT FROM pg_locks
+                        WHERE pid = pg_backend_pid() AND mode <> 'AccessShareLock'
+                          AND relation = ANY(SELECT to_regclass(t) FROM unnest($1::text[]) t))",
+        &[TABLES.to_vec().into()],
+    )?;
+    Ok(locked == Some(true))
+}
+
+/// Entry point of the workers started by [`tpch_load_parallel`], which get
+/// their job through `bgw_extra` as tab-separated fields: `prepare` or
+/// `step`, the scale factor, the number of steps, the step, the strategy and
+/// the data directory.
+#[pg_guard]
+#[no_mangle]
+pub extern "C-unwind" fn pg_tpch_load_worker(arg: pg_sys::Datum) {
+    connect_worker(arg);
+
+    let job = BackgroundWorker::get_extra();
+    let fields: Vec<&str> = job.splitn(6, '\t').collect();
+    let [kind, sf, children, step, strategy, data_dir] = fields[..] else {
+        panic!("invalid load job \"{job}\"");
+    };
+    let (Ok(sf), Ok(children), Ok(step)) = (
+        sf.parse::<f64>(),
+        children.parse::<i64>(),
+        step.parse::<i64>(),
+    ) else {
+        panic!("invalid load job \"{job}\"");
+    };
+    BackgroundWorker::transaction(|| match kind {
+        "prepare" => {
+            let dir = spill::data_dir(Some(data_dir));
+            let strategy = LoadStrategy::new(strategy, &dir)?;
+            crate::truncate_tables()?;
+            let options = crate::load_options(&CsvOptions::default(), "uniform", strategy, "csv");
+            metadata::find_or_begin_load(sf, children as i32, &options)?;
+            metadata::mark_tables(true)
+        }
+        _ => crate::load_step(
+            sf,
+            children,
+            step,
+            ",",
+            "\"",
+            false,
+            "error",
+            false,
+            "uniform",
+            None,
+            "none",
+            strategy,
+            "csv",
+            Some(data_dir),
+            false,
+            crate::StepMode::Coordinated,
+            None,
+        )
+        .map(|_| ()),
+    })
+    .unwrap_or_else(|e| panic!("could not run load job \"{job}\": {e}"));
+}
+
+/// Runs a worker for each of `jobs` at once and waits until all stopped.
+fn run_load_workers(jobs: &[String]) {
+    let mut handles = Vec::with_capacity(jobs.len());
+    for job in jobs {
+        if job.len() > MAX_JOB_LEN {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_NAME_TOO_LONG,
+                "pg_tpch.data_dir is too long to hand to a worker",
+                "Use a directory with a shorter path."
+            );
+        }
+        let handle = BackgroundWorkerBuilder::new("pg_tpch load")
+            .set_type("pg_tpch load")
+            .set_library("pg_tpch")
+            .set_function("pg_tpch_load_worker")
+            .enable_spi_access()
+            .set_argument(Some(session_argument()))
+            .set_extra(job)
+            // SAFETY: MyProcPid is set for the lifetime of the backend.
+            .set_notify_pid(unsafe { pg_sys::MyProcPid })
+            .load_dynamic()
+            .unwrap_or_else(|_| {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_CONFIGURATION_LIMIT_EXCEEDED,
+                    "could not start a background worker to load a step",
+                    "Use fewer workers or raise max_worker_processes."
+                );
+            });
+        handles.push(handle);
+    }
+    for handle in handles {
+        // A worker that already stopped is fine, its outcome is checked by
+        // the caller.
+        let _ = handle.wait_for_shutdown();
+    }
+}
+
+/// Loads SF=`sf` with `workers` background workers, each loading one step of
+/// a load in that many steps, and waits until they are done.
+///
+/// A first worker empties the tables and starts the load, then the steps run
+/// at once, each in its own transaction that commits on its own, so this
+/// must be called in a transaction that hasn't locked the TPC-H tables. The
+/// workers use the server's configuration, not the settings of this session,
+/// except for `pg_tpch.data_dir`. The tables stay dirty if a step fails, its
+/// error is in the server log, and `tpch_load` can load the missing step.
+#[pg_extern]
+fn tpch_load_parallel(
+    sf: default!(f64, 1.),
+    workers: default!(i32, 4),
+    strategy: default!(&str, "'auto'"),
+) -> spi::Result<String> {
+    if workers < 1 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("workers must be at least 1, got {workers}")
+        );
+    }
+    crate::check_scale_factor(sf, false);
+    if holds_table_locks()? {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            "loading with workers needs the TPC-H tables unlocked",
+            "Call tpch_load_parallel in a transaction of its own."
+        );
+    }
+    let dir = spill::data_dir(None);
+    let strategy = LoadStrategy::new(strategy, &dir)?;
+    if strategy.uses_files() {
+        spill::create_dir(&dir);
+    }
+
+    let job = |kind: &str, step: i32| {
+        format!(
+            "{kind}\t{sf}\t{workers}\t{step}\t{strategy}\t{}",
+            dir.display()
+        )
+    };
+    run_load_workers(&[job("prepare", 0)]);
+    let load_id = Spi::get_one_with_args::<i64>(
+        "SELECT max(load_id) FROM pg_tpch_dataset WHERE sf = $1 AND children = $2",
+        &[sf.into(), workers.into()],
+    )?;
+    let Some(load_id) = load_id else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_EXTERNAL_ROUTINE_EXCEPTION,
+            format!("could not start loading TPC-H SF={sf}"),
+            "The worker preparing the load failed, see the server log for its error."
+        );
+    };
+
+    let jobs: Vec<String> = (0..workers).map(|step| job("step", step)).collect();
+    run_load_workers(&jobs);
+    let completed = Spi::get_one_with_args::<i32>(
+        "SELECT cardinality(parts_completed) FROM pg_tpch_load_info WHERE load_id = $1",
+        &[load_id.into()],
+    )?
+    .unwrap_or_default();
+    if completed < workers {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_EXTERNAL_ROUTINE_EXCEPTION,
+            format!(
+                "{} of {workers} steps of loading TPC-H SF={sf} failed",
+                workers - completed
+            ),
+            "The workers loading them failed, see the server log for their errors."
+        );
+    }
+    metadata::mark_tables(false)?;
+    Ok(format!(
+        "TPC-H SF={sf} loaded by {workers} workers (strategy {strategy})"
+    ))
+}
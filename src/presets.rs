@@ -0,0 +1,147 @@
+//! Named end-to-end benchmark configurations.
+//!
+//! A preset fixes everything between an empty database and a finished run:
+//! the schema profile, the load options, what is built after the load and
+//! the settings the queries run with. Running a preset again reproduces the
+//! same setup, and its runs carry the preset's name in `pg_tpch_runs`.
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::runner::{self, RunOptions};
+use crate::tables::TABLES;
+use crate::{constraints, indexes, queries};
+
+/// An end-to-end configuration.
+struct Preset {
+    name: &'static str,
+    /// Schema profile the tables get before the load.
+    schema_profile: &'static str,
+    /// Whether `lineitem` gets the stored generated columns.
+    generated_columns: bool,
+    /// Arguments of `tpch_load` by name.
+    load_options: &'static [(&'static str, &'static str)],
+    /// Whether the primary and foreign keys are added after the load.
+    constraints: bool,
+    /// Index profile built after the load.
+    index_profile: Option<&'static str>,
+    /// Runner profile the queries run with.
+    run_profile: &'static str,
+}
+
+const PRESETS: &[Preset] = &[
+    // The tables as the extension creates them, with nothing added.
+    Preset {
+        name: "vanilla",
+        schema_profile: "default",
+        generated_columns: false,
+        load_options: &[],
+        constraints: false,
+        index_profile: None,
+        run_profile: "default",
+    },
+    // The keys of the specification and B-tree indexes on the dates.
+    Preset {
+        name: "indexed",
+        schema_profile: "default",
+        generated_columns: false,
+        load_options: &[],
+        constraints: true,
+        index_profile: Some("btree"),
+        run_profile: "analytics",
+    },
+    // Enums for the codes, precomputed revenue and covering indexes.
+    Preset {
+        name: "precomputed",
+        schema_profile: "enum",
+        generated_columns: true,
+        load_options: &[],
+        constraints: true,
+        index_profile: Some("covering"),
+        run_profile: "analytics",
+    },
+];
+
+/// Sets up the database as preset `name` says, loads SF=`sf` and runs all 22
+/// queries, returning their timings like `tpch_run_all`.
+///
+/// Presets are `vanilla`, the tables as they are created with nothing added,
+/// `indexed`, with the primary and foreign keys, the `btree` index profile
+/// and the `analytics` runner profile, and `precomputed`, which adds the
+/// `enum` schema profile, the generated columns of
+/// `tpch_set_generated_columns()` and the `covering` index profile. Keys and
+/// index profiles of an earlier setup are dropped first, so every preset
+/// starts from the same state. The tables are analyzed after the load.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_preset(
+    name: &str,
+    sf: default!(f64, 1.),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(run_id, i64),
+            name!(query_nr, i32),
+            name!(iteration, i32),
+            name!(duration_ms, f64),
+            name!(rows, i64),
+            name!(peak_memory, i64),
+            name!(temp_bytes, i64),
+        ),
+    >,
+> {
+    let Some(preset) = PRESETS.iter().find(|preset| preset.name == name) else {
+        let names: Vec<&str> = PRESETS.iter().map(|preset| preset.name).collect();
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("unknown preset \"{name}\", expected one of {names:?}")
+        );
+    };
+
+    indexes::drop_indexes(None)?;
+    constraints::drop_constraints()?;
+    Spi::run_with_args(
+        "SELECT tpch_set_schema_profile($1), tpch_set_generated_columns($2)",
+        &[
+            preset.schema_profile.into(),
+            preset.generated_columns.into(),
+        ],
+    )?;
+    // Analyzed below, once the indexes exist.
+    let mut arguments = vec![format!("sf => {sf}"), "analyze => false".to_string()];
+    for (option, value) in preset.load_options {
+        arguments.push(format!("{option} => {}", spi::quote_literal(value)));
+    }
+    Spi::run(&format!("SELECT tpch_load({})", arguments.join(", ")))?;
+    if preset.constraints {
+        Spi::run("SELECT count(*) FROM tpch_add_constraints()")?;
+    }
+    if let Some(profile) = preset.index_profile {
+        indexes::create_indexes(profile, 128)?;
+    }
+    Spi::run(&format!("ANALYZE {}", TABLES.join(", ")))?;
+
+    let plan: Vec<(i32, i32)> = queries::QUERIES.iter().map(|(nr, _)| (*nr, 1)).collect();
+    let results = runner::run_queries(
+        &plan,
+        &RunOptions {
+            profile: preset.run_profile,
+            role: None,
+            persist_results: false,
+            baseline_run: None,
+            max_regression: 0.,
+            max_duration: None,
+            cancel_in_flight: false,
+            dataset: None,
+            prewarm: false,
+        },
+    )?;
+    if let Some(result) = results.first() {
+        Spi::run_with_args(
+            "UPDATE pg_tpch_runs SET preset = $2 WHERE run_id = $1",
+            &[result.run_id.into(), preset.name.into()],
+        )?;
+    }
+    Ok(TableIterator::new(runner::into_rows(results)))
+}
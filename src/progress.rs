@@ -0,0 +1,318 @@
+//! Progress of the running loads, table by table.
+//!
+//! A load runs in one transaction, so nothing it records in tables shows
+//! before it commits. Instead, a loading backend keeps a small file in
+//! `pg_stat_tmp` with the tables of its step: the rows expected, generated
+//! and loaded and the bytes spilled. The file is rewritten whenever a table
+//! starts or ends and about once a second while rows are generated, and
+//! removed when the transaction ends. `pg_tpch_progress` shows the files of
+//! the backends still connected, with the rows `COPY` reports for the table
+//! being loaded.
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use pgrx::prelude::*;
+use pgrx::{register_xact_callback, PgXactCallbackEvent};
+
+use crate::spill;
+
+// The view estimates when a table is done from the share of its rows loaded
+// so far, or generated while nothing was loaded yet. `COPY` reports progress
+// from Postgres 14 on.
+#[cfg(not(feature = "pg13"))]
+extension_sql!(
+    r#"
+    CREATE VIEW pg_tpch_progress AS
+        SELECT p.pid, p.table_name, p.part, p.parts, p.rows_expected, p.rows_generated,
+               greatest(p.rows_loaded, c.tuples_processed) AS rows_loaded, p.bytes_spilled,
+               coalesce(p.finished_at, clock_timestamp()) - p.started_at AS elapsed,
+               CASE WHEN p.finished_at IS NOT NULL THEN p.finished_at
+                    WHEN greatest(p.rows_loaded, c.tuples_processed, p.rows_generated) > 0
+                        THEN p.started_at + (clock_timestamp() - p.started_at)
+                             * (p.rows_expected::float8
+                                / greatest(p.rows_loaded, c.tuples_processed, p.rows_generated))
+               END AS estimated_completion
+        FROM tpch_progress_files() p
+        JOIN pg_stat_activity a ON a.pid = p.pid
+        LEFT JOIN pg_stat_progress_copy c
+            ON c.pid = p.pid AND c.relid = to_regclass(p.table_name) AND p.finished_at IS NULL;
+    COMMENT ON VIEW pg_tpch_progress IS 'tables of the running loads and how far each got';
+    "#,
+    name = "progress",
+    requires = [tpch_progress_files]
+);
+
+#[cfg(feature = "pg13")]
+extension_sql!(
+    r#"
+    CREATE VIEW pg_tpch_progress AS
+        SELECT p.pid, p.table_name, p.part, p.parts, p.rows_expected, p.rows_generated,
+               p.rows_loaded, p.bytes_spilled,
+               coalesce(p.finished_at, clock_timestamp()) - p.started_at AS elapsed,
+               CASE WHEN p.finished_at IS NOT NULL THEN p.finished_at
+                    WHEN greatest(p.rows_loaded, p.rows_generated) > 0
+                        THEN p.started_at + (clock_timestamp() - p.started_at)
+                             * (p.rows_expected::float8 / greatest(p.rows_loaded, p.rows_generated))
+               END AS estimated_completion
+        FROM tpch_progress_files() p
+        JOIN pg_stat_activity a ON a.pid = p.pid;
+    COMMENT ON VIEW pg_tpch_progress IS 'tables of the running loads and how far each got';
+    "#,
+    name = "progress",
+    requires = [tpch_progress_files]
+);
+
+/// Rows generated between two looks at whether the file is due.
+const CHECK_INTERVAL_ROWS: u64 = 64 * 1024;
+
+/// How often the file is rewritten while rows are generated.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whether this backend is loading.
+static TRACKING: AtomicBool = AtomicBool::new(false);
+/// Rows generated for the table being loaded.
+static GENERATED: AtomicU64 = AtomicU64::new(0);
+
+static TRACKER: Mutex<Option<Tracker>> = Mutex::new(None);
+
+struct TableProgress {
+    table: &'static str,
+    part: i32,
+    parts: i32,
+    rows_expected: i64,
+    rows_generated: u64,
+    rows_loaded: u64,
+    bytes_spilled: u64,
+    /// Timestamps as Postgres keeps them.
+    started_at: Option<pg_sys::TimestampTz>,
+    finished_at: Option<pg_sys::TimestampTz>,
+}
+
+struct Tracker {
+    tables: Vec<TableProgress>,
+    /// Index of the table being loaded.
+    current: Option<usize>,
+    published: Instant,
+}
+
+impl Tracker {
+    fn table(&mut self, table: &str) -> Option<&mut TableProgress> {
+        self.tables
+            .iter_mut()
+            .find(|progress| progress.table == table)
+    }
+
+    /// Writes the file, with the rows generated and bytes spilled so far for
+    /// the table being loaded.
+    fn publish(&mut self) {
+        if let Some(current) = self.current.and_then(|i| self.tables.get_mut(i)) {
+            current.rows_generated = GENERATED.load(Ordering::Relaxed);
+            current.bytes_spilled = current.bytes_spilled.max(spill::spilled_bytes());
+        }
+        let field = |ts: Option<pg_sys::TimestampTz>| ts.map_or(String::new(), |ts| ts.to_string());
+        let mut contents = String::new();
+        for t in &self.tables {
+            contents.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                t.table,
+                t.part,
+                t.parts,
+                t.rows_expected,
+                t.rows_generated,
+                t.rows_loaded,
+                t.bytes_spilled,
+                field(t.started_at),
+                field(t.finished_at)
+            ));
+        }
+        // Readers see the old or the new file, never half of one. Progress
+        // is best effort, a load doesn't fail over it.
+        let path = path();
+        let tmp = path.with_extension("tmp");
+        if fs::write(&tmp, contents).is_ok() {
+            let _ = fs::rename(&tmp, &path);
+        }
+        self.published = Instant::now();
+    }
+}
+
+/// File with the progress of this backend.
+fn path() -> PathBuf {
+    // SAFETY: MyProcPid is set for the lifetime of the backend.
+    let pid = unsafe { pg_sys::MyProcPid };
+    PathBuf::from(format!("pg_stat_tmp/pg_tpch_progress.{pid}"))
+}
+
+fn now() -> pg_sys::TimestampTz {
+    // SAFETY: reads the clock, callable at any time.
+    unsafe { pg_sys::GetCurrentTimestamp() }
+}
+
+fn with_tracker(f: impl FnOnce(&mut Tracker)) {
+    let mut tracker = TRACKER.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(tracker) = tracker.as_mut() {
+        f(tracker);
+    }
+}
+
+/// Starts tracking a load of `tables`, given as `(table, part, parts)`, at
+/// SF=`sf`. Tracking ends with the transaction.
+pub fn begin(sf: f64, tables: &[(&'static str, i32, i32)]) {
+    let tables = tables
+        .iter()
+        .map(|&(table, part, parts)| TableProgress {
+            table,
+            part,
+            parts,
+            rows_expected: crate::tables::expected_rows(table, sf, part, parts),
+            rows_generated: 0,
+            rows_loaded: 0,
+            bytes_spilled: 0,
+            started_at: None,
+            finished_at: None,
+        })
+        .collect();
+    let mut tracker = Tracker {
+        tables,
+        current: None,
+        published: Instant::now(),
+    };
+    tracker.publish();
+    *TRACKER.lock().unwrap_or_else(|e| e.into_inner()) = Some(tracker);
+    TRACKING.store(true, Ordering::Relaxed);
+    register_xact_callback(PgXactCallbackEvent::Commit, end);
+    register_xact_callback(PgXactCallbackEvent::Abort, end);
+}
+
+/// Marks `table` as being loaded from now on.
+pub fn start_table(table: &str) {
+    GENERATED.store(0, Ordering::Relaxed);
+    with_tracker(|tracker| {
+        tracker.current = tracker.tables.iter().position(|t| t.table == table);
+        if let Some(progress) = tracker.table(table) {
+            progress.started_at = Some(now());
+        }
+        tracker.publish();
+    });
+}
+
+/// Counts a generated row of the table being loaded.
+pub fn row_generated() {
+    if !TRACKING.load(Ordering::Relaxed) {
+        return;
+    }
+    let rows = GENERATED.fetch_add(1, Ordering::Relaxed) + 1;
+    if rows.is_multiple_of(CHECK_INTERVAL_ROWS) {
+        with_tracker(|tracker| {
+            if tracker.published.elapsed() >= PUBLISH_INTERVAL {
+                tracker.publish();
+            }
+        });
+    }
+}
+
+/// Records that `rows` of `table` were generated ahead of loading it, as
+/// `lineitem` is along with `orders`.
+pub fn generated(table: &str, rows: u64) {
+    with_tracker(|tracker| {
+        if let Some(progress) = tracker.table(table) {
+            progress.rows_generated = rows;
+        }
+    });
+}
+
+/// Marks `table` as loaded with `rows`, after spilling `bytes`.
+pub fn finish_table(table: &str, rows: u64, bytes: u64) {
+    with_tracker(|tracker| {
+        tracker.current = None;
+        if let Some(progress) = tracker.table(table) {
+            progress.rows_generated = rows;
+            progress.rows_loaded = rows;
+            progress.bytes_spilled = bytes;
+            progress.finished_at = Some(now());
+        }
+        tracker.publish();
+    });
+}
+
+/// Stops tracking and removes the file.
+fn end() {
+    TRACKING.store(false, Ordering::Relaxed);
+    if TRACKER
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .is_some()
+    {
+        let _ = fs::remove_file(path());
+    }
+}
+
+/// Returns the progress files of all backends, stale ones of backends that
+/// exited included; `pg_tpch_progress` leaves those out.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_progress_files() -> TableIterator<
+    'static,
+    (
+        name!(pid, i32),
+        name!(table_name, String),
+        name!(part, i32),
+        name!(parts, i32),
+        name!(rows_expected, i64),
+        name!(rows_generated, i64),
+        name!(rows_loaded, i64),
+        name!(bytes_spilled, i64),
+        name!(started_at, Option<TimestampWithTimeZone>),
+        name!(finished_at, Option<TimestampWithTimeZone>),
+    ),
+> {
+    let mut rows = Vec::new();
+    let Ok(entries) = fs::read_dir("pg_stat_tmp") else {
+        return TableIterator::new(rows);
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(pid) = name
+            .strip_prefix("pg_tpch_progress.")
+            .and_then(|pid| pid.parse::<i32>().ok())
+        else {
+            continue;
+        };
+        // The file may be gone since it was listed.
+        let Ok(contents) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        for line in contents.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [table, part, parts, expected, generated, loaded, spilled, started, finished] =
+                fields[..]
+            else {
+                continue;
+            };
+            let int = |field: &str| field.parse::<i64>().unwrap_or_default();
+            let timestamp = |field: &str| {
+                field
+                    .parse::<pg_sys::TimestampTz>()
+                    .ok()
+                    .and_then(|ts| TimestampWithTimeZone::try_from(ts).ok())
+            };
+            rows.push((
+                pid,
+                table.to_string(),
+                int(part) as i32,
+                int(parts) as i32,
+                int(expected),
+                int(generated),
+                int(loaded),
+                int(spilled),
+                timestamp(started),
+                timestamp(finished),
+            ));
+        }
+    }
+    TableIterator::new(rows)
+}
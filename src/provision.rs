@@ -0,0 +1,140 @@
+//! Loading a TPC-H dataset into another database.
+use pgrx::prelude::*;
+use pgrx::{spi, JsonB};
+
+/// Options of `tpch_load` that `tpch_provision` passes on.
+const LOAD_OPTIONS: &[&str] = &[
+    "children",
+    "delimiter",
+    "quote",
+    "save_rejects",
+    "on_repeat",
+    "strict",
+    "partitioning",
+    "mapping",
+    "checkpoint",
+    "strategy",
+];
+
+/// Quotes `value` for a libpq connection string.
+fn conninfo_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Turns the name of a database in this cluster into a connection string,
+/// connection strings and URIs are used as they are.
+pub fn connection_string(target: &str) -> spi::Result<String> {
+    if target.contains('=')
+        || target.starts_with("postgres://")
+        || target.starts_with("postgresql://")
+    {
+        return Ok(target.to_string());
+    }
+    // libpq would otherwise go to its default port and socket, which need
+    // not be this server's.
+    let (port, socket) = Spi::get_two::<String, String>(
+        "SELECT current_setting('port'), \
+                trim(split_part(current_setting('unix_socket_directories', true), ',', 1))",
+    )?;
+    let mut connection = format!("dbname={}", conninfo_quote(target));
+    if let Some(port) = port {
+        connection.push_str(&format!(" port={port}"));
+    }
+    if let Some(socket) = socket.filter(|socket| !socket.is_empty()) {
+        connection.push_str(&format!(" host={}", conninfo_quote(&socket)));
+    }
+    Ok(connection)
+}
+
+/// Schema the dblink extension is installed in, which `operation` needs.
+pub fn dblink_schema(operation: &str) -> spi::Result<String> {
+    let schema = Spi::get_one::<String>(
+        "SELECT max(extnamespace::regnamespace::text) FROM pg_extension WHERE extname = 'dblink'",
+    )?;
+    Ok(schema.unwrap_or_else(|| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            format!("{operation} needs the dblink extension"),
+            "Run CREATE EXTENSION dblink."
+        );
+    }))
+}
+
+/// Creates the TPC-H schema in the database `target` and loads SF=`sf` into
+/// it.
+///
+/// `target` is a database name in this cluster or a libpq connection string
+/// or URI, which can point to another server with pg_tpch installed. The
+/// connection goes through dblink, so the usual dblink rules for passwords
+/// apply to non-superusers. `options` holds arguments of `tpch_load` by name,
+/// for example `'{"children": 4, "strategy": "insert"}'`. A load with
+/// several children runs all steps one after the other, each in its own
+/// transaction on the target.
+#[pg_extern]
+fn tpch_provision(
+    target: &str,
+    sf: default!(f64, 1.),
+    options: default!(Option<JsonB>, "NULL"),
+) -> spi::Result<Option<String>> {
+    let options = match options {
+        Some(options) => Spi::connect(|client| {
+            client
+                .select(
+                    "SELECT key, value FROM jsonb_each_text($1)",
+                    None,
+                    &[options.into()],
+                )?
+                .map(|row| {
+                    Ok((
+                        row.get::<String>(1)?.unwrap_or_default(),
+                        row.get::<String>(2)?,
+                    ))
+                })
+                .collect::<spi::Result<Vec<_>>>()
+        })?,
+        None => Vec::new(),
+    };
+    let mut arguments = vec![format!("sf => {sf}")];
+    let mut children = 1;
+    for (name, value) in &options {
+        if !LOAD_OPTIONS.contains(&name.as_str()) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                format!("unknown option \"{name}\""),
+                format!("tpch_provision passes on {}.", LOAD_OPTIONS.join(", "))
+            );
+        }
+        let Some(value) = value else {
+            continue;
+        };
+        if name == "children" {
+            children = value.parse::<i64>().unwrap_or(0);
+            if children < 1 {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    format!("children must be a positive integer, got {value}")
+                );
+            }
+        }
+        arguments.push(format!("{name} => {}", spi::quote_literal(value)));
+    }
+
+    let dblink = dblink_schema("tpch_provision")?;
+    let connection = connection_string(target)?;
+    Spi::run_with_args(
+        &format!("SELECT {dblink}.dblink_exec($1, 'CREATE EXTENSION IF NOT EXISTS pg_tpch')"),
+        &[connection.clone().into()],
+    )?;
+    let mut result = None;
+    for step in 0..children {
+        let load = format!("SELECT tpch_load({}, step => {step})", arguments.join(", "));
+        result = Spi::get_one_with_args::<String>(
+            &format!("SELECT result FROM {dblink}.dblink($1, $2) AS t(result text)"),
+            &[connection.clone().into(), load.into()],
+        )?;
+    }
+    Ok(result)
+}
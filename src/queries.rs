@@ -22,3 +22,41 @@ pub const QUERIES: &[(i32, &str)] = &[
     (21, include_str!("../sql/21.sql")),
     (22, include_str!("../sql/22.sql")),
 ];
+
+/// Result columns each query's `ORDER BY` sorts on.
+///
+/// The specification only requires results to be sorted as the query says, so
+/// rows that are equal in these columns may come back in any order. Queries
+/// returning a single row have none.
+pub const ORDER_BY: &[(i32, &[&str])] = &[
+    (1, &["l_returnflag", "l_linestatus"]),
+    (2, &["s_acctbal", "n_name", "s_name", "p_partkey"]),
+    (3, &["revenue", "o_orderdate"]),
+    (4, &["o_orderpriority"]),
+    (5, &["revenue"]),
+    (6, &[]),
+    (7, &["supp_nation", "cust_nation", "l_year"]),
+    (8, &["o_year"]),
+    (9, &["nation", "o_year"]),
+    (10, &["revenue"]),
+    (11, &["value"]),
+    (12, &["l_shipmode"]),
+    (13, &["custdist", "c_count"]),
+    (14, &[]),
+    (15, &["s_suppkey"]),
+    (16, &["supplier_cnt", "p_brand", "p_type", "p_size"]),
+    (17, &[]),
+    (18, &["o_totalprice", "o_orderdate"]),
+    (19, &[]),
+    (20, &["s_name"]),
+    (21, &["numwait", "s_name"]),
+    (22, &["cntrycode"]),
+];
+
+/// Returns the columns the result of `query_nr` is sorted on.
+pub fn order_by(query_nr: i32) -> &'static [&'static str] {
+    ORDER_BY
+        .iter()
+        .find(|(nr, _)| *nr == query_nr)
+        .map_or(&[], |(_, columns)| columns)
+}
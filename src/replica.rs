@@ -0,0 +1,167 @@
+//! Running the queries on a read-only standby.
+//!
+//! A standby can't write, so the queries run without the bookkeeping of the
+//! other runners, with query 15's view inlined, see [`runner::read_only`].
+//! The run is recorded afterwards on the primary named by
+//! `pg_tpch.primary_conninfo`, where it shows up in `pg_tpch_runs` like any
+//! other run and, once replayed, on the standby as well.
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::guc::PRIMARY_CONNINFO;
+use crate::instrument::Usage;
+use crate::{metadata, provision, queries, runner, schema};
+
+/// One executed query: number, start time, duration, rows and resources.
+type Executed = (i32, String, f64, i64, Usage);
+
+/// Records the run of `profile` with `executed` on the primary and returns its
+/// id.
+fn record_on_primary(
+    conninfo: &str,
+    profile: &str,
+    generated_columns: bool,
+    started_at: &str,
+    executed: &[Executed],
+) -> spi::Result<Option<i64>> {
+    let dblink = provision::dblink_schema("recording a run on the primary")?;
+    let results: Vec<_> = executed
+        .iter()
+        .map(|(query_nr, started_at, duration_ms, rows, usage)| {
+            format!(
+                "({query_nr}, {}::timestamptz, {duration_ms}, {rows}, {}, {}, {}, {})",
+                spi::quote_literal(started_at),
+                usage.peak_memory,
+                usage.temp_bytes,
+                usage.io_read_ms,
+                usage.io_write_ms
+            )
+        })
+        .collect();
+    let track_io_timing =
+        Spi::get_one::<bool>("SELECT current_setting('track_io_timing')::boolean")?;
+    let insert = format!(
+        "WITH run AS (
+             INSERT INTO pg_tpch_runs
+                 (load_id, profile, generated_columns, track_io_timing, started_at, completed_at)
+             VALUES ((SELECT max(load_id) FROM pg_tpch_dataset), {}, {generated_columns}, {},
+                     {}::timestamptz, clock_timestamp())
+             RETURNING run_id
+         ), results AS (
+             INSERT INTO pg_tpch_run_results
+                 (run_id, query_nr, started_at, duration_ms, rows, peak_memory, temp_bytes,
+                  io_read_ms, io_write_ms)
+             SELECT run_id, r.* FROM run, (VALUES {}) r
+         )
+         SELECT run_id FROM run",
+        spi::quote_literal(profile),
+        track_io_timing.unwrap_or(false),
+        spi::quote_literal(started_at),
+        results.join(", ")
+    );
+    Spi::get_one_with_args::<i64>(
+        &format!("SELECT run_id FROM {dblink}.dblink($1, $2) AS t(run_id bigint)"),
+        &[conninfo.into(), insert.into()],
+    )
+}
+
+/// Runs the queries in `query_nrs`, all 22 by default, on a standby and
+/// returns their timings, see `tpch_run`.
+///
+/// Nothing is truncated, loaded or written locally: the function refuses to
+/// run on a server that is not in recovery, so the data it measures is the
+/// data replicated from the primary. With `pg_tpch.primary_conninfo` set, the
+/// run is recorded on the primary through dblink and its `run_id` returned,
+/// otherwise `run_id` is NULL.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_run_replica(
+    query_nrs: default!(Option<Vec<i32>>, "NULL"),
+    profile: default!(&str, "'default'"),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(run_id, Option<i64>),
+            name!(query_nr, i32),
+            name!(duration_ms, f64),
+            name!(rows, i64),
+            name!(peak_memory, i64),
+            name!(temp_bytes, i64),
+        ),
+    >,
+> {
+    if Spi::get_one::<bool>("SELECT pg_is_in_recovery()")? != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            "tpch_run_replica must run on a standby",
+            "Use tpch_run_all or tpch_run_set on the primary."
+        );
+    }
+    let generated_columns = schema::has_generated_columns()?;
+    let query_nrs =
+        query_nrs.unwrap_or_else(|| queries::QUERIES.iter().map(|(nr, _)| *nr).collect());
+    let plan = query_nrs
+        .iter()
+        .map(|query_nr| {
+            queries::QUERIES
+                .iter()
+                .find(|(nr, _)| nr == query_nr)
+                .map(|(nr, query)| {
+                    let query = if generated_columns {
+                        schema::use_generated_columns(query)
+                    } else {
+                        query.to_string()
+                    };
+                    (*nr, runner::read_only(&query))
+                })
+                .unwrap_or_else(|| {
+                    ereport!(
+                        ERROR,
+                        PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                        format!("query number must be between 1 and 22, got {query_nr}")
+                    );
+                })
+        })
+        .collect::<Vec<_>>();
+    metadata::check_dataset("running queries")?;
+
+    let timestamp = || {
+        Ok::<_, spi::SpiError>(
+            Spi::get_one::<String>("SELECT clock_timestamp()::text")?.unwrap_or_default(),
+        )
+    };
+    let started_at = timestamp()?;
+    let previous = runner::apply_profile(profile)?;
+    let mut executed: Vec<Executed> = Vec::with_capacity(plan.len());
+    for (query_nr, query) in &plan {
+        let query_started_at = timestamp()?;
+        let (duration_ms, rows, usage) = runner::execute(query)?;
+        executed.push((*query_nr, query_started_at, duration_ms, rows, usage));
+    }
+    runner::restore_settings(&previous)?;
+
+    let run_id = match PRIMARY_CONNINFO.get() {
+        Some(conninfo) if !conninfo.is_empty() => record_on_primary(
+            &conninfo.to_string_lossy(),
+            profile,
+            generated_columns,
+            &started_at,
+            &executed,
+        )?,
+        _ => None,
+    };
+    Ok(TableIterator::new(executed.into_iter().map(
+        move |(query_nr, _, duration_ms, rows, usage)| {
+            (
+                run_id,
+                query_nr,
+                duration_ms,
+                rows,
+                usage.peak_memory as i64,
+                usage.temp_bytes as i64,
+            )
+        },
+    )))
+}
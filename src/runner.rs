@@ -0,0 +1,812 @@
+//! Running the benchmark queries and recording how they did.
+//!
+//! Every call of a runner is a run with a row in `pg_tpch_runs`, and every
+//! query it executes a row in `pg_tpch_run_results`. The plan of every query
+//! is stored once in `pg_tpch_plans`, keyed by a hash of its content, and the
+//! results refer to it, so that runs with the same plans share them.
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::instrument::{self, Usage};
+use crate::{events, maintenance, metadata, queries, schema};
+
+extension_sql!(
+    r#"
+    CREATE TABLE pg_tpch_runs (
+        run_id bigserial PRIMARY KEY,
+        load_id bigint REFERENCES pg_tpch_load_info ON DELETE SET NULL,
+        profile text NOT NULL,
+        role text,
+        max_duration interval,
+        budget_exhausted boolean NOT NULL DEFAULT false,
+        generated_columns boolean NOT NULL DEFAULT false,
+        preset text,
+        dataset text,
+        track_io_timing boolean NOT NULL DEFAULT current_setting('track_io_timing')::boolean,
+        started_at timestamptz NOT NULL DEFAULT now(),
+        completed_at timestamptz
+    );
+    COMMENT ON COLUMN pg_tpch_runs.budget_exhausted IS 'whether the run stopped before all its queries because max_duration ran out';
+    COMMENT ON COLUMN pg_tpch_runs.preset IS 'preset of tpch_preset the run was part of';
+    COMMENT ON COLUMN pg_tpch_runs.dataset IS 'dataset of pg_tpch_datasets the queries ran on, NULL for the tables of the extension';
+    COMMENT ON COLUMN pg_tpch_runs.generated_columns IS 'whether the queries read the columns of tpch_set_generated_columns';
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_runs', '');
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_runs_run_id_seq', '');
+
+    CREATE TABLE pg_tpch_plans (
+        plan_hash text PRIMARY KEY,
+        plan jsonb NOT NULL,
+        first_seen timestamptz NOT NULL DEFAULT now()
+    );
+    COMMENT ON COLUMN pg_tpch_plans.plan_hash IS 'md5 of the plan as EXPLAIN (FORMAT JSON) shows it';
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_plans', '');
+
+    CREATE TABLE pg_tpch_run_results (
+        run_id bigint NOT NULL REFERENCES pg_tpch_runs ON DELETE CASCADE,
+        query_nr integer NOT NULL,
+        iteration integer NOT NULL DEFAULT 1,
+        started_at timestamptz NOT NULL,
+        duration_ms double precision NOT NULL,
+        rows bigint NOT NULL,
+        peak_memory bigint NOT NULL,
+        temp_bytes bigint NOT NULL,
+        io_read_ms double precision NOT NULL,
+        io_write_ms double precision NOT NULL,
+        result_table text,
+        plan_hash text REFERENCES pg_tpch_plans,
+        PRIMARY KEY (run_id, query_nr, iteration)
+    );
+    CREATE INDEX ON pg_tpch_run_results (plan_hash);
+    COMMENT ON COLUMN pg_tpch_run_results.peak_memory IS 'executor memory of the query at its end, in bytes';
+    COMMENT ON COLUMN pg_tpch_run_results.temp_bytes IS 'bytes the query spilled to temporary files';
+    SELECT pg_catalog.pg_extension_config_dump('pg_tpch_run_results', '');
+
+    CREATE VIEW pg_tpch_run_report AS
+        SELECT r.run_id, r.query_nr, r.iteration, r.duration_ms, r.rows,
+               r.peak_memory, r.temp_bytes,
+               CASE WHEN run.track_io_timing THEN r.io_read_ms + r.io_write_ms END AS io_ms,
+               CASE WHEN run.track_io_timing
+                   THEN greatest(r.duration_ms - r.io_read_ms - r.io_write_ms, 0)
+               END AS cpu_ms
+        FROM pg_tpch_run_results r JOIN pg_tpch_runs run USING (run_id);
+    COMMENT ON VIEW pg_tpch_run_report IS
+        'time split into I/O and everything else, known only for runs with track_io_timing on';
+
+    CREATE VIEW pg_tpch_run_plans AS
+        SELECT r.run_id, r.query_nr, r.iteration, r.plan_hash, p.plan
+        FROM pg_tpch_run_results r JOIN pg_tpch_plans p USING (plan_hash);
+    "#,
+    name = "runs",
+    requires = ["load_info"]
+);
+
+/// Settings applied for the duration of a run, by profile name.
+///
+/// `default` leaves the session alone. `analytics` gives queries plenty of
+/// memory, parallel workers and JIT, `oltp` sets up the same parameters the way
+/// a server tuned for short transactions would.
+const PROFILES: &[(&str, &[(&str, &str)])] = &[
+    ("default", &[]),
+    (
+        "analytics",
+        &[
+            ("work_mem", "256MB"),
+            ("hash_mem_multiplier", "2"),
+            ("max_parallel_workers_per_gather", "4"),
+            ("parallel_setup_cost", "100"),
+            ("jit", "on"),
+            ("random_page_cost", "1.1"),
+        ],
+    ),
+    (
+        "oltp",
+        &[
+            ("work_mem", "4MB"),
+            ("hash_mem_multiplier", "1"),
+            ("max_parallel_workers_per_gather", "0"),
+            ("parallel_setup_cost", "1000"),
+            ("jit", "off"),
+            ("random_page_cost", "4"),
+        ],
+    ),
+];
+
+/// How a run executes its queries.
+pub struct RunOptions<'a> {
+    pub profile: &'a str,
+    /// Role the queries run as, instead of the current one.
+    pub role: Option<&'a str>,
+    /// Whether to keep the rows every query returned in a table.
+    pub persist_results: bool,
+    /// Run to compare every query against, failing on the first regression.
+    pub baseline_run: Option<i64>,
+    /// How much slower than in the baseline a query may get, as a fraction.
+    pub max_regression: f64,
+    /// Time after which no more queries are started.
+    pub max_duration: Option<Duration>,
+    /// Whether the query running when `max_duration` runs out is cancelled
+    /// instead of finished.
+    pub cancel_in_flight: bool,
+    /// Dataset of `pg_tpch_datasets` the queries run on, instead of the
+    /// tables on the search path.
+    pub dataset: Option<&'a str>,
+    /// Whether to read the tables and indexes into shared buffers before the
+    /// first query.
+    pub prewarm: bool,
+}
+
+/// Timing and resource usage of one executed query.
+pub struct QueryResult {
+    pub run_id: i64,
+    pub query_nr: i32,
+    pub iteration: i32,
+    pub duration_ms: f64,
+    pub rows: i64,
+    pub usage: Usage,
+}
+
+/// Splits a query file into its statements, dropping comment lines.
+fn statements(query: &str) -> Vec<String> {
+    let text = query
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    text.split(';')
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns `query` with the views it creates turned into common table
+/// expressions of the statements using them, so that it runs in a read-only
+/// transaction. Only query 15 creates a view.
+pub fn read_only(query: &str) -> String {
+    let mut views = Vec::new();
+    let mut statements_left = Vec::new();
+    for statement in statements(query) {
+        let lower = statement.to_lowercase();
+        if lower.starts_with("create view ") {
+            // create view <name> (<columns>) as <select>
+            let body = lower.find("select").unwrap_or(lower.len());
+            let header = statement["create view ".len()..body].trim_end();
+            let header = header[..header.len() - "as".len()].trim_end();
+            views.push(format!("{header} AS ({})", &statement[body..]));
+        } else if !lower.starts_with("drop view ") {
+            statements_left.push(statement);
+        }
+    }
+    if views.is_empty() {
+        return query.to_string();
+    }
+    statements_left
+        .iter()
+        .map(|statement| format!("WITH {} {statement}", views.join(", ")))
+        .collect::<Vec<_>>()
+        .join(";\n")
+}
+
+/// Applies the settings of `profile` and returns the values they replaced.
+pub fn apply_profile(profile: &str) -> spi::Result<Vec<(&'static str, String)>> {
+    let settings = PROFILES
+        .iter()
+        .find(|(name, _)| *name == profile)
+        .map(|(_, settings)| *settings)
+        .unwrap_or_else(|| {
+            let names: Vec<_> = PROFILES.iter().map(|(name, _)| *name).collect();
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                format!("unknown tuning profile \"{profile}\", expected one of {names:?}")
+            );
+        });
+
+    let mut previous = Vec::with_capacity(settings.len());
+    for &(name, value) in settings {
+        let old = Spi::get_one_with_args::<String>("SELECT current_setting($1)", &[name.into()])?
+            .unwrap_or_default();
+        Spi::run_with_args(
+            "SELECT set_config($1, $2, true)",
+            &[name.into(), value.into()],
+        )?;
+        previous.push((name, old));
+    }
+    Ok(previous)
+}
+
+/// Puts back the settings `apply_profile` replaced.
+pub fn restore_settings(previous: &[(&str, String)]) -> spi::Result<()> {
+    for (name, value) in previous {
+        Spi::run_with_args(
+            "SELECT set_config($1, $2, true)",
+            &[(*name).into(), value.as_str().into()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Runs `f` with `role` as the current role.
+///
+/// Only the query itself runs as `role`, results are recorded as the calling
+/// role, so `role` needs no access to the pg_tpch tables.
+fn as_role<T>(role: &str, f: impl FnOnce() -> spi::Result<T>) -> spi::Result<T> {
+    let previous = Spi::get_one::<String>("SELECT current_setting('role')")?.unwrap_or_default();
+    Spi::run_with_args("SELECT set_config('role', $1, true)", &[role.into()])?;
+    let result = f()?;
+    Spi::run_with_args(
+        "SELECT set_config('role', $1, true)",
+        &[previous.as_str().into()],
+    )?;
+    Ok(result)
+}
+
+/// Executes one query and returns its duration in milliseconds, the number of
+/// rows it returned and the resources it used.
+pub fn execute(query: &str) -> spi::Result<(f64, i64, Usage)> {
+    let statements = statements(query);
+    let started = Instant::now();
+    let (rows, usage) = instrument::measure(|| {
+        Spi::connect_mut(|client| {
+            let mut rows = 0;
+            for statement in &statements {
+                rows += client.update(statement.as_str(), None, &[])?.len() as i64;
+            }
+            Ok::<_, spi::SpiError>(rows)
+        })
+    });
+    Ok((started.elapsed().as_secs_f64() * 1000., rows?, usage))
+}
+
+/// Runs `f` in a subtransaction with `statement_timeout` set to `timeout`.
+///
+/// Returns `None` if a statement timed out, after rolling back what `f` did.
+fn with_timeout<T>(
+    timeout: Duration,
+    f: impl FnOnce() -> spi::Result<T>,
+) -> spi::Result<Option<T>> {
+    let started = Instant::now();
+    let previous =
+        Spi::get_one::<String>("SELECT current_setting('statement_timeout')")?.unwrap_or_default();
+    // SAFETY: the subtransaction is released or rolled back below, and the
+    // memory context and resource owner put back like PL/pgSQL does for
+    // exception blocks.
+    let (memory_context, resource_owner) = unsafe {
+        let saved = (pg_sys::CurrentMemoryContext, pg_sys::CurrentResourceOwner);
+        pg_sys::BeginInternalSubTransaction(std::ptr::null());
+        saved
+    };
+    let restore = move || unsafe {
+        pg_sys::MemoryContextSwitchTo(memory_context);
+        pg_sys::CurrentResourceOwner = resource_owner;
+    };
+    let mut f = AssertUnwindSafe(Some(f));
+    PgTryBuilder::new(move || {
+        let timeout_ms = (timeout.as_millis() as i64).max(1);
+        Spi::run_with_args(
+            "SELECT set_config('statement_timeout', $1, true)",
+            &[format!("{timeout_ms}ms").into()],
+        )?;
+        let f = &mut f;
+        let result = f.0.take().expect("called once")();
+        // SAFETY: see above.
+        unsafe { pg_sys::ReleaseCurrentSubTransaction() };
+        restore();
+        Spi::run_with_args(
+            "SELECT set_config('statement_timeout', $1, true)",
+            &[previous.as_str().into()],
+        )?;
+        result.map(Some)
+    })
+    .catch_when(PgSqlErrorCode::ERRCODE_QUERY_CANCELED, |error| {
+        // SAFETY: see above.
+        unsafe { pg_sys::RollbackAndReleaseCurrentSubTransaction() };
+        restore();
+        // A cancel request from the user ends the run as usual.
+        if started.elapsed() < timeout {
+            error.rethrow();
+        }
+        Ok(None)
+    })
+    .execute()
+}
+
+/// Returns the plans of the statements of `query` as `EXPLAIN (FORMAT JSON)`
+/// shows them.
+///
+/// A plain `EXPLAIN` doesn't execute the query. It plans [`read_only`]
+/// `query`, as query 15's view only exists while the query runs.
+pub fn explain(query: &str) -> spi::Result<Vec<String>> {
+    let mut explained = Vec::new();
+    for statement in statements(&read_only(query)) {
+        let plan = Spi::get_one::<pgrx::Json>(&format!("EXPLAIN (FORMAT JSON) {statement}"))?;
+        explained.extend(plan.map(|plan| plan.0.to_string()));
+    }
+    Ok(explained)
+}
+
+/// Stores the plans `explain` returned as one plan in `pg_tpch_plans`, unless
+/// it is already there, and returns its hash.
+fn store_plan(explained: Vec<String>) -> spi::Result<Option<String>> {
+    Spi::get_one_with_args::<String>(
+        "WITH explained AS (
+             SELECT jsonb_agg(step ORDER BY statement, position) AS plan
+             FROM unnest($1::text[]) WITH ORDINALITY AS e (plan, statement),
+                  jsonb_array_elements(e.plan::jsonb) WITH ORDINALITY AS s (step, position)
+         ), stored AS (
+             INSERT INTO pg_tpch_plans (plan_hash, plan)
+             SELECT md5(plan::text), plan FROM explained
+             ON CONFLICT (plan_hash) DO NOTHING
+         )
+         SELECT md5(plan::text) FROM explained",
+        &[explained.into()],
+    )
+}
+
+/// Executes `query` again and stores the rows it returns in a new table
+/// `name`, numbered in the order they came back as `result_row`.
+fn materialize(query: &str, name: &str) -> spi::Result<()> {
+    for statement in statements(query) {
+        if statement.to_lowercase().starts_with("select") {
+            Spi::run(&format!(
+                "CREATE TABLE {} AS SELECT row_number() OVER () AS result_row, q.* FROM ({statement}) q",
+                spi::quote_identifier(name)
+            ))?;
+        } else {
+            Spi::run(&statement)?;
+        }
+    }
+    Ok(())
+}
+
+/// Raises an error if `query_nr` took more than `max_regression` longer than
+/// its fastest iteration in run `baseline_run`.
+///
+/// Queries the baseline did not run are not compared.
+fn check_regression(
+    baseline_run: i64,
+    max_regression: f64,
+    query_nr: i32,
+    duration_ms: f64,
+) -> spi::Result<()> {
+    let baseline_ms = Spi::get_one_with_args::<f64>(
+        "SELECT min(duration_ms) FROM pg_tpch_run_results WHERE run_id = $1 AND query_nr = $2",
+        &[baseline_run.into(), query_nr.into()],
+    )?;
+    if let Some(baseline_ms) = baseline_ms {
+        if duration_ms > baseline_ms * (1. + max_regression) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_RAISE_EXCEPTION,
+                format!("query {query_nr} regressed against the baseline"),
+                format!(
+                    "Took {duration_ms:.1} ms, {baseline_ms:.1} ms in baseline run {baseline_run}, \
+                     more than {:.0}% slower.",
+                    max_regression * 100.
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Runs each `(query_nr, repetitions)` of `plan` in order as one run and
+/// records the results.
+pub fn run_queries(plan: &[(i32, i32)], options: &RunOptions) -> spi::Result<Vec<QueryResult>> {
+    for (query_nr, _) in plan {
+        if !queries::QUERIES.iter().any(|(nr, _)| nr == query_nr) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                format!("query number must be between 1 and 22, got {query_nr}")
+            );
+        }
+    }
+    if let Some(baseline_run) = options.baseline_run {
+        let exists = Spi::get_one_with_args::<bool>(
+            "SELECT EXISTS (SELECT FROM pg_tpch_runs WHERE run_id = $1)",
+            &[baseline_run.into()],
+        )?;
+        if exists != Some(true) {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_NO_DATA_FOUND,
+                format!("baseline run {baseline_run} does not exist")
+            );
+        }
+        if options.max_regression < 0. || options.max_regression.is_nan() {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                format!(
+                    "max_regression must not be negative, got {}",
+                    options.max_regression
+                )
+            );
+        }
+    }
+    let mut previous = Vec::new();
+    if let Some(dataset) = options.dataset {
+        metadata::check_registered(dataset)?;
+        let search_path = Spi::get_one::<String>("SELECT current_setting('search_path')")?;
+        previous.push(("search_path", search_path.unwrap_or_default()));
+        schema::search_first(dataset)?;
+    }
+    // The state of the tables on the search path, those of the dataset.
+    metadata::check_dataset("running queries")?;
+    if options.prewarm {
+        maintenance::prewarm()?;
+    }
+    let generated_columns = schema::has_generated_columns()?;
+
+    let run_id = Spi::get_one_with_args::<i64>(
+        "INSERT INTO pg_tpch_runs (load_id, profile, role, max_duration, generated_columns, dataset)
+         VALUES (CASE WHEN $5::text IS NULL THEN (SELECT max(load_id) FROM pg_tpch_dataset)
+                      ELSE (SELECT load_id FROM pg_tpch_datasets WHERE schema_name = $5) END,
+                 $1, $2, make_interval(secs => $3), $4, $5)
+         RETURNING run_id",
+        &[
+            options.profile.into(),
+            options.role.into(),
+            options.max_duration.map(|d| d.as_secs_f64()).into(),
+            generated_columns.into(),
+            options.dataset.into(),
+        ],
+    )?
+    .expect("INSERT ... RETURNING yields a row");
+
+    events::emit(
+        "run_start",
+        &[
+            ("run_id", run_id.into()),
+            ("profile", options.profile.into()),
+        ],
+    );
+    previous.extend(apply_profile(options.profile)?);
+    let started = Instant::now();
+    let mut budget_exhausted = false;
+    let mut results = Vec::new();
+    for (query_nr, iteration) in plan
+        .iter()
+        .flat_map(|&(query_nr, repetitions)| (1..=repetitions).map(move |i| (query_nr, i)))
+    {
+        let (_, query) = queries::QUERIES
+            .iter()
+            .find(|(nr, _)| *nr == query_nr)
+            .expect("query numbers were checked");
+        let query = &if generated_columns {
+            schema::use_generated_columns(query)
+        } else {
+            query.to_string()
+        };
+        let remaining = options
+            .max_duration
+            .map(|budget| budget.saturating_sub(started.elapsed()));
+        if remaining == Some(Duration::ZERO) {
+            budget_exhausted = true;
+            break;
+        }
+        events::emit(
+            "query_start",
+            &[
+                ("run_id", run_id.into()),
+                ("query_nr", query_nr.into()),
+                ("iteration", iteration.into()),
+            ],
+        );
+        let run = || match options.role {
+            Some(role) => as_role(role, || execute(query)),
+            None => execute(query),
+        };
+        let executed = match remaining {
+            Some(remaining) if options.cancel_in_flight => with_timeout(remaining, run)?,
+            _ => Some(run()?),
+        };
+        let Some((duration_ms, rows, usage)) = executed else {
+            events::emit(
+                "query_cancel",
+                &[
+                    ("run_id", run_id.into()),
+                    ("query_nr", query_nr.into()),
+                    ("iteration", iteration.into()),
+                ],
+            );
+            budget_exhausted = true;
+            break;
+        };
+        events::emit(
+            "query_end",
+            &[
+                ("run_id", run_id.into()),
+                ("query_nr", query_nr.into()),
+                ("iteration", iteration.into()),
+                ("duration_ms", duration_ms.into()),
+                ("rows", rows.into()),
+            ],
+        );
+        // Planning again outside of the measured time, as the role the query
+        // ran as. Repetitions run with the same plan.
+        let plan_hash = if iteration == 1 {
+            let explained = match options.role {
+                Some(role) => as_role(role, || explain(query))?,
+                None => explain(query)?,
+            };
+            store_plan(explained)?
+        } else {
+            None
+        };
+        Spi::run_with_args(
+            "INSERT INTO pg_tpch_run_results
+                 (run_id, query_nr, iteration, started_at, duration_ms, rows, peak_memory,
+                  temp_bytes, io_read_ms, io_write_ms, plan_hash)
+             VALUES ($1, $2, $9, clock_timestamp() - make_interval(secs => $3 / 1000), $3, $4,
+                     $5, $6, $7, $8, $10)",
+            &[
+                run_id.into(),
+                query_nr.into(),
+                duration_ms.into(),
+                rows.into(),
+                (usage.peak_memory as i64).into(),
+                (usage.temp_bytes as i64).into(),
+                usage.io_read_ms.into(),
+                usage.io_write_ms.into(),
+                iteration.into(),
+                plan_hash.into(),
+            ],
+        )?;
+        if let Some(baseline_run) = options.baseline_run {
+            check_regression(baseline_run, options.max_regression, query_nr, duration_ms)?;
+        }
+        // Every repetition returns the same rows, keeping the first is enough.
+        if options.persist_results && iteration == 1 {
+            // Materializing executes the query again, outside of the measured time.
+            let name = format!("tpch_result_q{query_nr}_run{run_id}");
+            materialize(query, &name)?;
+            Spi::run_with_args(
+                "UPDATE pg_tpch_run_results SET result_table = $3
+                 WHERE run_id = $1 AND query_nr = $2 AND iteration = 1",
+                &[run_id.into(), query_nr.into(), name.into()],
+            )?;
+        }
+        results.push(QueryResult {
+            run_id,
+            query_nr,
+            iteration,
+            duration_ms,
+            rows,
+            usage,
+        });
+    }
+    restore_settings(&previous)?;
+
+    Spi::run_with_args(
+        "UPDATE pg_tpch_runs SET completed_at = clock_timestamp(), budget_exhausted = $2
+         WHERE run_id = $1",
+        &[run_id.into(), budget_exhausted.into()],
+    )?;
+    events::emit(
+        "run_end",
+        &[
+            ("run_id", run_id.into()),
+            ("queries", (results.len() as i64).into()),
+            (
+                "duration_ms",
+                (started.elapsed().as_secs_f64() * 1000.).into(),
+            ),
+        ],
+    );
+    Ok(results)
+}
+
+/// Converts the `max_duration` argument of a runner.
+fn time_budget(max_duration: Option<Interval>) -> Option<Duration> {
+    max_duration.map(|interval| {
+        Duration::try_from(interval).unwrap_or_else(|e| {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                format!("invalid max_duration: {e}")
+            );
+        })
+    })
+}
+
+type RunRow = (i64, i32, i32, f64, i64, i64, i64);
+
+/// Turns `results` into the rows the runners return.
+pub fn into_rows(results: Vec<QueryResult>) -> Vec<RunRow> {
+    results
+        .into_iter()
+        .map(|r| {
+            (
+                r.run_id,
+                r.query_nr,
+                r.iteration,
+                r.duration_ms,
+                r.rows,
+                r.usage.peak_memory as i64,
+                r.usage.temp_bytes as i64,
+            )
+        })
+        .collect()
+}
+
+/// Runs query `query_nr` and returns its timing, the executor memory it needed
+/// and how much it spilled to temporary files.
+///
+/// With `role`, the query runs as that role through `SET ROLE`, for example to
+/// measure the overhead of row-level security policies. Query 15 creates a
+/// view, so the role also needs `CREATE` on the schema.
+///
+/// With `persist_results`, the rows the query returns are also stored in a
+/// table `tpch_result_q<query_nr>_run<run_id>`, recorded as `result_table` of
+/// the run results, to compare results across runs and Postgres versions.
+///
+/// With `baseline_run`, every query is compared against the same query in
+/// that earlier run as soon as it finishes, and the run fails on the first
+/// one that is more than `max_regression` (a fraction) slower. The error
+/// rolls the run back, which makes the runners usable as a CI gate.
+///
+/// With `dataset`, the query runs on the tables of that dataset of
+/// `pg_tpch_datasets`, loaded by `tpch_load(target_schema => ...)`, and the
+/// run refers to its last load. Runs on datasets of different scale factors
+/// can be compared side by side.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_run(
+    query_nr: i32,
+    profile: default!(&str, "'default'"),
+    role: default!(Option<&str>, "NULL"),
+    persist_results: default!(bool, false),
+    baseline_run: default!(Option<i64>, "NULL"),
+    max_regression: default!(f64, 0.1),
+    dataset: default!(Option<&str>, "NULL"),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(run_id, i64),
+            name!(query_nr, i32),
+            name!(iteration, i32),
+            name!(duration_ms, f64),
+            name!(rows, i64),
+            name!(peak_memory, i64),
+            name!(temp_bytes, i64),
+        ),
+    >,
+> {
+    let results = run_queries(
+        &[(query_nr, 1)],
+        &RunOptions {
+            profile,
+            role,
+            persist_results,
+            baseline_run,
+            max_regression,
+            max_duration: None,
+            cancel_in_flight: false,
+            dataset,
+            prewarm: false,
+        },
+    )?;
+    Ok(TableIterator::new(into_rows(results)))
+}
+
+/// Runs all 22 queries in order and returns their timings, see [`tpch_run`].
+///
+/// With `prewarm`, the tables and their indexes are read into shared buffers
+/// with `tpch_prewarm` first, so the run measures a warm cache.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+#[pg_extern]
+fn tpch_run_all(
+    profile: default!(&str, "'default'"),
+    role: default!(Option<&str>, "NULL"),
+    persist_results: default!(bool, false),
+    baseline_run: default!(Option<i64>, "NULL"),
+    max_regression: default!(f64, 0.1),
+    max_duration: default!(Option<Interval>, "NULL"),
+    cancel_in_flight: default!(bool, false),
+    dataset: default!(Option<&str>, "NULL"),
+    prewarm: default!(bool, false),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(run_id, i64),
+            name!(query_nr, i32),
+            name!(iteration, i32),
+            name!(duration_ms, f64),
+            name!(rows, i64),
+            name!(peak_memory, i64),
+            name!(temp_bytes, i64),
+        ),
+    >,
+> {
+    let plan: Vec<(i32, i32)> = queries::QUERIES.iter().map(|(nr, _)| (*nr, 1)).collect();
+    let results = run_queries(
+        &plan,
+        &RunOptions {
+            profile,
+            role,
+            persist_results,
+            baseline_run,
+            max_regression,
+            max_duration: time_budget(max_duration),
+            cancel_in_flight,
+            dataset,
+            prewarm,
+        },
+    )?;
+    Ok(TableIterator::new(into_rows(results)))
+}
+
+/// Runs the queries in `query_nrs` in order and returns their timings, see
+/// [`tpch_run`].
+///
+/// `repetitions` gives how often each query runs back to back, either one
+/// count for all queries or one per query. Every repetition is a row of the
+/// results, numbered by `iteration`. `max_duration` and `cancel_in_flight`
+/// bound the time the run takes and `prewarm` warms the cache, see
+/// [`tpch_run_all`].
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+#[pg_extern]
+fn tpch_run_set(
+    query_nrs: Vec<i32>,
+    repetitions: default!(Option<Vec<i32>>, "NULL"),
+    profile: default!(&str, "'default'"),
+    role: default!(Option<&str>, "NULL"),
+    persist_results: default!(bool, false),
+    baseline_run: default!(Option<i64>, "NULL"),
+    max_regression: default!(f64, 0.1),
+    max_duration: default!(Option<Interval>, "NULL"),
+    cancel_in_flight: default!(bool, false),
+    dataset: default!(Option<&str>, "NULL"),
+    prewarm: default!(bool, false),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(run_id, i64),
+            name!(query_nr, i32),
+            name!(iteration, i32),
+            name!(duration_ms, f64),
+            name!(rows, i64),
+            name!(peak_memory, i64),
+            name!(temp_bytes, i64),
+        ),
+    >,
+> {
+    let repetitions = repetitions.unwrap_or_else(|| vec![1]);
+    if repetitions.iter().any(|&r| r < 1)
+        || (repetitions.len() != 1 && repetitions.len() != query_nrs.len())
+    {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            "repetitions must be positive and either a single count or one per query"
+        );
+    }
+    let plan: Vec<(i32, i32)> = query_nrs
+        .iter()
+        .enumerate()
+        .map(|(i, &nr)| (nr, repetitions[i.min(repetitions.len() - 1)]))
+        .collect();
+    let results = run_queries(
+        &plan,
+        &RunOptions {
+            profile,
+            role,
+            persist_results,
+            baseline_run,
+            max_regression,
+            max_duration: time_budget(max_duration),
+            cancel_in_flight,
+            dataset,
+            prewarm,
+        },
+    )?;
+    Ok(TableIterator::new(into_rows(results)))
+}
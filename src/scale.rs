@@ -0,0 +1,111 @@
+//! Growing a loaded dataset to a bigger scale factor.
+//!
+//! The rows of `part`, `supplier` and `customer` only depend on their keys,
+//! so at SF=`k * sf` the first `k`th of them are the rows of SF=`sf`, and
+//! scaling up appends parts 2 to `k` of them. The other tables differ at every
+//! scale factor: `partsupp` picks suppliers among all of them, `orders`
+//! customers, and `lineitem` parts and suppliers, so those three are
+//! generated anew. `region` and `nation` stay as they are.
+use std::time::Instant;
+
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::format::CsvOptions;
+use crate::tables;
+use crate::{alloc, metadata, stream, timing};
+
+/// Tables whose rows at a bigger scale factor start with those of a smaller
+/// one, in load order.
+const APPENDED_TABLES: [&str; 3] = ["part", "supplier", "customer"];
+
+/// Tables generated anew at every scale factor, in load order.
+const REGENERATED_TABLES: [&str; 3] = ["partsupp", "orders", "lineitem"];
+
+/// Grows the loaded dataset to SF=`target_sf`, a multiple of its scale
+/// factor, and returns what it did.
+///
+/// Only the rows missing from `part`, `supplier` and `customer` are
+/// generated, `partsupp`, `orders` and `lineitem` are truncated and loaded
+/// again, so the tables end up the same as a load of SF=`target_sf`, without
+/// generating the smaller tables twice. The rows go straight into `COPY`.
+/// The load of the old scale factor is retired and one of the new scale
+/// factor recorded, with `scaled_up_from` in its options.
+#[pg_extern]
+fn tpch_scale_up(target_sf: f64) -> spi::Result<Option<String>> {
+    crate::check_scale_factor(target_sf, false);
+    crate::schema::check_key_range(target_sf)?;
+    if metadata::tpch_dataset_state()? != "complete" {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            "scaling up needs a complete TPC-H dataset",
+            "Load one with tpch_load first."
+        );
+    }
+    let sf = Spi::get_one::<f64>("SELECT max(sf) FROM pg_tpch_dataset")?
+        .expect("a complete dataset has a load");
+    let factor = target_sf / sf;
+    if !(factor > 1.5 && (factor - factor.round()).abs() < 1e-9) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("target_sf must be a multiple of the loaded SF={sf}, got {target_sf}")
+        );
+    }
+    let parts = factor.round() as i32;
+
+    let started = Instant::now();
+    metadata::mark_tables(true)?;
+    // A table whose rows don't split evenly at the new scale factor is
+    // generated anew like those that depend on the others.
+    let (appended, regenerated): (Vec<&str>, Vec<&str>) =
+        APPENDED_TABLES.iter().partition(|&&table| {
+            tables::expected_rows(table, target_sf, 1, parts)
+                == tables::expected_rows(table, sf, 1, 1)
+        });
+    let regenerated: Vec<&str> = regenerated.into_iter().chain(REGENERATED_TABLES).collect();
+    Spi::run(&format!("TRUNCATE {}", regenerated.join(", ")))?;
+
+    let csv = CsvOptions::default();
+    let mut stats = Vec::with_capacity(APPENDED_TABLES.len() + REGENERATED_TABLES.len());
+    for table in tables::TABLES {
+        // As `(part, parts)`.
+        let pieces: Vec<(i32, i32)> = if appended.contains(&table) {
+            (2..=parts).map(|part| (part, parts)).collect()
+        } else if regenerated.contains(&table) {
+            vec![(1, 1)]
+        } else {
+            continue;
+        };
+        let generation = alloc::PeakTracker::start();
+        let timer = timing::TableTimer::start();
+        let size_before = crate::table_size(table)?;
+        let mut rows = 0;
+        for (part, parts) in pieces {
+            rows += stream::copy_generated(table, target_sf, part, parts, &csv, None, false)?;
+        }
+        stats.push(metadata::TableStats {
+            table,
+            rows,
+            file_bytes: 0,
+            peak_memory: generation.peak(),
+            table_bytes: Some(crate::table_size(table)?.saturating_sub(size_before)),
+            timing: Some(timer.split()),
+        });
+    }
+
+    metadata::mark_truncated()?;
+    let load_id =
+        metadata::find_or_begin_load(target_sf, 1, &[("scaled_up_from", sf.to_string())])?;
+    for stats in &stats {
+        metadata::record_table_stats(load_id, 1, stats)?;
+    }
+    metadata::complete_part(load_id, 1, started.elapsed().as_secs_f64())?;
+    metadata::mark_tables(false)?;
+    Ok(Some(format!(
+        "TPC-H scaled up from SF={sf} to SF={target_sf} ({} appended, {} loaded again)",
+        appended.join(", "),
+        regenerated.join(", ")
+    )))
+}
@@ -0,0 +1,1551 @@
+//! Expected definitions of the TPC-H tables.
+//!
+//! These mirror the DDL in `create_schema` and are what `tpch_check_schema()`
+//! compares the live tables against. Besides the default definitions, the
+//! categorical columns can be switched to enums or domains with
+//! `tpch_set_schema_profile()`, `lineitem` can get stored generated
+//! columns with `tpch_set_generated_columns()`, the tables can be made
+//! unlogged with `tpch_set_unlogged()`, the key columns widened to `bigint`
+//! with `tpch_set_bigint_keys()`, the decimal columns turned into floats
+//! with `tpch_set_double_precision()`, the character columns into `text`
+//! with `tpch_set_text_columns()`, `orders` and `lineitem` range partitioned
+//! by date with `tpch_set_range_partitioning()` or hash partitioned by order
+//! key with `tpch_set_hash_partitioning()`, the tables stored with
+//! another table access method with `tpch_set_access_method()`, and moved to
+//! another tablespace with `tpch_set_tablespace()`. The storage parameters of
+//! `pg_tpch.storage_parameters` go on the tables when they are created or
+//! truncated for a load. `tpch_drop_tables()` drops them altogether and
+//! `tpch_reset()` creates them anew.
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::constraints;
+use crate::guc::STORAGE_PARAMETERS;
+use crate::tables::{self, TABLES};
+
+extension_sql!(
+    r#"
+    CREATE TYPE tpch_returnflag AS ENUM ('A', 'N', 'R');
+    CREATE TYPE tpch_linestatus AS ENUM ('F', 'O');
+    CREATE TYPE tpch_orderstatus AS ENUM ('F', 'O', 'P');
+    CREATE TYPE tpch_orderpriority AS ENUM ('1-URGENT', '2-HIGH', '3-MEDIUM', '4-NOT SPECIFIED', '5-LOW');
+
+    CREATE DOMAIN tpch_returnflag_domain AS character(1) CHECK (VALUE IN ('A', 'N', 'R'));
+    CREATE DOMAIN tpch_linestatus_domain AS character(1) CHECK (VALUE IN ('F', 'O'));
+    CREATE DOMAIN tpch_orderstatus_domain AS character(1) CHECK (VALUE IN ('F', 'O', 'P'));
+    CREATE DOMAIN tpch_orderpriority_domain AS character(15)
+        CHECK (VALUE IN ('1-URGENT', '2-HIGH', '3-MEDIUM', '4-NOT SPECIFIED', '5-LOW'));
+    "#,
+    name = "schema_profile_types"
+);
+
+/// Schema profiles the tables can be created with.
+pub const PROFILES: &[&str] = &["default", "enum", "domain"];
+
+/// Columns holding one of a few codes as `(table, column, enum, domain)`,
+/// with the types they have in the `enum` and `domain` profiles. The enum
+/// labels are declared in the order the codes sort in, so queries sort them
+/// the same under every profile.
+const CATEGORICAL_COLUMNS: &[(&str, &str, &str, &str)] = &[
+    (
+        "lineitem",
+        "l_returnflag",
+        "tpch_returnflag",
+        "tpch_returnflag_domain",
+    ),
+    (
+        "lineitem",
+        "l_linestatus",
+        "tpch_linestatus",
+        "tpch_linestatus_domain",
+    ),
+    (
+        "orders",
+        "o_orderstatus",
+        "tpch_orderstatus",
+        "tpch_orderstatus_domain",
+    ),
+    (
+        "orders",
+        "o_orderpriority",
+        "tpch_orderpriority",
+        "tpch_orderpriority_domain",
+    ),
+];
+
+/// Stored generated columns precomputing what the queries aggregate, as
+/// `(table, column, expression)`. The expressions are spelled the way the
+/// queries spell them, see [`use_generated_columns`].
+const GENERATED_COLUMNS: &[(&str, &str, &str)] = &[
+    (
+        "lineitem",
+        "l_charge",
+        "l_extendedprice * (1 - l_discount) * (1 + l_tax)",
+    ),
+    (
+        "lineitem",
+        "l_revenue",
+        "l_extendedprice * (1 - l_discount)",
+    ),
+];
+
+/// Columns of the default profile as `(table, column, type, not_null)`, with
+/// types spelled the way `format_type()` prints them.
+const DEFAULT_COLUMNS: &[(&str, &str, &str, bool)] = &[
+    ("region", "r_regionkey", "integer", true),
+    ("region", "r_name", "character(25)", true),
+    ("region", "r_comment", "character varying(152)", false),
+    ("nation", "n_nationkey", "integer", true),
+    ("nation", "n_name", "character(25)", true),
+    ("nation", "n_regionkey", "integer", true),
+    ("nation", "n_comment", "character varying(152)", false),
+    ("part", "p_partkey", "integer", true),
+    ("part", "p_name", "character varying(55)", true),
+    ("part", "p_mfgr", "character(25)", true),
+    ("part", "p_brand", "character(10)", true),
+    ("part", "p_type", "character varying(25)", true),
+    ("part", "p_size", "integer", true),
+    ("part", "p_container", "character(10)", true),
+    ("part", "p_retailprice", "numeric(15,2)", true),
+    ("part", "p_comment", "character varying(23)", true),
+    ("supplier", "s_suppkey", "integer", true),
+    ("supplier", "s_name", "character(25)", true),
+    ("supplier", "s_address", "character varying(40)", true),
+    ("supplier", "s_nationkey", "integer", true),
+    ("supplier", "s_phone", "character(15)", true),
+    ("supplier", "s_acctbal", "numeric(15,2)", true),
+    ("supplier", "s_comment", "character varying(101)", true),
+    ("partsupp", "ps_partkey", "integer", true),
+    ("partsupp", "ps_suppkey", "integer", true),
+    ("partsupp", "ps_availqty", "integer", true),
+    ("partsupp", "ps_supplycost", "numeric(15,2)", true),
+    ("partsupp", "ps_comment", "character varying(199)", true),
+    ("customer", "c_custkey", "integer", true),
+    ("customer", "c_name", "character varying(25)", true),
+    ("customer", "c_address", "character varying(40)", true),
+    ("customer", "c_nationkey", "integer", true),
+    ("customer", "c_phone", "character(15)", true),
+    ("customer", "c_acctbal", "numeric(15,2)", true),
+    ("customer", "c_mktsegment", "character(10)", true),
+    ("customer", "c_comment", "character varying(117)", true),
+    ("orders", "o_orderkey", "integer", true),
+    ("orders", "o_custkey", "integer", true),
+    ("orders", "o_orderstatus", "character(1)", true),
+    ("orders", "o_totalprice", "numeric(15,2)", true),
+    ("orders", "o_orderdate", "date", true),
+    ("orders", "o_orderpriority", "character(15)", true),
+    ("orders", "o_clerk", "character(15)", true),
+    ("orders", "o_shippriority", "integer", true),
+    ("orders", "o_comment", "character varying(79)", true),
+    ("lineitem", "l_orderkey", "integer", true),
+    ("lineitem", "l_partkey", "integer", true),
+    ("lineitem", "l_suppkey", "integer", true),
+    ("lineitem", "l_linenumber", "integer", true),
+    ("lineitem", "l_quantity", "numeric(15,2)", true),
+    ("lineitem", "l_extendedprice", "numeric(15,2)", true),
+    ("lineitem", "l_discount", "numeric(15,2)", true),
+    ("lineitem", "l_tax", "numeric(15,2)", true),
+    ("lineitem", "l_returnflag", "character(1)", true),
+    ("lineitem", "l_linestatus", "character(1)", true),
+    ("lineitem", "l_shipdate", "date", true),
+    ("lineitem", "l_commitdate", "date", true),
+    ("lineitem", "l_receiptdate", "date", true),
+    ("lineitem", "l_shipinstruct", "character(25)", true),
+    ("lineitem", "l_shipmode", "character(10)", true),
+    ("lineitem", "l_comment", "character varying(44)", true),
+];
+
+/// Returns the type of `column` of `table` under `profile`.
+fn column_type(profile: &str, table: &str, column: &str, default: &'static str) -> &'static str {
+    let categorical = CATEGORICAL_COLUMNS
+        .iter()
+        .find(|(t, c, _, _)| *t == table && *c == column);
+    match (profile, categorical) {
+        ("enum", Some(&(_, _, enum_type, _))) => enum_type,
+        ("domain", Some(&(_, _, _, domain))) => domain,
+        _ => default,
+    }
+}
+
+/// Returns the expected columns of `table` under `profile` in column order.
+pub fn expected_columns(
+    profile: &str,
+    table: &str,
+) -> Option<Vec<(&'static str, &'static str, bool)>> {
+    if !PROFILES.contains(&profile) {
+        return None;
+    }
+    Some(
+        DEFAULT_COLUMNS
+            .iter()
+            .filter(|(t, _, _, _)| *t == table)
+            .map(|&(_, column, type_name, not_null)| {
+                (
+                    column,
+                    column_type(profile, table, column, type_name),
+                    not_null,
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Type of `column` of `table` as `format_type()` prints it.
+fn live_type(table: &str, column: &str) -> spi::Result<Option<String>> {
+    Spi::get_one_with_args::<String>(
+        "SELECT max(format_type(atttypid, atttypmod)) FROM pg_attribute
+         WHERE attrelid = to_regclass($1) AND attname = $2 AND NOT attisdropped",
+        &[table.into(), column.into()],
+    )
+}
+
+/// Returns the profile the categorical columns of the live tables follow,
+/// `default` if they follow none.
+pub fn live_profile() -> spi::Result<&'static str> {
+    let mut live = Vec::with_capacity(CATEGORICAL_COLUMNS.len());
+    for &(table, column, _, _) in CATEGORICAL_COLUMNS {
+        live.push((table, column, live_type(table, column)?));
+    }
+    let profile = PROFILES.iter().find(|profile| {
+        live.iter().all(|(table, column, live_type)| {
+            let expected = expected_columns(profile, table)
+                .unwrap_or_default()
+                .into_iter()
+                .find(|(c, _, _)| c == column)
+                .map(|(_, type_name, _)| type_name);
+            live_type.as_deref() == expected
+        })
+    });
+    Ok(profile.copied().unwrap_or("default"))
+}
+
+/// Switches the TPC-H tables to the column types of schema profile
+/// `profile` and returns the columns it changed.
+///
+/// `enum` turns `l_returnflag`, `l_linestatus`, `o_orderstatus` and
+/// `o_orderpriority` into enums, `domain` into domains over their default
+/// types that only accept the codes the generator produces, and `default`
+/// turns them back. Loaded rows are converted, which rewrites `lineitem` and
+/// `orders`. Loads record the profile the tables had, to tell the timings of
+/// the profiles apart.
+#[pg_extern]
+fn tpch_set_schema_profile(profile: &str) -> spi::Result<Vec<String>> {
+    if !PROFILES.contains(&profile) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("unknown schema profile \"{profile}\", expected one of {PROFILES:?}")
+        );
+    }
+    let mut changed = Vec::new();
+    for table in TABLES {
+        let mut alterations = Vec::new();
+        for (column, type_name, _) in expected_columns(profile, table).unwrap_or_default() {
+            let live = live_type(table, column)?;
+            if live.is_some() && live.as_deref() != Some(type_name) {
+                alterations.push(format!(
+                    "ALTER COLUMN {column} TYPE {type_name} USING {column}::text::{type_name}"
+                ));
+                changed.push(format!("{table}.{column}"));
+            }
+        }
+        if !alterations.is_empty() {
+            Spi::run(&format!("ALTER TABLE {table} {}", alterations.join(", ")))?;
+        }
+    }
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info SET schema_profile = $1
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+        &[profile.into()],
+    )?;
+    Ok(changed)
+}
+
+/// Whether `column` of `table` is a stored generated column.
+fn is_generated(table: &str, column: &str) -> spi::Result<bool> {
+    Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS (SELECT FROM pg_attribute
+                        WHERE attrelid = to_regclass($1) AND attname = $2
+                          AND attgenerated = 's' AND NOT attisdropped)",
+        &[table.into(), column.into()],
+    )
+    .map(|generated| generated == Some(true))
+}
+
+/// Whether the live tables have all generated columns.
+pub fn has_generated_columns() -> spi::Result<bool> {
+    for &(table, column, _) in GENERATED_COLUMNS {
+        if !is_generated(table, column)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Returns the variant of `query` that reads the generated columns instead of
+/// computing their expressions.
+pub fn use_generated_columns(query: &str) -> String {
+    // Query 19 leaves out the blank before the `*`.
+    let mut query = query.replace("l_extendedprice* (", "l_extendedprice * (");
+    for &(_, column, expression) in GENERATED_COLUMNS {
+        query = query.replace(expression, column);
+    }
+    query
+}
+
+/// Adds the stored generated columns `l_revenue` and `l_charge` to
+/// `lineitem`, or drops them without `enabled`, and returns the columns it
+/// changed.
+///
+/// `l_revenue` is `l_extendedprice * (1 - l_discount)`, the revenue queries
+/// 1, 3, 5, 7 to 10, 14, 15 and 19 sum up, and `l_charge` the same with tax,
+/// which query 1 sums. While the columns exist, the runners execute variants
+/// of these queries that read the columns instead of computing the
+/// expressions, and record so as `generated_columns` of the run. Adding the
+/// columns rewrites `lineitem` and every later load computes them, which
+/// loads record as `generated_columns`, so the storage and load time they
+/// cost can be weighed against what the queries save.
+#[pg_extern]
+fn tpch_set_generated_columns(enabled: default!(bool, true)) -> spi::Result<Vec<String>> {
+    let mut changed = Vec::new();
+    for table in TABLES {
+        let mut alterations = Vec::new();
+        for &(_, column, expression) in GENERATED_COLUMNS.iter().filter(|(t, _, _)| *t == table) {
+            let generated = is_generated(table, column)?;
+            if enabled && !generated {
+                alterations.push(format!(
+                    "ADD COLUMN {column} numeric GENERATED ALWAYS AS ({expression}) STORED"
+                ));
+            } else if !enabled && generated {
+                alterations.push(format!("DROP COLUMN {column}"));
+            } else {
+                continue;
+            }
+            changed.push(format!("{table}.{column}"));
+        }
+        if !alterations.is_empty() {
+            Spi::run(&format!("ALTER TABLE {table} {}", alterations.join(", ")))?;
+        }
+    }
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info SET generated_columns = $1
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+        &[enabled.into()],
+    )?;
+    Ok(changed)
+}
+
+/// Whether every TPC-H table is unlogged, the partitions of partitioned
+/// ones.
+pub fn has_unlogged_tables() -> spi::Result<bool> {
+    Spi::get_one_with_args::<bool>(
+        "SELECT bool_and(c.relpersistence = 'u')
+                AND count(DISTINCT t.name) = cardinality($1)
+         FROM unnest($1::text[]) t(name)
+              JOIN pg_class c
+                ON c.oid = ANY(SELECT to_regclass(t.name)
+                               UNION ALL
+                               SELECT relid FROM pg_partition_tree(to_regclass(t.name))
+                               WHERE isleaf)
+         WHERE c.relkind <> 'p'",
+        &[TABLES.to_vec().into()],
+    )
+    .map(|unlogged| unlogged == Some(true))
+}
+
+/// Makes the TPC-H tables unlogged, or logged again without `enabled`, and
+/// returns the tables it changed.
+///
+/// Unlogged tables skip the WAL, so loads write their rows once instead of
+/// twice and don't wait for the WAL to be flushed, which suits benchmarks
+/// that are reloaded anyway. Their rows are lost in a crash and they aren't
+/// replicated to standbys. Switching rewrites the tables, and switching back
+/// also writes all their rows to the WAL. Partitioned tables have their
+/// partitions switched. Loads record whether the tables were unlogged.
+#[pg_extern]
+fn tpch_set_unlogged(enabled: default!(bool, true)) -> spi::Result<Vec<String>> {
+    // A logged table can't reference an unlogged one, so referencing tables
+    // go first one way and referenced ones the other.
+    let tables: Vec<&str> = match enabled {
+        true => TABLES.iter().rev().copied().collect(),
+        false => TABLES.to_vec(),
+    };
+    let mut changed = Vec::new();
+    let persistence = if enabled { "UNLOGGED" } else { "LOGGED" };
+    for table in tables {
+        let switching = Spi::get_one_with_args::<Vec<String>>(
+            "SELECT coalesce(array_agg(relation ORDER BY relation), '{}')
+             FROM unnest($1::text[]) relation
+             JOIN pg_class c ON c.oid = relation::regclass
+             WHERE (c.relpersistence = 'u') <> $2",
+            &[storage_relations(table)?.into(), enabled.into()],
+        )?
+        .unwrap_or_default();
+        for relation in &switching {
+            Spi::run(&format!("ALTER TABLE {relation} SET {persistence}"))?;
+        }
+        if !switching.is_empty() {
+            changed.push(table.to_string());
+        }
+    }
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info SET unlogged = $1
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+        &[enabled.into()],
+    )?;
+    Ok(changed)
+}
+
+/// Key columns of the tables in table and column order, `integer` in the
+/// specification.
+fn key_columns() -> impl Iterator<Item = (&'static str, &'static str)> {
+    DEFAULT_COLUMNS
+        .iter()
+        .filter(|(_, column, _, _)| column.ends_with("key"))
+        .map(|&(table, column, _, _)| (table, column))
+}
+
+/// Whether every key column of the TPC-H tables is a `bigint`.
+pub fn has_bigint_keys() -> spi::Result<bool> {
+    for (table, column) in key_columns() {
+        if live_type(table, column)?.as_deref() != Some("bigint") {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Refuses to load SF=`sf` if it generates keys too big for the `integer`
+/// key columns they go into.
+pub fn check_key_range(sf: f64) -> spi::Result<()> {
+    for (table, column) in key_columns() {
+        let max_key = tables::max_key(column, sf);
+        if max_key <= i32::MAX as i64 || live_type(table, column)?.as_deref() != Some("integer") {
+            continue;
+        }
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_NUMERIC_VALUE_OUT_OF_RANGE,
+            format!("SF={sf} generates {column} values up to {max_key}, too big for an integer"),
+            "Switch the key columns to bigint with tpch_set_bigint_keys() first."
+        );
+    }
+    Ok(())
+}
+
+/// Switches the key columns of the TPC-H tables to `bigint`, or back to
+/// `integer` without `enabled`, and returns the columns it changed.
+///
+/// Order keys outgrow `integer` from SF=358 on, which loads refuse with the
+/// `integer` keys of the specification. Switching rewrites every table and
+/// its indexes, and `bigint` keys make the tables and indexes bigger. Loads
+/// record whether the keys were `bigint`.
+#[pg_extern]
+fn tpch_set_bigint_keys(enabled: default!(bool, true)) -> spi::Result<Vec<String>> {
+    let type_name = if enabled { "bigint" } else { "integer" };
+    let changed = retype_columns(key_columns().map(|(table, column)| (table, column, type_name)))?;
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info SET bigint_keys = $1
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+        &[enabled.into()],
+    )?;
+    Ok(changed)
+}
+
+/// Columns the specification declares as `numeric(15,2)`: prices, account
+/// balances, quantities, discounts and taxes.
+fn decimal_columns() -> impl Iterator<Item = (&'static str, &'static str)> {
+    DEFAULT_COLUMNS
+        .iter()
+        .filter(|(_, _, type_name, _)| *type_name == "numeric(15,2)")
+        .map(|&(table, column, _, _)| (table, column))
+}
+
+/// Whether every decimal column of the TPC-H tables is a `double precision`.
+pub fn has_double_decimals() -> spi::Result<bool> {
+    for (table, column) in decimal_columns() {
+        if live_type(table, column)?.as_deref() != Some("double precision") {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Switches the `numeric(15,2)` columns of the TPC-H tables to `double
+/// precision`, or back without `enabled`, and returns the columns it changed.
+///
+/// Floats spare the executor the cost of `numeric` arithmetic, the way
+/// systems that store these columns as floats compute them, but sums and
+/// averages come out with rounding errors in their last digits. Loaded rows
+/// are converted, which rewrites every table but `region` and `nation`, and
+/// the generated columns of `tpch_set_generated_columns()` have to be dropped
+/// first. Loads record whether the columns were `double precision`.
+#[pg_extern]
+fn tpch_set_double_precision(enabled: default!(bool, true)) -> spi::Result<Vec<String>> {
+    let type_name = if enabled {
+        "double precision"
+    } else {
+        "numeric(15,2)"
+    };
+    let changed =
+        retype_columns(decimal_columns().map(|(table, column)| (table, column, type_name)))?;
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info SET double_precision = $1
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+        &[enabled.into()],
+    )?;
+    Ok(changed)
+}
+
+/// Character columns of the tables as `(table, column, type)`, with the
+/// blank-padded or length-limited types of the specification. The
+/// categorical columns are left to the schema profiles.
+fn character_columns() -> impl Iterator<Item = (&'static str, &'static str, &'static str)> {
+    DEFAULT_COLUMNS
+        .iter()
+        .filter(|(table, column, type_name, _)| {
+            type_name.starts_with("character")
+                && !CATEGORICAL_COLUMNS
+                    .iter()
+                    .any(|(t, c, _, _)| t == table && c == column)
+        })
+        .map(|&(table, column, type_name, _)| (table, column, type_name))
+}
+
+/// Whether every character column of the TPC-H tables is a `text`.
+pub fn has_text_columns() -> spi::Result<bool> {
+    for (table, column, _) in character_columns() {
+        if live_type(table, column)?.as_deref() != Some("text") {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Switches the `character(n)` and `character varying(n)` columns of the
+/// TPC-H tables to `text`, or back without `enabled`, and returns the columns
+/// it changed.
+///
+/// `text` is what most schemas use today and what DuckDB and DataFusion load
+/// TPC-H into, so timings compare with theirs: nothing is blank-padded to its
+/// length and comparisons don't ignore trailing blanks. The categorical
+/// columns follow `tpch_set_schema_profile()` unless `enabled` is set with the
+/// `default` profile, then they become `text` too. Loaded rows are converted,
+/// which rewrites every table. Loads record whether the columns were `text`.
+#[pg_extern]
+fn tpch_set_text_columns(enabled: default!(bool, true)) -> spi::Result<Vec<String>> {
+    let mut columns: Vec<_> = character_columns().collect();
+    if live_profile()? == "default" {
+        for &(table, column, _, _) in CATEGORICAL_COLUMNS {
+            let default = DEFAULT_COLUMNS
+                .iter()
+                .find(|(t, c, _, _)| *t == table && *c == column)
+                .map_or("text", |(_, _, type_name, _)| type_name);
+            columns.push((table, column, default));
+        }
+    }
+    let changed =
+        retype_columns(columns.into_iter().map(|(table, column, default)| {
+            (table, column, if enabled { "text" } else { default })
+        }))?;
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info SET text_columns = $1
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+        &[enabled.into()],
+    )?;
+    Ok(changed)
+}
+
+/// Changes the type of `columns`, as `(table, column, type)`, where it
+/// differs, one `ALTER TABLE` per table, and returns the columns it changed.
+fn retype_columns(
+    columns: impl Iterator<Item = (&'static str, &'static str, &'static str)>,
+) -> spi::Result<Vec<String>> {
+    let columns: Vec<_> = columns.collect();
+    let mut changed = Vec::new();
+    for table in TABLES {
+        let mut alterations = Vec::new();
+        for (_, column, type_name) in columns.iter().filter(|(t, _, _)| *t == table) {
+            let live = live_type(table, column)?;
+            if live.is_some() && live.as_deref() != Some(type_name) {
+                alterations.push(format!("ALTER COLUMN {column} TYPE {type_name}"));
+                changed.push(format!("{table}.{column}"));
+            }
+        }
+        if !alterations.is_empty() {
+            Spi::run(&format!("ALTER TABLE {table} {}", alterations.join(", ")))?;
+        }
+    }
+    Ok(changed)
+}
+
+/// Granularities `orders` and `lineitem` can be range partitioned by.
+pub const RANGE_PARTITIONINGS: &[&str] = &["none", "year", "month"];
+
+/// Tables that can be range partitioned, with the date column they are
+/// partitioned on.
+const RANGE_PARTITIONED_TABLES: [(&str, &str); 2] =
+    [("orders", "o_orderdate"), ("lineitem", "l_shipdate")];
+
+/// Tables that can be hash partitioned, with their order key column, so
+/// that partitions of the same remainder join with each other.
+const HASH_PARTITIONED_TABLES: [(&str, &str); 2] =
+    [("orders", "o_orderkey"), ("lineitem", "l_orderkey")];
+
+/// Most hash partitions `tpch_set_hash_partitioning()` creates.
+const MAX_HASH_PARTITIONS: i32 = 1024;
+
+/// How `orders` and `lineitem` are partitioned.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Partitioning {
+    None,
+    /// By date, with a granularity of [`RANGE_PARTITIONINGS`].
+    Range(&'static str),
+    /// By order key, into this many partitions.
+    Hash(i32),
+}
+
+/// Years the generated dates fall into, orders being placed until August
+/// 1998 and shipped until December.
+const DATE_YEARS: std::ops::RangeInclusive<i32> = 1992..=1998;
+
+/// Returns how `orders` and `lineitem` are partitioned, going by
+/// `lineitem`.
+pub fn live_partitioning() -> spi::Result<Partitioning> {
+    let (strategy, partitions, monthly) = Spi::get_three::<String, i64, bool>(
+        "SELECT (SELECT partstrat::text FROM pg_partitioned_table
+                 WHERE partrelid = to_regclass('lineitem')),
+                (SELECT count(*) FROM pg_inherits WHERE inhparent = to_regclass('lineitem')),
+                to_regclass('lineitem_1992_01') IS NOT NULL",
+    )?;
+    Ok(match (strategy.as_deref(), monthly) {
+        (Some("h"), _) => Partitioning::Hash(partitions.unwrap_or_default() as i32),
+        (Some("r"), Some(true)) => Partitioning::Range("month"),
+        (Some("r"), _) => Partitioning::Range("year"),
+        _ => Partitioning::None,
+    })
+}
+
+/// Returns how `orders` and `lineitem` are range partitioned, one of
+/// [`RANGE_PARTITIONINGS`].
+pub fn live_range_partitioning() -> spi::Result<&'static str> {
+    Ok(match live_partitioning()? {
+        Partitioning::Range(granularity) => granularity,
+        _ => "none",
+    })
+}
+
+/// Returns into how many partitions `orders` and `lineitem` are hash
+/// partitioned, 0 if they aren't.
+pub fn live_hash_partitions() -> spi::Result<i32> {
+    Ok(match live_partitioning()? {
+        Partitioning::Hash(partitions) => partitions,
+        _ => 0,
+    })
+}
+
+/// Bounds of the partitions of `partitioning` as `(suffix, bound)`.
+fn partition_bounds(partitioning: Partitioning) -> Vec<(String, String)> {
+    let mut bounds = Vec::new();
+    match partitioning {
+        Partitioning::None => {}
+        Partitioning::Range(granularity) => {
+            for year in DATE_YEARS {
+                if granularity == "year" {
+                    bounds.push((
+                        year.to_string(),
+                        format!("FOR VALUES FROM ('{year}-01-01') TO ('{}-01-01')", year + 1),
+                    ));
+                    continue;
+                }
+                for month in 1..=12 {
+                    let (next_year, next_month) = if month == 12 {
+                        (year + 1, 1)
+                    } else {
+                        (year, month + 1)
+                    };
+                    bounds.push((
+                        format!("{year}_{month:02}"),
+                        format!(
+                            "FOR VALUES FROM ('{year}-{month:02}-01') TO ('{next_year}-{next_month:02}-01')"
+                        ),
+                    ));
+                }
+            }
+            bounds.push(("default".to_string(), "DEFAULT".to_string()));
+        }
+        Partitioning::Hash(modulus) => {
+            for remainder in 0..modulus {
+                bounds.push((
+                    format!("p{remainder}"),
+                    format!("FOR VALUES WITH (MODULUS {modulus}, REMAINDER {remainder})"),
+                ));
+            }
+        }
+    }
+    bounds
+}
+
+/// Creates `table` anew, partitioned by `partitioning` if it is one of
+/// [`RANGE_PARTITIONED_TABLES`] or [`HASH_PARTITIONED_TABLES`], and stored
+/// with `access_method`, and moves its rows and indexes over.
+///
+/// The table keeps its name, schema, indexes and membership in the
+/// extension. Range partitions are named after the table and the year or
+/// month, with a default partition for dates outside the generated ones,
+/// hash partitions after the table and their remainder. Partitioned tables
+/// have no storage of their own, their partitions use `access_method` and
+/// the storage parameters, persistence and tablespace of the table. Grants
+/// on the table carry over too.
+fn rebuild(table: &str, partitioning: Partitioning, access_method: &str) -> spi::Result<()> {
+    let (schema, member) = Spi::get_two_with_args::<String, bool>(
+        "SELECT quote_ident(n.nspname),
+                EXISTS (SELECT FROM pg_depend d
+                        WHERE d.classid = 'pg_class'::regclass AND d.objid = c.oid
+                          AND d.refclassid = 'pg_extension'::regclass AND d.deptype = 'e')
+         FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+         WHERE c.oid = to_regclass($1)",
+        &[table.into()],
+    )?;
+    let schema = schema.expect("the table exists");
+    let member = member == Some(true);
+    let (indexes, columns) = Spi::get_two_with_args::<Vec<String>, String>(
+        "SELECT (SELECT coalesce(array_agg(pg_get_indexdef(indexrelid) ORDER BY indexrelid), '{}')
+                 FROM pg_index WHERE indrelid = $1::regclass),
+                (SELECT string_agg(quote_ident(attname), ', ' ORDER BY attnum) FROM pg_attribute
+                 WHERE attrelid = $1::regclass AND attnum > 0
+                   AND NOT attisdropped AND attgenerated = '')",
+        &[table.into()],
+    )?;
+    let columns = columns.unwrap_or_default();
+    // `LIKE` copies neither persistence nor tablespace, which the partitions
+    // of a partitioned table carry.
+    let (unlogged, tablespace) = Spi::get_two_with_args::<bool, String>(
+        "SELECT bool_and(c.relpersistence = 'u'), max(quote_ident(t.spcname))
+         FROM pg_class c LEFT JOIN pg_tablespace t ON t.oid = c.reltablespace
+         WHERE c.relkind <> 'p'
+           AND c.oid = ANY(SELECT to_regclass($1)
+                           UNION ALL
+                           SELECT relid FROM pg_partition_tree(to_regclass($1)) WHERE isleaf)",
+        &[table.into()],
+    )?;
+    let persistence = if unlogged == Some(true) {
+        "UNLOGGED "
+    } else {
+        ""
+    };
+    let old = format!("{table}_previous");
+    let using = format!(
+        " USING {}{}{}",
+        spi::quote_identifier(access_method),
+        with_storage_parameters(live_storage_parameters(table)?),
+        tablespace
+            .map(|tablespace| format!(" TABLESPACE {tablespace}"))
+            .unwrap_or_default()
+    );
+
+    let mut relations = vec![table.to_string()];
+    if member {
+        Spi::run(&format!(
+            "ALTER EXTENSION pg_tpch DROP TABLE {schema}.{table}"
+        ))?;
+    }
+    // The old partitions go away with the old table, but the new ones need
+    // their names before.
+    for partition in partitions(table)? {
+        if member {
+            Spi::run(&format!(
+                "ALTER EXTENSION pg_tpch DROP TABLE {schema}.{partition}"
+            ))?;
+        }
+        Spi::run(&format!(
+            "ALTER TABLE {schema}.{partition} RENAME TO {partition}_previous"
+        ))?;
+    }
+    Spi::run(&format!("ALTER TABLE {schema}.{table} RENAME TO {old}"))?;
+    let (strategy, partitioned) = match partitioning {
+        Partitioning::None => ("", &[][..]),
+        Partitioning::Range(_) => ("RANGE", &RANGE_PARTITIONED_TABLES[..]),
+        Partitioning::Hash(_) => ("HASH", &HASH_PARTITIONED_TABLES[..]),
+    };
+    let column = partitioned
+        .iter()
+        .find(|(t, _)| *t == table)
+        .map(|(_, column)| column);
+    // Partitioned tables can't be unlogged, their partitions are.
+    let (persistence_of_table, definition) = match column {
+        Some(column) => ("", format!("PARTITION BY {strategy} ({column})")),
+        None => (persistence, using.clone()),
+    };
+    Spi::run(&format!(
+        "CREATE {persistence_of_table}TABLE {schema}.{table}
+         (LIKE {schema}.{old} INCLUDING ALL EXCLUDING INDEXES) {definition}"
+    ))?;
+    if column.is_some() {
+        for (suffix, bound) in partition_bounds(partitioning) {
+            let partition = format!("{table}_{suffix}");
+            Spi::run(&format!(
+                "CREATE {persistence}TABLE {schema}.{partition}
+                 PARTITION OF {schema}.{table} {bound}{using}"
+            ))?;
+            relations.push(partition);
+        }
+    }
+    Spi::run(&format!(
+        "INSERT INTO {schema}.{table} ({columns}) SELECT {columns} FROM {schema}.{old}"
+    ))?;
+    // Nor does it copy grants, those of the owner go with the ownership.
+    let grants = Spi::get_one_with_args::<Vec<String>>(
+        "SELECT coalesce(array_agg(format('GRANT %s ON %s TO %s%s', a.privilege_type, $2,
+                                          CASE WHEN a.grantee = 0 THEN 'PUBLIC'
+                                               ELSE quote_ident(r.rolname) END,
+                                          CASE WHEN a.is_grantable THEN ' WITH GRANT OPTION'
+                                               ELSE '' END)), '{}')
+         FROM pg_class c CROSS JOIN aclexplode(c.relacl) a
+              LEFT JOIN pg_roles r ON r.oid = a.grantee
+         WHERE c.oid = $1::regclass AND a.grantee <> c.relowner",
+        &[
+            format!("{schema}.{old}").into(),
+            format!("{schema}.{table}").into(),
+        ],
+    )?;
+    for grant in grants.unwrap_or_default() {
+        Spi::run(&grant)?;
+    }
+    Spi::run(&format!("DROP TABLE {schema}.{old}"))?;
+    // Indexes of a partitioned table are defined `ON ONLY` it, without
+    // those of its partitions.
+    for index in indexes.unwrap_or_default() {
+        Spi::run(&index.replacen(" ON ONLY ", " ON ", 1))?;
+    }
+    if member {
+        for relation in relations {
+            Spi::run(&format!(
+                "ALTER EXTENSION pg_tpch ADD TABLE {schema}.{relation}"
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Refuses `operation`, which creates `tables` anew, if they have keys or
+/// other tables reference them.
+fn check_unkeyed(tables: &[&str], operation: &str) -> spi::Result<()> {
+    let keyed = Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS (SELECT FROM pg_constraint
+                        WHERE contype IN ('p', 'u', 'f')
+                          AND (conrelid = ANY(SELECT to_regclass(t) FROM unnest($1::text[]) t)
+                               OR confrelid = ANY(SELECT to_regclass(t) FROM unnest($1::text[]) t)))",
+        &[tables.to_vec().into()],
+    )?;
+    if keyed == Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_NOT_IN_PREREQUISITE_STATE,
+            format!("{operation} needs {} without keys", tables.join(", ")),
+            "Drop the primary and foreign keys of and to these tables first."
+        );
+    }
+    Ok(())
+}
+
+/// Table access method `table` is stored with, that of its partitions if
+/// it is partitioned.
+pub fn live_access_method(table: &str) -> spi::Result<String> {
+    Spi::get_one_with_args::<String>(
+        "SELECT max(a.amname::text) FROM pg_class c JOIN pg_am a ON a.oid = c.relam
+         WHERE c.oid = ANY(SELECT to_regclass($1)
+                           UNION ALL
+                           SELECT relid FROM pg_partition_tree(to_regclass($1)) WHERE isleaf)",
+        &[table.into()],
+    )
+    .map(|access_method| access_method.unwrap_or_else(|| "heap".to_string()))
+}
+
+/// Tables stored with another access method than `heap`, with theirs.
+pub fn live_access_methods() -> spi::Result<Vec<(String, String)>> {
+    let mut access_methods = Vec::new();
+    for table in TABLES {
+        let access_method = live_access_method(table)?;
+        if access_method != "heap" {
+            access_methods.push((table.to_string(), access_method));
+        }
+    }
+    Ok(access_methods)
+}
+
+/// Stores `tables`, all TPC-H tables by default, with table access method
+/// `access_method`, and returns the tables it changed.
+///
+/// This is how to benchmark columnar storage and other table access methods
+/// that extensions provide, `heap` being the one of Postgres. The tables are
+/// created anew with their rows, indexes, persistence, tablespace and grants,
+/// like `tpch_set_range_partitioning()` does, so they must not have keys,
+/// and partitioned tables get the access method on their partitions. Loads
+/// record the tables not stored with `heap` in `access_methods`.
+#[pg_extern]
+fn tpch_set_access_method(
+    access_method: &str,
+    tables: default!(Option<Vec<String>>, "NULL"),
+) -> spi::Result<Vec<String>> {
+    let exists = Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS (SELECT FROM pg_am WHERE amname = $1 AND amtype = 't')",
+        &[access_method.into()],
+    )?;
+    if exists != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("table access method \"{access_method}\" does not exist"),
+            "Create the extension providing it first."
+        );
+    }
+    let tables = tables.unwrap_or_else(|| TABLES.map(str::to_string).to_vec());
+    if let Some(table) = tables.iter().find(|t| !TABLES.contains(&t.as_str())) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("unknown TPC-H table \"{table}\", expected one of {TABLES:?}")
+        );
+    }
+    let mut changing = Vec::new();
+    for table in TABLES
+        .into_iter()
+        .filter(|t| tables.iter().any(|name| name == t))
+    {
+        if live_access_method(table)? != access_method {
+            changing.push(table);
+        }
+    }
+    check_unkeyed(&changing, "changing the access method")?;
+    let partitioning = live_partitioning()?;
+    for &table in &changing {
+        rebuild(table, partitioning, access_method)?;
+    }
+    let (tables, access_methods): (Vec<String>, Vec<String>) =
+        live_access_methods()?.into_iter().unzip();
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info SET access_methods = jsonb_object($1, $2)
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+        &[tables.into(), access_methods.into()],
+    )?;
+    Ok(changing.into_iter().map(str::to_string).collect())
+}
+
+/// Tablespace `lineitem` is stored in, that of its partitions if it is
+/// partitioned, NULL for the default tablespace of the database.
+pub fn live_tablespace() -> spi::Result<Option<String>> {
+    Spi::get_one(
+        "SELECT max(t.spcname::text) FROM pg_class c JOIN pg_tablespace t ON t.oid = c.reltablespace
+         WHERE c.oid = ANY(SELECT to_regclass('lineitem')
+                           UNION ALL
+                           SELECT relid FROM pg_partition_tree(to_regclass('lineitem')) WHERE isleaf)",
+    )
+}
+
+/// Moves the TPC-H tables, and their indexes unless `indexes` is false, to
+/// tablespace `tablespace` and returns the relations it moved.
+///
+/// Big scale factors can so go to a volume of their own, `pg_default` moves
+/// them back. Moving copies the files of every relation and locks it out
+/// while it does. Partitioned tables move their partitions and keep the
+/// tablespace for partitions created later. Indexes created afterwards go to
+/// `default_tablespace`, which can name the same tablespace. Loads record
+/// the tablespace of `lineitem`.
+#[pg_extern]
+fn tpch_set_tablespace(
+    tablespace: &str,
+    indexes: default!(bool, true),
+) -> spi::Result<Vec<String>> {
+    let exists = Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS (SELECT FROM pg_tablespace WHERE spcname = $1)",
+        &[tablespace.into()],
+    )?;
+    if exists != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_UNDEFINED_OBJECT,
+            format!("tablespace \"{tablespace}\" does not exist"),
+            "Create it with CREATE TABLESPACE first."
+        );
+    }
+    // Tables before their indexes, relations already in the tablespace left
+    // out.
+    let relations = Spi::connect(|client| {
+        client
+            .select(
+                "SELECT r.oid::regclass::text, r.relkind::text
+                 FROM unnest($1::text[]) WITH ORDINALITY t(name, nr)
+                 CROSS JOIN LATERAL (
+                     SELECT to_regclass(t.name) AS relid
+                     UNION ALL
+                     SELECT relid FROM pg_partition_tree(to_regclass(t.name)) WHERE level > 0
+                 ) l
+                 CROSS JOIN LATERAL (
+                     SELECT l.relid AS oid, 0 AS kind
+                     UNION ALL
+                     SELECT indexrelid, 1 FROM pg_index WHERE indrelid = l.relid AND $3
+                 ) i
+                 JOIN pg_class r ON r.oid = i.oid
+                 WHERE coalesce(nullif(r.reltablespace, 0),
+                                (SELECT dattablespace FROM pg_database
+                                 WHERE datname = current_database()))
+                       <> (SELECT oid FROM pg_tablespace WHERE spcname = $2)
+                 ORDER BY i.kind, t.nr, l.relid, r.oid",
+                None,
+                &[TABLES.to_vec().into(), tablespace.into(), indexes.into()],
+            )?
+            .map(|row| {
+                Ok((
+                    row.get::<String>(1)?.unwrap_or_default(),
+                    row.get::<String>(2)?.unwrap_or_default(),
+                ))
+            })
+            .collect::<spi::Result<Vec<_>>>()
+    })?;
+    let quoted = spi::quote_identifier(tablespace);
+    let mut moved = Vec::new();
+    for (relation, relkind) in relations {
+        let kind = if ["i", "I"].contains(&relkind.as_str()) {
+            "INDEX"
+        } else {
+            "TABLE"
+        };
+        Spi::run(&format!("ALTER {kind} {relation} SET TABLESPACE {quoted}"))?;
+        moved.push(relation);
+    }
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info SET tablespace = $1
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+        &[live_tablespace()?.into()],
+    )?;
+    Ok(moved)
+}
+
+/// Storage parameters of `pg_tpch.storage_parameters`, in the `name=value`
+/// form of `pg_class.reloptions`.
+pub fn storage_parameters() -> Vec<String> {
+    let Some(setting) = STORAGE_PARAMETERS.get() else {
+        return Vec::new();
+    };
+    let mut parameters = Vec::new();
+    for entry in setting.to_string_lossy().split(',') {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let word = |s: &str| {
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        };
+        match entry.split_once('=') {
+            Some((name, value)) if word(name.trim()) && word(value.trim()) => {
+                parameters.push(format!(
+                    "{}={}",
+                    name.trim().to_ascii_lowercase(),
+                    value.trim()
+                ));
+            }
+            _ => {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    format!("invalid storage parameter \"{entry}\" in pg_tpch.storage_parameters"),
+                    "Write them as name=value pairs separated by commas."
+                );
+            }
+        }
+    }
+    parameters
+}
+
+/// Relations holding the rows of `table`: the table itself, or its
+/// partitions if it is partitioned.
+fn storage_relations(table: &str) -> spi::Result<Vec<String>> {
+    Spi::get_one_with_args::<Vec<String>>(
+        "SELECT coalesce(array_agg(c.oid::regclass::text ORDER BY c.oid), '{}') FROM pg_class c
+         WHERE c.relkind <> 'p'
+           AND c.oid = ANY(SELECT to_regclass($1)
+                           UNION ALL
+                           SELECT relid FROM pg_partition_tree(to_regclass($1)) WHERE isleaf)",
+        &[table.into()],
+    )
+    .map(Option::unwrap_or_default)
+}
+
+/// Storage parameters `table` is stored with, those of its first partition
+/// if it is partitioned.
+pub fn live_storage_parameters(table: &str) -> spi::Result<Vec<String>> {
+    let Some(relation) = storage_relations(table)?.into_iter().next() else {
+        return Ok(Vec::new());
+    };
+    Spi::get_one_with_args::<Vec<String>>(
+        "SELECT coalesce(reloptions, '{}') FROM pg_class WHERE oid = $1::regclass",
+        &[relation.into()],
+    )
+    .map(Option::unwrap_or_default)
+}
+
+/// `WITH` clause creating a table with storage parameters `reloptions` and
+/// those of `pg_tpch.storage_parameters`, which win over them, empty if
+/// there are none.
+fn with_storage_parameters(reloptions: Vec<String>) -> String {
+    let name = |parameter: &str| parameter.split('=').next().unwrap_or_default().to_string();
+    let mut parameters = reloptions;
+    for parameter in storage_parameters() {
+        parameters.retain(|p| name(p) != name(&parameter));
+        parameters.push(parameter);
+    }
+    if parameters.is_empty() {
+        return String::new();
+    }
+    format!(" WITH ({})", parameters.join(", "))
+}
+
+/// Sets the storage parameters of `pg_tpch.storage_parameters` on the
+/// TPC-H tables on the search path, on the partitions of partitioned ones.
+pub fn apply_storage_parameters() -> spi::Result<()> {
+    let parameters = storage_parameters();
+    if parameters.is_empty() {
+        return Ok(());
+    }
+    for table in TABLES {
+        for relation in storage_relations(table)? {
+            Spi::run(&format!(
+                "ALTER TABLE {relation} SET ({})",
+                parameters.join(", ")
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Partitions of `table`, none if it isn't partitioned.
+fn partitions(table: &str) -> spi::Result<Vec<String>> {
+    Spi::get_one_with_args::<Vec<String>>(
+        "SELECT coalesce(array_agg(c.relname::text), '{}')
+         FROM pg_inherits i JOIN pg_class c ON c.oid = i.inhrelid
+         WHERE i.inhparent = to_regclass($1)",
+        &[table.into()],
+    )
+    .map(Option::unwrap_or_default)
+}
+
+/// Range partitions `orders` on `o_orderdate` and `lineitem` on `l_shipdate`
+/// by `granularity`, `year` or `month`, or turns them back into plain tables
+/// with `none`, and returns the tables it changed.
+///
+/// Queries that restrict these dates then scan only the partitions they
+/// need, which is the usual setup to measure partition pruning with TPC-H.
+/// Loads go through the partitioned tables, which route every row to its
+/// partition, so `freeze => true` can't be used. The tables are created
+/// anew with their rows and indexes, in the schema they were in. Postgres
+/// wants the partition key in primary keys and doesn't allow foreign keys
+/// pointing to partitioned tables, so the tables must not have keys. Loads
+/// record the partitioning as `range_partitioning`.
+#[pg_extern]
+fn tpch_set_range_partitioning(granularity: default!(&str, "'month'")) -> spi::Result<Vec<String>> {
+    let Some(granularity) = RANGE_PARTITIONINGS
+        .iter()
+        .copied()
+        .find(|g| *g == granularity)
+    else {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!(
+                "unknown range partitioning \"{granularity}\", expected one of {RANGE_PARTITIONINGS:?}"
+            )
+        );
+    };
+    let partitioning = match granularity {
+        "none" => Partitioning::None,
+        granularity => Partitioning::Range(granularity),
+    };
+    let tables: Vec<&str> = RANGE_PARTITIONED_TABLES.iter().map(|(t, _)| *t).collect();
+    repartition(&tables, partitioning, "range partitioning")
+}
+
+/// Hash partitions `orders` on `o_orderkey` and `lineitem` on `l_orderkey`
+/// into `partitions` partitions each, or turns them back into plain tables
+/// with 0, and returns the tables it changed.
+///
+/// Both tables get the same partition bounds, so with
+/// `enable_partitionwise_join` Postgres joins them partition by partition,
+/// and with `enable_partitionwise_aggregate` aggregates grouping by the
+/// order key per partition. Hash partitioning replaces range partitioning
+/// and the other way around. Like with `tpch_set_range_partitioning()`, the
+/// tables are created anew with their rows and indexes, must not have keys,
+/// and can't be loaded with `freeze => true`. Loads record the number of
+/// partitions as `hash_partitions`.
+#[pg_extern]
+fn tpch_set_hash_partitioning(partitions: default!(i32, 8)) -> spi::Result<Vec<String>> {
+    if !(0..=MAX_HASH_PARTITIONS).contains(&partitions) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("partitions must be between 0 and {MAX_HASH_PARTITIONS}, got {partitions}")
+        );
+    }
+    let partitioning = match partitions {
+        0 => Partitioning::None,
+        partitions => Partitioning::Hash(partitions),
+    };
+    let tables: Vec<&str> = HASH_PARTITIONED_TABLES.iter().map(|(t, _)| *t).collect();
+    repartition(&tables, partitioning, "hash partitioning")
+}
+
+/// Creates `tables` anew partitioned by `partitioning`, unless they already
+/// are, records the partitioning of the current load and returns the tables
+/// it changed.
+fn repartition(
+    tables: &[&str],
+    partitioning: Partitioning,
+    operation: &str,
+) -> spi::Result<Vec<String>> {
+    if live_partitioning()? == partitioning {
+        return Ok(Vec::new());
+    }
+    check_unkeyed(tables, operation)?;
+    let mut changed = Vec::new();
+    for &table in tables {
+        rebuild(table, partitioning, &live_access_method(table)?)?;
+        changed.push(table.to_string());
+    }
+    Spi::run_with_args(
+        "UPDATE pg_tpch_load_info SET range_partitioning = $1, hash_partitions = $2
+         WHERE load_id IN (SELECT load_id FROM pg_tpch_dataset)",
+        &[
+            live_range_partitioning()?.into(),
+            live_hash_partitions()?.into(),
+        ],
+    )?;
+    Ok(changed)
+}
+
+/// Drops the TPC-H tables that exist, with their partitions and whatever
+/// depends on them, and the tables `tpch_run` stored query results in, and
+/// returns the tables it dropped.
+///
+/// The dropped tables are taken out of the extension first, and the current
+/// load is recorded as truncated.
+pub fn drop_tables() -> spi::Result<Vec<String>> {
+    // The current load is the one of the tables on the search path, which
+    // have to be there to tell.
+    crate::metadata::mark_truncated()?;
+    let mut dropped = Vec::new();
+    for table in TABLES.iter().rev() {
+        let relations = Spi::connect(|client| {
+            client
+                .select(
+                    "SELECT c.oid::regclass::text,
+                            EXISTS (SELECT FROM pg_depend d
+                                    WHERE d.classid = 'pg_class'::regclass AND d.objid = c.oid
+                                      AND d.refclassid = 'pg_extension'::regclass
+                                      AND d.deptype = 'e')
+                     FROM pg_class c
+                     WHERE c.oid IN (SELECT to_regclass($1)
+                                     UNION
+                                     SELECT relid FROM pg_partition_tree(to_regclass($1)))
+                     ORDER BY c.oid",
+                    None,
+                    &[(*table).into()],
+                )?
+                .map(|row| {
+                    Ok((
+                        row.get::<String>(1)?.unwrap_or_default(),
+                        row.get::<bool>(2)?.unwrap_or_default(),
+                    ))
+                })
+                .collect::<spi::Result<Vec<_>>>()
+        })?;
+        for (relation, member) in &relations {
+            if *member {
+                Spi::run(&format!("ALTER EXTENSION pg_tpch DROP TABLE {relation}"))?;
+            }
+        }
+        if !relations.is_empty() {
+            Spi::run(&format!("DROP TABLE {table} CASCADE"))?;
+            dropped.push(table.to_string());
+        }
+    }
+    // Their indexes went with the tables.
+    Spi::run_with_args(
+        "DELETE FROM pg_tpch_indexes WHERE table_name = ANY($1)",
+        &[dropped.clone().into()],
+    )?;
+    let results = Spi::get_one::<Vec<String>>(
+        "SELECT coalesce(array_agg(DISTINCT result_table), '{}') FROM pg_tpch_run_results
+         WHERE to_regclass(quote_ident(result_table)) IS NOT NULL",
+    )?
+    .unwrap_or_default();
+    for result in &results {
+        Spi::run(&format!("DROP TABLE {}", spi::quote_identifier(result)))?;
+    }
+    Spi::run("UPDATE pg_tpch_run_results SET result_table = NULL WHERE result_table IS NOT NULL")?;
+    dropped.extend(results);
+    Ok(dropped)
+}
+
+/// Drops the TPC-H tables and returns them, cleaning up after benchmarking
+/// in one call.
+///
+/// Views and foreign keys depending on the tables are dropped with them,
+/// like a view `revenue0` that a failed run of query 15 left behind, and so
+/// are the tables `persist_results => true` stored query results in. The
+/// records of loads and runs are kept. Loading again needs the tables, which
+/// `tpch_reset()` creates anew.
+#[pg_extern]
+fn tpch_drop_tables() -> spi::Result<Vec<String>> {
+    drop_tables()
+}
+
+/// Drops the TPC-H tables and creates them anew, empty, with the schema
+/// options the live tables have, and returns the tables it created.
+///
+/// The options are those of the `tpch_set_*` functions: the schema profile,
+/// generated columns, unlogged tables, the types of the key, decimal and
+/// character columns, range or hash partitioning, access methods, storage
+/// parameters and tablespace, the storage parameters of
+/// `pg_tpch.storage_parameters` added.
+/// Tables that don't exist, after `tpch_drop_tables()` for one, are created
+/// like the extension creates them. Keys and indexes are not created again.
+#[pg_extern]
+fn tpch_reset() -> spi::Result<Vec<String>> {
+    let profile = live_profile()?;
+    let generated = has_generated_columns()?;
+    let unlogged = has_unlogged_tables()?;
+    let bigint = has_bigint_keys()?;
+    let double = has_double_decimals()?;
+    let text = has_text_columns()?;
+    let partitioning = live_partitioning()?;
+    let access_methods = live_access_methods()?;
+    let tablespace = live_tablespace()?;
+    let mut reloptions = Vec::with_capacity(TABLES.len());
+    for table in TABLES {
+        reloptions.push(live_storage_parameters(table)?);
+    }
+
+    drop_tables()?;
+    let schema = Spi::get_one::<String>(
+        "SELECT extnamespace::regnamespace::text FROM pg_extension WHERE extname = 'pg_tpch'",
+    )?
+    .expect("the extension is installed");
+    for (table, reloptions) in TABLES.into_iter().zip(reloptions) {
+        let columns: Vec<String> = expected_columns(profile, table)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(column, type_name, not_null)| {
+                format!(
+                    "{column} {type_name}{}",
+                    if not_null { " NOT NULL" } else { "" }
+                )
+            })
+            .collect();
+        Spi::run(&format!(
+            "CREATE TABLE {schema}.{table} ({}){}",
+            columns.join(", "),
+            with_storage_parameters(reloptions)
+        ))?;
+        Spi::run(&format!(
+            "ALTER EXTENSION pg_tpch ADD TABLE {schema}.{table}"
+        ))?;
+    }
+
+    // The generated columns are computed from the decimal columns, which
+    // can't change type under them.
+    tpch_set_bigint_keys(bigint)?;
+    tpch_set_double_precision(double)?;
+    tpch_set_text_columns(text)?;
+    tpch_set_generated_columns(generated)?;
+    match partitioning {
+        Partitioning::None => {}
+        Partitioning::Range(granularity) => {
+            tpch_set_range_partitioning(granularity)?;
+        }
+        Partitioning::Hash(partitions) => {
+            tpch_set_hash_partitioning(partitions)?;
+        }
+    }
+    for (table, access_method) in access_methods {
+        tpch_set_access_method(&access_method, Some(vec![table]))?;
+    }
+    tpch_set_unlogged(unlogged)?;
+    if let Some(tablespace) = tablespace {
+        tpch_set_tablespace(&tablespace, true)?;
+    }
+    Ok(TABLES.map(str::to_string).to_vec())
+}
+
+/// Makes the TPC-H tables of schema `name` the ones this transaction loads,
+/// creating the schema and the tables, like those of the extension, if they
+/// don't exist.
+///
+/// The schema goes first on `search_path` until the transaction ends, so
+/// every statement naming a TPC-H table finds it there, `COPY` included,
+/// while the `pg_tpch_*` tables stay with the extension. Those record the
+/// loads and table states of every schema apart, by `schema_name`.
+pub fn use_target_schema(name: &str) -> spi::Result<()> {
+    let quoted = spi::quote_identifier(name);
+    Spi::run(&format!("CREATE SCHEMA IF NOT EXISTS {quoted}"))?;
+    let home = Spi::get_one::<String>(
+        "SELECT extnamespace::regnamespace::text FROM pg_extension WHERE extname = 'pg_tpch'",
+    )?
+    .expect("the extension is installed");
+    for table in TABLES {
+        Spi::run(&format!(
+            "CREATE TABLE IF NOT EXISTS {quoted}.{table} (LIKE {home}.{table} INCLUDING ALL){}",
+            with_storage_parameters(Vec::new())
+        ))?;
+    }
+    search_first(name)
+}
+
+/// Tables recording loads and runs, which temporary tables shadow along with
+/// the TPC-H tables, see [`use_temp_tables`].
+const BOOKKEEPING_TABLES: &[&str] = &[
+    "pg_tpch_load_info",
+    "pg_tpch_table_state",
+    "pg_tpch_load_stats",
+    "pg_tpch_load_steps",
+    "pg_tpch_runs",
+    "pg_tpch_plans",
+    "pg_tpch_run_results",
+];
+
+/// Views over [`BOOKKEEPING_TABLES`], shadowed by views over the temporary
+/// ones.
+const BOOKKEEPING_VIEWS: &[&str] = &[
+    "pg_tpch_dataset",
+    "pg_tpch_load_throughput",
+    "pg_tpch_run_report",
+    "pg_tpch_run_plans",
+];
+
+/// Makes temporary tables of this session the TPC-H tables, creating them
+/// like those of the extension unless they exist.
+///
+/// Temporary tables come first on every search path, so from then on the
+/// session loads and queries them. The tables and views recording loads and
+/// runs get temporary copies too, empty, so the records of the extension are
+/// left alone and the runs refer to the temporary loads. They all go away
+/// with the session.
+pub fn use_temp_tables() -> spi::Result<()> {
+    if Spi::get_one::<bool>("SELECT to_regclass('pg_temp.lineitem') IS NOT NULL")? == Some(true) {
+        return Ok(());
+    }
+    let home = Spi::get_one::<String>(
+        "SELECT extnamespace::regnamespace::text FROM pg_extension WHERE extname = 'pg_tpch'",
+    )?
+    .expect("the extension is installed");
+    // The definitions name the tables of the extension, which the temporary
+    // views should find among the temporary tables instead.
+    let mut views = Vec::with_capacity(BOOKKEEPING_VIEWS.len());
+    for view in BOOKKEEPING_VIEWS {
+        let definition =
+            Spi::get_one::<String>(&format!("SELECT pg_get_viewdef('{home}.{view}')"))?
+                .expect("the view exists");
+        views.push((view, definition.replace(&format!("{home}."), "")));
+    }
+    for table in TABLES.iter().chain(BOOKKEEPING_TABLES) {
+        Spi::run(&format!(
+            "CREATE TEMPORARY TABLE {table} (LIKE {home}.{table} INCLUDING ALL)"
+        ))?;
+    }
+    for (view, definition) in views {
+        Spi::run(&format!("CREATE TEMPORARY VIEW {view} AS {definition}"))?;
+    }
+    Ok(())
+}
+
+/// Puts schema `name` first on `search_path` until the transaction ends.
+pub fn search_first(name: &str) -> spi::Result<()> {
+    Spi::run_with_args(
+        "SELECT set_config('search_path', $1 || ', ' || current_setting('search_path'), true)",
+        &[spi::quote_identifier(name).into()],
+    )
+}
+
+/// Number of rows of every table as the specification gives it, in terms of
+/// the scale factor.
+const CARDINALITIES: &[(&str, &str)] = &[
+    ("region", "5"),
+    ("nation", "25"),
+    ("part", "SF * 200,000"),
+    ("supplier", "SF * 10,000"),
+    ("partsupp", "SF * 800,000"),
+    ("customer", "SF * 150,000"),
+    ("orders", "SF * 1,500,000"),
+    ("lineitem", "SF * 6,000,000 (approximately)"),
+];
+
+/// Describes the TPC-H model, one row per column in table and column order:
+/// its type under schema profile `profile`, whether it is part of the
+/// primary key, which column it references and the cardinality of its table.
+///
+/// Describes the model the specification defines, whatever the live tables
+/// look like, see `tpch_check_schema()` for how they differ. Composite keys
+/// reference column by column, `l_partkey` and `l_suppkey` together reference
+/// the key of `partsupp`.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_schema_info(
+    profile: default!(&str, "'default'"),
+) -> TableIterator<
+    'static,
+    (
+        name!(table_name, String),
+        name!(column_name, String),
+        name!(ordinal, i32),
+        name!(data_type, String),
+        name!(not_null, bool),
+        name!(primary_key, bool),
+        name!(references_table, Option<String>),
+        name!(references_column, Option<String>),
+        name!(cardinality, String),
+    ),
+> {
+    if !PROFILES.contains(&profile) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("unknown schema profile \"{profile}\", expected one of {PROFILES:?}")
+        );
+    }
+    let mut rows = Vec::new();
+    for table in TABLES {
+        let primary_key = constraints::PRIMARY_KEYS
+            .iter()
+            .find(|(t, _)| *t == table)
+            .map_or("", |(_, columns)| columns);
+        let cardinality = CARDINALITIES
+            .iter()
+            .find(|(t, _)| *t == table)
+            .map_or("", |(_, cardinality)| cardinality);
+        let columns = expected_columns(profile, table).unwrap_or_default();
+        for (ordinal, (column, type_name, not_null)) in columns.into_iter().enumerate() {
+            let reference = constraints::FOREIGN_KEYS
+                .iter()
+                .filter(|(t, _, _, _)| *t == table)
+                .find_map(|(_, columns, referenced, referenced_columns)| {
+                    let position = columns.split(", ").position(|c| c == column)?;
+                    let referenced_column = referenced_columns.split(", ").nth(position)?;
+                    Some((referenced.to_string(), referenced_column.to_string()))
+                });
+            let (references_table, references_column) = reference.unzip();
+            rows.push((
+                table.to_string(),
+                column.to_string(),
+                ordinal as i32 + 1,
+                type_name.to_string(),
+                not_null,
+                primary_key.split(", ").any(|c| c == column),
+                references_table,
+                references_column,
+                cardinality.to_string(),
+            ));
+        }
+    }
+    TableIterator::new(rows)
+}
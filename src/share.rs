@@ -0,0 +1,91 @@
+//! Exporting a run as one document to share.
+use pgrx::prelude::*;
+use pgrx::{spi, JsonB};
+
+/// Settings that can identify the server, its users or its file system,
+/// matched against the setting name. Settings whose value looks like a path
+/// are left out as well.
+const SENSITIVE_SETTINGS: &str =
+    "(host|user|address|^port$|conninfo|password|directory|file|path|socket|ssl|krb|gss|cluster_name|application_name)";
+
+/// Returns run `run_id` as a JSON document with the run, the dataset it ran
+/// on, the server version and settings, and every query's timing, resource
+/// usage and plan.
+///
+/// Settings are those of the server at the time of the export, as far as
+/// they differ from the defaults. With `anonymize`, the document leaves out
+/// the role the queries ran as and every setting that names hosts, users,
+/// files or connections, so it can be attached to a public bug report.
+#[pg_extern]
+fn tpch_export_run(run_id: i64, anonymize: default!(bool, true)) -> spi::Result<JsonB> {
+    let exists = Spi::get_one_with_args::<bool>(
+        "SELECT EXISTS (SELECT FROM pg_tpch_runs WHERE run_id = $1)",
+        &[run_id.into()],
+    )?;
+    if exists != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_NO_DATA_FOUND,
+            format!("run {run_id} does not exist")
+        );
+    }
+    let document = Spi::get_one_with_args::<JsonB>(
+        &format!(
+            "SELECT jsonb_build_object(
+                 'run', jsonb_build_object(
+                     'run_id', run.run_id,
+                     'profile', run.profile,
+                     'role', CASE WHEN NOT $2 THEN run.role END,
+                     'max_duration', run.max_duration,
+                     'budget_exhausted', run.budget_exhausted,
+                     'generated_columns', run.generated_columns,
+                     'track_io_timing', run.track_io_timing,
+                     'started_at', run.started_at,
+                     'completed_at', run.completed_at
+                 ),
+                 'dataset', (
+                     SELECT jsonb_build_object(
+                         'sf', l.sf,
+                         'spec_compliant', l.spec_compliant,
+                         'children', l.children,
+                         'schema_profile', l.schema_profile,
+                         'generated_columns', l.generated_columns,
+                         'options', l.options,
+                         'generator_version', l.generator_version,
+                         'duration', l.duration
+                     )
+                     FROM pg_tpch_load_info l WHERE l.load_id = run.load_id
+                 ),
+                 'server', jsonb_build_object(
+                     'version', current_setting('server_version'),
+                     'settings', (
+                         SELECT coalesce(jsonb_object_agg(name, setting ORDER BY name), '{{}}')
+                         FROM pg_settings
+                         WHERE source NOT IN ('default', 'override')
+                           AND NOT ($2 AND (name ~ {} OR setting ~ '[/\\\\]'))
+                     )
+                 ),
+                 'results', (
+                     SELECT coalesce(jsonb_agg(jsonb_build_object(
+                         'query_nr', r.query_nr,
+                         'iteration', r.iteration,
+                         'duration_ms', r.duration_ms,
+                         'rows', r.rows,
+                         'peak_memory', r.peak_memory,
+                         'temp_bytes', r.temp_bytes,
+                         'io_read_ms', r.io_read_ms,
+                         'io_write_ms', r.io_write_ms,
+                         'plan', p.plan
+                     ) ORDER BY r.started_at, r.query_nr, r.iteration), '[]')
+                     FROM pg_tpch_run_results r LEFT JOIN pg_tpch_plans p USING (plan_hash)
+                     WHERE r.run_id = run.run_id
+                 ),
+                 'anonymized', $2
+             )
+             FROM pg_tpch_runs run WHERE run.run_id = $1",
+            spi::quote_literal(SENSITIVE_SETTINGS)
+        ),
+        &[run_id.into(), anonymize.into()],
+    )?;
+    Ok(document.expect("the run exists"))
+}
@@ -0,0 +1,600 @@
+//! The files a load spills generated data into before `COPY` reads them.
+//!
+//! With `pg_tpch.spill_compression` set, the data goes through the codec's
+//! command line tool on its way to disk and `COPY FROM PROGRAM` decompresses
+//! it again while loading, so a table never takes its full size on disk.
+//!
+//! `pg_tpch.max_temp_bytes` caps the space the files of a load take at once.
+//! Writing fails as soon as the files grow past it, and a file is removed
+//! when its [`SpillFile`] goes out of scope, also when the load fails. Files
+//! still around when the transaction aborts, because the backend is
+//! terminated for one, are removed then. `tpch_cleanup()` removes what a
+//! crashed server left behind.
+//!
+//! With `pg_tpch.spill_chunk_size` set, [`ChunkVisitor`] spills a table into
+//! a series of files of about that size instead, each loaded and removed
+//! before the next one is written, so the space a load needs no longer grows
+//! with the scale factor.
+use std::cell::Cell;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use pgrx::prelude::*;
+use pgrx::{register_xact_callback, spi, PgXactCallbackEvent};
+
+use crate::error::LoadError;
+use crate::format::{BinaryWriter, ColumnKind, CsvOptions, CsvWriter, RowWriter, TpchRow};
+use crate::guc::{self, SpillCompression, DATA_DIR, SPILL_COMPRESSION, SPILL_COMPRESSION_LEVEL};
+use crate::tables::{TableVisitor, TABLES};
+use crate::{copy, ingest, strategy, timing, tuning};
+
+/// Bytes the spill files of this backend currently take on disk.
+static SPILLED: AtomicU64 = AtomicU64::new(0);
+
+/// Spill files of this backend that exist, see [`remove_live_files`].
+static LIVE_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Returns the bytes the spill files of this backend currently take on disk.
+pub fn spilled_bytes() -> u64 {
+    SPILLED.load(Ordering::Relaxed)
+}
+
+/// How often the size of a compressed file is looked up while writing it, in
+/// bytes handed to the codec.
+const COMPRESSED_CHECK_INTERVAL: u64 = 16 * 1024 * 1024;
+
+/// Approximate size of the CSV file of `table` at SF=1, 0 for the tables of
+/// [`crate::tables::FIXED_SIZE_TABLES`], which take a few kB.
+pub fn csv_bytes_per_sf(table: &str) -> f64 {
+    match table {
+        "part" => 24e6,
+        "supplier" => 1.4e6,
+        "partsupp" => 118e6,
+        "customer" => 24e6,
+        "orders" => 170e6,
+        "lineitem" => 754e6,
+        _ => 0.,
+    }
+}
+
+/// Fails if spilling `tables` at once, each as one of `parts` parts of SF=`sf`,
+/// is expected to exceed `pg_tpch.max_temp_bytes`, a table taking at most
+/// `pg_tpch.spill_chunk_size` if set. Compressed files are only checked while
+/// they are written, their size isn't known in advance.
+pub fn check_projected_size(tables: &[&str], sf: f64, parts: i32) {
+    let Some(limit) = guc::max_temp_bytes() else {
+        return;
+    };
+    if codec(SPILL_COMPRESSION.get()).is_some() {
+        return;
+    }
+    let projected: f64 = tables
+        .iter()
+        .map(|table| {
+            let bytes = csv_bytes_per_sf(table) * sf / parts as f64;
+            guc::spill_chunk_bytes().map_or(bytes, |chunk| bytes.min(chunk as f64))
+        })
+        .sum();
+    if projected as u64 > limit {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_CONFIGURATION_LIMIT_EXCEEDED,
+            format!(
+                "spilling {} would take about {}, more than pg_tpch.max_temp_bytes ({})",
+                tables.join(" and "),
+                tuning::size(projected as u64),
+                tuning::size(limit)
+            ),
+            "Load in more steps, set pg_tpch.spill_chunk_size or pg_tpch.spill_compression, or raise pg_tpch.max_temp_bytes."
+        );
+    }
+}
+
+/// Writer that accounts for the bytes of `file` in [`SPILLED`] as they are
+/// written and fails once the total exceeds `limit`. It sits below the
+/// buffer, so the check runs once per buffer flush.
+struct Limited<'a, W> {
+    inner: W,
+    file: &'a SpillFile,
+    limit: Option<u64>,
+    /// Bytes handed to `inner` so far.
+    written: u64,
+    /// `written` when the size of a compressed file was last looked up.
+    checked: u64,
+}
+
+impl<W: Write> Write for Limited<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        if self.file.codec.is_none() {
+            self.file.account(self.written);
+        } else if self.written - self.checked >= COMPRESSED_CHECK_INTERVAL {
+            self.checked = self.written;
+            self.file.account(fs::metadata(&self.file.path)?.len());
+        }
+        match self.limit {
+            Some(limit) if SPILLED.load(Ordering::Relaxed) > limit => Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                "pg_tpch.max_temp_bytes exceeded",
+            )),
+            _ => Ok(n),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Directory intermediate files go to: `dir` if given, `pg_tpch.data_dir`
+/// otherwise.
+pub fn data_dir(dir: Option<&str>) -> PathBuf {
+    let dir = match dir {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(
+            DATA_DIR
+                .get()
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        ),
+    };
+    if !dir.is_absolute() {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!(
+                "data directory must be an absolute path, got \"{}\"",
+                dir.display()
+            )
+        );
+    }
+    dir
+}
+
+/// Creates `dir` if needed and checks that the current user may, and the
+/// server can, write files into it.
+pub fn create_dir(dir: &Path) {
+    strategy::require_role(
+        "pg_write_server_files",
+        "spilling to files on the server",
+        "Load with strategy => 'stream', which needs no files.",
+    );
+    let probe = dir.join(format!(".pg_tpch_probe_{}", std::process::id()));
+    let created = fs::create_dir_all(dir).and_then(|()| fs::write(&probe, b""));
+    let _ = fs::remove_file(&probe);
+    if let Err(e) = created {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_IO_ERROR,
+            format!(
+                "could not write to data directory \"{}\": {e}",
+                dir.display()
+            ),
+            "Set pg_tpch.data_dir or pass data_dir to a directory the server can write to."
+        );
+    }
+    register_xact_callback(PgXactCallbackEvent::Abort, remove_live_files);
+}
+
+/// Removes the spill files of this backend that are left when its
+/// transaction aborts without unwinding through their [`SpillFile`]s.
+fn remove_live_files() {
+    let files = std::mem::take(&mut *LIVE_FILES.lock().unwrap_or_else(|e| e.into_inner()));
+    for path in files {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// `(extension, program, highest level)` of every codec.
+fn codec(compression: SpillCompression) -> Option<(&'static str, &'static str, i32)> {
+    match compression {
+        SpillCompression::None => None,
+        SpillCompression::Gzip => Some(("gz", "gzip", 9)),
+        SpillCompression::Zstd => Some(("zst", "zstd", 19)),
+        SpillCompression::Lz4 => Some(("lz4", "lz4", 12)),
+    }
+}
+
+/// A CSV file in `dir` holding one table, compressed as configured.
+pub struct SpillFile {
+    pub path: PathBuf,
+    /// Whether the file is in the binary format of `COPY` instead.
+    binary: bool,
+    codec: Option<(&'static str, &'static str, i32)>,
+    level: i32,
+    /// Bytes of the file counted in [`SPILLED`].
+    accounted: Cell<u64>,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        SPILLED.fetch_sub(self.accounted.get(), Ordering::Relaxed);
+        let _ = fs::remove_file(&self.path);
+        LIVE_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|path| *path != self.path);
+    }
+}
+
+impl SpillFile {
+    pub fn new(dir: &Path, table: &str) -> SpillFile {
+        let codec = codec(SPILL_COMPRESSION.get());
+        let level = SPILL_COMPRESSION_LEVEL.get();
+        if let Some((_, program, max_level)) = codec {
+            if level > max_level {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    format!("{program} compression level must be at most {max_level}, got {level}")
+                );
+            }
+        }
+        let name = match codec {
+            Some((extension, _, _)) => format!("{table}.csv.{extension}"),
+            None => format!("{table}.csv"),
+        };
+        SpillFile {
+            path: dir.join(name),
+            binary: false,
+            codec,
+            level,
+            accounted: Cell::new(0),
+        }
+    }
+
+    /// Makes the file hold the binary format of `COPY` if `binary` is set.
+    pub fn binary(mut self, binary: bool) -> SpillFile {
+        if binary {
+            let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+            self.path = self.path.with_file_name(name.replacen(".csv", ".bin", 1));
+            self.binary = true;
+        }
+        self
+    }
+
+    /// Records that the file now takes `bytes` on disk.
+    fn account(&self, bytes: u64) {
+        let accounted = self.accounted.replace(bytes);
+        SPILLED.fetch_add(bytes, Ordering::Relaxed);
+        SPILLED.fetch_sub(accounted, Ordering::Relaxed);
+    }
+
+    /// Fails the load once the spill files exceed `pg_tpch.max_temp_bytes`.
+    fn check_limit(&self, limit: Option<u64>) {
+        let Some(limit) = limit else {
+            return;
+        };
+        if SPILLED.load(Ordering::Relaxed) > limit {
+            let table = self.path.file_name().unwrap_or_default().to_string_lossy();
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_CONFIGURATION_LIMIT_EXCEEDED,
+                format!(
+                    "spill files exceeded pg_tpch.max_temp_bytes ({}) while writing \"{table}\"",
+                    tuning::size(limit)
+                ),
+                "Load in more steps, set pg_tpch.spill_compression or raise pg_tpch.max_temp_bytes."
+            );
+        }
+    }
+
+    /// Creates the file and lets `f` write the data into it, returning what
+    /// `f` returns. Fails with an error if the spill files exceed
+    /// `pg_tpch.max_temp_bytes` on the way.
+    pub fn write<T>(&self, f: impl FnOnce(&mut dyn Write) -> io::Result<T>) -> io::Result<T> {
+        let limit = guc::max_temp_bytes();
+        let written = self.write_limited(limit, f);
+        if written.is_err() {
+            self.check_limit(limit);
+        }
+        let written = written?;
+        self.account(fs::metadata(&self.path)?.len());
+        self.check_limit(limit);
+        Ok(written)
+    }
+
+    fn write_limited<T>(
+        &self,
+        limit: Option<u64>,
+        f: impl FnOnce(&mut dyn Write) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let file = fs::File::create(&self.path)?;
+        let mut live = LIVE_FILES.lock().unwrap_or_else(|e| e.into_inner());
+        if !live.contains(&self.path) {
+            live.push(self.path.clone());
+        }
+        drop(live);
+        let Some((_, program, _)) = self.codec else {
+            let mut out =
+                BufWriter::with_capacity(guc::generation_buffer_bytes(), self.limited(file, limit));
+            let rows = f(&mut out)?;
+            out.flush()?;
+            return Ok(rows);
+        };
+
+        strategy::require_role(
+            "pg_execute_server_program",
+            &format!("compressing spill files with {program}"),
+            "Set pg_tpch.spill_compression to 'none'.",
+        );
+        let mut child = Command::new(program)
+            .args(["-q", "-c", &format!("-{}", self.level)])
+            .stdin(Stdio::piped())
+            .stdout(file)
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("could not run {program}: {e}")))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut out =
+            BufWriter::with_capacity(guc::generation_buffer_bytes(), self.limited(stdin, limit));
+        let written = f(&mut out).and_then(|rows| out.flush().map(|()| rows));
+        // Closing stdin lets the codec finish.
+        drop(out);
+        let status = child.wait()?;
+        let rows = written?;
+        if !status.success() {
+            return Err(io::Error::other(format!("{program} failed with {status}")));
+        }
+        Ok(rows)
+    }
+
+    fn limited<W: Write>(&self, inner: W, limit: Option<u64>) -> Limited<'_, W> {
+        Limited {
+            inner,
+            file: self,
+            limit,
+            written: 0,
+            checked: 0,
+        }
+    }
+
+    /// Loads the file into `table`, see [`copy::copy_from`]. With
+    /// `via_program` an uncompressed file is read through `cat`, which needs
+    /// the right to run programs instead of the right to read files. A binary
+    /// file ignores `csv` and `rejects`.
+    pub fn copy_into(
+        &self,
+        table: &str,
+        csv: &CsvOptions,
+        rejects: Option<&Path>,
+        via_program: bool,
+        freeze: bool,
+    ) -> u64 {
+        let path = fs::canonicalize(&self.path).unwrap_or_else(|e| {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_IO_ERROR,
+                format!("could not resolve \"{}\": {e}", self.path.display())
+            );
+        });
+        let quoted = ingest::shell_quote(&path.display().to_string());
+        let options = match self.binary {
+            true => "FORMAT binary".to_string(),
+            false => crate::copy_options(csv),
+        } + copy::freeze_option(freeze);
+        match self.codec {
+            None if !via_program && self.binary => copy::copy_from_binary(table, &path, freeze),
+            None if !via_program => copy::copy_from(table, &path, csv, rejects, freeze),
+            None => copy::copy_from_program(table, &format!("cat {quoted}"), &options),
+            Some((_, program, _)) => {
+                copy::copy_from_program(table, &format!("{program} -dc {quoted}"), &options)
+            }
+        }
+    }
+}
+
+/// Counts the bytes written through it.
+struct Counting<'a, W> {
+    inner: W,
+    count: &'a Cell<u64>,
+}
+
+impl<W: Write> Write for Counting<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes rows from `rows` with `writer` until `written` reaches `limit` or
+/// the rows run out, and returns how many it wrote.
+fn write_chunk<R: TpchRow>(
+    mut writer: impl RowWriter,
+    rows: &mut impl Iterator<Item = R>,
+    written: &Cell<u64>,
+    limit: u64,
+) -> io::Result<u64> {
+    writer.write_header(R::COLUMNS)?;
+    let mut count = 0;
+    // At least one row, so every chunk makes progress.
+    for row in rows.by_ref() {
+        writer.write_row(&row)?;
+        count += 1;
+        if written.get() >= limit {
+            break;
+        }
+    }
+    writer.finish()?;
+    Ok(count)
+}
+
+/// Spills a table into files of about `chunk_bytes` uncompressed bytes,
+/// loading each into the table and removing it before writing the next,
+/// and returns the rows and the bytes the files took on disk.
+///
+/// Every file is complete on its own, with the CSV header or the binary
+/// header and trailer, so it is loaded like the file of a whole table.
+pub struct ChunkVisitor<'a> {
+    pub dir: &'a Path,
+    pub table: &'a str,
+    pub csv: &'a CsvOptions,
+    /// Column kinds of the table if the files are binary.
+    pub columns: Option<Vec<ColumnKind>>,
+    pub rejects: Option<&'a Path>,
+    pub via_program: bool,
+    pub freeze: bool,
+    pub chunk_bytes: u64,
+    /// The part of the table being loaded, for errors.
+    pub part: i32,
+    pub parts: i32,
+}
+
+impl TableVisitor for ChunkVisitor<'_> {
+    type Output = Result<(u64, u64), LoadError>;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output {
+        let mut rows = rows.peekable();
+        let error = |spill: &SpillFile, e| {
+            LoadError::new(self.table, self.part, self.parts, e).at(&spill.path)
+        };
+        let (mut total_rows, mut total_bytes) = (0, 0);
+        loop {
+            let spill = SpillFile::new(self.dir, self.table).binary(self.columns.is_some());
+            let written = Cell::new(0);
+            timing::generating(|| {
+                spill.write(|out| {
+                    let out = Counting {
+                        inner: out,
+                        count: &written,
+                    };
+                    match &self.columns {
+                        Some(columns) => write_chunk(
+                            BinaryWriter::new(out, columns.clone()),
+                            &mut rows,
+                            &written,
+                            self.chunk_bytes,
+                        ),
+                        None => write_chunk(
+                            CsvWriter::new(out, self.csv),
+                            &mut rows,
+                            &written,
+                            self.chunk_bytes,
+                        ),
+                    }
+                })
+            })
+            .map_err(|e| error(&spill, e))?;
+            total_bytes += fs::metadata(&spill.path)
+                .map_err(|e| error(&spill, e))?
+                .len();
+            total_rows += spill.copy_into(
+                self.table,
+                self.csv,
+                self.rejects,
+                self.via_program,
+                self.freeze,
+            );
+            if rows.peek().is_none() {
+                return Ok((total_rows, total_bytes));
+            }
+        }
+    }
+}
+
+/// Whether `name` is that of a [`SpillFile`] of any table and codec.
+fn is_spill_file(name: &str) -> bool {
+    let Some((table, rest)) = name.split_once('.') else {
+        return false;
+    };
+    let format = ["csv", "bin"].into_iter().find(|&format| {
+        rest == format
+            || rest
+                .strip_prefix(format)
+                .and_then(|rest| rest.strip_prefix('.'))
+                .is_some_and(|extension| ["gz", "zst", "lz4"].contains(&extension))
+    });
+    TABLES.contains(&table) && format.is_some()
+}
+
+/// Removes the files loads left behind and returns them with the bytes each
+/// took: spill files in `data_dir`, `pg_tpch.data_dir` unless given, and
+/// progress files of backends that are gone.
+///
+/// Loads remove their files when they end, also when they fail, but not when
+/// the server crashes. Rejected rows saved by `save_rejects` are kept, and so
+/// is a directory holding a manifest, whose files are an export or a dataset
+/// to load rather than spilled. Nothing is removed while another session is
+/// loading, its files could be among them.
+#[pg_extern]
+fn tpch_cleanup(
+    data_dir: default!(Option<&str>, "NULL"),
+) -> spi::Result<TableIterator<'static, (name!(path, String), name!(bytes, i64))>> {
+    strategy::require_role(
+        "pg_write_server_files",
+        "removing files on the server",
+        "Ask a superuser to run tpch_cleanup.",
+    );
+    let loading = Spi::get_one::<i64>(
+        "SELECT count(DISTINCT pid) FROM pg_tpch_progress WHERE pid <> pg_backend_pid()",
+    )?
+    .unwrap_or_default();
+    if loading > 0 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_OBJECT_IN_USE,
+            format!("{loading} other sessions are loading, their files could be removed"),
+            "Run tpch_cleanup once the loads are done."
+        );
+    }
+
+    let mut removed = Vec::new();
+    let mut remove = |path: PathBuf| {
+        let bytes = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+        if fs::remove_file(&path).is_ok() {
+            removed.push((path.display().to_string(), bytes as i64));
+        }
+    };
+    let dir = self::data_dir(data_dir);
+    let names = |dir: &Path| -> Vec<String> {
+        fs::read_dir(dir).map_or_else(
+            |_| Vec::new(),
+            |entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .collect()
+            },
+        )
+    };
+    let files = names(&dir);
+    if !files
+        .iter()
+        .any(|name| name.starts_with(crate::manifest::MANIFEST))
+    {
+        for name in files {
+            if is_spill_file(&name) || name.starts_with(".pg_tpch_probe_") {
+                remove(dir.join(name));
+            }
+        }
+    }
+
+    let live: Vec<i32> = Spi::connect(|client| {
+        client
+            .select("SELECT pid FROM pg_stat_activity", None, &[])?
+            .map(|row| row.get::<i32>(1).map(Option::unwrap_or_default))
+            .collect::<spi::Result<Vec<_>>>()
+    })?;
+    for name in names(Path::new("pg_stat_tmp")) {
+        let pid = name
+            .strip_prefix("pg_tpch_progress.")
+            .and_then(|pid| pid.parse::<i32>().ok());
+        if pid.is_some_and(|pid| !live.contains(&pid)) {
+            remove(Path::new("pg_stat_tmp").join(name));
+        }
+    }
+    Ok(TableIterator::new(removed))
+}
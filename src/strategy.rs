@@ -0,0 +1,221 @@
+//! How `tpch_load` gets generated rows into the tables.
+//!
+//! Spilling to a file and reading it with `COPY` is fastest, but needs a
+//! writable directory on the server and the right to read server files, which
+//! managed services don't grant. Reading the file through `COPY FROM PROGRAM`
+//! needs a different right. Streaming rows into `COPY` as a client would
+//! needs no file and no right beyond `INSERT`, and neither do plain
+//! `INSERT`s. Writing the files takes `pg_write_server_files`, so `auto`
+//! streams for roles without it, such as those of managed services, and for
+//! throttled loads, see [`crate::throttle`].
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use pgrx::prelude::*;
+use pgrx::spi;
+use tpchgen::dates::TPCHDate;
+use tpchgen::decimal::TPCHDecimal;
+
+use crate::format::{FieldSink, TpchRow};
+use crate::guc::{SpillCompression, SPILL_COMPRESSION};
+use crate::tables::{self, TableVisitor};
+use crate::{throttle, timing};
+
+/// Rows inserted per `INSERT` statement.
+const INSERT_BATCH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStrategy {
+    /// `COPY FROM` a spilled file.
+    File,
+    /// `COPY FROM PROGRAM` reading a spilled file.
+    Program,
+    /// `COPY` fed straight from the generator, without files.
+    Stream,
+    /// Batched `INSERT` statements, without files.
+    Insert,
+}
+
+impl LoadStrategy {
+    /// Parses the `strategy` argument of `tpch_load`, probing the server for
+    /// `auto`.
+    pub fn new(name: &str, dir: &Path) -> spi::Result<LoadStrategy> {
+        let strategy = match name {
+            "file" => LoadStrategy::File,
+            "program" => LoadStrategy::Program,
+            "stream" => LoadStrategy::Stream,
+            "insert" => LoadStrategy::Insert,
+            "auto" => Self::detect(dir)?,
+            _ => {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    format!(
+                        "unknown strategy \"{name}\", expected \"auto\", \"file\", \"program\", \"stream\" or \"insert\""
+                    )
+                );
+            }
+        };
+        if strategy.uses_files() && throttle::enabled() {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                format!("loads with strategy \"{strategy}\" can't be throttled"),
+                "Load with strategy => 'stream' or 'insert', or reset pg_tpch.max_load_rows_per_sec and pg_tpch.max_load_mb_per_sec."
+            );
+        }
+        Ok(strategy)
+    }
+
+    /// Picks the fastest strategy the current user can use. Compressed spill
+    /// files are always read through a program, throttled loads stream.
+    fn detect(dir: &Path) -> spi::Result<LoadStrategy> {
+        if throttle::enabled() || !has_role("pg_write_server_files")? || !writable(dir) {
+            return Ok(LoadStrategy::Stream);
+        }
+        let compressed = SPILL_COMPRESSION.get() != SpillCompression::None;
+        if !compressed && has_role("pg_read_server_files")? {
+            Ok(LoadStrategy::File)
+        } else if has_role("pg_execute_server_program")? {
+            Ok(LoadStrategy::Program)
+        } else {
+            Ok(LoadStrategy::Stream)
+        }
+    }
+
+    /// Whether the strategy spills rows to files.
+    pub fn uses_files(self) -> bool {
+        matches!(self, LoadStrategy::File | LoadStrategy::Program)
+    }
+}
+
+impl Display for LoadStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LoadStrategy::File => "file",
+            LoadStrategy::Program => "program",
+            LoadStrategy::Stream => "stream",
+            LoadStrategy::Insert => "insert",
+        })
+    }
+}
+
+/// Whether the current user has the privileges of the predefined `role`,
+/// which superusers always have.
+fn has_role(role: &str) -> spi::Result<bool> {
+    Ok(
+        Spi::get_one_with_args::<bool>("SELECT pg_has_role($1, 'USAGE')", &[role.into()])?
+            == Some(true),
+    )
+}
+
+/// Fails unless the current user has the privileges of the predefined
+/// `role`, before pg_tpch touches server files for `what`.
+///
+/// Roles that aren't superusers can call its functions, and its own file
+/// access doesn't go through the checks `COPY` makes, so it makes the same
+/// ones: `pg_write_server_files` to write files, `pg_read_server_files` to
+/// read them and `pg_execute_server_program` to run programs on them.
+pub fn require_role(role: &str, what: &str, hint: &str) {
+    if !has_role(role).unwrap_or(false) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+            format!("{what} needs the privileges of the {role} role"),
+            hint
+        );
+    }
+}
+
+/// Whether the server can create files in `dir`.
+fn writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".pg_tpch_probe_{}", std::process::id()));
+    let writable = fs::create_dir_all(dir)
+        .and_then(|()| fs::write(&probe, b""))
+        .is_ok();
+    let _ = fs::remove_file(&probe);
+    writable
+}
+
+/// Renders fields as an SQL row constructor.
+struct ValuesSink<'a> {
+    sql: &'a mut String,
+    first_field: bool,
+}
+
+impl ValuesSink<'_> {
+    fn field(&mut self, value: &str) -> io::Result<()> {
+        self.sql.push_str(if self.first_field { "(" } else { ", " });
+        self.first_field = false;
+        self.sql.push_str(value);
+        Ok(())
+    }
+}
+
+impl FieldSink for ValuesSink<'_> {
+    fn int(&mut self, value: i64) -> io::Result<()> {
+        self.field(&value.to_string())
+    }
+
+    fn decimal(&mut self, value: TPCHDecimal) -> io::Result<()> {
+        self.field(&value.to_string())
+    }
+
+    fn date(&mut self, value: TPCHDate) -> io::Result<()> {
+        self.field(&format!("'{value}'"))
+    }
+
+    fn text(&mut self, value: &dyn Display) -> io::Result<()> {
+        self.field(&spi::quote_literal(value.to_string()))
+    }
+}
+
+/// Inserts every row into the table, [`INSERT_BATCH`] rows per statement.
+struct InsertVisitor<'a>(&'a str);
+
+impl TableVisitor for InsertVisitor<'_> {
+    type Output = spi::Result<u64>;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output {
+        let prefix = format!("INSERT INTO {} VALUES ", self.0);
+        let mut rows = rows.fuse();
+        let mut count = 0;
+        loop {
+            let mut sql = prefix.clone();
+            let batched = timing::generating(|| {
+                let mut batched = 0;
+                for row in rows.by_ref().take(INSERT_BATCH) {
+                    if batched > 0 {
+                        sql.push_str(", ");
+                    }
+                    let mut sink = ValuesSink {
+                        sql: &mut sql,
+                        first_field: true,
+                    };
+                    row.write_fields(&mut sink)
+                        .expect("rendering to memory doesn't fail");
+                    sql.push(')');
+                    batched += 1;
+                }
+                batched
+            });
+            if batched == 0 {
+                return Ok(count);
+            }
+            throttle::bytes(sql.len() as u64);
+            Spi::run(&sql)?;
+            count += batched;
+        }
+    }
+}
+
+/// Inserts part `part` of `num_parts` of `table` without going through files
+/// and returns the number of rows.
+pub fn insert_rows(table: &str, sf: f64, part: i32, num_parts: i32) -> spi::Result<u64> {
+    tables::generate(table, sf, part, num_parts, InsertVisitor(table))
+}
@@ -0,0 +1,399 @@
+//! Generated data handed to clients or `COPY` instead of written to server
+//! files.
+//!
+//! [`copy_generated`] feeds the rows straight into the `COPY` machinery of the
+//! backend, the way `COPY FROM STDIN` gets them from a client, so a load
+//! neither spills to disk nor needs the right to read server files.
+use std::cell::RefCell;
+use std::ffi::{c_int, c_void, CString};
+use std::fmt::Display;
+use std::ptr;
+
+use pgrx::prelude::*;
+use pgrx::{pg_sys, spi};
+
+use crate::error::LoadError;
+use crate::format::{BinaryWriter, ColumnKind, CsvOptions, CsvWriter, RowWriter, TpchRow};
+use crate::ingest;
+use crate::tables::{self, TableVisitor};
+use crate::threads::{self, Encoding};
+use crate::{throttle, timing};
+
+/// Renders rows as CSV in chunks of `chunk_rows` rows, the first one
+/// starting with the header.
+struct ChunkVisitor {
+    csv: CsvOptions,
+    chunk_rows: usize,
+}
+
+impl TableVisitor for ChunkVisitor {
+    type Output = Box<dyn Iterator<Item = String>>;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output {
+        let ChunkVisitor { csv, chunk_rows } = self;
+        let mut rows = rows.fuse();
+        let mut header = csv.header.then(|| csv.header(R::COLUMNS));
+        Box::new(std::iter::from_fn(move || {
+            let mut out = Vec::new();
+            let first = header.take();
+            if let Some(header) = &first {
+                out.extend_from_slice(header.as_bytes());
+                out.push(b'\n');
+            }
+            let mut writer = CsvWriter::new(&mut out, &csv);
+            let mut count = 0;
+            for row in rows.by_ref().take(chunk_rows) {
+                writer
+                    .write_row(&row)
+                    .expect("writing to memory doesn't fail");
+                count += 1;
+            }
+            if count == 0 && first.is_none() {
+                return None;
+            }
+            Some(String::from_utf8(out).expect("generated data is UTF-8"))
+        }))
+    }
+}
+
+/// Renders rows in the binary format of `COPY` in chunks of `chunk_rows`
+/// rows, the first one starting with the header and the last one ending with
+/// the trailer.
+struct BinaryChunkVisitor {
+    columns: Vec<ColumnKind>,
+    chunk_rows: usize,
+}
+
+impl TableVisitor for BinaryChunkVisitor {
+    type Output = Box<dyn Iterator<Item = Vec<u8>>>;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output {
+        let BinaryChunkVisitor {
+            columns,
+            chunk_rows,
+        } = self;
+        let mut rows = rows.fuse();
+        let (mut first, mut done) = (true, false);
+        Box::new(std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let mut out = Vec::new();
+            let mut writer = BinaryWriter::new(&mut out, columns.clone());
+            if std::mem::take(&mut first) {
+                writer
+                    .write_header(R::COLUMNS)
+                    .expect("writing to memory doesn't fail");
+            }
+            let mut count = 0;
+            for row in rows.by_ref().take(chunk_rows) {
+                writer
+                    .write_row(&row)
+                    .expect("columns are checked against the rows");
+                count += 1;
+            }
+            if count < chunk_rows {
+                writer.finish().expect("writing to memory doesn't fail");
+                done = true;
+            }
+            Some(out)
+        }))
+    }
+}
+
+/// Generates part `part` of `parts` of `table_name` as `COPY ... (FORMAT csv,
+/// HEADER)` input, in chunks of `chunk_rows` rows. With `header => false` the
+/// header line is left out, and values equal to `null` are quoted, like
+/// `tpch_export` does.
+///
+/// This lets clients pull generated data over a regular connection and load
+/// it wherever they like, for example where the server's file system can't be
+/// used: concatenated in order, the chunks are the data to send with
+/// `COPY <table> FROM STDIN`. Rows are generated as the chunks are fetched.
+#[allow(clippy::too_many_arguments)]
+#[pg_extern]
+fn tpch_copy_chunk(
+    table_name: &str,
+    sf: default!(f64, 1.),
+    part: default!(i32, 1),
+    parts: default!(i32, 1),
+    delimiter: default!(&str, "','"),
+    quote: default!(&str, "'\"'"),
+    chunk_rows: default!(i32, 10000),
+    header: default!(bool, true),
+    null: default!(&str, "''"),
+) -> SetOfIterator<'static, String> {
+    ingest::check_table(table_name);
+    if !(sf > 0. && sf.is_finite()) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("sf must be a positive number, got {sf}")
+        );
+    }
+    if parts < 1 || part < 1 || part > parts {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("part must be between 1 and {parts}, got {part}")
+        );
+    }
+    if chunk_rows < 1 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("chunk_rows must be at least 1, got {chunk_rows}")
+        );
+    }
+    // The result is text, which Postgres converts to the client encoding.
+    let csv = CsvOptions::new(delimiter, quote)
+        .and_then(|csv| csv.with_export_options(header, null, "UTF8"))
+        .unwrap_or_else(|message| {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                message
+            );
+        });
+
+    SetOfIterator::new(tables::generate(
+        table_name,
+        sf,
+        part,
+        parts,
+        ChunkVisitor {
+            csv,
+            chunk_rows: chunk_rows as usize,
+        },
+    ))
+}
+
+/// Bytes of generated `COPY` input, handed out in whatever pieces `COPY` asks for.
+struct GeneratedInput {
+    chunks: Box<dyn Iterator<Item = Vec<u8>>>,
+    chunk: Vec<u8>,
+    offset: usize,
+}
+
+thread_local! {
+    /// Input of the `COPY` [`copy_generated`] is running, which the data
+    /// source callback has no other way to reach.
+    static INPUT: RefCell<Option<GeneratedInput>> = const { RefCell::new(None) };
+}
+
+/// Clears [`INPUT`] when the `COPY` ends, also when it fails.
+struct InputGuard;
+
+impl Drop for InputGuard {
+    fn drop(&mut self) {
+        INPUT.with_borrow_mut(|input| *input = None);
+    }
+}
+
+/// Data source callback of the `COPY`: fills `outbuf` with up to `maxread`
+/// bytes and returns how many, 0 at the end of the input.
+#[pg_guard]
+unsafe extern "C-unwind" fn read_generated(
+    outbuf: *mut c_void,
+    _minread: c_int,
+    maxread: c_int,
+) -> c_int {
+    INPUT.with_borrow_mut(|input| {
+        let input = input
+            .as_mut()
+            .expect("COPY of generated rows has its input");
+        // SAFETY: COPY passes a buffer of at least `maxread` bytes.
+        let out = unsafe { std::slice::from_raw_parts_mut(outbuf.cast::<u8>(), maxread as usize) };
+        let mut read = 0;
+        while read < out.len() {
+            if input.offset == input.chunk.len() {
+                let Some(chunk) = timing::generating(|| input.chunks.next()) else {
+                    break;
+                };
+                input.chunk = chunk;
+                input.offset = 0;
+            }
+            let n = (input.chunk.len() - input.offset).min(out.len() - read);
+            out[read..read + n].copy_from_slice(&input.chunk[input.offset..input.offset + n]);
+            input.offset += n;
+            read += n;
+        }
+        throttle::bytes(read as u64);
+        read as c_int
+    })
+}
+
+/// Copies `value` into memory of the current memory context, where `COPY`
+/// keeps pointing to it.
+fn palloc_str(value: &str) -> *mut std::ffi::c_char {
+    let value = CString::new(value).expect("COPY options have no NUL bytes");
+    // SAFETY: pstrdup copies the terminated string.
+    unsafe { pg_sys::pstrdup(value.as_ptr()) }
+}
+
+/// Renders `csv` as the option list of a `COPY` statement, or the options
+/// of the binary format with `binary`, adding `FREEZE` with `freeze`.
+fn copy_option_list(csv: &CsvOptions, binary: bool, freeze: bool) -> *mut pg_sys::List {
+    let mut options = if binary {
+        vec![("format", "binary".to_string())]
+    } else {
+        vec![
+            ("format", "csv".to_string()),
+            ("header", csv.header.to_string()),
+            ("delimiter", csv.delimiter.to_string()),
+            ("quote", csv.quote.to_string()),
+            ("escape", csv.escape.to_string()),
+            ("null", csv.null.clone()),
+            ("encoding", csv.encoding.clone()),
+        ]
+    };
+    if freeze {
+        options.push(("freeze", "true".to_string()));
+    }
+    option_list(&options)
+}
+
+fn option_list(options: &[(&str, String)]) -> *mut pg_sys::List {
+    let mut list = ptr::null_mut();
+    for (name, value) in options {
+        // SAFETY: both strings are palloc'd, the nodes keep pointing to them.
+        unsafe {
+            let value = pg_sys::makeString(palloc_str(value));
+            let option = pg_sys::makeDefElem(palloc_str(name), value.cast(), -1);
+            list = pg_sys::lappend(list, option.cast());
+        }
+    }
+    list
+}
+
+/// Loads part `part` of `parts` of `table` through `COPY` without any file in
+/// between and returns the number of rows.
+///
+/// This is what `COPY table FROM STDIN` does, with the generator in place of
+/// the client, so it needs the same privileges: `INSERT` on the table, and
+/// no row-level security in the way. With `binary`, the rows go in the
+/// binary format of `COPY` into columns of those kinds instead of as CSV.
+/// With `freeze`, they are loaded frozen, see [`crate::copy::freeze_option`].
+pub fn copy_generated(
+    table: &str,
+    sf: f64,
+    part: i32,
+    parts: i32,
+    csv: &CsvOptions,
+    binary: Option<Vec<ColumnKind>>,
+    freeze: bool,
+) -> spi::Result<u64> {
+    let (insert, row_security) = Spi::get_two_with_args::<bool, bool>(
+        "SELECT has_table_privilege($1, 'INSERT'), row_security_active($1)",
+        &[table.into()],
+    )?;
+    if insert != Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INSUFFICIENT_PRIVILEGE,
+            format!("permission denied for table {table}")
+        );
+    }
+    if row_security == Some(true) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            format!("streaming into {table} is not supported with row-level security"),
+            "Load with strategy => 'insert' instead."
+        );
+    }
+
+    let is_binary = binary.is_some();
+    let encoding = match &binary {
+        Some(columns) => Encoding::Binary(columns.clone()),
+        None => Encoding::Csv(csv.clone()),
+    };
+    let threaded = threads::generate(table, sf, part, parts, encoding)
+        .unwrap_or_else(|e| LoadError::new(table, part, parts, e).report());
+    let chunks: Box<dyn Iterator<Item = Vec<u8>>> = match (threaded, binary) {
+        (Some(generated), _) => Box::new(generated),
+        (None, Some(columns)) => tables::generate(
+            table,
+            sf,
+            part,
+            parts,
+            BinaryChunkVisitor {
+                columns,
+                chunk_rows: 10000,
+            },
+        ),
+        (None, None) => Box::new(
+            tables::generate(
+                table,
+                sf,
+                part,
+                parts,
+                ChunkVisitor {
+                    csv: csv.clone(),
+                    chunk_rows: 10000,
+                },
+            )
+            .map(String::into_bytes),
+        ),
+    };
+    INPUT.with_borrow_mut(|input| {
+        *input = Some(GeneratedInput {
+            chunks,
+            chunk: Vec::new(),
+            offset: 0,
+        })
+    });
+    let _guard = InputGuard;
+
+    let lockmode = pg_sys::RowExclusiveLock as pg_sys::LOCKMODE;
+    // SAFETY: the same calls logical replication makes for the initial copy of
+    // a table. The relation stays locked until the end of the transaction.
+    let rows = unsafe {
+        let range = pg_sys::makeRangeVar(ptr::null_mut(), palloc_str(table), -1);
+        let relation = pg_sys::table_openrv(range, lockmode);
+        let pstate = pg_sys::make_parsestate(ptr::null_mut());
+        pg_sys::addRangeTableEntryForRelation(
+            pstate,
+            relation,
+            lockmode,
+            ptr::null_mut(),
+            false,
+            false,
+        );
+        let options = copy_option_list(csv, is_binary, freeze);
+        #[cfg(feature = "pg13")]
+        let copy = pg_sys::BeginCopyFrom(
+            pstate,
+            relation,
+            ptr::null(),
+            false,
+            Some(read_generated),
+            ptr::null_mut(),
+            options,
+        );
+        #[cfg(not(feature = "pg13"))]
+        let copy = pg_sys::BeginCopyFrom(
+            pstate,
+            relation,
+            ptr::null_mut(),
+            ptr::null(),
+            false,
+            Some(read_generated),
+            ptr::null_mut(),
+            options,
+        );
+        let rows = pg_sys::CopyFrom(copy);
+        pg_sys::EndCopyFrom(copy);
+        pg_sys::free_parsestate(pstate);
+        pg_sys::table_close(relation, pg_sys::NoLock as pg_sys::LOCKMODE);
+        pg_sys::CommandCounterIncrement();
+        rows
+    };
+    Ok(rows)
+}
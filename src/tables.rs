@@ -0,0 +1,205 @@
+//! The eight TPC-H tables and dispatch to their generators.
+use std::fmt::Display;
+
+use tpchgen::generators::{
+    CustomerGenerator, LineItemGenerator, NationGenerator, OrderGenerator, PartGenerator,
+    PartSuppGenerator, RegionGenerator, SupplierGenerator,
+};
+
+use crate::format::TpchRow;
+use crate::{progress, throttle};
+
+/// TPC-H tables in load order.
+pub const TABLES: [&str; 8] = [
+    "region", "nation", "part", "supplier", "partsupp", "customer", "orders", "lineitem",
+];
+
+/// Tables with the same rows at every scale factor, which are generated in
+/// full whatever part is asked for.
+pub const FIXED_SIZE_TABLES: [&str; 2] = ["region", "nation"];
+
+/// Scale factors the TPC-H specification allows for reported results.
+pub const SPEC_SCALE_FACTORS: [f64; 10] = [
+    1., 10., 30., 100., 300., 1000., 3000., 10000., 30000., 100000.,
+];
+
+/// Consumes the rows of one table.
+///
+/// Row types differ per table, so callers that need to handle any table
+/// implement this instead of matching on the table name themselves.
+/// The generators own their data, so visitors may keep the rows around.
+pub trait TableVisitor {
+    type Output;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output;
+}
+
+/// Counts the rows of `rows` for the progress and throttling of the running
+/// load.
+fn tracked<I: Iterator>(rows: I) -> impl Iterator<Item = I::Item> {
+    rows.inspect(|_| {
+        progress::row_generated();
+        throttle::row();
+    })
+}
+
+/// Feeds part `part` of `num_parts` of `table` to `visitor`.
+pub fn generate<V: TableVisitor>(
+    table: &str,
+    sf: f64,
+    part: i32,
+    num_parts: i32,
+    visitor: V,
+) -> V::Output {
+    match table {
+        "region" => visitor.visit(tracked(
+            RegionGenerator::new(sf, part, num_parts).into_iter(),
+        )),
+        "nation" => visitor.visit(tracked(
+            NationGenerator::new(sf, part, num_parts).into_iter(),
+        )),
+        "part" => visitor.visit(tracked(PartGenerator::new(sf, part, num_parts).into_iter())),
+        "supplier" => visitor.visit(tracked(
+            SupplierGenerator::new(sf, part, num_parts).into_iter(),
+        )),
+        "partsupp" => visitor.visit(tracked(
+            PartSuppGenerator::new(sf, part, num_parts).into_iter(),
+        )),
+        "customer" => visitor.visit(tracked(
+            CustomerGenerator::new(sf, part, num_parts).into_iter(),
+        )),
+        "orders" => visitor.visit(tracked(
+            OrderGenerator::new(sf, part, num_parts).into_iter(),
+        )),
+        "lineitem" => visitor.visit(tracked(
+            LineItemGenerator::new(sf, part, num_parts).into_iter(),
+        )),
+        _ => unreachable!("unknown TPC-H table {table}"),
+    }
+}
+
+/// Returns the columns of `table` in the order its rows are generated.
+pub fn columns(table: &str) -> &'static [&'static str] {
+    struct Columns;
+
+    impl TableVisitor for Columns {
+        type Output = &'static [&'static str];
+
+        fn visit<R: TpchRow + Display + 'static>(
+            self,
+            _rows: impl Iterator<Item = R> + 'static,
+        ) -> Self::Output {
+            R::COLUMNS
+        }
+    }
+
+    generate(table, 1., 1, 1, Columns)
+}
+
+/// Returns the leading key column of `table` and the inclusive range of its
+/// values generated by part `part` of `num_parts`.
+///
+/// `nation` and `region` are fixed-size and generated in full by every part,
+/// so they have no range.
+pub fn key_range(
+    table: &str,
+    sf: f64,
+    part: i32,
+    num_parts: i32,
+) -> Option<(&'static str, i64, i64)> {
+    // Every part but the last has the same number of rows, so the rows before
+    // `part` are (part - 1) times the size of the first one.
+    let range = |row_count: fn(f64, i32, i32) -> i64| {
+        let start = row_count(sf, 1, num_parts) * (part as i64 - 1);
+        (start + 1, start + row_count(sf, part, num_parts))
+    };
+    let (column, (first, last)) = match table {
+        "region" | "nation" => return None,
+        "part" => ("p_partkey", range(PartGenerator::calculate_row_count)),
+        "partsupp" => ("ps_partkey", range(PartSuppGenerator::calculate_row_count)),
+        "supplier" => ("s_suppkey", range(SupplierGenerator::calculate_row_count)),
+        "customer" => ("c_custkey", range(CustomerGenerator::calculate_row_count)),
+        "orders" | "lineitem" => {
+            let (first, last) = range(OrderGenerator::calculate_row_count);
+            let column = if table == "orders" {
+                "o_orderkey"
+            } else {
+                "l_orderkey"
+            };
+            (
+                column,
+                (
+                    OrderGenerator::make_order_key(first),
+                    OrderGenerator::make_order_key(last),
+                ),
+            )
+        }
+        _ => unreachable!("unknown TPC-H table {table}"),
+    };
+    Some((column, first, last))
+}
+
+/// Returns about how many rows part `part` of `num_parts` of `table` has at
+/// SF=`sf`, exactly but for `lineitem`, whose orders have 4 line items on
+/// average.
+pub fn expected_rows(table: &str, sf: f64, part: i32, num_parts: i32) -> i64 {
+    let rows = |row_count: fn(f64, i32, i32) -> i64| row_count(sf, part, num_parts);
+    match table {
+        "region" => 5,
+        "nation" => 25,
+        "part" => rows(PartGenerator::calculate_row_count),
+        "partsupp" => rows(PartSuppGenerator::calculate_row_count),
+        "supplier" => rows(SupplierGenerator::calculate_row_count),
+        "customer" => rows(CustomerGenerator::calculate_row_count),
+        "orders" => rows(OrderGenerator::calculate_row_count),
+        "lineitem" => 4 * rows(OrderGenerator::calculate_row_count),
+        _ => unreachable!("unknown TPC-H table {table}"),
+    }
+}
+
+/// Returns the biggest value key column `column` holds at SF=`sf`.
+///
+/// Keys number the rows of their table from 1, but for the order keys, of
+/// which only the first 8 of every 32 are used.
+pub fn max_key(column: &str, sf: f64) -> i64 {
+    let rows = |table| expected_rows(table, sf, 1, 1);
+    match column.split_once('_').map(|(_, name)| name) {
+        Some("regionkey") => rows("region"),
+        Some("nationkey") => rows("nation"),
+        Some("partkey") => rows("part"),
+        Some("suppkey") => rows("supplier"),
+        Some("custkey") => rows("customer"),
+        Some("orderkey") => OrderGenerator::make_order_key(rows("orders")),
+        _ => unreachable!("unknown TPC-H key column {column}"),
+    }
+}
+
+/// Returns how many parts `table` is split into when a load of `sf` takes
+/// `children` steps and every table is split by its size.
+///
+/// `lineitem` gets one part per step and every other table as many as keep
+/// its parts no bigger than those of `lineitem`, so `supplier` gets few and
+/// the fixed-size `nation` and `region` a single one. Step `k` loads part `k`
+/// of every table that has one.
+pub fn auto_parts(table: &str, sf: f64, children: i32) -> i32 {
+    let rows = |row_count: fn(f64, i32, i32) -> i64| row_count(sf, 1, 1) as f64;
+    if FIXED_SIZE_TABLES.contains(&table) {
+        return 1;
+    }
+    let table_rows = match table {
+        "part" => rows(PartGenerator::calculate_row_count),
+        "partsupp" => rows(PartSuppGenerator::calculate_row_count),
+        "supplier" => rows(SupplierGenerator::calculate_row_count),
+        "customer" => rows(CustomerGenerator::calculate_row_count),
+        "orders" => rows(OrderGenerator::calculate_row_count),
+        "lineitem" => return children,
+        _ => unreachable!("unknown TPC-H table {table}"),
+    };
+    // Orders have 4 line items on average.
+    let lineitem_rows = 4. * rows(OrderGenerator::calculate_row_count);
+    let parts = (children as f64 * table_rows / lineitem_rows).ceil() as i32;
+    parts.clamp(1, children)
+}
@@ -0,0 +1,202 @@
+//! Generating the rows of one table with several threads at once.
+//!
+//! The generators split a table into parts by row number, so part `p` of `n`
+//! holds exactly the rows of parts `(p - 1) * t + 1` to `p * t` of `n * t`
+//! whenever those divide its rows evenly. Each thread generates one of them
+//! and renders its rows into chunks of `COPY` input, which the backend takes
+//! in whatever order they come. The threads only touch Rust memory, anything
+//! that calls into Postgres stays on the backend's own thread.
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::panic;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::error::LoadError;
+use crate::format::{BinaryWriter, ColumnKind, CsvOptions, CsvWriter, RowWriter, TpchRow};
+use crate::guc::GENERATION_THREADS;
+use crate::tables::{self, TableVisitor};
+
+/// Rows in a chunk a thread hands over.
+const CHUNK_ROWS: usize = 10000;
+
+/// Chunks per thread that may wait for the backend before the threads block.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// How generated rows are rendered.
+#[derive(Clone)]
+pub enum Encoding {
+    Csv(CsvOptions),
+    /// The binary format of `COPY` into columns of these kinds.
+    Binary(Vec<ColumnKind>),
+}
+
+/// Returns how many threads generate a part of `table` when it is split into
+/// `num_parts` parts at SF=`sf`: the most `pg_tpch.generation_threads` allows
+/// that split each part evenly.
+fn thread_count(table: &str, sf: f64, num_parts: i32) -> i32 {
+    let rows = |parts: i32| tables::expected_rows(table, sf, 1, parts);
+    let part_rows = rows(num_parts);
+    (1..=GENERATION_THREADS.get())
+        .rev()
+        .find(|&threads| {
+            let thread_rows = rows(num_parts * threads);
+            thread_rows > 0 && part_rows == threads as i64 * thread_rows
+        })
+        .unwrap_or(1)
+}
+
+/// Starts generating part `part` of `num_parts` of `table` at SF=`sf` with
+/// the threads of `pg_tpch.generation_threads`, or returns `None` if it
+/// takes a single one.
+pub fn generate(
+    table: &str,
+    sf: f64,
+    part: i32,
+    num_parts: i32,
+    encoding: Encoding,
+) -> io::Result<Option<Generated>> {
+    let threads = thread_count(table, sf, num_parts);
+    if threads == 1 {
+        return Ok(None);
+    }
+
+    let (header, trailer) = match &encoding {
+        Encoding::Csv(csv) => {
+            let header = csv
+                .header
+                .then(|| format!("{}\n", csv.header(tables::columns(table))).into_bytes());
+            (header, None)
+        }
+        Encoding::Binary(columns) => {
+            let (mut header, mut trailer) = (Vec::new(), Vec::new());
+            BinaryWriter::new(&mut header, columns.clone()).write_header(&[])?;
+            BinaryWriter::new(&mut trailer, columns.clone()).finish()?;
+            (Some(header), Some(trailer))
+        }
+    };
+    let (sender, chunks) = mpsc::sync_channel(threads as usize * CHUNKS_IN_FLIGHT);
+    let mut handles = Vec::with_capacity(threads as usize);
+    for i in 1..=threads {
+        let (table, encoding, sender) = (table.to_string(), encoding.clone(), sender.clone());
+        let sub_part = (part - 1) * threads + i;
+        let handle = thread::Builder::new()
+            .name(format!("pg_tpch {table} {i}/{threads}"))
+            .spawn(move || {
+                let visitor = ChunkSender { encoding, sender };
+                tables::generate(&table, sf, sub_part, num_parts * threads, visitor)
+            })?;
+        handles.push(handle);
+    }
+    Ok(Some(Generated {
+        chunks,
+        header,
+        trailer,
+        handles,
+        rows: 0,
+        table: table.to_string(),
+        part,
+        num_parts,
+    }))
+}
+
+/// Chunks of `COPY` input generated by several threads, the header first and
+/// the trailer last.
+pub struct Generated {
+    chunks: Receiver<(Vec<u8>, u64)>,
+    header: Option<Vec<u8>>,
+    trailer: Option<Vec<u8>>,
+    handles: Vec<JoinHandle<io::Result<()>>>,
+    /// Rows in the chunks handed out so far.
+    rows: u64,
+    /// The part of the table being generated, for errors.
+    table: String,
+    part: i32,
+    num_parts: i32,
+}
+
+impl Generated {
+    /// Writes all chunks to `out` and returns the number of rows.
+    pub fn write_to(mut self, out: &mut dyn Write) -> io::Result<u64> {
+        for chunk in self.by_ref() {
+            out.write_all(&chunk)?;
+        }
+        Ok(self.rows)
+    }
+
+    /// Waits for the threads, which are done, and passes on their failures.
+    fn join(&mut self) {
+        for handle in self.handles.drain(..) {
+            match handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => LoadError::new(&self.table, self.part, self.num_parts, e).report(),
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        }
+    }
+}
+
+impl Iterator for Generated {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        if let Some(header) = self.header.take() {
+            return Some(header);
+        }
+        match self.chunks.recv() {
+            Ok((chunk, rows)) => {
+                self.rows += rows;
+                Some(chunk)
+            }
+            // Every thread dropped its sender.
+            Err(_) => {
+                self.join();
+                self.trailer.take()
+            }
+        }
+    }
+}
+
+/// Renders rows into chunks of [`CHUNK_ROWS`] and sends them to the backend,
+/// without header or trailer.
+struct ChunkSender {
+    encoding: Encoding,
+    sender: SyncSender<(Vec<u8>, u64)>,
+}
+
+impl TableVisitor for ChunkSender {
+    type Output = io::Result<()>;
+
+    fn visit<R: TpchRow + Display + 'static>(
+        self,
+        rows: impl Iterator<Item = R> + 'static,
+    ) -> Self::Output {
+        let mut rows = rows.fuse();
+        loop {
+            let mut chunk = Vec::new();
+            let count = match &self.encoding {
+                Encoding::Csv(csv) => write_chunk(CsvWriter::new(&mut chunk, csv), &mut rows)?,
+                Encoding::Binary(columns) => {
+                    write_chunk(BinaryWriter::new(&mut chunk, columns.clone()), &mut rows)?
+                }
+            };
+            // Nothing is left, or the backend stopped taking chunks.
+            if count == 0 || self.sender.send((chunk, count)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Writes up to [`CHUNK_ROWS`] of `rows` with `writer` and returns how many.
+fn write_chunk<R: TpchRow>(
+    mut writer: impl RowWriter,
+    rows: &mut impl Iterator<Item = R>,
+) -> io::Result<u64> {
+    let mut count = 0;
+    for row in rows.take(CHUNK_ROWS) {
+        writer.write_row(&row)?;
+        count += 1;
+    }
+    Ok(count)
+}
@@ -0,0 +1,103 @@
+//! Throttling loads on shared servers.
+//!
+//! `pg_tpch.max_load_rows_per_sec` and `pg_tpch.max_load_mb_per_sec` cap how
+//! fast a load step feeds Postgres. The generators count the rows they
+//! produce and the backend the bytes of `COPY` input or `INSERT` statements
+//! it hands over; whichever runs ahead of its limit since the step started
+//! sleeps until it is back at it. The limits hold per backend and need
+//! Postgres to pull the rows from the generator as it takes them, so they
+//! only work with the `stream` and `insert` strategies: `COPY` reads spill
+//! files as fast as it can.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pgrx::{register_xact_callback, PgXactCallbackEvent};
+
+use crate::guc::{MAX_LOAD_MB_PER_SEC, MAX_LOAD_ROWS_PER_SEC};
+
+/// Most rows generated between two looks at the clock.
+const CHECK_INTERVAL_ROWS: u64 = 1024;
+
+/// Counts what a load step produced against a limit per second.
+struct Limit {
+    /// The limit, 0 when there is none.
+    per_sec: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Limit {
+    const fn new() -> Self {
+        Limit {
+            per_sec: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts `n` more and, once `check_every` more were counted since the
+    /// last look, sleeps until the count is back within the limit.
+    fn add(&self, n: u64, check_every: u64) {
+        let per_sec = self.per_sec.load(Ordering::Relaxed);
+        if per_sec == 0 {
+            return;
+        }
+        let count = self.count.fetch_add(n, Ordering::Relaxed) + n;
+        if (count - n) / check_every == count / check_every {
+            return;
+        }
+        let Some(started) = *STARTED.lock().unwrap_or_else(|e| e.into_inner()) else {
+            return;
+        };
+        let due = Duration::from_secs_f64(count as f64 / per_sec as f64);
+        if let Some(ahead) = due.checked_sub(started.elapsed()) {
+            thread::sleep(ahead);
+        }
+    }
+}
+
+static ROWS: Limit = Limit::new();
+static BYTES: Limit = Limit::new();
+/// When the load step being throttled started.
+static STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Whether a limit is set.
+pub fn enabled() -> bool {
+    MAX_LOAD_ROWS_PER_SEC.get() > 0 || MAX_LOAD_MB_PER_SEC.get() > 0
+}
+
+/// Starts throttling a load step with the limits of the settings, until the
+/// transaction ends.
+pub fn begin() {
+    ROWS.per_sec
+        .store(MAX_LOAD_ROWS_PER_SEC.get() as u64, Ordering::Relaxed);
+    BYTES.per_sec.store(
+        MAX_LOAD_MB_PER_SEC.get() as u64 * 1024 * 1024,
+        Ordering::Relaxed,
+    );
+    ROWS.count.store(0, Ordering::Relaxed);
+    BYTES.count.store(0, Ordering::Relaxed);
+    *STARTED.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+    register_xact_callback(PgXactCallbackEvent::Commit, end);
+    register_xact_callback(PgXactCallbackEvent::Abort, end);
+}
+
+/// Counts a generated row, from any thread.
+pub fn row() {
+    // Looks often enough at low limits that no sleep takes much longer than
+    // a tenth of a second.
+    let per_sec = ROWS.per_sec.load(Ordering::Relaxed);
+    ROWS.add(1, (per_sec / 10).clamp(1, CHECK_INTERVAL_ROWS));
+}
+
+/// Counts `n` bytes handed to Postgres.
+pub fn bytes(n: u64) {
+    BYTES.add(n, 1);
+}
+
+/// Stops throttling.
+fn end() {
+    ROWS.per_sec.store(0, Ordering::Relaxed);
+    BYTES.per_sec.store(0, Ordering::Relaxed);
+    *STARTED.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
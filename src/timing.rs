@@ -0,0 +1,50 @@
+//! Where the time loading a table takes goes: generating rows or ingesting
+//! them.
+//!
+//! The loaders wrap the work of the generators, rendering rows and spilling
+//! them to files included, in [`generating`], which adds up its duration.
+//! Whatever else loading a table takes is Postgres ingesting the rows, with
+//! `COPY` or `INSERT`. Generation time with threads is the time the backend
+//! waited for them, not the sum of what the threads spent.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Nanoseconds spent generating since the backend started.
+static GENERATING: AtomicU64 = AtomicU64::new(0);
+
+/// Runs `f`, counting its duration as generation time.
+pub fn generating<T>(f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    GENERATING.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
+/// Splits the time loading a table takes into generation and ingest.
+pub struct TableTimer {
+    started: Instant,
+    generating: u64,
+}
+
+impl TableTimer {
+    /// Starts timing from now.
+    pub fn start() -> Self {
+        TableTimer {
+            started: Instant::now(),
+            generating: GENERATING.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the time spent generating and ingesting since
+    /// [`TableTimer::start`], in that order.
+    pub fn split(&self) -> (Duration, Duration) {
+        let elapsed = self.started.elapsed();
+        let generating = Duration::from_nanos(
+            GENERATING
+                .load(Ordering::Relaxed)
+                .saturating_sub(self.generating),
+        )
+        .min(elapsed);
+        (generating, elapsed - generating)
+    }
+}
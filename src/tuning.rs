@@ -0,0 +1,344 @@
+//! Configuration advice for running TPC-H at a given scale factor.
+use std::fs;
+
+use pgrx::prelude::*;
+use pgrx::spi;
+
+use crate::spill;
+use crate::tables::{self, TABLES};
+
+/// Size of the eight tables on disk per unit of scale factor, without
+/// indexes, measured on Postgres 15.
+pub const TABLE_BYTES_PER_SF: f64 = 1.344e9;
+
+/// Size of `table` on disk at SF=1 as [`TABLE_BYTES_PER_SF`] adds them up,
+/// or at any scale factor for the tables of
+/// [`crate::tables::FIXED_SIZE_TABLES`].
+fn table_bytes_per_sf(table: &str) -> f64 {
+    match table {
+        "region" | "nation" => 40960.,
+        "part" => 33.6e6,
+        "supplier" => 1.85e6,
+        "partsupp" => 143.0e6,
+        "customer" => 29.4e6,
+        "orders" => 213.9e6,
+        "lineitem" => 921.9e6,
+        _ => 0.,
+    }
+}
+
+/// Bytes of CSV input a load turns into table data per second when this
+/// server never loaded the table before, about what a single `COPY` takes.
+const DEFAULT_CSV_BYTES_PER_SEC: f64 = 50e6;
+
+const MB: u64 = 1024 * 1024;
+const GB: u64 = 1024 * MB;
+
+/// Physical memory of the server, if the platform tells.
+fn total_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let kb = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Current value of the memory setting `name`, in bytes.
+fn setting_bytes(name: &str) -> spi::Result<u64> {
+    let bytes =
+        Spi::get_one_with_args::<i64>("SELECT pg_size_bytes(current_setting($1))", &[name.into()])?;
+    Ok(bytes.unwrap_or(0) as u64)
+}
+
+fn setting_int(name: &str) -> spi::Result<i64> {
+    let value = Spi::get_one_with_args::<String>("SELECT current_setting($1)", &[name.into()])?;
+    Ok(value.and_then(|value| value.parse().ok()).unwrap_or(0))
+}
+
+/// Whether changing `name` needs a server restart.
+fn needs_restart(name: &str) -> spi::Result<bool> {
+    let context = Spi::get_one_with_args::<String>(
+        "SELECT max(context) FROM pg_settings WHERE name = $1",
+        &[name.into()],
+    )?;
+    Ok(context.as_deref() == Some("postmaster"))
+}
+
+/// Renders `bytes` the way `postgresql.conf` spells sizes, in whole MB or GB.
+pub fn size(bytes: u64) -> String {
+    if bytes >= GB && bytes.is_multiple_of(GB) {
+        format!("{}GB", bytes / GB)
+    } else {
+        format!("{}MB", bytes.div_ceil(MB))
+    }
+}
+
+/// Rounds `bytes` up to a whole GB above 1GB and to 64MB below.
+fn round_size(bytes: u64) -> u64 {
+    let step = if bytes > GB { GB } else { 64 * MB };
+    bytes.div_ceil(step).max(1) * step
+}
+
+/// Settings `tpch_load(tune => true)` uses while it loads, as `(name,
+/// value)`. Sizes and counts only ever go up from the session's own values.
+const LOAD_SETTINGS: &[(&str, &str)] = &[
+    // The load commits once, nothing is lost that it couldn't redo, and the
+    // WAL of a big load is written out by the WAL writer in the meantime.
+    ("synchronous_commit", "off"),
+    // Primary keys and indexes built right after loading sort whole tables.
+    ("maintenance_work_mem", "1GB"),
+    ("max_parallel_maintenance_workers", "4"),
+    // Loading doesn't plan anything worth compiling.
+    ("jit", "off"),
+];
+
+/// Applies [`LOAD_SETTINGS`] until the end of the transaction.
+///
+/// They are set like `SET LOCAL`, so they last through the commit of the
+/// load, which `synchronous_commit` is about, and the session's own values
+/// come back afterwards, also if the load fails.
+pub fn tune_load() -> spi::Result<()> {
+    for &(name, value) in LOAD_SETTINGS {
+        let apply = Spi::get_one_with_args::<bool>(
+            "SELECT CASE WHEN unit IS NULL AND vartype <> 'integer' THEN true
+                         ELSE pg_size_bytes(current_setting($1)) < pg_size_bytes($2) END
+             FROM pg_settings WHERE name = $1",
+            &[name.into(), value.into()],
+        )?;
+        if apply == Some(true) {
+            Spi::run_with_args(
+                "SELECT set_config($1, $2, true)",
+                &[name.into(), value.into()],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+type Advice = (String, String, String, String, String);
+
+/// Suggests settings for running the queries on SF=`sf`, one row per
+/// setting that is worth changing, with the `ALTER SYSTEM` statement and the
+/// reasoning.
+///
+/// The dataset size comes from [`TABLE_BYTES_PER_SF`]. Memory and CPUs are
+/// those of the machine the server runs on, which only Linux reports memory
+/// for; elsewhere the advice is based on the dataset alone. Queries are
+/// assumed to run one at a time, as they do in a power run.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_tuning_advice(
+    sf: default!(f64, 1.),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(setting, String),
+            name!(current_value, String),
+            name!(suggested_value, String),
+            name!(statement, String),
+            name!(reason, String),
+        ),
+    >,
+> {
+    if !(sf > 0. && sf.is_finite()) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("sf must be a positive number, got {sf}")
+        );
+    }
+    let data_bytes = (sf * TABLE_BYTES_PER_SF) as u64;
+    let memory = total_memory();
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get()) as i64;
+
+    let mut advice: Vec<Advice> = Vec::new();
+    let mut suggest = |name: &str, current: String, suggested: String, reason: String| {
+        let restart = needs_restart(name)?;
+        let reason = if restart {
+            format!("{reason} Takes effect after a restart.")
+        } else {
+            format!("{reason} Takes effect after SELECT pg_reload_conf().")
+        };
+        advice.push((
+            name.to_string(),
+            current,
+            suggested.clone(),
+            format!(
+                "ALTER SYSTEM SET {name} = {};",
+                spi::quote_literal(&suggested)
+            ),
+            reason,
+        ));
+        Ok::<_, spi::SpiError>(())
+    };
+
+    // Keep the whole dataset in shared buffers if it fits in a quarter of
+    // the memory, the usual ceiling for shared_buffers.
+    let shared_buffers = setting_bytes("shared_buffers")?;
+    let wanted_shared = round_size(memory.map_or(data_bytes, |memory| data_bytes.min(memory / 4)));
+    if shared_buffers < wanted_shared {
+        let reason = match memory {
+            Some(memory) if data_bytes > memory / 4 => format!(
+                "The dataset takes about {}, a quarter of the {} of memory is as much as \
+                 shared_buffers should get.",
+                size(data_bytes),
+                size(memory)
+            ),
+            _ => format!(
+                "The dataset takes about {} and fits in shared buffers, so repeated runs \
+                 don't read from disk.",
+                size(data_bytes)
+            ),
+        };
+        suggest(
+            "shared_buffers",
+            size(shared_buffers),
+            size(wanted_shared),
+            reason,
+        )?;
+    }
+
+    // The planner assumes this much of the data is cached by shared buffers
+    // and the operating system together.
+    if let Some(memory) = memory {
+        let effective_cache_size = setting_bytes("effective_cache_size")?;
+        let wanted = round_size(memory / 4 * 3);
+        if effective_cache_size < wanted {
+            suggest(
+                "effective_cache_size",
+                size(effective_cache_size),
+                size(wanted),
+                format!(
+                    "Three quarters of the {} of memory are available for caching, a lower \
+                     value makes the planner avoid index scans.",
+                    size(memory)
+                ),
+            )?;
+        }
+    }
+
+    // A quarter of the memory left after shared buffers, shared by the
+    // sorts and hashes of a query's workers. Two per query is typical.
+    let workers = (cpus / 2).clamp(1, 8);
+    let work_mem = setting_bytes("work_mem")?;
+    let wanted = match memory {
+        Some(memory) => {
+            let available = memory.saturating_sub(wanted_shared) / 4;
+            round_size(available / (2 * (workers as u64 + 1))).clamp(64 * MB, 2 * GB)
+        }
+        None => 64 * MB,
+    };
+    if work_mem < wanted {
+        suggest(
+            "work_mem",
+            size(work_mem),
+            size(wanted),
+            format!(
+                "Aggregates and joins over lineitem and orders spill to disk with little \
+                 work_mem. Each of up to {} processes of a query gets this much per sort or \
+                 hash.",
+                workers + 1
+            ),
+        )?;
+    }
+
+    if sf >= 1. && cpus > 1 {
+        let per_gather = setting_int("max_parallel_workers_per_gather")?;
+        if per_gather < workers {
+            suggest(
+                "max_parallel_workers_per_gather",
+                per_gather.to_string(),
+                workers.to_string(),
+                format!(
+                    "Half of the {cpus} CPUs can scan lineitem in parallel, the rest stay free \
+                     for the leader and the system."
+                ),
+            )?;
+        }
+        let max_parallel = setting_int("max_parallel_workers")?;
+        if max_parallel < workers {
+            suggest(
+                "max_parallel_workers",
+                max_parallel.to_string(),
+                workers.to_string(),
+                "Caps the workers of all queries together, it has to allow those of one query."
+                    .to_string(),
+            )?;
+        }
+        let max_processes = setting_int("max_worker_processes")?;
+        if max_processes < workers {
+            suggest(
+                "max_worker_processes",
+                max_processes.to_string(),
+                workers.to_string(),
+                "Parallel workers are taken from this pool.".to_string(),
+            )?;
+        }
+    }
+
+    Ok(TableIterator::new(advice))
+}
+
+/// Estimates what loading SF=`sf` takes, without generating anything: per
+/// table the rows, the bytes of CSV a file-based load spills, the size of
+/// the table on disk without indexes and the seconds loading it takes.
+///
+/// Sizes hold for the default schema profile. Load times come from the rows
+/// per second earlier loads of the table reached on this server, as
+/// `pg_tpch_load_throughput` shows them, or from a typical `COPY` rate when
+/// there are none, so they are rough either way and leave out building
+/// indexes and constraints.
+#[allow(clippy::type_complexity)]
+#[pg_extern]
+fn tpch_load_estimate(
+    sf: default!(f64, 1.),
+) -> spi::Result<
+    TableIterator<
+        'static,
+        (
+            name!(table_name, String),
+            name!(rows, i64),
+            name!(csv_bytes, i64),
+            name!(table_bytes, i64),
+            name!(load_seconds, f64),
+        ),
+    >,
+> {
+    if !(sf > 0. && sf.is_finite()) {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("sf must be a positive number, got {sf}")
+        );
+    }
+    let mut estimates = Vec::with_capacity(TABLES.len());
+    for table in TABLES {
+        let rows = tables::expected_rows(table, sf, 1, 1);
+        let fixed = tables::FIXED_SIZE_TABLES.contains(&table);
+        let csv_bytes = spill::csv_bytes_per_sf(table) * sf;
+        let table_bytes = table_bytes_per_sf(table) * if fixed { 1. } else { sf };
+        let rows_per_sec = Spi::get_one_with_args::<f64>(
+            "SELECT sum(rows) * 1000 / nullif(sum(generation_ms + copy_ms), 0)
+             FROM pg_tpch_load_stats WHERE table_name = $1",
+            &[table.into()],
+        )?;
+        let load_seconds = match rows_per_sec {
+            Some(rows_per_sec) => rows as f64 / rows_per_sec,
+            None => csv_bytes / DEFAULT_CSV_BYTES_PER_SEC,
+        };
+        estimates.push((
+            table.to_string(),
+            rows,
+            csv_bytes as i64,
+            table_bytes as i64,
+            load_seconds,
+        ));
+    }
+    Ok(TableIterator::new(estimates))
+}